@@ -0,0 +1,96 @@
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use crate::bazel;
+
+const BUILD_FILES: [&str; 2] = ["BUILD", "BUILD.bazel"];
+
+/// The package of `file`: the closest directory holding a `BUILD` file.
+fn package(root: &Path, file: &Path) -> Option<PathBuf> {
+    file.ancestors()
+        .skip(1)
+        .find(|dir| BUILD_FILES.iter().any(|b| root.join(dir).join(b).is_file()))
+        .map(Path::to_path_buf)
+}
+
+/// Maps the changed files onto the targets they affect: sources become
+/// their file label, `BUILD` files stand for every rule of their package.
+/// `None` when a file outside any package changed, e.g. the manifest or the
+/// WORKSPACE, which may affect anything.
+pub fn labels(root: &Path, files: &[PathBuf]) -> Option<Vec<String>> {
+    let mut labels = Vec::new();
+    for file in files {
+        let package = package(root, file)?;
+        let name = file.strip_prefix(&package).ok()?.to_str()?;
+        let package = package.to_str()?.replace('\\', "/");
+        if BUILD_FILES.contains(&name) {
+            labels.push(format!("//{}:all", package));
+        } else {
+            labels.push(format!("//{}:{}", package, name.replace('\\', "/")));
+        }
+    }
+    Some(labels)
+}
+
+/// Queries the tests depending on `labels`. Files no rule refers to are
+/// skipped rather than failing the query.
+pub fn tests(bazel_bin: &Path, labels: &[String]) -> Result<Vec<String>, String> {
+    let query = format!("tests(rdeps(//..., set({})))", labels.join(" "));
+    let output = bazel::command(bazel_bin, "query")
+        .args(["--keep_going", "--output=label"])
+        .arg(query)
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|e| format!("failed to run bazel: {}", e))?;
+    // 3 reports a partial result, some labels not being targets.
+    if !matches!(output.status.code(), Some(0) | Some(3)) {
+        return Err(format!(
+            "bazel query exited with {}, cannot tell the affected tests",
+            output.status
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| line.starts_with("//"))
+        .map(String::from)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_labels() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path();
+        fs::create_dir_all(root.join("src/detail")).unwrap();
+        fs::create_dir_all(root.join("test")).unwrap();
+        fs::write(root.join("src/BUILD"), "").unwrap();
+        fs::write(root.join("test/BUILD.bazel"), "").unwrap();
+
+        assert_eq!(
+            labels(
+                root,
+                &[
+                    PathBuf::from("src/detail/parse.cc"),
+                    PathBuf::from("src/BUILD"),
+                    PathBuf::from("test/parse_test.cc"),
+                ]
+            ),
+            Some(vec![
+                "//src:detail/parse.cc".to_string(),
+                "//src:all".to_string(),
+                "//test:parse_test.cc".to_string(),
+            ])
+        );
+        assert_eq!(
+            labels(
+                root,
+                &[PathBuf::from("src/a.cc"), PathBuf::from("Buddy.toml")]
+            ),
+            None
+        );
+    }
+}
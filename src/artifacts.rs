@@ -0,0 +1,173 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::runtime;
+use crate::style;
+
+/// A file built for a target, from the Build Event Protocol.
+#[derive(Debug, PartialEq)]
+pub struct Artifact {
+    pub label: String,
+    pub path: PathBuf,
+}
+
+/// Decodes the `file://` URIs bazel reports outputs with.
+pub fn file_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| path.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    Some(PathBuf::from(String::from_utf8(decoded).ok()?))
+}
+
+/// The default outputs of the targets built successfully, as listed by the
+/// JSON build events: completed targets point at sets of files, which may
+/// nest other sets.
+pub fn parse(events: &str) -> Vec<Artifact> {
+    let mut sets: HashMap<String, (Vec<PathBuf>, Vec<String>)> = HashMap::new();
+    let mut completed: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+    for line in events.lines() {
+        let Ok(event) = serde_json::from_str::<serde_json::Value>(line) else {
+            continue;
+        };
+        let ids = |value: &serde_json::Value| -> Vec<String> {
+            value["fileSets"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|set| Some(set["id"].as_str()?.to_string()))
+                .collect()
+        };
+
+        if let Some(id) = event["id"]["namedSet"]["id"].as_str() {
+            let set = &event["namedSetOfFiles"];
+            let files = set["files"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|file| file_path(file["uri"].as_str()?))
+                .collect();
+            sets.insert(id.to_string(), (files, ids(set)));
+        }
+
+        let target = &event["id"]["targetCompleted"];
+        if let Some(label) = target["label"].as_str() {
+            // Aspects complete with the label of the target they ran on.
+            if !target["aspect"].is_null() || event["completed"]["success"] != true {
+                continue;
+            }
+            let groups = event["completed"]["outputGroup"].as_array();
+            let default = groups
+                .into_iter()
+                .flatten()
+                .find(|group| group["name"] == "default");
+            if let Some(group) = default {
+                completed.insert(label.to_string(), ids(group));
+            }
+        }
+    }
+
+    let mut artifacts = Vec::new();
+    for (label, roots) in completed {
+        let mut pending = roots;
+        let mut seen = Vec::new();
+        while let Some(id) = pending.pop() {
+            if seen.contains(&id) {
+                continue;
+            }
+            let Some((files, nested)) = sets.get(&id) else {
+                continue;
+            };
+            for path in files {
+                artifacts.push(Artifact {
+                    label: label.clone(),
+                    path: path.clone(),
+                });
+            }
+            pending.extend(nested.iter().cloned());
+            seen.push(id);
+        }
+    }
+    artifacts
+}
+
+/// Copies the artifacts into `out_dir` under their file name, with the
+/// shared libraries they load. Names built by several targets are copied
+/// once, from the first one.
+pub fn copy(artifacts: &[Artifact], out_dir: &Path) -> Result<(), String> {
+    fs::create_dir_all(out_dir).map_err(|e| format!("{}: {}", out_dir.display(), e))?;
+
+    let mut copied: HashMap<String, &str> = HashMap::new();
+    for artifact in artifacts {
+        let Some(name) = artifact.path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        if let Some(label) = copied.get(name) {
+            if *label != artifact.label {
+                style::warning(format!(
+                    "{} and {} both build `{}`, keeping the one of {}",
+                    label, artifact.label, name, label
+                ));
+            }
+            continue;
+        }
+
+        let dest = out_dir.join(name);
+        // Bazel's outputs are read-only, replace rather than overwrite.
+        let _ = fs::remove_file(&dest);
+        fs::copy(&artifact.path, &dest)
+            .map_err(|e| format!("{}: {}", artifact.path.display(), e))?;
+        style::status("Copied", dest.display());
+        if let Err(error) = runtime::bundle(&artifact.path, &dest) {
+            style::warning(error);
+        }
+        copied.insert(name.to_string(), &artifact.label);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let events = r#"{"id":{"namedSet":{"id":"1"}},"namedSetOfFiles":{"files":[{"name":"src/libdemo.a","uri":"file:///out/bin/src/libdemo.a"}]}}
+{"id":{"namedSet":{"id":"0"}},"namedSetOfFiles":{"files":[{"name":"src/my%20app","uri":"file:///out/bin/src/my%20app"}],"fileSets":[{"id":"1"}]}}
+{"id":{"targetCompleted":{"label":"//src:app"}},"completed":{"success":true,"outputGroup":[{"name":"default","fileSets":[{"id":"0"}]}]}}
+{"id":{"targetCompleted":{"label":"//src:broken"}},"completed":{"success":false}}
+{"id":{"targetCompleted":{"label":"//src:app","aspect":"lint"}},"completed":{"success":true,"outputGroup":[{"name":"default","fileSets":[{"id":"1"}]}]}}
+"#;
+        assert_eq!(
+            parse(events),
+            [
+                Artifact {
+                    label: "//src:app".to_string(),
+                    path: PathBuf::from("/out/bin/src/my app"),
+                },
+                Artifact {
+                    label: "//src:app".to_string(),
+                    path: PathBuf::from("/out/bin/src/libdemo.a"),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,814 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::OnceLock;
+use which::which;
+
+use crate::config::{BuildConfig, ProfileConfig};
+use crate::credentials;
+use crate::failure;
+use crate::global::{self, GlobalConfig};
+use crate::mirror;
+use crate::progress::{self, Events, Progress};
+use crate::style::{self, Level};
+
+/// Verbs accepting the remote cache flags, `query` and friends reject them.
+const CACHED_VERBS: [&str; 4] = ["build", "run", "test", "coverage"];
+
+/// Where bazel writes the build events buddy follows the downloads with.
+pub const EVENT_FILE: &str = "target/build_events.json";
+
+/// Verbs which may fetch external repositories, i.e. the ones loading
+/// packages.
+const FETCH_VERBS: [&str; 7] = [
+    "build", "run", "test", "coverage", "query", "aquery", "fetch",
+];
+
+/// The credential helper bazel gets the token of the remote cache from, as
+/// anyone can see its arguments. It answers every request with the header
+/// authenticating with `token`.
+fn credential_helper(token: &str) -> String {
+    let response = serde_json::json!({
+        "headers": { "Authorization": [format!("Bearer {}", token)] },
+    });
+    format!(
+        "#!/bin/sh\n# Written by buddy for the remote cache.\ncat > /dev/null\ncat <<'EOF'\n{}\nEOF\n",
+        response
+    )
+}
+
+/// Writes the credential helper of `token` under `~/.buddy`.
+fn write_credential_helper(token: &str) -> Result<PathBuf, String> {
+    let home = global::buddy_home();
+    fs::create_dir_all(&home).map_err(|e| format!("{}: {}", home.display(), e))?;
+    let path = home.join("credential-helper");
+    credentials::write_private(&path, &credential_helper(token), 0o700)?;
+    Ok(path)
+}
+
+/// Points bazel at the user's remote cache, if any, authenticated with the
+/// token stored for it.
+fn remote_cache_flags(config: &GlobalConfig) -> Vec<String> {
+    let Some(cache) = &config.remote_cache else {
+        return Vec::new();
+    };
+
+    let mut flags = vec![format!("--remote_cache={}", cache.url)];
+    let helper = credentials::token_for_url(config, &cache.url).and_then(|token| {
+        token
+            .map(|token| write_credential_helper(&token))
+            .transpose()
+    });
+    match helper {
+        Ok(Some(path)) => {
+            // Helpers are scoped to a host, without its port.
+            let host = credentials::host(&cache.url);
+            let host = host.rsplit_once(':').map_or(host, |(host, _)| host);
+            flags.push(format!("--credential_helper={}={}", host, path.display()));
+        }
+        Ok(None) => {}
+        Err(error) => style::warning(error),
+    }
+    flags
+}
+
+static OFFLINE: OnceLock<bool> = OnceLock::new();
+
+/// Keeps the bazel commands off the network for `--offline` and
+/// `--frozen`, before any is run: the remote cache is left out.
+pub fn set_offline(offline: bool) {
+    let _ = OFFLINE.set(offline);
+}
+
+fn offline() -> bool {
+    OFFLINE.get().copied().unwrap_or(false)
+}
+
+/// Applies the user configuration: proxies, mirrors, prefetched archives
+/// and, unless `offline`, remote cache.
+fn apply_user_config(cmd: &mut Command, verb: &str, config: &GlobalConfig, offline: bool) {
+    cmd.envs(config.http.env());
+
+    if FETCH_VERBS.contains(&verb) {
+        for distdir in global::distdirs() {
+            cmd.arg(format!("--distdir={}", distdir.display()));
+        }
+    }
+
+    let rules = &config.source.replace;
+    if !rules.is_empty() {
+        if std::env::var_os("BAZELISK_BASE_URL").is_none() {
+            if let Some(url) = mirror::bazelisk_base_url(rules) {
+                cmd.env("BAZELISK_BASE_URL", url);
+            }
+        }
+        if FETCH_VERBS.contains(&verb) {
+            match mirror::write_downloader_config(rules) {
+                Ok(path) => {
+                    cmd.arg(format!(
+                        "--experimental_downloader_config={}",
+                        path.display()
+                    ));
+                }
+                Err(error) => style::warning(error),
+            }
+        }
+    }
+
+    if CACHED_VERBS.contains(&verb) && !offline {
+        cmd.args(remote_cache_flags(config));
+    }
+}
+
+/// Converts a RAM size such as `8G`, `512M` or `50%` into the value of
+/// `--local_ram_resources`, in megabytes or relative to the host's RAM.
+pub fn ram_resources(size: &str) -> Result<String, String> {
+    let size = size.trim();
+    let invalid = || {
+        format!(
+            "invalid RAM size `{}`, expected e.g. `8G`, `512M` or `50%`",
+            size
+        )
+    };
+
+    if let Some(percent) = size.strip_suffix('%') {
+        let percent: f64 = percent.parse().map_err(|_| invalid())?;
+        if !(percent > 0.0 && percent <= 100.0) {
+            return Err(invalid());
+        }
+        return Ok(format!("HOST_RAM*{}", percent / 100.0));
+    }
+
+    let upper = size.to_ascii_uppercase();
+    let upper = upper.strip_suffix('B').unwrap_or(&upper);
+    let (number, factor) = match upper.char_indices().last() {
+        Some((i, 'G')) => (&upper[..i], 1024),
+        Some((i, 'M')) => (&upper[..i], 1),
+        _ => (upper, 1),
+    };
+    let megabytes: u64 = number.trim().parse().map_err(|_| invalid())?;
+    if megabytes == 0 {
+        return Err(invalid());
+    }
+    Ok((megabytes * factor).to_string())
+}
+
+/// Limits the parallelism of a build to `jobs` and the RAM of the local
+/// actions to `local_ram`.
+pub fn resource_flags(jobs: Option<u32>, local_ram: Option<&str>) -> Result<Vec<String>, String> {
+    let mut flags = Vec::new();
+    if let Some(jobs) = jobs {
+        if jobs == 0 {
+            return Err("the number of jobs must be at least 1".to_string());
+        }
+        flags.push(format!("--jobs={}", jobs));
+        flags.push(format!("--local_cpu_resources={}", jobs));
+    }
+    if let Some(size) = local_ram {
+        flags.push(format!("--local_ram_resources={}", ram_resources(size)?));
+    }
+    Ok(flags)
+}
+
+/// Keeps bazel from downloading anything, external repositories come from
+/// the distdir and the repository cache only.
+pub const OFFLINE_FLAG: &str = "--experimental_repository_disable_download";
+
+/// Sets the variables of `[env]` for the build actions and the tests.
+pub fn env_flags(vars: &[(String, String)]) -> Vec<String> {
+    vars.iter()
+        .flat_map(|(name, value)| {
+            [
+                format!("--action_env={}={}", name, value),
+                format!("--test_env={}={}", name, value),
+            ]
+        })
+        .collect()
+}
+
+/// The compilation modes of Bazel.
+const COMPILATION_MODES: [&str; 3] = ["dbg", "opt", "fastbuild"];
+
+/// Compiles as `profile` says, the release one when `release` is set, see
+/// `[profile]`.
+pub fn profile_flags(profile: &ProfileConfig, release: bool) -> Result<Vec<String>, String> {
+    let default_mode = if release { "opt" } else { "dbg" };
+    let mode = profile.compilation_mode.as_deref().unwrap_or(default_mode);
+    if !COMPILATION_MODES.contains(&mode) {
+        return Err(format!(
+            "unknown compilation mode `{}`, expected one of: {}",
+            mode,
+            COMPILATION_MODES.join(", ")
+        ));
+    }
+
+    let mut flags = vec![format!("--compilation_mode={}", mode)];
+    flags.extend(profile.copts.iter().map(|copt| format!("--copt={}", copt)));
+    flags.extend(
+        profile
+            .defines
+            .iter()
+            .map(|define| format!("--copt=-D{}", define)),
+    );
+    flags.extend(
+        profile
+            .linkopts
+            .iter()
+            .map(|linkopt| format!("--linkopt={}", linkopt)),
+    );
+    if profile.lto {
+        flags.extend(["--copt=-flto".to_string(), "--linkopt=-flto".to_string()]);
+    }
+    Ok(flags)
+}
+
+/// Compiles the sources of the project, not the ones of its external
+/// dependencies, with `copts`, `includes` and `defines` of `[build]`, and
+/// links with its `linkopts`.
+pub fn build_flags(build: &BuildConfig) -> Vec<String> {
+    // Commas separate the options of `--per_file_copt`.
+    let per_file = |copt: String| {
+        format!(
+            "--per_file_copt=.*,-external/.*@{}",
+            copt.replace(',', "\\,")
+        )
+    };
+    let mut flags: Vec<String> = build.copts.iter().cloned().map(per_file).collect();
+    flags.extend(
+        build
+            .includes
+            .iter()
+            .map(|dir| per_file(format!("-I{}", dir.trim_end_matches('/')))),
+    );
+    flags.extend(
+        build
+            .defines
+            .iter()
+            .map(|define| per_file(format!("-D{}", define))),
+    );
+    flags.extend(
+        build
+            .linkopts
+            .iter()
+            .map(|linkopt| format!("--linkopt={}", linkopt)),
+    );
+    flags
+}
+
+/// Runs only the tests tagged with one of `tags`, if any, and none of
+/// `excluded`.
+pub fn test_tag_filters(tags: &[String], excluded: &[String]) -> Vec<String> {
+    let filters: Vec<String> = tags
+        .iter()
+        .cloned()
+        .chain(excluded.iter().map(|tag| format!("-{}", tag)))
+        .collect();
+    if filters.is_empty() {
+        return Vec::new();
+    }
+    vec![format!("--test_tag_filters={}", filters.join(","))]
+}
+
+/// Linkers `[build] linker` can select, fastest first: name, binary looked
+/// up on the `PATH`, and how to install it.
+const LINKERS: [(&str, &str, &str); 3] = [
+    (
+        "mold",
+        "mold",
+        "Install it with your package manager (e.g. `apt install mold` or `brew install mold`), see https://github.com/rui314/mold",
+    ),
+    (
+        "lld",
+        "ld.lld",
+        "Install it with your package manager (e.g. `apt install lld` or `brew install llvm`)",
+    ),
+    (
+        "gold",
+        "ld.gold",
+        "It is part of binutils (e.g. `apt install binutils`)",
+    ),
+];
+
+fn linker_flags_with(
+    linker: Option<&str>,
+    installed: impl Fn(&str) -> bool,
+) -> Result<Vec<String>, String> {
+    let name = match linker {
+        None | Some("default") => return Ok(Vec::new()),
+        Some("auto") => match LINKERS.iter().find(|(_, binary, _)| installed(binary)) {
+            Some((name, _, _)) => *name,
+            None => return Ok(Vec::new()),
+        },
+        Some(name) => {
+            let (_, binary, hint) = LINKERS
+                .iter()
+                .find(|(known, _, _)| *known == name)
+                .ok_or_else(|| {
+                    format!(
+                        "unknown linker `{}`, expected one of: auto, default, mold, lld, gold",
+                        name
+                    )
+                })?;
+            if !installed(binary) {
+                return Err(format!(
+                    "`{}` not found, it is needed by `[build] linker = \"{}\"`. {}",
+                    binary, name, hint
+                ));
+            }
+            name
+        }
+    };
+    Ok(vec![format!("--linkopt=-fuse-ld={}", name)])
+}
+
+/// Makes the toolchain link with `linker`, see `[build] linker`.
+pub fn linker_flags(linker: Option<&str>) -> Result<Vec<String>, String> {
+    linker_flags_with(linker, |binary| which(binary).is_ok())
+}
+
+/// The standards `[package] c-standard` accepts.
+const C_STANDARDS: [&str; 10] = [
+    "c89", "c99", "c11", "c17", "c23", "gnu89", "gnu99", "gnu11", "gnu17", "gnu23",
+];
+
+/// Compiles the C sources, and only them, against `standard`.
+pub fn c_standard_flags(standard: Option<&str>) -> Result<Vec<String>, String> {
+    let Some(standard) = standard else {
+        return Ok(Vec::new());
+    };
+    if !C_STANDARDS.contains(&standard) {
+        return Err(format!(
+            "unknown C standard `{}`, expected one of: {}",
+            standard,
+            C_STANDARDS.join(", ")
+        ));
+    }
+    Ok(vec![format!("--conlyopt=-std={}", standard)])
+}
+
+/// The C++ standard of packages whose manifest names none.
+pub const DEFAULT_CXX_STANDARD: &str = "c++17";
+
+const CXX_STANDARDS: [&str; 12] = [
+    "c++11", "c++14", "c++17", "c++20", "c++23", "c++26", "gnu++11", "gnu++14", "gnu++17",
+    "gnu++20", "gnu++23", "gnu++26",
+];
+
+/// Compiles the C++ sources against `standard`, overriding the one of
+/// `.bazelrc`.
+pub fn cxx_standard_flags(standard: Option<&str>) -> Result<Vec<String>, String> {
+    let Some(standard) = standard else {
+        return Ok(Vec::new());
+    };
+    if !CXX_STANDARDS.contains(&standard) {
+        return Err(format!(
+            "unknown C++ standard `{}`, expected one of: {}",
+            standard,
+            CXX_STANDARDS.join(", ")
+        ));
+    }
+    Ok(vec![format!("--cxxopt=-std={}", standard)])
+}
+
+/// A bazel release, as reported by `bazel --version`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl Version {
+    const fn new(major: u32, minor: u32, patch: u32) -> Version {
+        Version {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Parses `bazel 7.1.0` or `bazel 8.0.0rc2`. Development builds report
+    /// `bazel no_version` and have no version.
+    pub fn parse(output: &str) -> Option<Version> {
+        let version = output.trim().strip_prefix("bazel ")?;
+        let mut parts = version.splitn(3, '.').map(|part| {
+            let digits = part.find(|c: char| !c.is_ascii_digit());
+            part[..digits.unwrap_or(part.len())].parse::<u32>().ok()
+        });
+        Some(Version::new(
+            parts.next()??,
+            parts.next().flatten().unwrap_or(0),
+            parts.next().flatten().unwrap_or(0),
+        ))
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// The bazel versions each edition works with: the oldest supported one,
+/// and the first release which isn't. Bazel 9 drops the WORKSPACE buddy
+/// generates.
+const EDITIONS: [(&str, Version, Version); 1] =
+    [("2023", Version::new(6, 0, 0), Version::new(9, 0, 0))];
+
+/// Checks that the edition of the package supports `version`.
+pub fn check_version(edition: &str, version: Version) -> Result<(), String> {
+    let Some((_, oldest, unsupported)) = EDITIONS.iter().find(|(name, _, _)| *name == edition)
+    else {
+        return Ok(());
+    };
+    if version < *oldest || version >= *unsupported {
+        return Err(format!(
+            "bazel {} is not supported by edition {}, which needs bazel {} or later, before {}. Pin a supported version in .bazelversion, e.g. `echo 7.4.1 > .bazelversion`",
+            version, edition, oldest, unsupported
+        ));
+    }
+    Ok(())
+}
+
+/// The version of bazel `bazel_bin` runs, bazelisk picking it from
+/// `.bazelversion`. Asked once per buddy invocation.
+pub fn version(bazel_bin: &Path) -> Option<Version> {
+    static VERSION: OnceLock<Option<Version>> = OnceLock::new();
+    *VERSION.get_or_init(|| {
+        let mut cmd = Command::new(bazel_bin);
+        cmd.arg("--version");
+        if let Ok(config) = GlobalConfig::load() {
+            // Bazelisk may download bazel through the mirrors.
+            apply_user_config(&mut cmd, "version", &config, offline());
+        }
+        let output = cmd.stderr(Stdio::null()).output().ok()?;
+        Version::parse(&String::from_utf8_lossy(&output.stdout))
+    })
+}
+
+/// Flags keeping buddy's setup working across bazel releases: the
+/// WORKSPACE rather than bzlmod, which is the default since bazel 7 and
+/// the only option from bazel 8 on unless asked for, and
+/// `cc_shared_library`, experimental before bazel 7.
+pub fn compatibility_flags(version: Version) -> Vec<String> {
+    let mut flags = Vec::new();
+    if version.major < 7 {
+        flags.push("--experimental_cc_shared_library".to_string());
+    } else {
+        flags.push("--noenable_bzlmod".to_string());
+    }
+    if version.major >= 8 {
+        flags.push("--enable_workspace".to_string());
+    }
+    flags
+}
+
+/// Verbs taking no build options, which would reject `--symlink_prefix`.
+const BARE_VERBS: [&str; 4] = ["help", "license", "shutdown", "version"];
+
+/// Whether `verb` accepts the flags of a build, e.g. the ones of
+/// features or `[build]`.
+pub fn builds(verb: &str) -> bool {
+    CACHED_VERBS.contains(&verb)
+}
+
+/// Prepares a bazel invocation of `verb` with buddy's standard setup applied.
+pub fn command(bazel_bin: &Path, verb: &str) -> Command {
+    command_with(bazel_bin, &[], verb)
+}
+
+/// Like `command`, with the startup options of bazel, e.g.
+/// `--output_user_root`, placed before `verb`.
+pub fn command_with(bazel_bin: &Path, startup_options: &[String], verb: &str) -> Command {
+    let mut cmd = Command::new(bazel_bin);
+
+    // cmd.arg("--output_base=target/build");
+    cmd.args(startup_options);
+    cmd.arg(verb);
+    if !BARE_VERBS.contains(&verb) {
+        cmd.arg("--symlink_prefix=target/");
+    }
+    if FETCH_VERBS.contains(&verb) {
+        if let Some(version) = version(bazel_bin) {
+            cmd.args(compatibility_flags(version));
+        }
+    }
+    if CACHED_VERBS.contains(&verb) {
+        // Start from an empty file, stale events would be reported again.
+        let _ = fs::create_dir_all("target");
+        let _ = fs::remove_file(EVENT_FILE);
+        cmd.arg(format!("--build_event_json_file={}", EVENT_FILE));
+    }
+    match GlobalConfig::load() {
+        Ok(config) => apply_user_config(&mut cmd, verb, &config, offline()),
+        Err(error) => style::warning(error),
+    }
+    cmd
+}
+
+/// Runs `cmd`, echoing bazel's progress output with buddy's styling, and
+/// waits for it to finish.
+pub fn stream(cmd: &mut Command) -> io::Result<ExitStatus> {
+    let mut child = cmd.stderr(Stdio::piped()).spawn()?;
+
+    let stderr = child.stderr.take().unwrap();
+    let reader = io::BufReader::new(stderr);
+
+    let args: Vec<_> = cmd.get_args().collect();
+    let events = progress::event_file(&args).map(Events::watch);
+    let mut progress = Progress::default();
+    let mut log = Vec::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        if let Some(download) = progress::parse_download(&line) {
+            progress.show(&download);
+            continue;
+        }
+        if let Some(events) = &events {
+            progress.fetched(events.completed());
+        }
+
+        progress.clear();
+        if line.starts_with("INFO:") {
+            let (_, message) = line.split_at(6);
+            println!("{} {}", style::paint("INFO:", Level::Info), message);
+        } else {
+            println!("{}", line);
+        }
+        log.push(line);
+    }
+
+    let status = child.wait()?;
+    if let Some(events) = events {
+        progress.fetched(events.finish());
+    }
+    progress.clear();
+
+    if !status.success() {
+        let event_file = progress::event_file(&args);
+        if let Err(error) = failure::record(cmd, status, log, event_file.as_deref()) {
+            style::warning(error);
+        }
+    }
+
+    // Not sure why is still being generated. Eitherway, we get rid of it.
+    let folder_path = Path::new("bazel-out");
+    if folder_path.exists() {
+        fs::remove_dir_all(folder_path)?;
+    }
+
+    Ok(status)
+}
+
+/// Runs `cmd` and returns its standard output, e.g. for `bazel query`.
+pub fn output(cmd: &mut Command) -> Result<String, String> {
+    let output = cmd
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| format!("failed to run bazel: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!("bazel exited with {}", output.status));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Has a build stop once the sources are compiled: the object files of the
+/// targets are produced, nothing is linked.
+pub const COMPILE_ONLY: &str = "--output_groups=compilation_outputs";
+
+/// The arguments of the build `buddy check` runs for `targets`, labels or
+/// source files relative to `base`. A source file compiles the target it
+/// belongs to, e.g. the library including a header just edited.
+pub fn check_args(targets: &[String], base: &Path) -> Result<Vec<String>, String> {
+    let files: Vec<String> = targets
+        .iter()
+        .filter(|target| !target.starts_with("//") && !target.starts_with('@'))
+        .map(|target| base.join(target))
+        .filter(|path| path.is_file())
+        .map(|path| path.to_string_lossy().replace('\\', "/"))
+        .collect();
+
+    let mut args = vec![COMPILE_ONLY.to_string()];
+    if files.is_empty() {
+        args.extend(targets.iter().cloned());
+    } else if files.len() == targets.len() {
+        args.push("--compile_one_dependency".to_string());
+        args.extend(files);
+    } else {
+        return Err("`buddy check` takes either labels or source files, not both".to_string());
+    }
+    Ok(args)
+}
+
+/// Maps a label such as `//bench:sort` onto its output under `target/bin`.
+pub fn bin_path(label: &str) -> Option<std::path::PathBuf> {
+    let (package, name) = label.strip_prefix("//")?.split_once(':')?;
+    Some(Path::new("target").join("bin").join(package).join(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ram_resources() {
+        assert_eq!(ram_resources("8G").unwrap(), "8192");
+        assert_eq!(ram_resources("512mb").unwrap(), "512");
+        assert_eq!(ram_resources("2048").unwrap(), "2048");
+        assert_eq!(ram_resources("50%").unwrap(), "HOST_RAM*0.5");
+        assert!(ram_resources("lots").is_err());
+        assert!(ram_resources("150%").is_err());
+        assert!(ram_resources("0G").is_err());
+    }
+
+    #[test]
+    fn test_env_flags() {
+        assert_eq!(
+            env_flags(&[("LOG_LEVEL".to_string(), "debug".to_string())]),
+            ["--action_env=LOG_LEVEL=debug", "--test_env=LOG_LEVEL=debug"]
+        );
+    }
+
+    #[test]
+    fn test_build_flags() {
+        let build: BuildConfig = toml::from_str(
+            r#"
+copts = ["-Wall", "-Wl,-z,defs"]
+linkopts = ["-lm"]
+includes = ["third_party/"]
+defines = ["USE_SSL=1"]
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            build_flags(&build),
+            [
+                "--per_file_copt=.*,-external/.*@-Wall",
+                "--per_file_copt=.*,-external/.*@-Wl\\,-z\\,defs",
+                "--per_file_copt=.*,-external/.*@-Ithird_party",
+                "--per_file_copt=.*,-external/.*@-DUSE_SSL=1",
+                "--linkopt=-lm",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_profile_flags() {
+        let dev = ProfileConfig::default();
+        assert_eq!(
+            profile_flags(&dev, false).unwrap(),
+            ["--compilation_mode=dbg"]
+        );
+        assert_eq!(
+            profile_flags(&dev, true).unwrap(),
+            ["--compilation_mode=opt"]
+        );
+
+        let release: ProfileConfig = toml::from_str(
+            r#"
+copts = ["-march=native"]
+linkopts = ["-Wl,--gc-sections"]
+defines = ["NDEBUG"]
+lto = true
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            profile_flags(&release, true).unwrap(),
+            [
+                "--compilation_mode=opt",
+                "--copt=-march=native",
+                "--copt=-DNDEBUG",
+                "--linkopt=-Wl,--gc-sections",
+                "--copt=-flto",
+                "--linkopt=-flto",
+            ]
+        );
+
+        let fast = ProfileConfig {
+            compilation_mode: Some("fast".to_string()),
+            ..Default::default()
+        };
+        assert!(profile_flags(&fast, false).is_err());
+    }
+
+    #[test]
+    fn test_test_tag_filters() {
+        assert!(test_tag_filters(&[], &[]).is_empty());
+        assert_eq!(
+            test_tag_filters(
+                &["integration".to_string()],
+                &["requires-network".to_string(), "slow".to_string()]
+            ),
+            ["--test_tag_filters=integration,-requires-network,-slow"]
+        );
+    }
+
+    #[test]
+    fn test_check_args() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let base = tmp_dir.path();
+        fs::create_dir_all(base.join("include/demo")).unwrap();
+        fs::write(base.join("include/demo/demo.h"), "").unwrap();
+        let targets = |targets: &[&str]| targets.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            check_args(&targets(&["//src/..."]), base).unwrap(),
+            [COMPILE_ONLY, "//src/..."]
+        );
+        let header = base.join("include/demo/demo.h");
+        assert_eq!(
+            check_args(&targets(&["include/demo/demo.h"]), base).unwrap(),
+            [
+                COMPILE_ONLY,
+                "--compile_one_dependency",
+                header.to_str().unwrap()
+            ]
+        );
+        assert!(check_args(&targets(&["include/demo/demo.h", "//src:demo"]), base).is_err());
+    }
+
+    #[test]
+    fn test_linker_flags() {
+        let only_lld = |binary: &str| binary == "ld.lld";
+        assert!(linker_flags_with(None, only_lld).unwrap().is_empty());
+        assert_eq!(
+            linker_flags_with(Some("auto"), only_lld).unwrap(),
+            ["--linkopt=-fuse-ld=lld"]
+        );
+        assert!(linker_flags_with(Some("auto"), |_| false)
+            .unwrap()
+            .is_empty());
+        assert!(linker_flags_with(Some("mold"), only_lld)
+            .unwrap_err()
+            .contains("apt install mold"));
+        assert!(linker_flags_with(Some("bfd"), only_lld)
+            .unwrap_err()
+            .contains("unknown linker `bfd`"));
+    }
+
+    #[test]
+    fn test_credential_helper() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("credential-helper");
+        fs::write(&path, credential_helper("t0k\"en")).unwrap();
+        let output = Command::new("sh")
+            .arg(&path)
+            .arg("get")
+            .stdin(Stdio::null())
+            .output()
+            .unwrap();
+        let response: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(response["headers"]["Authorization"][0], "Bearer t0k\"en");
+    }
+
+    #[test]
+    fn test_offline_skips_remote_cache() {
+        let config: GlobalConfig =
+            toml::from_str("[remote-cache]\nurl = \"grpcs://cache.example.com\"\n").unwrap();
+        let flags = |offline| {
+            let mut cmd = Command::new("bazel");
+            apply_user_config(&mut cmd, "build", &config, offline);
+            cmd.get_args()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+        assert!(flags(false).contains(&"--remote_cache=grpcs://cache.example.com".to_string()));
+        assert!(!flags(true)
+            .iter()
+            .any(|flag| flag.starts_with("--remote_cache")
+                || flag.starts_with("--credential_helper")));
+    }
+
+    #[test]
+    fn test_version() {
+        assert_eq!(Version::parse("bazel 7.1.0\n"), Some(Version::new(7, 1, 0)));
+        assert_eq!(
+            Version::parse("bazel 8.0.0rc2"),
+            Some(Version::new(8, 0, 0))
+        );
+        assert_eq!(Version::parse("bazel no_version"), None);
+
+        assert!(check_version("2023", Version::new(7, 4, 1)).is_ok());
+        assert!(check_version("2023", Version::new(5, 4, 1))
+            .unwrap_err()
+            .contains("needs bazel 6.0.0 or later, before 9.0.0"));
+        assert!(check_version("2023", Version::new(9, 0, 0)).is_err());
+        // Without a manifest there is no edition to check against.
+        assert!(check_version("", Version::new(9, 0, 0)).is_ok());
+
+        assert_eq!(
+            compatibility_flags(Version::new(6, 4, 0)),
+            ["--experimental_cc_shared_library"]
+        );
+        assert_eq!(
+            compatibility_flags(Version::new(8, 1, 0)),
+            ["--noenable_bzlmod", "--enable_workspace"]
+        );
+    }
+}
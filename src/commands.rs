@@ -1 +1,32 @@
+pub mod abi_check;
+pub mod add;
+pub mod audit;
+pub mod bench;
+pub mod ci;
+pub mod clean;
+pub mod dist;
+pub mod doc;
+pub mod fetch;
+pub mod fmt;
+pub mod fuzz;
+pub mod graph;
+pub mod hooks;
+pub mod ide;
 pub mod init;
+pub mod install;
+pub mod lint;
+pub mod log;
+pub mod login;
+pub mod package;
+pub mod profile;
+pub mod publish;
+pub mod query;
+pub mod remove;
+pub mod report;
+pub mod run;
+pub mod sbom;
+pub mod search;
+pub mod update_index;
+pub mod upgrade;
+pub mod vendor;
+pub mod wizard;
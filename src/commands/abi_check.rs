@@ -0,0 +1,352 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use which::which;
+
+use crate::bazel;
+use crate::config::{Config, LibType};
+use crate::features;
+use crate::git;
+use crate::global::GlobalConfig;
+use crate::plugins::Plugin;
+use crate::style;
+use crate::targets::{self, ExternalDeps};
+use crate::workspace;
+
+const ABI_DIR: &str = "target/abi";
+
+/// Flags keeping the debug info the ABI is read from.
+const ABI_FLAGS: [&str; 2] = ["--strip=never", "--copt=-g"];
+
+const INSTALL_HINT: &str = "Install libabigail (e.g. `apt install abigail-tools` or `dnf install libabigail`) or abi-compliance-checker and abi-dumper";
+
+/// Reads abidiff's exit status, a bit field: 1 and 2 report errors, 4 ABI
+/// changes and 8 incompatible ones.
+fn abidiff_breaking(code: i32) -> Result<bool, String> {
+    if code & 3 != 0 {
+        return Err(format!("abidiff failed with exit code {}", code));
+    }
+    Ok(code & 8 != 0)
+}
+
+/// The version of a library named like `libdemo.so.1.2.0`.
+fn file_version<'a>(package_name: &str, file_name: &'a str) -> Option<&'a str> {
+    file_name
+        .strip_prefix(&format!("lib{}.so.", package_name))
+        .filter(|version| !version.is_empty())
+}
+
+fn major(version: &str) -> &str {
+    version.split('.').next().unwrap_or_default()
+}
+
+/// Gates breaking changes: they are only expected along with a new major
+/// version, which changes the soname. Without a known baseline version any
+/// breaking change is an error.
+fn verdict(baseline: Option<&str>, current: &str, breaking: bool) -> Result<(), String> {
+    if !breaking {
+        style::status("Finished", "no breaking ABI change");
+        return Ok(());
+    }
+    match baseline {
+        Some(baseline) if major(baseline) != major(current) => {
+            style::status("Finished", format!("breaking ABI changes, expected from the major version bump ({} -> {})", baseline, current));
+            Ok(())
+        }
+        Some(baseline) => Err(format!(
+            "breaking ABI changes since {} while the major version stays {}, bump it or restore compatibility",
+            baseline,
+            major(current)
+        )),
+        None => Err("breaking ABI changes against the baseline library".to_string()),
+    }
+}
+
+/// The label of the package's `cc_shared_library`.
+fn shared_label(root: &Path, config: &Config) -> Result<String, String> {
+    let build_files =
+        targets::scan_library(root, config, &ExternalDeps::default()).map_err(|e| e.to_string())?;
+    let library = targets::package_library(&build_files)
+        .ok_or("the package has no library in src/ to check the ABI of")?;
+    Ok(format!("//src:{}_shared", library.name))
+}
+
+/// Brings the `BUILD` files and WORKSPACE of the package at `root` up to
+/// date, returning the flags enabling the features.
+fn prepare(
+    root: &Path,
+    config: &Config,
+    requested: &[String],
+    default_features: bool,
+    plugins: &[Plugin],
+    global: &GlobalConfig,
+) -> Result<Vec<String>, String> {
+    let enabled = features::resolve(&config.features, requested, default_features)?;
+    let external = ExternalDeps::resolve(config, &enabled, plugins, global)?;
+    if !targets::sync_library(root, Path::new(""), config, &external).map_err(|e| e.to_string())? {
+        return Err(format!(
+            "{} is not generated by buddy and lacks a cc_shared_library",
+            root.join("src").join("BUILD").display()
+        ));
+    }
+    let dependencies = features::dependencies(config, &enabled)?;
+    workspace::sync(root, &dependencies, &config.sources(), plugins, global)?;
+    let mut flags = features::flags(&config.package.name, &enabled);
+    flags.extend(bazel::linker_flags(config.build.linker.as_deref())?);
+    flags.extend(bazel::build_flags(&config.build));
+    Ok(flags)
+}
+
+/// Builds the shared library of the package at `root` with debug info and
+/// copies it into `dest`.
+fn build_library(
+    bazel_bin: &Path,
+    root: &Path,
+    config: &Config,
+    flags: &[String],
+    dest: &Path,
+) -> Result<PathBuf, String> {
+    let label = shared_label(root, config)?;
+    let mut cmd = bazel::command(bazel_bin, "build");
+    cmd.current_dir(root)
+        .args(ABI_FLAGS)
+        .args(flags)
+        .arg(&label);
+    let status = bazel::stream(&mut cmd).map_err(|e| format!("failed to run bazel: {}", e))?;
+    if !status.success() {
+        return Err(format!("failed to build {}", label));
+    }
+
+    let name = targets::shared_lib_name(&config.package);
+    let output = root.join("target").join("bin").join("src").join(&name);
+    fs::create_dir_all(dest).map_err(|e| e.to_string())?;
+    let copy = dest.join(name);
+    // Bazel's outputs are read-only, replace rather than overwrite.
+    let _ = fs::remove_file(&copy);
+    fs::copy(&output, &copy).map_err(|e| format!("{}: {}", output.display(), e))?;
+    Ok(copy)
+}
+
+/// Builds the package as of `reference` in a temporary worktree, returning
+/// the library and its version.
+fn build_baseline(
+    bazel_bin: &Path,
+    reference: &str,
+    requested: &[String],
+    default_features: bool,
+    plugins: &[Plugin],
+    global: &GlobalConfig,
+) -> Result<(PathBuf, String), String> {
+    // The project may live in a subdirectory of the repository.
+    let prefix = git::output(&["rev-parse", "--show-prefix"])?;
+    let tmp_dir = tempfile::tempdir().map_err(|e| e.to_string())?;
+    let worktree = tmp_dir.path().join("baseline");
+    let worktree_arg = worktree.to_str().ok_or("invalid temporary directory")?;
+    git::output(&["worktree", "add", "-q", "--detach", worktree_arg, reference])
+        .map_err(|_| format!("cannot check out `{}`", reference))?;
+    let root = worktree.join(prefix);
+
+    let built = fs::read_to_string(root.join("Buddy.toml"))
+        .map_err(|_| format!("`{}` has no Buddy.toml", reference))
+        .and_then(|contents| {
+            toml::from_str::<Config>(&contents)
+                .map_err(|e| format!("invalid Buddy.toml in `{}`: {}", reference, e))
+        })
+        .and_then(|mut config| {
+            // Versions only shipping a static library still have an ABI.
+            if !config.lib.types.contains(&LibType::Shared) {
+                config.lib.types.push(LibType::Shared);
+            }
+            style::status(
+                "Building",
+                format!(
+                    "{} v{} ({})",
+                    config.package.name, config.package.version, reference
+                ),
+            );
+            let flags = prepare(&root, &config, requested, default_features, plugins, global)?;
+            let dest = Path::new(ABI_DIR).join("baseline");
+            let library = build_library(bazel_bin, &root, &config, &flags, &dest)?;
+            Ok((library, config.package.version))
+        });
+
+    // The worktree goes away, so does the bazel server started in it.
+    let _ = Command::new(bazel_bin)
+        .arg("shutdown")
+        .current_dir(&root)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    let _ = git::output(&["worktree", "remove", "--force", worktree_arg]);
+    built
+}
+
+/// Compares the libraries with abidiff, restricted to the types of the
+/// public headers in `headers` when given, or with abi-compliance-checker. Returns
+/// whether the changes break the ABI.
+fn compare(
+    config: &Config,
+    baseline: &Path,
+    current: &Path,
+    headers: Option<PathBuf>,
+) -> Result<bool, String> {
+    if let Ok(abidiff) = which("abidiff") {
+        let mut cmd = Command::new(abidiff);
+        if let Some(dir) = headers {
+            cmd.arg("--headers-dir1")
+                .arg(&dir)
+                .arg("--headers-dir2")
+                .arg(dir);
+        }
+        let status = cmd
+            .arg(baseline)
+            .arg(current)
+            .status()
+            .map_err(|e| format!("failed to run abidiff: {}", e))?;
+        let code = status.code().ok_or("abidiff was interrupted")?;
+        return abidiff_breaking(code);
+    }
+
+    let (Ok(dumper), Ok(checker)) = (which("abi-dumper"), which("abi-compliance-checker")) else {
+        return Err(format!("`abidiff` not found. {}", INSTALL_HINT));
+    };
+    let mut dumps = Vec::new();
+    for library in [baseline, current] {
+        let dump = library.with_extension("dump");
+        let status = Command::new(&dumper)
+            .arg(library)
+            .arg("-o")
+            .arg(&dump)
+            .stdout(Stdio::null())
+            .status()
+            .map_err(|e| format!("failed to run abi-dumper: {}", e))?;
+        if !status.success() {
+            return Err(format!("abi-dumper failed on {}", library.display()));
+        }
+        dumps.push(dump);
+    }
+    let report = Path::new(ABI_DIR).join("report.html");
+    let status = Command::new(checker)
+        .args(["-l", &config.package.name, "-old"])
+        .arg(&dumps[0])
+        .arg("-new")
+        .arg(&dumps[1])
+        .arg("-report-path")
+        .arg(&report)
+        .status()
+        .map_err(|e| format!("failed to run abi-compliance-checker: {}", e))?;
+    match status.code() {
+        Some(0) => Ok(false),
+        Some(1) => {
+            style::status("Report", report.display());
+            Ok(true)
+        }
+        _ => Err(format!("abi-compliance-checker exited with {}", status)),
+    }
+}
+
+/// Builds the package's shared library and the one of `baseline`, a git
+/// ref or a library file, defaulting to the latest tag, and fails on ABI
+/// breaking changes the version doesn't announce.
+pub fn run(
+    bazel_bin: &Path,
+    config: &Config,
+    baseline: Option<&str>,
+    requested: &[String],
+    default_features: bool,
+    plugins: &[Plugin],
+    global: &GlobalConfig,
+) -> Result<(), String> {
+    if !config.lib.types.contains(&LibType::Shared) {
+        return Err(
+            "`buddy abi-check` checks shared libraries, add `type = [\"shared\"]` to [lib]"
+                .to_string(),
+        );
+    }
+
+    let root = Path::new(".");
+    let (baseline_library, baseline_version) = match baseline {
+        Some(path) if Path::new(path).is_file() => {
+            let file_name = fs::canonicalize(path)
+                .ok()
+                .and_then(|path| Some(path.file_name()?.to_str()?.to_string()))
+                .unwrap_or_default();
+            let version = file_version(&config.package.name, &file_name).map(String::from);
+            (PathBuf::from(path), version)
+        }
+        _ => {
+            let reference = match baseline {
+                Some(reference) => reference.to_string(),
+                None => git::output(&["describe", "--tags", "--abbrev=0"])
+                    .map_err(|_| "no tag to compare against, pass --baseline")?,
+            };
+            let (library, version) = build_baseline(
+                bazel_bin,
+                &reference,
+                requested,
+                default_features,
+                plugins,
+                global,
+            )?;
+            (library, Some(version))
+        }
+    };
+
+    style::status(
+        "Building",
+        format!("{} v{}", config.package.name, config.package.version),
+    );
+    let flags = prepare(root, config, requested, default_features, plugins, global)?;
+    let current = build_library(
+        bazel_bin,
+        root,
+        config,
+        &flags,
+        &Path::new(ABI_DIR).join("current"),
+    )?;
+
+    style::status(
+        "Comparing",
+        format!(
+            "{} against {}",
+            current.display(),
+            baseline_library.display()
+        ),
+    );
+    // The baseline's headers are gone by then, the current public headers
+    // tell which types are public on both sides.
+    let headers = config
+        .lib
+        .public_headers
+        .first()
+        .map(|pattern| PathBuf::from(targets::pattern_base(pattern)));
+    let breaking = compare(config, &baseline_library, &current, headers)?;
+    verdict(
+        baseline_version.as_deref(),
+        &config.package.version,
+        breaking,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verdict() {
+        assert_eq!(abidiff_breaking(0), Ok(false));
+        assert_eq!(abidiff_breaking(4), Ok(false));
+        assert_eq!(abidiff_breaking(12), Ok(true));
+        assert!(abidiff_breaking(1).is_err());
+
+        assert_eq!(file_version("demo", "libdemo.so.1.2.0"), Some("1.2.0"));
+        assert_eq!(file_version("demo", "libother.so.1"), None);
+
+        assert!(verdict(Some("1.2.0"), "1.3.0", false).is_ok());
+        assert!(verdict(Some("1.2.0"), "2.0.0", true).is_ok());
+        assert!(verdict(Some("1.2.0"), "1.3.0", true)
+            .unwrap_err()
+            .contains("major version stays 1"));
+        assert!(verdict(None, "1.3.0", true).is_err());
+    }
+}
@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::config::{Config, TestConfig};
+use crate::global::GlobalConfig;
+use crate::lockfile::{Lockfile, LOCKFILE};
+use crate::plugins::{self, Plugin};
+use crate::style;
+use crate::targets::{self, GENERATED_HEADER};
+use crate::workspace;
+
+use super::fetch;
+
+/// Splits `name@requirement`, the requirement being optional.
+fn parse_spec(spec: &str) -> (&str, Option<&str>) {
+    match spec.rsplit_once('@') {
+        Some((name, version)) => (name, Some(version)),
+        None => (spec, None),
+    }
+}
+
+/// Splits the label of a target of the project, `//dir:name`, `//dir` or
+/// the bare name of a target of `src/`, into its package and name.
+fn split_label(label: &str) -> (&str, &str) {
+    match label.strip_prefix("//") {
+        Some(label) => match label.split_once(':') {
+            Some((dir, name)) => (dir, name),
+            None => (label, label.rsplit('/').next().unwrap_or(label)),
+        },
+        None => ("src", label.trim_start_matches(':')),
+    }
+}
+
+/// Sets the version of `name` in the `[dependencies]` of `manifest`,
+/// keeping the rest of it as it is.
+pub fn set_dependency(manifest: &str, name: &str, version: &str) -> Result<String, String> {
+    let mut document = manifest
+        .parse::<toml_edit::Document>()
+        .map_err(|e| format!("failed to parse `Buddy.toml`: {}", e))?;
+    document["dependencies"][name] = toml_edit::value(version);
+    Ok(document.to_string())
+}
+
+/// Adds `label` to the dependencies of the generated test targets.
+fn add_test_dep(manifest: &str, label: &str) -> Result<String, String> {
+    let mut document = manifest
+        .parse::<toml_edit::Document>()
+        .map_err(|e| format!("failed to parse `Buddy.toml`: {}", e))?;
+    let deps = &mut document["test"]["deps"];
+    if deps.is_none() {
+        *deps = toml_edit::value(
+            TestConfig::default()
+                .deps
+                .into_iter()
+                .collect::<toml_edit::Array>(),
+        );
+    }
+    let deps = deps
+        .as_array_mut()
+        .ok_or("`deps` of [test] in `Buddy.toml` isn't a list")?;
+    if !deps.iter().any(|dep| dep.as_str() == Some(label)) {
+        deps.push(label);
+    }
+    Ok(document.to_string())
+}
+
+/// Adds `label` to the deps of `target` in its hand-written BUILD file.
+fn link(root: &Path, target: &str, label: &str) -> Result<(), String> {
+    let (dir, name) = split_label(target);
+    let path = root.join(dir).join("BUILD");
+    let contents = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    if contents.starts_with(GENERATED_HEADER) {
+        style::warning(format!(
+            "{}/BUILD is generated by buddy, link `{}` from a hand-written BUILD file",
+            dir, label
+        ));
+        return Ok(());
+    }
+    let edited = targets::add_dep(&contents, name, label)
+        .map_err(|error| format!("cannot add `{}` to //{}:{}: {}", label, dir, name, error))?;
+    if let Some(edited) = edited {
+        fs::write(&path, edited).map_err(|e| e.to_string())?;
+        style::status("Linked", format!("`{}` into //{}:{}", label, dir, name));
+    }
+    Ok(())
+}
+
+/// Adds the dependency `spec`, `name` or `name@requirement`, to the manifest
+/// and the WORKSPACE, and links its target into `target`, the package's
+/// binary by default, or into the tests when `dev` is set. Yanked versions
+/// are refused unless `allow_yanked` is set.
+#[allow(clippy::too_many_arguments)]
+pub fn run(
+    root: &Path,
+    config: &Config,
+    spec: &str,
+    target: Option<&str>,
+    dev: bool,
+    allow_yanked: bool,
+    plugins: &[Plugin],
+    global: &GlobalConfig,
+) -> Result<(), String> {
+    let (name, version) = parse_spec(spec);
+    let plugin = plugins::resolve(plugins, name, global)?;
+    let requirement = match version {
+        Some(requirement) => requirement,
+        None => plugin
+            .latest_version()
+            .ok_or_else(|| format!("`{}` has no version available", name))?,
+    };
+    let version = plugin.select(requirement, None)?;
+    // Checks the version is a known one.
+    plugin.render(&version)?;
+    let lockfile = Lockfile::load(&root.join(LOCKFILE))?;
+    let selected = HashMap::from([(name.to_string(), version.clone())]);
+    for warning in fetch::check_versions(&selected, plugins, &lockfile, allow_yanked)? {
+        style::warning(warning);
+    }
+    let label = plugin.target_label(&version)?;
+
+    let manifest_path = root.join("Buddy.toml");
+    let manifest = fs::read_to_string(&manifest_path)
+        .map_err(|_| "could not find `Buddy.toml` in the current directory".to_string())?;
+    let mut manifest = set_dependency(&manifest, name, requirement)?;
+    if let (true, Some(label)) = (dev, &label) {
+        manifest = add_test_dep(&manifest, label)?;
+    }
+    fs::write(&manifest_path, manifest).map_err(|e| e.to_string())?;
+    style::status(
+        "Adding",
+        format!("`{} {}` to dependencies", name, requirement),
+    );
+
+    let mut dependencies = config.versions();
+    dependencies.insert(name.to_string(), requirement.to_string());
+    let mut sources = config.sources();
+    sources.remove(name);
+    workspace::sync(root, &dependencies, &sources, plugins, global)?;
+
+    match label {
+        Some(label) if !dev => {
+            let default = format!("//src:{}", config.package.name);
+            link(root, target.unwrap_or(&default), &label)
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path();
+        let manifest = r#"[package]
+name = "demo"
+version = "0.1.0"
+edition = "2023"
+
+[dependencies]
+bazel-toolchain = "0.8.2" # the LLVM toolchain
+"#;
+        fs::write(root.join("Buddy.toml"), manifest).unwrap();
+        fs::create_dir(root.join("src")).unwrap();
+        fs::write(
+            root.join("src/BUILD"),
+            "cc_binary(\n    name = \"demo\",\n    srcs = [\"main.cc\"],\n)",
+        )
+        .unwrap();
+        let config: Config = toml::from_str(manifest).unwrap();
+        let plugins = plugins::builtin();
+        let global = GlobalConfig::default();
+
+        run(
+            root,
+            &config,
+            "google-test@1.12.1",
+            None,
+            false,
+            false,
+            &plugins,
+            &global,
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(root.join("Buddy.toml")).unwrap(),
+            format!("{}google-test = \"1.12.1\"\n", manifest)
+        );
+        assert!(fs::read_to_string(root.join("WORKSPACE"))
+            .unwrap()
+            .contains("googletest-58d77fa8070e8cec2dc1ed015d66b454c8d78850"));
+        assert_eq!(
+            fs::read_to_string(root.join("src/BUILD")).unwrap(),
+            "cc_binary(\n    name = \"demo\",\n    srcs = [\"main.cc\"],\n    deps = [\"@com_google_googletest//:gtest\"],\n)"
+        );
+
+        run(
+            root,
+            &config,
+            "google-test",
+            None,
+            true,
+            false,
+            &plugins,
+            &global,
+        )
+        .unwrap();
+        let config: Config =
+            toml::from_str(&fs::read_to_string(root.join("Buddy.toml")).unwrap()).unwrap();
+        assert_eq!(config.versions()["google-test"], "1.13.0");
+        assert_eq!(
+            config.test.deps,
+            [
+                "@com_google_googletest//:gtest_main",
+                "@com_google_googletest//:gtest"
+            ]
+        );
+
+        assert!(run(
+            root,
+            &config,
+            "google-test@2.0",
+            None,
+            false,
+            false,
+            &plugins,
+            &global
+        )
+        .is_err());
+        assert_eq!(split_label("//src/net:http"), ("src/net", "http"));
+        assert_eq!(split_label("//tools"), ("tools", "tools"));
+    }
+
+    #[test]
+    fn test_run_yanked() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path();
+        let manifest = "[package]\nname = \"demo\"\nversion = \"0.1.0\"\nedition = \"2023\"\n\n[dependencies]\n";
+        fs::write(root.join("Buddy.toml"), manifest).unwrap();
+        fs::create_dir(root.join("src")).unwrap();
+        fs::write(root.join("src/BUILD"), "cc_binary(name = \"demo\")").unwrap();
+        let config: Config = toml::from_str(manifest).unwrap();
+        let mut plugins = plugins::builtin();
+        plugins[0]
+            .yanked
+            .insert("1.12.1".to_string(), "miscompiles on gcc 13".to_string());
+        let global = GlobalConfig::default();
+        let add = |allow_yanked| {
+            run(
+                root,
+                &config,
+                "google-test@1.12.1",
+                None,
+                false,
+                allow_yanked,
+                &plugins,
+                &global,
+            )
+        };
+
+        assert_eq!(
+            add(false).unwrap_err(),
+            "`google-test 1.12.1` was yanked (miscompiles on gcc 13), consider `google-test = \"1.13.0\"`, or pass --allow-yanked to use it anyway"
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("Buddy.toml")).unwrap(),
+            manifest
+        );
+        add(true).unwrap();
+        assert!(fs::read_to_string(root.join("Buddy.toml"))
+            .unwrap()
+            .contains("google-test = \"1.12.1\""));
+    }
+}
@@ -0,0 +1,215 @@
+use clap::ValueEnum;
+use std::path::Path;
+
+use crate::lockfile::{Lockfile, LOCKFILE};
+use crate::plugins::{self, Plugin};
+use crate::style::{self, Level};
+
+/// What `--deny` turns into errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Deny {
+    /// Informational advisories, yanked versions and deprecated packages
+    Warnings,
+}
+
+/// A problem of a locked dependency.
+#[derive(Debug, PartialEq)]
+struct Finding {
+    level: Level,
+    name: String,
+    version: String,
+    /// The advisory identifier, or what else is wrong.
+    title: String,
+    details: Vec<String>,
+}
+
+/// The advisories, yanked versions and deprecations of the registry
+/// dependencies of `lockfile`, vulnerabilities as errors and the rest as
+/// warnings. Advisories whose id is in `ignore` are left out.
+fn findings(lockfile: &Lockfile, plugins: &[Plugin], ignore: &[String]) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for package in &lockfile.package {
+        // Git and path dependencies have no registry version to look up.
+        let local = package
+            .source
+            .as_deref()
+            .is_some_and(|source| source.starts_with("git+") || source.starts_with("path+"));
+        if package.patched || local {
+            continue;
+        }
+        let Some(plugin) = plugins::find(plugins, &package.name) else {
+            continue;
+        };
+        let finding = |level, title: &str, details: Vec<String>| Finding {
+            level,
+            name: package.name.clone(),
+            version: package.version.clone(),
+            title: title.to_string(),
+            details,
+        };
+
+        for advisory in &plugin.advisories {
+            if ignore.contains(&advisory.id) || !advisory.affects(&package.version) {
+                continue;
+            }
+            let mut title = advisory.id.clone();
+            if let Some(severity) = &advisory.severity {
+                title = format!("{} ({})", title, severity);
+            }
+            let mut details = vec![advisory.summary.clone()];
+            details.extend(advisory.url.iter().map(|url| format!("see {}", url)));
+            let level = match advisory.informational {
+                true => Level::Warning,
+                false => Level::Error,
+            };
+            findings.push(finding(level, &title, details));
+        }
+        if let Some(reason) = plugin.yanked.get(&package.version) {
+            findings.push(finding(Level::Warning, "yanked", vec![reason.clone()]));
+        }
+        if let Some(replacement) = &plugin.deprecated {
+            findings.push(finding(
+                Level::Warning,
+                "deprecated",
+                vec![replacement.clone()],
+            ));
+        }
+    }
+    findings
+}
+
+fn print(finding: &Finding) {
+    let severity = match finding.level {
+        Level::Error => "vulnerability",
+        _ => "warning",
+    };
+    println!(
+        "{}: {} {}: {}",
+        style::paint(severity, finding.level),
+        finding.name,
+        finding.version,
+        finding.title
+    );
+    for line in &finding.details {
+        println!("  {}", line);
+    }
+}
+
+/// Checks the dependencies locked in `Buddy.lock` against the advisories
+/// of their recipes, as last fetched by `buddy update-index`. Fails on
+/// vulnerabilities, and on warnings too with `deny`.
+pub fn run(plugins: &[Plugin], ignore: &[String], deny: Option<Deny>) -> Result<(), String> {
+    if !Path::new(LOCKFILE).is_file() {
+        return Err(format!(
+            "no {} to audit, `buddy fetch` resolves the dependencies",
+            LOCKFILE
+        ));
+    }
+    let lockfile = Lockfile::load(Path::new(LOCKFILE))?;
+    let findings = findings(&lockfile, plugins, ignore);
+    for finding in &findings {
+        print(finding);
+    }
+
+    let vulnerabilities = findings
+        .iter()
+        .filter(|finding| finding.level == Level::Error)
+        .count();
+    let warnings = findings.len() - vulnerabilities;
+    style::status(
+        "Audited",
+        format!(
+            "{} dependencies, {} vulnerabilities, {} warnings",
+            lockfile.package.len(),
+            vulnerabilities,
+            warnings
+        ),
+    );
+    if vulnerabilities > 0 {
+        return Err(format!(
+            "{} vulnerable dependencies, upgrade them or --ignore the advisories",
+            vulnerabilities
+        ));
+    }
+    if warnings > 0 && deny == Some(Deny::Warnings) {
+        return Err("warnings are denied".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_findings() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        fs::write(
+            tmp_dir.path().join("zlib.toml"),
+            r#"
+build-rule = ""
+deprecated = "use zlib-ng"
+
+[versions]
+"1.2.11" = "abc"
+"1.3.1" = "def"
+
+[yanked]
+"1.2.11" = "broken inflate"
+
+[[advisory]]
+id = "CVE-2022-37434"
+summary = "Heap-based buffer over-read in inflate"
+affected = ["<1.2.13"]
+severity = "critical"
+url = "https://nvd.nist.gov/vuln/detail/CVE-2022-37434"
+
+[[advisory]]
+id = "BUDDY-2024-0001"
+summary = "Slow on large inputs"
+affected = [">=1.2, <1.3"]
+informational = true
+"#,
+        )
+        .unwrap();
+        let plugins = plugins::load_directory("corp", tmp_dir.path()).unwrap();
+        let lockfile: Lockfile = toml::from_str(
+            r#"
+[[package]]
+name = "zlib"
+version = "1.2.11"
+
+[[package]]
+name = "google-test"
+version = "1.13.0"
+"#,
+        )
+        .unwrap();
+
+        let findings = findings(&lockfile, &plugins, &[]);
+        let titles: Vec<_> = findings
+            .iter()
+            .map(|finding| (finding.level, finding.title.as_str()))
+            .collect();
+        assert_eq!(
+            titles,
+            [
+                (Level::Error, "CVE-2022-37434 (critical)"),
+                (Level::Warning, "BUDDY-2024-0001"),
+                (Level::Warning, "yanked"),
+                (Level::Warning, "deprecated"),
+            ]
+        );
+        assert_eq!(
+            findings[0].details[1],
+            "see https://nvd.nist.gov/vuln/detail/CVE-2022-37434"
+        );
+
+        let ignored = ["CVE-2022-37434".to_string()];
+        assert_eq!(
+            super::findings(&lockfile, &plugins, &ignored)[0].title,
+            "BUDDY-2024-0001"
+        );
+    }
+}
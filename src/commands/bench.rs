@@ -0,0 +1,428 @@
+use colored::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::bazel;
+use crate::style::{self, Level};
+
+const BENCH_DIR: &str = "target/benches";
+
+/// Changes below this percentage are treated as noise when the benchmarks
+/// did not report a standard deviation (i.e. ran without repetitions).
+const NOISE_THRESHOLD: f64 = 2.0;
+
+/// A single entry of Google Benchmark's JSON output.
+#[derive(Debug, Deserialize)]
+struct Entry {
+    name: String,
+    run_name: Option<String>,
+    run_type: Option<String>,
+    aggregate_name: Option<String>,
+    cpu_time: f64,
+    time_unit: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Report {
+    benchmarks: Vec<Entry>,
+}
+
+/// The measurement kept for one benchmark, in nanoseconds of CPU time.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Sample {
+    pub time: f64,
+    pub stddev: Option<f64>,
+}
+
+#[derive(Debug)]
+pub struct Comparison {
+    pub name: String,
+    pub baseline: Sample,
+    pub current: Sample,
+    /// Relative change in percent, positive means slower.
+    pub change: f64,
+    pub significant: bool,
+}
+
+fn to_nanos(time: f64, unit: Option<&str>) -> f64 {
+    match unit {
+        Some("us") => time * 1e3,
+        Some("ms") => time * 1e6,
+        Some("s") => time * 1e9,
+        _ => time,
+    }
+}
+
+/// Reduces a Google Benchmark report to one sample per benchmark, preferring
+/// the `mean`/`stddev` aggregates when the benchmark ran with repetitions.
+fn summarize(report: &str) -> Result<BTreeMap<String, Sample>, String> {
+    let report: Report =
+        serde_json::from_str(report).map_err(|e| format!("invalid benchmark output: {}", e))?;
+
+    let mut iterations: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+    let mut means = BTreeMap::new();
+    let mut stddevs = BTreeMap::new();
+
+    for entry in &report.benchmarks {
+        let name = entry.run_name.clone().unwrap_or_else(|| entry.name.clone());
+        let time = to_nanos(entry.cpu_time, entry.time_unit.as_deref());
+
+        match (entry.run_type.as_deref(), entry.aggregate_name.as_deref()) {
+            (Some("aggregate"), Some("mean")) => {
+                means.insert(name, time);
+            }
+            (Some("aggregate"), Some("stddev")) => {
+                stddevs.insert(name, time);
+            }
+            (Some("aggregate"), _) => {}
+            _ => iterations.entry(name).or_default().push(time),
+        }
+    }
+
+    let mut samples = BTreeMap::new();
+    for (name, times) in iterations {
+        let n = times.len() as f64;
+        let mean = means
+            .get(&name)
+            .copied()
+            .unwrap_or(times.iter().sum::<f64>() / n);
+        let stddev = stddevs.get(&name).copied().or_else(|| {
+            (times.len() > 1)
+                .then(|| (times.iter().map(|t| (t - mean).powi(2)).sum::<f64>() / (n - 1.0)).sqrt())
+        });
+        samples.insert(name, Sample { time: mean, stddev });
+    }
+
+    Ok(samples)
+}
+
+/// Compares the benchmarks present in both runs. With standard deviations
+/// available a change is significant when it exceeds twice the combined
+/// deviation, otherwise when it exceeds the noise threshold.
+pub fn compare(
+    baseline: &BTreeMap<String, Sample>,
+    current: &BTreeMap<String, Sample>,
+) -> Vec<Comparison> {
+    current
+        .iter()
+        .filter_map(|(name, current)| {
+            let baseline = baseline.get(name)?;
+            let change = (current.time - baseline.time) / baseline.time * 100.0;
+            let significant = match (baseline.stddev, current.stddev) {
+                (Some(a), Some(b)) => {
+                    (current.time - baseline.time).abs() > 2.0 * (a * a + b * b).sqrt()
+                }
+                _ => change.abs() >= NOISE_THRESHOLD,
+            };
+
+            Some(Comparison {
+                name: name.clone(),
+                baseline: *baseline,
+                current: *current,
+                change,
+                significant,
+            })
+        })
+        .collect()
+}
+
+/// Parses thresholds written as `5%` or `5`.
+pub fn parse_percent(value: &str) -> Result<f64, String> {
+    value
+        .trim()
+        .trim_end_matches('%')
+        .parse::<f64>()
+        .map_err(|_| format!("invalid percentage `{}`", value))
+}
+
+fn format_time(nanos: f64) -> String {
+    if nanos >= 1e9 {
+        format!("{:.2} s", nanos / 1e9)
+    } else if nanos >= 1e6 {
+        format!("{:.2} ms", nanos / 1e6)
+    } else if nanos >= 1e3 {
+        format!("{:.2} {}", nanos / 1e3, style::symbol("µs", "us"))
+    } else {
+        format!("{:.2} ns", nanos)
+    }
+}
+
+/// Prints the time of every benchmark, with its standard deviation when it
+/// ran with repetitions.
+fn print_summary(samples: &BTreeMap<String, Sample>) {
+    let width = samples
+        .keys()
+        .map(|name| name.len())
+        .max()
+        .unwrap_or(0)
+        .max("Benchmark".len());
+
+    println!(
+        "{:<width$}  {:>12}  {:>12}",
+        "Benchmark",
+        "time",
+        style::symbol("±", "+/-"),
+        width = width
+    );
+    for (name, sample) in samples {
+        let stddev = match sample.stddev {
+            Some(stddev) => format_time(stddev),
+            None => "-".to_string(),
+        };
+        println!(
+            "{:<width$}  {:>12}  {:>12}",
+            name,
+            format_time(sample.time),
+            stddev,
+            width = width
+        );
+    }
+}
+
+fn print_comparison(baseline_name: &str, comparisons: &[Comparison]) {
+    let width = comparisons
+        .iter()
+        .map(|c| c.name.len())
+        .max()
+        .unwrap_or(0)
+        .max("Benchmark".len());
+
+    println!(
+        "{:<width$}  {:>12}  {:>12}  {:>9}  Significance",
+        "Benchmark",
+        baseline_name,
+        "current",
+        style::symbol("Δ%", "+/-%"),
+        width = width
+    );
+
+    for c in comparisons {
+        let change = format!("{:+.2}%", c.change);
+        let (change, verdict) = if !c.significant {
+            (change.normal(), style::dim("no change"))
+        } else if c.change > 0.0 {
+            (
+                style::paint(&change, Level::Error),
+                style::paint("regressed", Level::Error),
+            )
+        } else {
+            (
+                style::paint(&change, Level::Info),
+                style::paint("improved", Level::Info),
+            )
+        };
+
+        println!(
+            "{:<width$}  {:>12}  {:>12}  {:>9}  {}",
+            c.name,
+            format_time(c.baseline.time),
+            format_time(c.current.time),
+            change,
+            verdict,
+            width = width
+        );
+    }
+}
+
+fn baseline_path(name: &str) -> PathBuf {
+    Path::new(BENCH_DIR)
+        .join("baselines")
+        .join(format!("{}.json", name))
+}
+
+fn load_baseline(name: &str) -> Result<BTreeMap<String, Sample>, String> {
+    let contents = fs::read_to_string(baseline_path(name)).map_err(|_| {
+        format!(
+            "no baseline named `{}` found, create one with --save-baseline",
+            name
+        )
+    })?;
+    serde_json::from_str(&contents).map_err(|e| format!("corrupted baseline `{}`: {}", name, e))
+}
+
+fn save_baseline(name: &str, samples: &BTreeMap<String, Sample>) -> Result<(), String> {
+    let path = baseline_path(name);
+    fs::create_dir_all(path.parent().unwrap()).map_err(|e| e.to_string())?;
+    let contents = serde_json::to_string_pretty(samples).map_err(|e| e.to_string())?;
+    fs::write(&path, contents).map_err(|e| e.to_string())
+}
+
+fn discover(bazel_bin: &Path) -> Result<Vec<String>, String> {
+    let mut cmd = bazel::command(bazel_bin, "query");
+    cmd.arg("kind(\"cc_binary\", //bench/...)");
+    cmd.arg("--output=label");
+
+    Ok(bazel::output(&mut cmd)?
+        .lines()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect())
+}
+
+/// Builds the benchmark binaries optimized, with the build `flags`, runs
+/// them and returns the merged samples.
+fn run_benchmarks(
+    bazel_bin: &Path,
+    targets: &[String],
+    flags: &[String],
+) -> Result<BTreeMap<String, Sample>, String> {
+    let labels = if targets.is_empty() {
+        discover(bazel_bin)?
+    } else {
+        targets.to_vec()
+    };
+    if labels.is_empty() {
+        return Err(
+            "no benchmarks found under //bench, add Google Benchmark programs to bench/"
+                .to_string(),
+        );
+    }
+
+    let mut cmd = bazel::command(bazel_bin, "build");
+    cmd.args(flags).arg("-c").arg("opt").args(&labels);
+    let status = bazel::stream(&mut cmd).map_err(|e| e.to_string())?;
+    if !status.success() {
+        return Err("failed to build benchmarks".to_string());
+    }
+
+    let raw_dir = Path::new(BENCH_DIR).join("raw");
+    fs::create_dir_all(&raw_dir).map_err(|e| e.to_string())?;
+
+    let mut samples = BTreeMap::new();
+    for label in &labels {
+        let binary = bazel::bin_path(label)
+            .ok_or_else(|| format!("unsupported benchmark label `{}`", label))?;
+        let out = raw_dir.join(format!(
+            "{}.json",
+            binary.file_name().unwrap().to_str().unwrap()
+        ));
+
+        style::status("Running", label);
+        let status = Command::new(&binary)
+            .arg(format!("--benchmark_out={}", out.display()))
+            .arg("--benchmark_out_format=json")
+            .status()
+            .map_err(|e| format!("failed to run `{}`: {}", binary.display(), e))?;
+        if !status.success() {
+            return Err(format!("benchmark `{}` failed with {}", label, status));
+        }
+
+        let report = fs::read_to_string(&out).map_err(|e| e.to_string())?;
+        samples.extend(summarize(&report)?);
+    }
+
+    Ok(samples)
+}
+
+pub fn run(
+    bazel_bin: &Path,
+    targets: &[String],
+    flags: &[String],
+    save_as: Option<&str>,
+    baseline: Option<&str>,
+    fail_on_regression: Option<&str>,
+) -> Result<(), String> {
+    let threshold = fail_on_regression.map(parse_percent).transpose()?;
+    // Load the baseline up front so a typo doesn't cost a whole benchmark run.
+    let previous = baseline.map(load_baseline).transpose()?;
+
+    let samples = run_benchmarks(bazel_bin, targets, flags)?;
+
+    if let Some(name) = save_as {
+        save_baseline(name, &samples)?;
+        style::status("Saved", format!("baseline `{}`", name));
+    }
+
+    if previous.is_none() {
+        println!();
+        print_summary(&samples);
+    }
+
+    if let (Some(name), Some(previous)) = (baseline, previous) {
+        let comparisons = compare(&previous, &samples);
+        println!();
+        print_comparison(name, &comparisons);
+
+        if let Some(threshold) = threshold {
+            let regressions = comparisons
+                .iter()
+                .filter(|c| c.significant && c.change > threshold)
+                .count();
+            if regressions > 0 {
+                return Err(format!(
+                    "{} benchmark(s) regressed by more than {}% against `{}`",
+                    regressions, threshold, name
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summarize_iterations_and_aggregates() {
+        let report = r#"{
+  "context": {},
+  "benchmarks": [
+    {"name": "BM_Sort/1024", "run_name": "BM_Sort/1024", "run_type": "iteration", "cpu_time": 2.0, "real_time": 2.1, "time_unit": "us"},
+    {"name": "BM_Copy_mean", "run_name": "BM_Copy", "run_type": "aggregate", "aggregate_name": "mean", "cpu_time": 100.0, "real_time": 101.0, "time_unit": "ns"},
+    {"name": "BM_Copy_stddev", "run_name": "BM_Copy", "run_type": "aggregate", "aggregate_name": "stddev", "cpu_time": 3.0, "real_time": 3.0, "time_unit": "ns"},
+    {"name": "BM_Copy", "run_name": "BM_Copy", "run_type": "iteration", "cpu_time": 99.0, "real_time": 99.0, "time_unit": "ns"}
+  ]
+}"#;
+
+        let samples = summarize(report).unwrap();
+        assert_eq!(
+            samples["BM_Sort/1024"],
+            Sample {
+                time: 2000.0,
+                stddev: None
+            }
+        );
+        assert_eq!(
+            samples["BM_Copy"],
+            Sample {
+                time: 100.0,
+                stddev: Some(3.0)
+            }
+        );
+    }
+
+    #[test]
+    fn test_compare_flags_significant_changes() {
+        let sample = |time, stddev| Sample { time, stddev };
+        let baseline = BTreeMap::from([
+            ("noisy".to_string(), sample(100.0, Some(10.0))),
+            ("slower".to_string(), sample(100.0, None)),
+            ("gone".to_string(), sample(100.0, None)),
+        ]);
+        let current = BTreeMap::from([
+            ("noisy".to_string(), sample(110.0, Some(10.0))),
+            ("slower".to_string(), sample(110.0, None)),
+            ("new".to_string(), sample(1.0, None)),
+        ]);
+
+        let comparisons = compare(&baseline, &current);
+        assert_eq!(comparisons.len(), 2);
+        assert_eq!(comparisons[0].name, "noisy");
+        assert!(!comparisons[0].significant);
+        assert_eq!(comparisons[1].name, "slower");
+        assert!(comparisons[1].significant);
+        assert!((comparisons[1].change - 10.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_parse_percent() {
+        assert_eq!(parse_percent("5%").unwrap(), 5.0);
+        assert_eq!(parse_percent("2.5").unwrap(), 2.5);
+        assert!(parse_percent("five").is_err());
+    }
+}
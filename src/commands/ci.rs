@@ -0,0 +1,395 @@
+use clap::ValueEnum;
+use std::fs;
+use std::path::Path;
+
+use crate::config::Config;
+use crate::git;
+use crate::style;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Provider {
+    /// GitHub Actions
+    Github,
+    /// GitLab CI/CD
+    Gitlab,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// The plain command sequence, for any other CI system
+    Generic,
+}
+
+/// The canonical pipeline every generator implements, in order.
+const PIPELINE: [(&str, &str); 4] = [
+    ("build", "buddy build"),
+    ("test", "buddy test"),
+    ("fmt", "buddy fmt --check"),
+    ("lint", "buddy lint"),
+];
+
+/// Files whose contents decide whether the Bazel caches can be reused.
+const CACHE_KEY_FILES: [&str; 4] = ["Buddy.toml", "Buddy.lock", "WORKSPACE", ".bazelrc"];
+
+/// Maps the platforms of the `[ci]` table onto GitHub-hosted runners.
+fn github_runner(platform: &str) -> Result<&'static str, String> {
+    match platform {
+        "linux" => Ok("ubuntu-latest"),
+        "macos" => Ok("macos-latest"),
+        "windows" => Ok("windows-latest"),
+        _ => Err(format!(
+            "unsupported platform `{}` in [ci] platforms, expected one of: linux, macos, windows",
+            platform
+        )),
+    }
+}
+
+/// The branch the workflow runs on pushes to: the repository's default
+/// branch, `main` outside of a repository.
+fn push_branch() -> String {
+    git::default_branch()
+        .map(|branch| branch.trim_start_matches("origin/").to_string())
+        .unwrap_or_else(|_| "main".to_string())
+}
+
+/// Renders the GitHub Actions workflow, run on the pushes to `branch` and
+/// on pull requests. Bazel is pointed at disk and repository caches in
+/// fixed locations so that `actions/cache` can persist them across runs on
+/// every OS, keyed on the files that affect the build.
+pub fn github_workflow(config: &Config, branch: &str) -> Result<String, String> {
+    let runners = config
+        .ci
+        .platforms
+        .iter()
+        .map(|p| github_runner(p))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(format!(
+        r#"# This file is automatically @generated by Buddy.
+# Regenerate it with `buddy ci init github --force`.
+name: CI
+
+on:
+  push:
+    branches: [ "{branch}" ]
+  pull_request:
+
+jobs:
+  build:
+    name: Build and test (${{{{ matrix.os }}}})
+    runs-on: ${{{{ matrix.os }}}}
+    strategy:
+      fail-fast: false
+      matrix:
+        os: [{runners}]
+    defaults:
+      run:
+        shell: bash
+
+    steps:
+    - uses: actions/checkout@v4
+    - uses: bazelbuild/setup-bazelisk@v3
+    - name: Configure Bazel caches
+      run: |
+        echo "BAZELISK_HOME=$HOME/.cache/bazelisk" >> $GITHUB_ENV
+        echo "build --disk_cache=$HOME/.cache/bazel-disk" >> ~/.bazelrc
+        echo "build --repository_cache=$HOME/.cache/bazel-repo" >> ~/.bazelrc
+    - name: Cache Bazel
+      uses: actions/cache@v4
+      with:
+        path: |
+          ~/.cache/bazel-disk
+          ~/.cache/bazel-repo
+          ~/.cache/bazelisk
+          ~/.buddy
+        key: buddy-${{{{ runner.os }}}}-${{{{ hashFiles('Buddy.toml', 'Buddy.lock', 'WORKSPACE', '.bazelrc') }}}}-${{{{ github.sha }}}}
+        restore-keys: |
+          buddy-${{{{ runner.os }}}}-${{{{ hashFiles('Buddy.toml', 'Buddy.lock', 'WORKSPACE', '.bazelrc') }}}}-
+          buddy-${{{{ runner.os }}}}-
+    - name: Install buddy
+      run: cargo install buddy --version {version} --locked
+    - name: Build
+      run: buddy build
+    - name: Test
+      run: buddy test
+    - name: Upload test results
+      if: always()
+      uses: actions/upload-artifact@v4
+      with:
+        name: test-results-${{{{ matrix.os }}}}
+        path: target/testlogs/**/test.xml
+
+  checks:
+    name: Format and lint
+    runs-on: ubuntu-latest
+
+    steps:
+    - uses: actions/checkout@v4
+    - name: Install clang tools
+      run: sudo apt-get update && sudo apt-get install -y clang-format clang-tidy
+    - name: Install buddy
+      run: cargo install buddy --version {version} --locked
+    - name: Check formatting
+      run: buddy fmt --check
+    - name: Lint
+      run: buddy lint
+"#,
+        branch = branch,
+        runners = runners.join(", "),
+        version = env!("CARGO_PKG_VERSION"),
+    ))
+}
+
+/// Renders `.gitlab-ci.yml`. GitLab only caches and uploads paths inside
+/// the project directory, so the Bazel caches live under `.cache/` and the
+/// JUnit reports are copied out of the `target/testlogs` symlink.
+pub fn gitlab_pipeline(config: &Config) -> Result<String, String> {
+    let mut out = String::from(
+        r#"# This file is automatically @generated by Buddy.
+# Regenerate it with `buddy ci init gitlab --force`.
+stages:
+  - build
+  - check
+
+variables:
+  BAZELISK_HOME: "$CI_PROJECT_DIR/.cache/bazelisk"
+
+.buddy:
+  cache:
+    key:
+      files:
+        - Buddy.toml
+        - Buddy.lock
+      prefix: "$CI_JOB_NAME"
+    paths:
+      - .cache/
+  before_script:
+    - echo "build --disk_cache=$CI_PROJECT_DIR/.cache/bazel-disk" >> ~/.bazelrc
+    - echo "build --repository_cache=$CI_PROJECT_DIR/.cache/bazel-repo" >> ~/.bazelrc
+"#,
+    );
+
+    for platform in &config.ci.platforms {
+        let (image, tags, bazelisk) = match platform.as_str() {
+            "linux" => (Some("rust:latest"), None, "bazelisk-linux-amd64"),
+            "macos" => (None, Some("saas-macos-medium-m1"), "bazelisk-darwin-arm64"),
+            _ => {
+                return Err(format!(
+                    "unsupported platform `{}` in [ci] platforms, the GitLab generator supports: linux, macos",
+                    platform
+                ))
+            }
+        };
+
+        out.push_str(&format!(
+            "\nbuild:{}:\n  extends: .buddy\n  stage: build\n",
+            platform
+        ));
+        if let Some(image) = image {
+            out.push_str(&format!("  image: {}\n", image));
+        }
+        if let Some(tags) = tags {
+            out.push_str(&format!("  tags:\n    - {}\n", tags));
+        }
+        out.push_str(&format!(
+            r#"  script:
+    - mkdir -p "$CI_PROJECT_DIR/.cache/bin"
+    - curl -fsSL -o "$CI_PROJECT_DIR/.cache/bin/bazelisk" https://github.com/bazelbuild/bazelisk/releases/latest/download/{bazelisk}
+    - chmod +x "$CI_PROJECT_DIR/.cache/bin/bazelisk"
+    - export PATH="$CI_PROJECT_DIR/.cache/bin:$PATH"
+    - cargo install buddy --version {version} --locked
+    - {build}
+    - {test}
+  after_script:
+    - mkdir -p test-reports
+    - find -L target/testlogs -name test.xml -exec cp --parents {{}} test-reports \; || true
+  artifacts:
+    when: always
+    reports:
+      junit: test-reports/**/test.xml
+"#,
+            bazelisk = bazelisk,
+            version = env!("CARGO_PKG_VERSION"),
+            build = PIPELINE[0].1,
+            test = PIPELINE[1].1,
+        ));
+    }
+
+    out.push_str(&format!(
+        r#"
+checks:
+  stage: check
+  image: rust:latest
+  script:
+    - apt-get update && apt-get install -y clang-format clang-tidy
+    - cargo install buddy --version {version} --locked
+    - {fmt}
+    - {lint}
+"#,
+        version = env!("CARGO_PKG_VERSION"),
+        fmt = PIPELINE[2].1,
+        lint = PIPELINE[3].1,
+    ));
+
+    Ok(out)
+}
+
+/// The pipeline as a plain shell script, with the caching and reporting
+/// conventions spelled out for whoever wires it into another CI system.
+pub fn generic_pipeline() -> String {
+    let mut out = format!(
+        r#"#!/bin/sh
+# Canonical buddy pipeline, run it from the project root.
+#
+# Requirements: bazelisk, clang-format, clang-tidy and buddy {version}
+#   (cargo install buddy --version {version} --locked)
+# Caching: persist ~/.cache/bazel-disk, ~/.cache/bazel-repo and ~/.buddy
+#   between runs, keyed on the contents of {key_files}, and add
+#   `build --disk_cache=...` / `build --repository_cache=...` for those
+#   directories to ~/.bazelrc.
+# Test reports: JUnit XML files are written to target/testlogs/**/test.xml
+set -e
+"#,
+        version = env!("CARGO_PKG_VERSION"),
+        key_files = CACHE_KEY_FILES.join(", "),
+    );
+
+    for (step, command) in PIPELINE {
+        out.push_str(&format!("\n# {}\n{}\n", step, command));
+    }
+
+    out
+}
+
+pub fn init(
+    config: &Config,
+    provider: Option<Provider>,
+    format: Option<Format>,
+    force: bool,
+) -> Result<(), String> {
+    let (path, contents) = match (provider, format) {
+        (_, Some(Format::Generic)) => {
+            print!("{}", generic_pipeline());
+            return Ok(());
+        }
+        (Some(Provider::Github), None) => (
+            Path::new(".github").join("workflows").join("buddy.yml"),
+            github_workflow(config, &push_branch())?,
+        ),
+        (Some(Provider::Gitlab), None) => (
+            Path::new(".gitlab-ci.yml").to_path_buf(),
+            gitlab_pipeline(config)?,
+        ),
+        (None, None) => return Err("either a provider or --format is required".to_string()),
+    };
+
+    if path.exists() && !force {
+        return Err(format!(
+            "`{}` already exists, use --force to overwrite it",
+            path.display()
+        ));
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    fs::write(&path, contents).map_err(|e| e.to_string())?;
+
+    style::status("Created", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CiConfig;
+
+    #[test]
+    fn test_github_workflow_matrix() {
+        let config = Config {
+            ci: CiConfig {
+                platforms: vec!["linux".to_string(), "macos".to_string()],
+            },
+            ..Default::default()
+        };
+
+        let workflow = github_workflow(&config, "master").unwrap();
+        assert!(workflow.contains("    branches: [ \"master\" ]\n  pull_request:\n"));
+        assert!(workflow.contains("os: [ubuntu-latest, macos-latest]"));
+        assert!(workflow.contains("runs-on: ${{ matrix.os }}"));
+        assert!(workflow.contains("run: buddy fmt --check"));
+    }
+
+    #[test]
+    fn test_gitlab_pipeline_jobs_per_platform() {
+        let config = Config {
+            ci: CiConfig {
+                platforms: vec!["linux".to_string(), "macos".to_string()],
+            },
+            ..Default::default()
+        };
+
+        let pipeline = gitlab_pipeline(&config).unwrap();
+        assert!(pipeline.contains("\nbuild:linux:\n"));
+        assert!(pipeline.contains("\nbuild:macos:\n"));
+        assert!(pipeline.contains("junit: test-reports/**/test.xml"));
+        assert!(pipeline.contains("    - buddy fmt --check\n"));
+    }
+
+    #[test]
+    fn test_generic_pipeline_lists_commands_in_order() {
+        let pipeline = generic_pipeline();
+        let build = pipeline.find("\nbuddy build\n").unwrap();
+        let test = pipeline.find("\nbuddy test\n").unwrap();
+        let lint = pipeline.find("\nbuddy lint\n").unwrap();
+        assert!(build < test && test < lint);
+    }
+
+    #[test]
+    fn test_generic_pipeline_stops_on_failure() {
+        use std::os::unix::fs::PermissionsExt;
+        use std::process::Command;
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path();
+        // A buddy whose tests fail, logging the commands it runs.
+        let buddy = root.join("buddy");
+        fs::write(
+            &buddy,
+            "#!/bin/sh\necho \"$1\" >> \"$(dirname \"$0\")/log\"\n[ \"$1\" != test ]\n",
+        )
+        .unwrap();
+        fs::set_permissions(&buddy, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(generic_pipeline())
+            .env(
+                "PATH",
+                format!(
+                    "{}:{}",
+                    root.display(),
+                    std::env::var("PATH").unwrap_or_default()
+                ),
+            )
+            .status()
+            .unwrap();
+        assert!(!status.success());
+        assert_eq!(
+            fs::read_to_string(root.join("log")).unwrap(),
+            "build\ntest\n"
+        );
+    }
+
+    #[test]
+    fn test_github_workflow_unknown_platform() {
+        let config = Config {
+            ci: CiConfig {
+                platforms: vec!["amiga".to_string()],
+            },
+            ..Default::default()
+        };
+
+        assert!(github_workflow(&config, "main").is_err());
+    }
+}
@@ -0,0 +1,76 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::bazel;
+use crate::global;
+use crate::style;
+
+/// Where bazel's output symlinks and buddy's own files, e.g. the build
+/// events and the benchmark baselines, live.
+const TARGET_DIR: &str = "target";
+
+/// Removes `dir` and everything under it, the symlinks rather than what
+/// they point to. Returns whether there was anything to remove.
+fn remove(dir: &Path) -> Result<bool, String> {
+    let result = match fs::symlink_metadata(dir) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(dir),
+        Ok(_) => fs::remove_file(dir),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => Err(e),
+    };
+    result
+        .map(|_| true)
+        .map_err(|e| format!("failed to remove {}: {}", dir.display(), e))
+}
+
+/// Removes the outputs of the builds: `bazel clean`, or with `expunge` its
+/// whole output base, external repositories included, then `target/`. With
+/// `cache` the archives downloaded by `buddy fetch` go too.
+pub fn run(bazel_bin: &Path, expunge: bool, cache: bool) -> Result<(), String> {
+    // Without a WORKSPACE bazel has never built anything here.
+    if Path::new("WORKSPACE").is_file() {
+        let mut cmd = bazel::command(bazel_bin, "clean");
+        if expunge {
+            cmd.arg("--expunge");
+        }
+        let status = bazel::stream(&mut cmd).map_err(|e| format!("failed to run bazel: {}", e))?;
+        if !status.success() {
+            return Err("`bazel clean` failed".to_string());
+        }
+    }
+
+    if remove(Path::new(TARGET_DIR))? {
+        style::status("Removed", format!("{}/", TARGET_DIR));
+    }
+    if cache {
+        let distdir = global::distdir();
+        if remove(&distdir)? {
+            style::status("Removed", distdir.display());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_remove_keeps_symlinked_outputs() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let output = tmp_dir.path().join("output");
+        fs::create_dir(&output).unwrap();
+        fs::write(output.join("app"), "").unwrap();
+        let target = tmp_dir.path().join("target");
+        fs::create_dir(&target).unwrap();
+        std::os::unix::fs::symlink(&output, target.join("bin")).unwrap();
+        fs::write(target.join("build_events.json"), "").unwrap();
+
+        assert!(remove(&target).unwrap());
+        assert!(!target.exists());
+        assert!(output.join("app").is_file());
+        assert!(!remove(&target).unwrap());
+    }
+}
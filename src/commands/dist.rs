@@ -0,0 +1,308 @@
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+use which::which;
+
+use crate::artifacts;
+use crate::bazel;
+use crate::config::Config;
+use crate::git;
+use crate::lockfile::Lockfile;
+use crate::plugins::{self, Plugin};
+use crate::style;
+
+/// Where `buddy dist` puts the release artifacts.
+pub const DIST_DIR: &str = "target/dist";
+
+/// The build type of buddy's provenance, telling verifiers how to read the
+/// parameters.
+const BUILD_TYPE: &str = "https://github.com/cppbuddy/buddy/dist/v1";
+
+/// Formats `time` as an RFC 3339 UTC timestamp.
+pub fn rfc3339(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let days = (seconds / 86400) as i64;
+    let time = seconds % 86400;
+
+    // Civil date of a day count, see Howard Hinnant's `civil_from_days`.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+/// The SHA-256 of `path`, from `sha256sum` or macOS's `shasum`.
+pub fn sha256(path: &Path) -> Result<String, String> {
+    let mut cmd = if let Ok(bin) = which("sha256sum") {
+        Command::new(bin)
+    } else {
+        let bin = which("shasum").map_err(|_| {
+            "`sha256sum` not found, it is needed to digest the artifacts (it is part of coreutils)"
+        })?;
+        let mut cmd = Command::new(bin);
+        cmd.args(["-a", "256"]);
+        cmd
+    };
+    let output = cmd
+        .arg(path)
+        .output()
+        .map_err(|e| format!("failed to digest {}: {}", path.display(), e))?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    match stdout.split_whitespace().next() {
+        Some(digest) if output.status.success() => Ok(digest.to_string()),
+        _ => Err(format!("failed to digest {}", path.display())),
+    }
+}
+
+/// The digest of a pin of a recipe: a git commit or an archive checksum.
+fn pin_digest(sha: &str) -> Value {
+    match sha.len() {
+        40 => json!({ "gitCommit": sha }),
+        64 => json!({ "sha256": sha }),
+        _ => json!({}),
+    }
+}
+
+/// The locked dependencies, as resolved dependencies of the provenance.
+fn dependencies(lockfile: &Lockfile, plugins: &[Plugin]) -> Vec<Value> {
+    lockfile
+        .package
+        .iter()
+        .map(|package| {
+            let plugin = plugins::find(plugins, &package.name);
+            let uri = plugin
+                .and_then(|plugin| plugin.archive_url(&package.version).ok().flatten())
+                .unwrap_or_else(|| format!("pkg:buddy/{}@{}", package.name, package.version));
+            let digest = plugin
+                .and_then(|plugin| plugin.versions.get(&package.version))
+                .map_or_else(|| json!({}), |sha| pin_digest(sha));
+            json!({
+                "name": package.name,
+                "uri": uri,
+                "digest": digest,
+                "annotations": { "version": package.version },
+            })
+        })
+        .collect()
+}
+
+/// The commit the sources were built from, if they live in git.
+fn source() -> Option<Value> {
+    let commit = git::output(&["rev-parse", "HEAD"]).ok()?;
+    let remote = git::output(&["remote", "get-url", "origin"]).or_else(|_| {
+        let root = git::output(&["rev-parse", "--show-toplevel"])?;
+        Ok::<_, String>(format!("file://{}", root))
+    });
+    let remote = remote.ok()?;
+    let dirty = !git::output(&["status", "--porcelain"])
+        .unwrap_or_default()
+        .is_empty();
+    Some(json!({
+        "uri": format!("git+{}", remote),
+        "digest": { "gitCommit": commit },
+        "annotations": { "dirty": dirty },
+    }))
+}
+
+/// What is attested of a build, shared by the statements of its artifacts.
+pub struct Build<'a> {
+    pub config: &'a Config,
+    pub targets: &'a [String],
+    pub flags: &'a [String],
+    pub bazel_version: Option<bazel::Version>,
+    pub started: SystemTime,
+    pub finished: SystemTime,
+    /// The source, then the dependencies.
+    pub resolved: Vec<Value>,
+}
+
+/// The in-toto statement of SLSA provenance for the artifact `name`.
+pub fn statement(build: &Build, name: &str, digest: &str) -> Value {
+    json!({
+        "_type": "https://in-toto.io/Statement/v1",
+        "subject": [{ "name": name, "digest": { "sha256": digest } }],
+        "predicateType": "https://slsa.dev/provenance/v1",
+        "predicate": {
+            "buildDefinition": {
+                "buildType": BUILD_TYPE,
+                "externalParameters": {
+                    "package": build.config.package.name,
+                    "version": build.config.package.version,
+                    "targets": build.targets,
+                    "flags": build.flags,
+                },
+                "resolvedDependencies": build.resolved,
+            },
+            "runDetails": {
+                "builder": {
+                    "id": format!("https://github.com/cppbuddy/buddy@v{}", env!("CARGO_PKG_VERSION")),
+                    "version": {
+                        "buddy": env!("CARGO_PKG_VERSION"),
+                        "bazel": build.bazel_version.map(|v| v.to_string()),
+                    },
+                },
+                "metadata": {
+                    "startedOn": rfc3339(build.started),
+                    "finishedOn": rfc3339(build.finished),
+                },
+            },
+        },
+    })
+}
+
+/// Signs `path` with `key`, `minisign:<secret key file>` or
+/// `cosign:<key reference>`, next to it.
+fn sign(path: &Path, key: &str) -> Result<PathBuf, String> {
+    let (tool, key) = key.split_once(':').ok_or_else(|| {
+        format!(
+            "invalid signing key `{}`, expected `minisign:<file>` or `cosign:<key>`",
+            key
+        )
+    })?;
+    if !matches!(tool, "minisign" | "cosign") {
+        return Err(format!(
+            "unknown signing tool `{}`, expected minisign or cosign",
+            tool
+        ));
+    }
+    let bin = which(tool).map_err(|_| format!("`{}` not found, it is needed to sign", tool))?;
+    let mut cmd = Command::new(bin);
+    let signature = match tool {
+        "minisign" => {
+            let signature = PathBuf::from(format!("{}.minisig", path.display()));
+            cmd.args(["-S", "-s", key, "-m"])
+                .arg(path)
+                .arg("-x")
+                .arg(&signature);
+            signature
+        }
+        _ => {
+            let signature = PathBuf::from(format!("{}.sig", path.display()));
+            cmd.args(["sign-blob", "--yes", "--key", key, "--output-signature"])
+                .arg(&signature)
+                .arg(path);
+            signature
+        }
+    };
+    let status = cmd
+        .status()
+        .map_err(|e| format!("failed to run {}: {}", tool, e))?;
+    if !status.success() {
+        return Err(format!("{} failed to sign {}", tool, path.display()));
+    }
+    Ok(signature)
+}
+
+/// Writes the provenance of every artifact of the last build copied into
+/// `out_dir`, as `<artifact>.intoto.json`, signed with `key` if given.
+pub fn attest(
+    build: &mut Build,
+    plugins: &[Plugin],
+    out_dir: &Path,
+    key: Option<&str>,
+) -> Result<(), String> {
+    build.resolved = source().into_iter().collect();
+    build.resolved.extend(dependencies(
+        &Lockfile::load(Path::new("Buddy.lock"))?,
+        plugins,
+    ));
+
+    let events = fs::read_to_string(bazel::EVENT_FILE).map_err(|e| e.to_string())?;
+    let names: BTreeSet<String> = artifacts::parse(&events)
+        .iter()
+        .filter_map(|artifact| Some(artifact.path.file_name()?.to_str()?.to_string()))
+        .collect();
+    for name in names {
+        let path = out_dir.join(&name);
+        if !path.is_file() {
+            continue;
+        }
+        let statement = statement(build, &name, &sha256(&path)?);
+        let provenance = out_dir.join(format!("{}.intoto.json", name));
+        let contents = serde_json::to_string_pretty(&statement).map_err(|e| e.to_string())?;
+        fs::write(&provenance, contents + "\n")
+            .map_err(|e| format!("{}: {}", provenance.display(), e))?;
+        style::status("Attested", provenance.display());
+        if let Some(key) = key {
+            style::status("Signed", sign(&provenance, key)?.display());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_statement() {
+        assert_eq!(
+            rfc3339(UNIX_EPOCH + std::time::Duration::from_secs(1709210096)),
+            "2024-02-29T12:34:56Z"
+        );
+
+        let mut config = Config::default();
+        config.package.name = "demo".to_string();
+        config.package.version = "1.0.0".to_string();
+        let lockfile: Lockfile = toml::from_str(
+            r#"
+[[package]]
+name = "google-test"
+version = "1.13.0"
+"#,
+        )
+        .unwrap();
+        let flags = ["--compilation_mode=opt".to_string()];
+        let build = Build {
+            config: &config,
+            targets: &[],
+            flags: &flags,
+            bazel_version: bazel::Version::parse("bazel 7.4.1"),
+            started: UNIX_EPOCH,
+            finished: UNIX_EPOCH,
+            resolved: dependencies(&lockfile, &plugins::builtin()),
+        };
+
+        let statement = statement(&build, "demo", "abc123");
+        assert_eq!(statement["subject"][0]["digest"]["sha256"], "abc123");
+        let predicate = &statement["predicate"];
+        assert_eq!(
+            predicate["buildDefinition"]["externalParameters"]["flags"][0],
+            "--compilation_mode=opt"
+        );
+        let dependency = &predicate["buildDefinition"]["resolvedDependencies"][0];
+        assert_eq!(
+            dependency["uri"],
+            "https://github.com/google/googletest/archive/b796f7d44681514f58a683a3a71ff17c94edb0c1.zip"
+        );
+        assert_eq!(
+            dependency["digest"]["gitCommit"],
+            "b796f7d44681514f58a683a3a71ff17c94edb0c1"
+        );
+        assert_eq!(
+            predicate["runDetails"]["builder"]["version"]["bazel"],
+            "7.4.1"
+        );
+    }
+}
@@ -0,0 +1,338 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+use which::which;
+
+use crate::commands::profile::open_viewer;
+use crate::config::Config;
+use crate::git;
+use crate::style;
+
+const DOC_DIR: &str = "target/doc";
+
+/// Doxygen wants values containing spaces quoted.
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\\\""))
+}
+
+/// Generates the Doxyfile documenting the project at `root`, titled after
+/// its `[package]`.
+pub fn doxyfile(root: &Path, config: &Config) -> String {
+    let input: Vec<_> = config
+        .doc
+        .input
+        .iter()
+        .filter(|dir| root.join(dir).exists())
+        .map(|dir| quote(dir))
+        .collect();
+    let exclude: Vec<_> = config.doc.exclude.iter().map(|p| quote(p)).collect();
+
+    let mut out = format!(
+        "PROJECT_NAME = {}
+PROJECT_NUMBER = {}
+OUTPUT_DIRECTORY = {}
+INPUT = {}
+RECURSIVE = YES
+FILE_PATTERNS = *.h *.hh *.hpp *.hxx *.c *.cc *.cpp *.cxx *.md
+EXCLUDE_PATTERNS = {}
+EXTRACT_ALL = YES
+GENERATE_LATEX = NO
+QUIET = YES
+WARN_IF_UNDOCUMENTED = NO
+",
+        quote(&config.package.name),
+        quote(&config.package.version),
+        DOC_DIR,
+        input.join(" "),
+        exclude.join(" ")
+    );
+    if let Some(description) = &config.package.description {
+        out.push_str(&format!("PROJECT_BRIEF = {}\n", quote(description)));
+    }
+    if root.join("README.md").is_file() {
+        out.push_str("INPUT += README.md\nUSE_MDFILE_AS_MAINPAGE = README.md\n");
+    }
+    out
+}
+
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let dest = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &dest)?;
+        } else {
+            fs::copy(entry.path(), dest)?;
+        }
+    }
+    Ok(())
+}
+
+/// The page at the root of the published site, sending visitors to the
+/// documentation of `version`.
+fn redirect(version: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<meta charset=\"utf-8\">\n<meta http-equiv=\"refresh\" content=\"0; url={0}/\">\n<a href=\"{0}/\">{0}</a>\n",
+        version
+    )
+}
+
+/// Lays the documentation of `version` out in the checkout of the pages
+/// branch and commits it, other versions are kept.
+fn commit_pages(pages: &Path, html: &Path, config: &Config) -> Result<bool, String> {
+    let version = &config.package.version;
+    let dest = pages.join(version);
+    if dest.exists() {
+        fs::remove_dir_all(&dest).map_err(|e| e.to_string())?;
+    }
+    copy_dir(html, &dest).map_err(|e| e.to_string())?;
+    fs::write(pages.join("index.html"), redirect(version)).map_err(|e| e.to_string())?;
+    // Keep GitHub Pages from running Jekyll, which hides `_`-prefixed files.
+    fs::write(pages.join(".nojekyll"), "").map_err(|e| e.to_string())?;
+
+    git::output_in(pages, &["add", "-A"])?;
+    if git::output_in(pages, &["status", "--porcelain"])?.is_empty() {
+        return Ok(false);
+    }
+    let message = format!(
+        "Publish the documentation of {} {}",
+        config.package.name, version
+    );
+    git::output_in(pages, &["commit", "-q", "-m", &message])?;
+    Ok(true)
+}
+
+/// Commits `html` under a directory named after the version to `branch`,
+/// then pushes the branch to `origin` when there is one.
+fn publish_branch(root: &Path, html: &Path, branch: &str, config: &Config) -> Result<(), String> {
+    let has_origin = git::output_in(root, &["remote", "get-url", "origin"]).is_ok();
+    let remote_branch = format!("origin/{}", branch);
+    if has_origin {
+        // The branch may not exist yet on the remote.
+        let refspec = format!("{0}:refs/remotes/origin/{0}", branch);
+        let _ = git::output_in(root, &["fetch", "-q", "origin", &refspec]);
+    }
+    let exists =
+        |name: &str| git::output_in(root, &["rev-parse", "--verify", "--quiet", name]).is_ok();
+
+    let tmp_dir = tempfile::tempdir().map_err(|e| e.to_string())?;
+    let pages = tmp_dir.path().join(branch);
+    let pages_arg = pages.to_str().ok_or("invalid temporary directory")?;
+    if has_origin && exists(&remote_branch) {
+        git::output_in(
+            root,
+            &[
+                "worktree",
+                "add",
+                "-q",
+                "-B",
+                branch,
+                pages_arg,
+                &remote_branch,
+            ],
+        )?;
+    } else if exists(branch) {
+        git::output_in(root, &["worktree", "add", "-q", pages_arg, branch])?;
+    } else {
+        git::output_in(root, &["worktree", "add", "-q", "--detach", pages_arg])?;
+        git::output_in(&pages, &["checkout", "-q", "--orphan", branch])?;
+        // Fails when the project has no tracked file, nothing to remove then.
+        let _ = git::output_in(&pages, &["rm", "-rfq", "."]);
+    }
+
+    let published = commit_pages(&pages, html, config).and_then(|changed| {
+        if has_origin {
+            git::output_in(&pages, &["push", "-q", "origin", branch])?;
+        }
+        Ok(changed)
+    });
+    let _ = git::output_in(root, &["worktree", "remove", "--force", pages_arg]);
+
+    if published? {
+        style::status(
+            "Published",
+            format!(
+                "{} {} to the `{}` branch",
+                config.package.name, config.package.version, branch
+            ),
+        );
+    } else {
+        println!("the `{}` branch is already up to date", branch);
+    }
+    Ok(())
+}
+
+/// Uploads `html` to `<url>/<version>` with the AWS CLI.
+fn publish_s3(html: &Path, url: &str, config: &Config) -> Result<(), String> {
+    let aws = which("aws").map_err(|_| {
+        "`aws` not found, it is needed to publish to S3. See https://aws.amazon.com/cli/"
+    })?;
+    let url = url.trim_end_matches('/');
+    let version = &config.package.version;
+
+    let status = Command::new(&aws)
+        .args(["s3", "sync", "--delete"])
+        .arg(html)
+        .arg(format!("{}/{}/", url, version))
+        .status()
+        .map_err(|e| format!("failed to run aws: {}", e))?;
+    if !status.success() {
+        return Err(format!("failed to upload the documentation to `{}`", url));
+    }
+
+    let index = Path::new(DOC_DIR).join("index.html");
+    fs::write(&index, redirect(version)).map_err(|e| e.to_string())?;
+    let status = Command::new(&aws)
+        .args(["s3", "cp"])
+        .arg(&index)
+        .arg(format!("{}/index.html", url))
+        .status()
+        .map_err(|e| format!("failed to run aws: {}", e))?;
+    if !status.success() {
+        return Err(format!("failed to upload the documentation to `{}`", url));
+    }
+
+    style::status(
+        "Published",
+        format!(
+            "{} {} to {}/{}/",
+            config.package.name, version, url, version
+        ),
+    );
+    Ok(())
+}
+
+/// Generates the API documentation with Doxygen into `target/doc`.
+pub fn run(config: &Config, open: bool, publish: bool) -> Result<(), String> {
+    let doxygen = which("doxygen").map_err(|_| {
+        "`doxygen` not found. Install it from https://www.doxygen.nl/download.html (e.g. `apt install doxygen` or `brew install doxygen`)"
+    })?;
+
+    let root = Path::new(".");
+    fs::create_dir_all(DOC_DIR).map_err(|e| e.to_string())?;
+    let doxyfile_path = Path::new(DOC_DIR).join("Doxyfile");
+    fs::write(&doxyfile_path, doxyfile(root, config)).map_err(|e| e.to_string())?;
+
+    style::status(
+        "Documenting",
+        format!("{} v{}", config.package.name, config.package.version),
+    );
+    let status = Command::new(doxygen)
+        .arg(&doxyfile_path)
+        .status()
+        .map_err(|e| format!("failed to run doxygen: {}", e))?;
+    if !status.success() {
+        return Err(format!("doxygen exited with {}", status));
+    }
+
+    let html = Path::new(DOC_DIR).join("html");
+    let index = html.join("index.html");
+    style::status("Generated", index.display());
+
+    if publish {
+        let destination = &config.doc.publish;
+        if destination.starts_with("s3://") {
+            publish_s3(&html, destination, config)?;
+        } else {
+            publish_branch(root, &html, destination, config)?;
+        }
+    }
+
+    if open {
+        open_viewer(
+            if cfg!(target_os = "macos") {
+                "open"
+            } else {
+                "xdg-open"
+            },
+            &index,
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_doxyfile() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("README.md"), "# demo\n").unwrap();
+
+        let config: Config = toml::from_str(
+            r#"
+[package]
+name = "demo"
+version = "0.1.0"
+edition = "2023"
+description = "A \"demo\" library"
+
+[dependencies]
+
+[doc]
+exclude = ["*/internal/*"]
+"#,
+        )
+        .unwrap();
+
+        let doxyfile = doxyfile(root, &config);
+        assert!(doxyfile.contains("PROJECT_NAME = \"demo\"\n"));
+        assert!(doxyfile.contains("PROJECT_NUMBER = \"0.1.0\"\n"));
+        assert!(doxyfile.contains("PROJECT_BRIEF = \"A \\\"demo\\\" library\"\n"));
+        // `include` is a default input but doesn't exist here.
+        assert!(doxyfile.contains("INPUT = \"src\"\n"));
+        assert!(doxyfile.contains("EXCLUDE_PATTERNS = \"*/internal/*\"\n"));
+        assert!(doxyfile.contains("USE_MDFILE_AS_MAINPAGE = README.md\n"));
+    }
+
+    #[test]
+    fn test_publish_branch_keeps_other_versions() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path().join("project");
+        let origin = tmp_dir.path().join("origin.git");
+        let html = tmp_dir.path().join("html");
+        fs::create_dir_all(&root).unwrap();
+        fs::create_dir_all(&html).unwrap();
+        fs::write(html.join("index.html"), "docs").unwrap();
+
+        let git = |dir: &Path, args: &[&str]| git::output_in(dir, args).unwrap();
+        git(tmp_dir.path(), &["init", "-q", "--bare", "origin.git"]);
+        git(&root, &["init", "-q", "-b", "main"]);
+        git(&root, &["config", "user.name", "buddy"]);
+        git(&root, &["config", "user.email", "buddy@localhost"]);
+        git(
+            &root,
+            &["remote", "add", "origin", origin.to_str().unwrap()],
+        );
+        fs::write(root.join("Buddy.toml"), "").unwrap();
+        git(&root, &["add", "."]);
+        git(&root, &["commit", "-q", "-m", "initial"]);
+
+        let mut config = Config::default();
+        config.package.name = "demo".to_string();
+        config.package.version = "0.1.0".to_string();
+        publish_branch(&root, &html, "gh-pages", &config).unwrap();
+        config.package.version = "0.2.0".to_string();
+        publish_branch(&root, &html, "gh-pages", &config).unwrap();
+
+        let files = git(&origin, &["ls-tree", "-r", "--name-only", "gh-pages"]);
+        assert_eq!(
+            files.lines().collect::<Vec<_>>(),
+            [
+                ".nojekyll",
+                "0.1.0/index.html",
+                "0.2.0/index.html",
+                "index.html"
+            ]
+        );
+        assert!(git(&origin, &["show", "gh-pages:index.html"]).contains("url=0.2.0/"));
+        // The project's own branch is left alone.
+        assert_eq!(git(&root, &["rev-parse", "--abbrev-ref", "HEAD"]), "main");
+    }
+}
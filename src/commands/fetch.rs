@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use which::which;
+
+use crate::config;
+use crate::global::{self, GlobalConfig};
+use crate::lockfile::Lockfile;
+use crate::mirror;
+use crate::plugins::{self, Plugin};
+use crate::signature;
+use crate::style;
+use crate::workspace;
+
+/// Downloads `url` to `dest` with curl, through the mirrors and proxy of
+/// the user configuration.
+pub fn download(url: &str, dest: &Path, global: &GlobalConfig) -> Result<(), String> {
+    let curl =
+        which("curl").map_err(|_| "`curl` not found, it is needed to download dependencies")?;
+    let url = mirror::rewrite_url(&global.source.replace, url).unwrap_or_else(|| url.to_string());
+
+    // Download next to the destination so that an interrupted download is
+    // never mistaken for a complete one.
+    let mut partial = dest.as_os_str().to_os_string();
+    partial.push(".part");
+    let status = Command::new(curl)
+        .args(["-fsSL", "--retry", "3", "-o"])
+        .arg(&partial)
+        .arg(&url)
+        .envs(global.http.env())
+        .status()
+        .map_err(|e| format!("failed to run curl: {}", e))?;
+    if !status.success() {
+        let _ = fs::remove_file(&partial);
+        return Err(format!("failed to download `{}`", url));
+    }
+    fs::rename(&partial, dest).map_err(|e| e.to_string())
+}
+
+/// Checks the requested versions against what their registry withdrew.
+/// Yanked versions are refused unless already locked or `allow_yanked` is
+/// set, returns the warnings to show otherwise.
+pub fn check_versions(
+    dependencies: &HashMap<String, String>,
+    plugins: &[Plugin],
+    lockfile: &Lockfile,
+    allow_yanked: bool,
+) -> Result<Vec<String>, String> {
+    let mut names: Vec<_> = dependencies.keys().collect();
+    names.sort();
+
+    let mut warnings = Vec::new();
+    for name in names {
+        let Some(plugin) = plugins::find(plugins, name) else {
+            continue;
+        };
+        if let Some(message) = &plugin.deprecated {
+            warnings.push(format!("`{}` is deprecated: {}", name, message));
+        }
+
+        let version = &dependencies[name];
+        let Some(reason) = plugin.yanked.get(version) else {
+            continue;
+        };
+        let hint = match plugin.latest_version() {
+            Some(latest) => format!(", consider `{} = \"{}\"`", name, latest),
+            None => String::new(),
+        };
+        if lockfile.locked_version(name) == Some(version.as_str()) {
+            warnings.push(format!(
+                "`{} {}` is locked but was yanked ({}){}",
+                name, version, reason, hint
+            ));
+        } else if allow_yanked {
+            warnings.push(format!(
+                "using yanked `{} {}` ({}){}",
+                name, version, reason, hint
+            ));
+        } else {
+            return Err(format!(
+                "`{} {}` was yanked ({}){}, or pass --allow-yanked to use it anyway",
+                name, version, reason, hint
+            ));
+        }
+    }
+
+    Ok(warnings)
+}
+
+/// Downloads the archives of the versions the requirements of
+/// `dependencies` pick into the distdir, checking the signature of the ones
+/// whose recipe or registry declares a key. Under `[policy]
+/// require-signatures` unsigned dependencies are refused.
+pub fn run(
+    dependencies: &HashMap<String, String>,
+    plugins: &[Plugin],
+    allow_yanked: bool,
+) -> Result<(), String> {
+    let lockfile = Lockfile::load(Path::new("Buddy.lock"))?;
+    let global = GlobalConfig::load()?;
+    let dependencies = &plugins::select_versions(dependencies, plugins, &global, &lockfile)?;
+    for warning in check_versions(dependencies, plugins, &lockfile, allow_yanked)? {
+        style::warning(warning);
+    }
+
+    let distdir = global::distdir();
+    fs::create_dir_all(&distdir).map_err(|e| e.to_string())?;
+
+    let mut names: Vec<_> = dependencies.keys().collect();
+    names.sort();
+
+    for name in names {
+        let plugin = plugins::resolve(plugins, name, &global)?;
+        let key = signature::signing_key(plugin, &global);
+        if key.is_none() && global.policy.require_signatures {
+            return Err(format!(
+                "refusing unsigned dependency `{}`: [policy] require-signatures is set but no signing key is declared for it",
+                name
+            ));
+        }
+
+        let Some(url) = plugin.archive_url(&dependencies[name])? else {
+            continue;
+        };
+        if global.policy.require_signatures && !plugin.pins_checksum() {
+            return Err(format!(
+                "refusing `{}`: [policy] require-signatures is set but its recipe pins no sha256 of the archive, Bazel would download it again unverified",
+                name
+            ));
+        }
+        let archive = archive_path(&distdir, name, &url);
+        if !archive.exists() {
+            let dir = archive.parent().unwrap();
+            fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+            download(&url, &archive, &global)?;
+            style::status("Downloaded", format!("{} ({})", name, url));
+        }
+
+        if let Some(key) = key {
+            let signature_url = key.signature_url(&url);
+            let signature = archive.with_file_name(signature_url.rsplit('/').next().unwrap());
+            if !signature.exists() {
+                download(&signature_url, &signature, &global)?;
+            }
+            if let Err(error) = key.verify(&archive, &signature) {
+                // Don't let Bazel pick up an archive which failed verification.
+                let _ = fs::remove_file(&archive);
+                let _ = fs::remove_file(&signature);
+                return Err(error);
+            }
+            style::status("Verified", format!("signature of {}", name));
+        }
+    }
+
+    Ok(())
+}
+
+/// Where the archive of `name` downloaded from `url` is kept in `distdir`:
+/// Bazel looks archives up by the basename of their URL, the directory of
+/// the repository keeps the archives of tags of different projects, named
+/// alike, apart.
+pub fn archive_path(distdir: &Path, name: &str, url: &str) -> PathBuf {
+    distdir
+        .join(config::repository_name(name))
+        .join(url.rsplit('/').next().unwrap_or(url))
+}
+
+/// Checks that the archives of `dependencies` were fetched into `distdir`
+/// or vendored into `vendor`, for builds which may not download them. Bazel
+/// only takes the archives of the distdir whose sha256 the recipe pins.
+pub fn check_cached(
+    dependencies: &HashMap<String, String>,
+    plugins: &[Plugin],
+    global: &GlobalConfig,
+    distdir: &Path,
+    vendor: Option<&Path>,
+) -> Result<(), String> {
+    let mut names: Vec<_> = dependencies.keys().collect();
+    names.sort();
+
+    let mut missing = Vec::new();
+    let mut unpinned = Vec::new();
+    for name in names {
+        let plugin = plugins::resolve(plugins, name, global)?;
+        let Some(url) = plugin.archive_url(&dependencies[name])? else {
+            continue;
+        };
+        // Only the rules naming their URL are pointed at the vendored archive.
+        if let Some(vendor) = vendor {
+            if workspace::vendor_path(vendor, name, &url).is_file()
+                && plugin.render(&dependencies[name])?.contains(&url)
+            {
+                continue;
+            }
+        }
+        if !plugin.pins_checksum() {
+            unpinned.push(format!("`{}`", name));
+        } else if !archive_path(distdir, name, &url).exists() {
+            missing.push(format!("`{}`", name));
+        }
+    }
+    if !unpinned.is_empty() {
+        return Err(format!(
+            "the recipes of {} pin no sha256 of the archive, which Bazel needs to use the local archive cache, run `buddy vendor` while online",
+            unpinned.join(", ")
+        ));
+    }
+    if missing.is_empty() {
+        return Ok(());
+    }
+    Err(format!(
+        "{} missing from the local archive cache, run `buddy fetch` while online",
+        missing.join(", ")
+    ))
+}
+
+/// Runs `run` before building when the policy requires signatures, so that
+/// Bazel only ever sees verified archives.
+pub fn verify_if_required(
+    dependencies: &HashMap<String, String>,
+    plugins: &[Plugin],
+) -> Result<(), String> {
+    if GlobalConfig::load()?.policy.require_signatures {
+        run(dependencies, plugins, false)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_versions_yanked() {
+        let mut plugins = plugins::builtin();
+        plugins[0]
+            .yanked
+            .insert("1.12.1".to_string(), "miscompiles on gcc 13".to_string());
+
+        let mut dependencies = HashMap::new();
+        dependencies.insert("google-test".to_string(), "1.12.1".to_string());
+
+        let error =
+            check_versions(&dependencies, &plugins, &Lockfile::default(), false).unwrap_err();
+        assert!(error.contains("consider `google-test = \"1.13.0\"`"));
+
+        let warnings = check_versions(&dependencies, &plugins, &Lockfile::default(), true).unwrap();
+        assert_eq!(warnings.len(), 1);
+
+        let lockfile: Lockfile =
+            toml::from_str("[[package]]\nname = \"google-test\"\nversion = \"1.12.1\"\n").unwrap();
+        let warnings = check_versions(&dependencies, &plugins, &lockfile, false).unwrap();
+        assert!(warnings[0].contains("is locked but was yanked"));
+    }
+
+    #[test]
+    fn test_check_cached() {
+        let plugins = plugins::builtin();
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let distdir = tmp_dir.path().join("distdir");
+        let vendor = tmp_dir.path().join("vendor");
+        fs::create_dir_all(&distdir).unwrap();
+        fs::create_dir_all(&vendor).unwrap();
+        let global = GlobalConfig::default();
+
+        let mut dependencies = HashMap::new();
+        dependencies.insert("bazel-toolchain".to_string(), "0.8.2".to_string());
+        assert!(
+            check_cached(&dependencies, &plugins, &global, &distdir, None)
+                .unwrap_err()
+                .starts_with("`bazel-toolchain` missing from the local archive cache")
+        );
+        fs::create_dir_all(distdir.join("bazel_toolchain")).unwrap();
+        fs::write(distdir.join("bazel_toolchain/0.8.2.tar.gz"), "").unwrap();
+        check_cached(&dependencies, &plugins, &global, &distdir, None).unwrap();
+
+        // Bazel would not take it from the distdir, only vendored it builds.
+        dependencies.insert("google-test".to_string(), "1.12.1".to_string());
+        let archive = "58d77fa8070e8cec2dc1ed015d66b454c8d78850.zip";
+        fs::create_dir_all(distdir.join("google_test")).unwrap();
+        fs::write(distdir.join("google_test").join(archive), "").unwrap();
+        assert!(
+            check_cached(&dependencies, &plugins, &global, &distdir, Some(&vendor))
+                .unwrap_err()
+                .starts_with("the recipes of `google-test` pin no sha256")
+        );
+        fs::write(vendor.join(format!("google_test-{}", archive)), "").unwrap();
+        check_cached(&dependencies, &plugins, &global, &distdir, Some(&vendor)).unwrap();
+    }
+}
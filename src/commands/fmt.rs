@@ -0,0 +1,74 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use which::which;
+
+use crate::style::{self, Level};
+use crate::{git, targets};
+
+/// Runs on the given files (ignoring anything that isn't C/C++), on the files
+/// changed since a git ref with `changed`, or on all the project sources when
+/// none are given.
+pub fn run(check: bool, files: &[PathBuf], changed: Option<Option<&str>>) -> Result<(), String> {
+    let clang_format = which("clang-format").map_err(|_| {
+        "`clang-format` not found. Install it with your LLVM/clang packages (e.g. `apt install clang-format`)"
+            .to_string()
+    })?;
+
+    let files = match changed {
+        Some(reference) => git::changed_files(reference)?,
+        None if files.is_empty() => {
+            targets::project_sources(Path::new(".")).map_err(|e| e.to_string())?
+        }
+        None => files.to_vec(),
+    };
+    let files: Vec<PathBuf> = files
+        .into_iter()
+        .filter(|f| targets::is_cc_file(f))
+        .collect();
+    if files.is_empty() {
+        if changed.is_some() {
+            style::status("Skipped", "no changed C/C++ files");
+        }
+        return Ok(());
+    }
+
+    if !check {
+        let status = Command::new(&clang_format)
+            .arg("-i")
+            .args(&files)
+            .status()
+            .map_err(|e| format!("failed to run clang-format: {}", e))?;
+        if !status.success() {
+            return Err(format!("clang-format exited with {}", status));
+        }
+        style::status("Formatted", format!("{} files", files.len()));
+        return Ok(());
+    }
+
+    let mut unformatted = Vec::new();
+    for file in &files {
+        let status = Command::new(&clang_format)
+            .args(["--dry-run", "--Werror"])
+            .arg(file)
+            .stderr(Stdio::null())
+            .status()
+            .map_err(|e| format!("failed to run clang-format: {}", e))?;
+        if !status.success() {
+            println!(
+                "{} {}",
+                style::paint("Diff in", Level::Error),
+                file.display()
+            );
+            unformatted.push(file);
+        }
+    }
+
+    if unformatted.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} file(s) are not formatted, run `buddy fmt` to fix them",
+            unformatted.len()
+        ))
+    }
+}
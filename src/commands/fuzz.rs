@@ -0,0 +1,302 @@
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::bazel;
+use crate::sanitizer::{self, Report, Sanitizer};
+use crate::style;
+use crate::targets::{self, FUZZ_DIR};
+
+/// Where the corpora of the fuzz targets grow, one directory each.
+pub const CORPUS_DIR: &str = "target/fuzz/corpus";
+
+/// Where libFuzzer writes the inputs crashing a target, one directory each.
+pub const ARTIFACTS_DIR: &str = "target/fuzz/artifacts";
+
+/// Frames of libFuzzer and of the sanitizer runtimes, the same whatever
+/// crashed.
+const RUNTIME_FRAMES: [&str; 5] = [
+    "__sanitizer",
+    "__asan",
+    "__interceptor",
+    "fuzzer::",
+    "__libc",
+];
+
+const TEMPLATE: &str = r#"#include <cstddef>
+#include <cstdint>
+
+// Called by libFuzzer with every input it generates: feed `data` to the code
+// under test, `buddy fuzz run {name}` reports the inputs crashing it.
+extern "C" int LLVMFuzzerTestOneInput(const uint8_t *data, size_t size) {
+  (void)data;
+  (void)size;
+  return 0;
+}
+"#;
+
+/// Creates the fuzz target `name`, `fuzz/<name>.cc`.
+pub fn init(name: &str) -> Result<(), String> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Err(format!(
+            "invalid fuzz target name `{}`, use letters, digits, `_` and `-`",
+            name
+        ));
+    }
+    let path = Path::new(FUZZ_DIR).join(format!("{}.cc", name));
+    if path.exists() {
+        return Err(format!("{} already exists", path.display()));
+    }
+    fs::create_dir_all(FUZZ_DIR).map_err(|e| format!("{}: {}", FUZZ_DIR, e))?;
+    fs::write(&path, TEMPLATE.replace("{name}", name))
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    style::status("Created", format!("fuzz target `{}`", name));
+    Ok(())
+}
+
+/// The fuzz targets of the project at `root`, by name.
+pub fn list(root: &Path) -> Result<Vec<String>, String> {
+    let dir = root.join(FUZZ_DIR);
+    let Ok(entries) = fs::read_dir(&dir) else {
+        return Ok(Vec::new());
+    };
+    let mut names = Vec::new();
+    for entry in entries {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if path.is_file() && targets::is_translation_unit(&path) {
+            names.extend(
+                path.file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .map(String::from),
+            );
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// Builds with libFuzzer's instrumentation and AddressSanitizer, which
+/// need clang.
+pub fn flags() -> Vec<String> {
+    let mut flags = vec!["--copt=-fsanitize=fuzzer-no-link".to_string()];
+    flags.extend(sanitizer::flags(&[Sanitizer::Address]).unwrap_or_default());
+    flags
+}
+
+/// The functions of the frames of `report`, libFuzzer's and the runtimes'
+/// left out.
+fn frames(report: &Report) -> Vec<&str> {
+    report
+        .lines
+        .iter()
+        .filter(|line| line.trim_start().starts_with('#'))
+        .filter_map(|line| {
+            let (_, function) = line.split_once(" in ")?;
+            // The location, when known, follows the function.
+            Some(function.rsplit_once(' ').map_or(function, |(f, _)| f))
+        })
+        .filter(|function| {
+            !RUNTIME_FRAMES
+                .iter()
+                .any(|frame| function.starts_with(frame))
+        })
+        .collect()
+}
+
+/// A crash found while fuzzing, the inputs triggering it.
+#[derive(Debug, PartialEq)]
+struct Crash {
+    report: Report,
+    inputs: Vec<String>,
+}
+
+/// The crashes reported in libFuzzer's `output`, the ones with the same
+/// error and top frames merged.
+fn crashes(output: &str) -> Vec<Crash> {
+    let signature = |report: &Report| {
+        let top: Vec<String> = frames(report)
+            .into_iter()
+            .take(3)
+            .map(String::from)
+            .collect();
+        (report.title.clone(), top)
+    };
+    let mut crashes: Vec<Crash> = Vec::new();
+    let mut reports = sanitizer::reports(output).into_iter();
+    let mut last = None;
+    for line in output.lines() {
+        if sanitizer::title(line).is_some() {
+            let Some(report) = reports.next() else {
+                continue;
+            };
+            let key = signature(&report);
+            last = match crashes
+                .iter()
+                .position(|crash| signature(&crash.report) == key)
+            {
+                Some(i) => Some(i),
+                None => {
+                    crashes.push(Crash {
+                        report,
+                        inputs: Vec::new(),
+                    });
+                    Some(crashes.len() - 1)
+                }
+            };
+        }
+        // Each report is followed by the input libFuzzer saved.
+        if let (Some((_, input)), Some(i)) = (line.split_once("Test unit written to "), last) {
+            crashes[i].inputs.push(input.trim().to_string());
+        }
+    }
+    crashes
+}
+
+/// Runs the fuzz target `binary`, showing libFuzzer's output as it goes,
+/// and returns that output.
+fn execute(mut cmd: Command) -> Result<String, String> {
+    let mut child = cmd
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to run the fuzz target: {}", e))?;
+    let mut output = String::new();
+    if let Some(stderr) = child.stderr.take() {
+        for line in BufReader::new(stderr).lines() {
+            let line = line.map_err(|e| e.to_string())?;
+            eprintln!("{}", line);
+            output.push_str(&line);
+            output.push('\n');
+        }
+    }
+    child.wait().map_err(|e| e.to_string())?;
+    Ok(output)
+}
+
+/// Builds the fuzz target `name` with `flags` and fuzzes it, growing its
+/// corpus under `target/fuzz/corpus/<name>` from the seeds of
+/// `fuzz/corpus/<name>`. `args` go to libFuzzer. Fails on crashes, each
+/// reported once with the inputs triggering it.
+pub fn run(
+    bazel_bin: &Path,
+    name: &str,
+    flags: &[String],
+    max_total_time: Option<u64>,
+    args: &[String],
+) -> Result<(), String> {
+    let names = list(Path::new("."))?;
+    if !names.iter().any(|known| known == name) {
+        return Err(match names.is_empty() {
+            true => "no fuzz target, `buddy fuzz init` creates one".to_string(),
+            false => format!(
+                "no fuzz target `{}`, expected one of: {}",
+                name,
+                names.join(", ")
+            ),
+        });
+    }
+
+    let label = format!("//{}:{}", FUZZ_DIR, name);
+    let mut cmd = bazel::command(bazel_bin, "build");
+    cmd.args(flags).arg(&label);
+    if !bazel::stream(&mut cmd)
+        .map_err(|e| e.to_string())?
+        .success()
+    {
+        return Err(format!("failed to build {}", label));
+    }
+
+    let corpus = Path::new(CORPUS_DIR).join(name);
+    let artifacts = Path::new(ARTIFACTS_DIR).join(name);
+    for dir in [&corpus, &artifacts] {
+        fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    }
+    let binary = bazel::bin_path(&label).unwrap_or_else(|| PathBuf::from(&label));
+    let mut fuzz = Command::new(&binary);
+    fuzz.arg(format!("-artifact_prefix={}/", artifacts.display()));
+    if let Some(seconds) = max_total_time {
+        fuzz.arg(format!("-max_total_time={}", seconds));
+    }
+    fuzz.args(args);
+    // Inputs given to reproduce a crash are run on their own. Otherwise new
+    // inputs go to the first corpus directory, the seeds stay as they are.
+    if !args.iter().any(|arg| !arg.starts_with('-')) {
+        fuzz.arg(&corpus);
+        let seeds = Path::new(FUZZ_DIR).join("corpus").join(name);
+        if seeds.is_dir() {
+            fuzz.arg(&seeds);
+        }
+    }
+    style::status("Fuzzing", &label);
+    let output = execute(fuzz)?;
+
+    let crashes = crashes(&output);
+    for crash in &crashes {
+        sanitizer::print(&label, &crash.report);
+        for input in &crash.inputs {
+            println!(
+                "  {} buddy fuzz run {} -- {}",
+                style::dim("reproduce with"),
+                name,
+                input
+            );
+        }
+    }
+    match crashes.len() {
+        0 => Ok(()),
+        count => Err(format!(
+            "{} crashed {} distinct ways, the inputs are under {}",
+            label,
+            count,
+            artifacts.display()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crashes() {
+        let output = r#"INFO: Seed: 1234
+==7==ERROR: AddressSanitizer: heap-buffer-overflow on address 0x6020 at pc 0x55
+READ of size 1 at 0x6020 thread T0
+    #0 0x55 in demo::parse(char const*) src/parse.cc:8:12
+    #1 0x56 in LLVMFuzzerTestOneInput fuzz/parse.cc:6:3
+    #2 0x57 in fuzzer::Fuzzer::ExecuteCallback(unsigned char const*, unsigned long) (target/bin/fuzz/parse+0x1)
+SUMMARY: AddressSanitizer: heap-buffer-overflow src/parse.cc:8:12 in demo::parse(char const*)
+artifact_prefix='target/fuzz/artifacts/parse/'; Test unit written to target/fuzz/artifacts/parse/crash-aa
+==8==ERROR: AddressSanitizer: heap-buffer-overflow on address 0x6030 at pc 0x58
+    #0 0x55 in demo::parse(char const*) src/parse.cc:8:12
+    #1 0x56 in LLVMFuzzerTestOneInput fuzz/parse.cc:6:3
+SUMMARY: AddressSanitizer: heap-buffer-overflow src/parse.cc:8:12 in demo::parse(char const*)
+artifact_prefix='target/fuzz/artifacts/parse/'; Test unit written to target/fuzz/artifacts/parse/crash-bb
+==9== ERROR: libFuzzer: deadly signal
+    #0 0x60 in __sanitizer_print_stack_trace
+    #1 0x61 in abort
+    #2 0x62 in demo::check() src/check.cc:3:3
+SUMMARY: libFuzzer: deadly signal
+artifact_prefix='target/fuzz/artifacts/parse/'; Test unit written to target/fuzz/artifacts/parse/crash-cc
+"#;
+        let crashes = crashes(output);
+        assert_eq!(crashes.len(), 2);
+        assert_eq!(
+            crashes[0].inputs,
+            [
+                "target/fuzz/artifacts/parse/crash-aa",
+                "target/fuzz/artifacts/parse/crash-bb"
+            ]
+        );
+        assert_eq!(
+            frames(&crashes[0].report),
+            ["demo::parse(char const*)", "LLVMFuzzerTestOneInput"]
+        );
+        assert_eq!(crashes[1].report.title, "libFuzzer: deadly signal");
+        assert_eq!(frames(&crashes[1].report), ["abort", "demo::check()"]);
+    }
+}
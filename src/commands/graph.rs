@@ -0,0 +1,300 @@
+use clap::ValueEnum;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use which::which;
+
+use crate::bazel;
+use crate::config::{self, Config};
+use crate::style;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Graphviz source
+    Dot,
+    /// An image rendered by Graphviz's `dot`
+    Svg,
+    /// Nodes and edges, for other tools
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Kind {
+    Package,
+    Dependency,
+    /// A dependency enabled by a feature.
+    OptionalDependency,
+    Target,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Node {
+    pub id: String,
+    pub kind: Kind,
+    pub version: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+impl Graph {
+    fn add_node(&mut self, id: &str, kind: Kind, version: Option<&str>) {
+        if !self.nodes.iter().any(|node| node.id == id) {
+            self.nodes.push(Node {
+                id: id.to_string(),
+                kind,
+                version: version.map(String::from),
+            });
+        }
+    }
+
+    fn add_edge(&mut self, from: &str, to: &str) {
+        if !self.edges.iter().any(|e| e.from == from && e.to == to) {
+            self.edges.push(Edge {
+                from: from.to_string(),
+                to: to.to_string(),
+            });
+        }
+    }
+}
+
+/// The package and the dependencies it resolved to.
+pub fn dependencies(config: &Config, dependencies: &HashMap<String, String>) -> Graph {
+    let mut graph = Graph::default();
+    let package = &config.package.name;
+    graph.add_node(package, Kind::Package, Some(&config.package.version));
+
+    let optional = config.optional_versions();
+    let mut names: Vec<_> = dependencies.keys().collect();
+    names.sort();
+    for name in names {
+        let kind = if optional.contains_key(name) {
+            Kind::OptionalDependency
+        } else {
+            Kind::Dependency
+        };
+        graph.add_node(name, kind, Some(&dependencies[name]));
+        graph.add_edge(package, name);
+    }
+    graph
+}
+
+/// Reads the edges of `bazel query --output=graph`, unfactored.
+fn query_edges(output: &str) -> Vec<(String, String)> {
+    output
+        .lines()
+        .filter_map(|line| line.trim().split_once(" -> "))
+        .map(|(from, to)| {
+            let unquote = |label: &str| label.trim().trim_matches('"').to_string();
+            (unquote(from), unquote(to))
+        })
+        .collect()
+}
+
+/// Adds the rules of the workspace and what they depend on: other rules of
+/// the workspace, or the dependencies of the graph through their Bazel
+/// repository. Edges to anything else, e.g. toolchains, are left out.
+fn add_targets(graph: &mut Graph, edges: &[(String, String)]) {
+    let repositories: HashMap<String, String> = graph
+        .nodes
+        .iter()
+        .filter(|node| matches!(node.kind, Kind::Dependency | Kind::OptionalDependency))
+        .map(|node| (config::repository_name(&node.id), node.id.clone()))
+        .collect();
+    let dependency = |label: &str| {
+        let repository = label.trim_start_matches('@').split("//").next()?;
+        repositories.get(repository).cloned()
+    };
+
+    for (from, to) in edges {
+        if !from.starts_with("//") {
+            continue;
+        }
+        let to = if to.starts_with("//") {
+            graph.add_node(to, Kind::Target, None);
+            to.clone()
+        } else if let Some(name) = dependency(to) {
+            name
+        } else {
+            continue;
+        };
+        graph.add_node(from, Kind::Target, None);
+        graph.add_edge(from, &to);
+    }
+}
+
+/// Queries the rules of the workspace and their direct dependencies.
+fn query(bazel_bin: &Path) -> Result<Vec<(String, String)>, String> {
+    let output = bazel::command(bazel_bin, "query")
+        .args([
+            "--noimplicit_deps",
+            "--output=graph",
+            "--graph:factored=false",
+        ])
+        .arg("kind(rule, deps(//..., 1))")
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| format!("failed to run bazel: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("bazel query exited with {}", output.status));
+    }
+    Ok(query_edges(&String::from_utf8_lossy(&output.stdout)))
+}
+
+fn quote(id: &str) -> String {
+    format!("\"{}\"", id.replace('"', "\\\""))
+}
+
+pub fn render_dot(graph: &Graph) -> String {
+    let mut out = String::from("digraph dependencies {\n  rankdir=LR;\n  node [shape=box];\n");
+    for node in &graph.nodes {
+        let label = match &node.version {
+            Some(version) => format!("{} {}", node.id, version),
+            None => node.id.clone(),
+        };
+        let style = match node.kind {
+            Kind::Package => ", style=bold",
+            Kind::Dependency => "",
+            Kind::OptionalDependency => ", style=dashed",
+            Kind::Target => ", shape=ellipse",
+        };
+        out.push_str(&format!(
+            "  {} [label={}{}];\n",
+            quote(&node.id),
+            quote(&label),
+            style
+        ));
+    }
+    for edge in &graph.edges {
+        out.push_str(&format!(
+            "  {} -> {};\n",
+            quote(&edge.from),
+            quote(&edge.to)
+        ));
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn render_svg(dot: &str) -> Result<String, String> {
+    let bin = which("dot").map_err(|_| {
+        "`dot` not found, it is needed for SVG output. Install Graphviz (e.g. `apt install graphviz`, `brew install graphviz`) or use --format dot"
+    })?;
+    let mut child = Command::new(bin)
+        .arg("-Tsvg")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to run dot: {}", e))?;
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(dot.as_bytes())
+        .map_err(|e| e.to_string())?;
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(format!("dot exited with {}", output.status));
+    }
+    String::from_utf8(output.stdout).map_err(|e| e.to_string())
+}
+
+/// Renders the dependency graph, with the targets of the workspace when
+/// `bazel_bin` is given, to `output` or stdout.
+pub fn run(
+    config: &Config,
+    resolved: &HashMap<String, String>,
+    bazel_bin: Option<&Path>,
+    format: Format,
+    output: Option<&Path>,
+) -> Result<(), String> {
+    let mut graph = dependencies(config, resolved);
+    if let Some(bazel_bin) = bazel_bin {
+        add_targets(&mut graph, &query(bazel_bin)?);
+    }
+
+    let rendered = match format {
+        Format::Dot => render_dot(&graph),
+        Format::Svg => render_svg(&render_dot(&graph))?,
+        Format::Json => serde_json::to_string_pretty(&graph).map_err(|e| e.to_string())? + "\n",
+    };
+    match output {
+        Some(path) => {
+            fs::write(path, rendered).map_err(|e| format!("{}: {}", path.display(), e))?;
+            style::status("Wrote", path.display());
+        }
+        None => print!("{}", rendered),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Dependency, DetailedDependency};
+
+    #[test]
+    fn test_render_graph() {
+        let mut config = Config::default();
+        config.package.name = "demo".to_string();
+        config.package.version = "0.1.0".to_string();
+        config.dependencies.insert(
+            "google-test".to_string(),
+            Dependency::Version("1.13.0".to_string()),
+        );
+        config.dependencies.insert(
+            "fmt".to_string(),
+            Dependency::Detailed(DetailedDependency {
+                version: "10.0.0".to_string(),
+                optional: true,
+            }),
+        );
+        let mut resolved = config.versions();
+        resolved.insert("fmt".to_string(), "10.0.0".to_string());
+
+        let mut graph = dependencies(&config, &resolved);
+        let edges = query_edges(
+            r#"digraph mygraph {
+  node [shape=box];
+  "//test:demo_test"
+  "//test:demo_test" -> "//src:demo"
+  "//test:demo_test" -> "@google_test//:gtest_main"
+  "//src:demo"
+  "//src:demo" -> "@bazel_tools//tools/cpp:malloc"
+}
+"#,
+        );
+        add_targets(&mut graph, &edges);
+
+        assert_eq!(
+            render_dot(&graph),
+            r#"digraph dependencies {
+  rankdir=LR;
+  node [shape=box];
+  "demo" [label="demo 0.1.0", style=bold];
+  "fmt" [label="fmt 10.0.0", style=dashed];
+  "google-test" [label="google-test 1.13.0"];
+  "//src:demo" [label="//src:demo", shape=ellipse];
+  "//test:demo_test" [label="//test:demo_test", shape=ellipse];
+  "demo" -> "fmt";
+  "demo" -> "google-test";
+  "//test:demo_test" -> "//src:demo";
+  "//test:demo_test" -> "google-test";
+}
+"#
+        );
+    }
+}
@@ -0,0 +1,288 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::{Config, HooksConfig};
+use crate::git;
+use crate::style;
+
+const MARKER: &str = "# This hook is automatically @generated by Buddy.";
+const BACKUP_SUFFIX: &str = ".buddy-backup";
+const HOOKS: [&str; 2] = ["pre-commit", "pre-push"];
+
+fn checks<'a>(config: &'a HooksConfig, hook: &str) -> &'a [String] {
+    match hook {
+        "pre-commit" => &config.pre_commit,
+        _ => &config.pre_push,
+    }
+}
+
+/// Git command listing the files a hook should look at, NUL separated: the
+/// staged ones before a commit, the ones not yet pushed upstream before a
+/// push.
+fn changed_files(hook: &str) -> &'static str {
+    match hook {
+        "pre-commit" => "git diff --cached -z --name-only --diff-filter=ACMR",
+        _ => "git diff -z --name-only --diff-filter=ACMR '@{upstream}...HEAD' 2>/dev/null || git ls-files -z",
+    }
+}
+
+/// Renders the shell script of `hook`. File based checks only run when
+/// relevant files changed, given to them through `xargs -0` whatever their
+/// names, `build` and `test` always run.
+pub fn script(hook: &str, checks: &[String]) -> Result<String, String> {
+    let mut file_checks = Vec::new();
+    let mut project_checks = Vec::new();
+    for check in checks {
+        match check.as_str() {
+            "fmt" => file_checks.push("buddy fmt --check --"),
+            "lint" => file_checks.push("buddy lint --"),
+            "build" => project_checks.push("buddy build"),
+            "test" => project_checks.push("buddy test"),
+            _ => {
+                return Err(format!(
+                    "unknown check `{}` in [hooks] {}, expected one of: fmt, lint, build, test",
+                    check, hook
+                ))
+            }
+        }
+    }
+
+    let mut out = format!(
+        "#!/bin/sh\n{}\n# Remove it with `buddy hooks uninstall`.\nset -e\n",
+        MARKER
+    );
+    if !file_checks.is_empty() {
+        out.push_str(&format!(
+            "\nchanged_files() {{\n    {}\n}}\n\n",
+            changed_files(hook)
+        ));
+        for check in file_checks {
+            out.push_str(&format!("changed_files | xargs -0 -r {}\n", check));
+        }
+    }
+    if !project_checks.is_empty() {
+        out.push('\n');
+        for check in project_checks {
+            out.push_str(&format!("{}\n", check));
+        }
+    }
+
+    Ok(out)
+}
+
+fn is_managed(path: &Path) -> bool {
+    fs::read_to_string(path)
+        .map(|contents| contents.contains(MARKER))
+        .unwrap_or(false)
+}
+
+/// Where a file buddy replaces is kept aside.
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap().to_os_string();
+    name.push(BACKUP_SUFFIX);
+    path.with_file_name(name)
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) -> Result<(), String> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755)).map_err(|e| e.to_string())
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &Path) -> Result<(), String> {
+    Ok(())
+}
+
+fn hooks_dir() -> Result<PathBuf, String> {
+    // Respects core.hooksPath and worktrees.
+    git::output(&["rev-parse", "--git-path", "hooks"])
+        .map(PathBuf::from)
+        .map_err(|_| "not inside a git repository".to_string())
+}
+
+/// Writes the configured hooks into `dir`. Foreign hooks are only replaced
+/// with `force`, in which case they are kept aside and restored on uninstall.
+pub fn install_into(dir: &Path, config: &HooksConfig, force: bool) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+
+    for hook in HOOKS {
+        let path = dir.join(hook);
+        let checks = checks(config, hook);
+
+        if checks.is_empty() {
+            if is_managed(&path) {
+                fs::remove_file(&path).map_err(|e| e.to_string())?;
+                style::status("Removed", format!("{} hook", hook));
+            }
+            continue;
+        }
+
+        let script = script(hook, checks)?;
+        if path.exists() && !is_managed(&path) {
+            if !force {
+                return Err(format!(
+                    "a {} hook already exists at `{}`, use --force to replace it (it will be backed up)",
+                    hook,
+                    path.display()
+                ));
+            }
+            fs::rename(&path, backup_path(&path)).map_err(|e| e.to_string())?;
+        }
+
+        fs::write(&path, script).map_err(|e| e.to_string())?;
+        make_executable(&path)?;
+        style::status(
+            "Installed",
+            format!("{} hook ({})", hook, checks.join(", ")),
+        );
+    }
+
+    Ok(())
+}
+
+/// Removes buddy's hooks from `dir`, restoring whatever they replaced.
+pub fn uninstall_from(dir: &Path) -> Result<(), String> {
+    for hook in HOOKS {
+        let path = dir.join(hook);
+        if !is_managed(&path) {
+            continue;
+        }
+
+        fs::remove_file(&path).map_err(|e| e.to_string())?;
+        let backup = backup_path(&path);
+        if backup.exists() {
+            fs::rename(&backup, &path).map_err(|e| e.to_string())?;
+            style::status("Restored", format!("previous {} hook", hook));
+        } else {
+            style::status("Removed", format!("{} hook", hook));
+        }
+    }
+
+    Ok(())
+}
+
+pub fn install(config: &Config, force: bool) -> Result<(), String> {
+    install_into(&hooks_dir()?, &config.hooks, force)
+}
+
+pub fn uninstall() -> Result<(), String> {
+    uninstall_from(&hooks_dir()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_script() {
+        let checks = vec!["fmt".to_string(), "lint".to_string(), "test".to_string()];
+        let hook = script("pre-commit", &checks).unwrap();
+        assert!(hook.starts_with("#!/bin/sh\n"));
+        assert!(hook.contains(
+            "changed_files() {\n    git diff --cached -z --name-only --diff-filter=ACMR\n}\n"
+        ));
+        assert!(hook.contains(
+            "changed_files | xargs -0 -r buddy fmt --check --\nchanged_files | xargs -0 -r buddy lint --\n"
+        ));
+        assert!(hook.ends_with("\nbuddy test\n"));
+
+        assert!(script("pre-commit", &["deploy".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_install_and_uninstall_restores_existing_hook() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let dir = tmp_dir.path();
+        fs::write(dir.join("pre-commit"), "#!/bin/sh\necho mine\n").unwrap();
+
+        let config = HooksConfig::default();
+        assert!(install_into(dir, &config, false).is_err());
+
+        install_into(dir, &config, true).unwrap();
+        assert!(is_managed(&dir.join("pre-commit")));
+        assert!(!dir.join("pre-push").exists());
+
+        uninstall_from(dir).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("pre-commit")).unwrap(),
+            "#!/bin/sh\necho mine\n"
+        );
+        assert!(!backup_path(&dir.join("pre-commit")).exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_pre_push_blocks_on_failure() {
+        use std::process::Command;
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let dir = tmp_dir.path();
+        // A buddy whose build fails, logging the commands it runs.
+        let buddy = dir.join("buddy");
+        fs::write(
+            &buddy,
+            "#!/bin/sh\necho \"$1\" >> \"$(dirname \"$0\")/log\"\n[ \"$1\" != build ]\n",
+        )
+        .unwrap();
+        make_executable(&buddy).unwrap();
+        let hook = dir.join("pre-push");
+        fs::write(
+            &hook,
+            script("pre-push", &["build".to_string(), "test".to_string()]).unwrap(),
+        )
+        .unwrap();
+        make_executable(&hook).unwrap();
+
+        let path = format!(
+            "{}:{}",
+            dir.display(),
+            std::env::var("PATH").unwrap_or_default()
+        );
+        let status = Command::new(&hook).env("PATH", path).status().unwrap();
+        assert!(!status.success());
+        assert_eq!(fs::read_to_string(dir.join("log")).unwrap(), "build\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_pre_commit_passes_file_names_whole() {
+        use std::process::Command;
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let dir = tmp_dir.path();
+        // A buddy logging its arguments, one per line.
+        let bin = dir.join("bin");
+        fs::create_dir(&bin).unwrap();
+        fs::write(
+            bin.join("buddy"),
+            "#!/bin/sh\nprintf '%s\\n' \"$@\" >> \"$(dirname \"$0\")/log\"\n",
+        )
+        .unwrap();
+        make_executable(&bin.join("buddy")).unwrap();
+        let repo = dir.join("repo");
+        fs::create_dir(&repo).unwrap();
+        git::output_in(&repo, &["init", "-q"]).unwrap();
+        fs::write(repo.join("my file*.cc"), "").unwrap();
+        git::output_in(&repo, &["add", "-A"]).unwrap();
+        let hook = dir.join("pre-commit");
+        fs::write(&hook, script("pre-commit", &["fmt".to_string()]).unwrap()).unwrap();
+        make_executable(&hook).unwrap();
+
+        let path = format!(
+            "{}:{}",
+            bin.display(),
+            std::env::var("PATH").unwrap_or_default()
+        );
+        let status = Command::new(&hook)
+            .current_dir(&repo)
+            .env("PATH", path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        assert_eq!(
+            fs::read_to_string(bin.join("log")).unwrap(),
+            "fmt\n--check\n--\nmy file*.cc\n"
+        );
+    }
+}
@@ -0,0 +1,274 @@
+use clap::ValueEnum;
+use serde_json::{json, Value};
+use std::fs;
+use std::path::Path;
+
+use crate::bazel;
+use crate::commands::run;
+use crate::compdb::{self, COMPDB};
+use crate::config::{Config, Language};
+use crate::style;
+
+/// The editors `buddy ide --editor` sets up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Editor {
+    /// Visual Studio Code: tasks, debug configurations and extensions
+    Vscode,
+}
+
+/// The extensions recommended to VS Code users: clangd for the code,
+/// cpptools for the debugger and the Bazel files' support.
+const VSCODE_EXTENSIONS: [&str; 3] = [
+    "llvm-vs-code-extensions.vscode-clangd",
+    "ms-vscode.cpptools",
+    "BazelBuild.vscode-bazel",
+];
+
+/// The clangd configuration, at the root of the project.
+pub const CLANGD: &str = ".clangd";
+
+/// First line of the `.clangd` buddy writes, which it may rewrite.
+const CLANGD_HEADER: &str =
+    "# Written by buddy from Buddy.toml, remove this line to keep your edits.";
+
+/// The `.clangd` of the project at `root`: the compile commands of
+/// `buddy ide`, and for the files they lack, such as headers, the standard
+/// and the include directories of the project.
+pub fn clangd(config: &Config, root: &Path) -> String {
+    let mut includes: Vec<&str> = ["include", "src"]
+        .into_iter()
+        .filter(|dir| root.join(dir).is_dir())
+        .collect();
+    includes.extend(config.build.includes.iter().map(String::as_str));
+
+    let mut out = format!(
+        "{}\nCompileFlags:\n  CompilationDatabase: .\n",
+        CLANGD_HEADER
+    );
+    if !includes.is_empty() {
+        out.push_str("  Add:\n");
+        for dir in includes {
+            out.push_str(&format!("    - -I{}\n", dir.trim_end_matches('/')));
+        }
+    }
+    let package = &config.package;
+    let (pattern, standard) = match package.language {
+        Language::Cxx => (
+            r".*\.(cc|cpp|cxx|h|hh|hpp|hxx)",
+            Some(
+                package
+                    .cxx_standard
+                    .as_deref()
+                    .unwrap_or(bazel::DEFAULT_CXX_STANDARD),
+            ),
+        ),
+        Language::C => (r".*\.(c|h)", package.c_standard.as_deref()),
+    };
+    if let Some(standard) = standard {
+        out.push_str(&format!(
+            "---\nIf:\n  PathMatch: {}\nCompileFlags:\n  Add: [-std={}]\n",
+            pattern, standard
+        ));
+    }
+    out
+}
+
+/// Writes the `.clangd` of the project at `root`, unless the user wrote
+/// their own.
+pub fn write_clangd(config: &Config, root: &Path) -> Result<(), String> {
+    let path = root.join(CLANGD);
+    let current = fs::read_to_string(&path).ok();
+    if current
+        .as_ref()
+        .is_some_and(|current| !current.starts_with(CLANGD_HEADER))
+    {
+        style::warning(format!("{} was edited, leaving it as is", CLANGD));
+        return Ok(());
+    }
+    let contents = clangd(config, root);
+    if current.as_deref() != Some(contents.as_str()) {
+        fs::write(&path, contents).map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+/// A task of `.vscode/tasks.json` running `buddy` with `args`, its
+/// diagnostics pointing into the project.
+fn task(label: &str, args: &[&str], group: Option<&str>) -> Value {
+    let mut task = json!({
+        "label": label,
+        "type": "process",
+        "command": "buddy",
+        "args": args,
+        "problemMatcher": {
+            "base": "$gcc",
+            "fileLocation": ["relative", "${workspaceFolder}"],
+        },
+    });
+    if let Some(group) = group {
+        task["group"] = json!({ "kind": group, "isDefault": true });
+    }
+    task
+}
+
+/// The files of `.vscode/` for the project at `root`, by name: tasks
+/// building, testing and running its binaries, launch configurations
+/// debugging them, and the recommended extensions.
+fn vscode(config: &Config, root: &Path) -> Vec<(&'static str, Value)> {
+    let binaries = run::binaries(root, config);
+    let mut tasks = vec![
+        task("buddy: build", &["build"], Some("build")),
+        task("buddy: test", &["test"], Some("test")),
+    ];
+    tasks.extend(binaries.iter().map(|binary| {
+        task(
+            &format!("buddy: run {}", binary.name),
+            &["run", "--bin", &binary.name],
+            None,
+        )
+    }));
+
+    let debugger = if cfg!(target_os = "macos") {
+        "lldb"
+    } else {
+        "gdb"
+    };
+    let configurations: Vec<Value> = binaries
+        .iter()
+        .filter_map(|binary| {
+            let program = bazel::bin_path(&binary.label)?;
+            Some(json!({
+                "name": format!("Debug {}", binary.name),
+                "type": "cppdbg",
+                "request": "launch",
+                "program": format!("${{workspaceFolder}}/{}", program.display()),
+                "args": [],
+                "cwd": "${workspaceFolder}",
+                "MIMode": debugger,
+                "preLaunchTask": "buddy: build",
+                // Bazel records `/proc/self/cwd` as the compilation directory.
+                "sourceFileMap": { "/proc/self/cwd": "${workspaceFolder}" },
+            }))
+        })
+        .collect();
+
+    vec![
+        ("tasks.json", json!({ "version": "2.0.0", "tasks": tasks })),
+        (
+            "launch.json",
+            json!({ "version": "0.2.0", "configurations": configurations }),
+        ),
+        (
+            "extensions.json",
+            json!({ "recommendations": VSCODE_EXTENSIONS }),
+        ),
+    ]
+}
+
+/// Writes the files of `editor` for the project at `root`. Existing ones
+/// are the user's unless `force` is set.
+fn write_editor(editor: Editor, config: &Config, root: &Path, force: bool) -> Result<(), String> {
+    let (dir, files) = match editor {
+        Editor::Vscode => (root.join(".vscode"), vscode(config, root)),
+    };
+    fs::create_dir_all(&dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    for (name, contents) in files {
+        let path = dir.join(name);
+        if path.exists() && !force {
+            style::warning(format!(
+                "`{}` already exists, skipping (use --force to overwrite)",
+                path.display()
+            ));
+            continue;
+        }
+        let contents = serde_json::to_string_pretty(&contents).map_err(|e| e.to_string())? + "\n";
+        fs::write(&path, contents).map_err(|e| format!("{}: {}", path.display(), e))?;
+        style::status("Wrote", path.display());
+    }
+    Ok(())
+}
+
+/// Writes `compile_commands.json` at the root of the project for the
+/// editors, compiled with the build `flags`, and the `.clangd` reading it,
+/// then the files of `editor`, if any. `buddy build` keeps the compile
+/// commands up to date from then on.
+pub fn run(
+    bazel_bin: &Path,
+    flags: &[String],
+    config: &Config,
+    editor: Option<Editor>,
+    force: bool,
+) -> Result<(), String> {
+    let count = compdb::generate(bazel_bin, flags)?;
+    style::status("Wrote", format!("{} ({} files)", COMPDB, count));
+    write_clangd(config, Path::new("."))?;
+    if let Some(editor) = editor {
+        write_editor(editor, config, Path::new("."), force)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clangd() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path();
+        fs::create_dir_all(root.join("src")).unwrap();
+        let config: Config = toml::from_str(
+            r#"
+[package]
+name = "demo"
+version = "0.1.0"
+edition = "2023"
+cxx-standard = "c++20"
+
+[dependencies]
+
+[build]
+includes = ["third_party/"]
+"#,
+        )
+        .unwrap();
+
+        write_clangd(&config, root).unwrap();
+        let contents = fs::read_to_string(root.join(CLANGD)).unwrap();
+        assert_eq!(
+            contents,
+            format!(
+                "{}\nCompileFlags:\n  CompilationDatabase: .\n  Add:\n    - -Isrc\n    - -Ithird_party\n---\nIf:\n  PathMatch: .*\\.(cc|cpp|cxx|h|hh|hpp|hxx)\nCompileFlags:\n  Add: [-std=c++20]\n",
+                CLANGD_HEADER
+            )
+        );
+
+        // Edited by hand, it is the user's.
+        fs::write(root.join(CLANGD), "CompileFlags:\n  Add: [-Wall]\n").unwrap();
+        write_clangd(&config, root).unwrap();
+        assert_eq!(
+            fs::read_to_string(root.join(CLANGD)).unwrap(),
+            "CompileFlags:\n  Add: [-Wall]\n"
+        );
+
+        fs::write(root.join("src/main.cc"), "int main() {}").unwrap();
+        write_editor(Editor::Vscode, &config, root, false).unwrap();
+        let read = |name: &str| -> Value {
+            serde_json::from_str(&fs::read_to_string(root.join(".vscode").join(name)).unwrap())
+                .unwrap()
+        };
+        let tasks = read("tasks.json");
+        assert_eq!(tasks["tasks"][0]["args"], json!(["build"]));
+        assert_eq!(tasks["tasks"][2]["label"], "buddy: run demo");
+        let launch = read("launch.json");
+        assert_eq!(
+            launch["configurations"][0]["program"],
+            "${workspaceFolder}/target/bin/src/demo"
+        );
+        assert_eq!(launch["configurations"][0]["preLaunchTask"], "buddy: build");
+        assert_eq!(
+            read("extensions.json")["recommendations"][0],
+            "llvm-vs-code-extensions.vscode-clangd"
+        );
+    }
+}
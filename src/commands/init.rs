@@ -1,123 +1,278 @@
-use colored::*;
+use clap::ValueEnum;
 use std::fs;
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::Path;
 use std::path::PathBuf;
 
-fn folder_name_from_path(path: &str) -> String {
-    let (_, package_name) = path.rsplit_once('/').unwrap();
-    package_name.to_string()
-}
-
-fn get_base_config(package_name: &str) -> String {
-    format!(
-        r#"[package]
-name = "{}"
-version = "0.1.0"
-edition = "2023"
-
-[dependencies]
-bazel-toolchain = "0.8.0"
-google-test = "1.13.0""#,
-        package_name,
-    )
+use crate::bazel;
+use crate::commands::hooks::backup_path;
+use crate::commands::upgrade::print_diff;
+use crate::config::{self, Config, Language, LibType, Package, TestConfig};
+use crate::features;
+use crate::git;
+use crate::global::GlobalConfig;
+use crate::lockfile::{Lockfile, LOCKFILE};
+use crate::plugins::Plugin;
+use crate::sanitizer;
+use crate::style;
+use crate::targets::{self, ExternalDeps, Kind};
+use crate::template::{self, Vars};
+use crate::workspace;
+
+/// The `.bazelrc` lines `package` builds with. The C standard comes from
+/// `Buddy.toml` instead, see `[package] c-standard`.
+pub fn bazelrc(package: &Package) -> Vec<String> {
+    let toolchain = "build --incompatible_enable_cc_toolchain_resolution".to_string();
+    let mut lines = match package.language {
+        Language::Cxx => {
+            let standard = package
+                .cxx_standard
+                .as_deref()
+                .unwrap_or(bazel::DEFAULT_CXX_STANDARD);
+            vec![format!("{}{}", CXX_STANDARD_FLAG, standard), toolchain]
+        }
+        Language::C => vec![toolchain],
+    };
+    // `bazel --config=asan` and the others, as `--sanitize` builds.
+    lines.extend(sanitizer::bazelrc());
+    lines
 }
 
-fn get_main() -> String {
-    r#"#include <ctime>
-#include <string>
-#include <iostream>
-
-std::string get_greet(const std::string& who) {
-  return "Hello " + who;
+/// The `.bazelrc` line setting the C++ standard, without the standard.
+const CXX_STANDARD_FLAG: &str = "build --cxxopt=-std=";
+
+/// What a new package builds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PackageKind {
+    /// A program, `src/main.cc`.
+    #[default]
+    Bin,
+    /// A library with public headers under `include/<name>/`.
+    Lib,
 }
 
-void print_localtime() {
-  std::time_t result = std::time(nullptr);
-  std::cout << std::asctime(std::localtime(&result));
+/// The version control system a new package is put under.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Vcs {
+    /// A git repository with an initial commit.
+    Git,
+    /// No repository.
+    None,
 }
 
-int main(int argc, char** argv) {
-  std::string who = "world";
-  if (argc > 1) {
-    who = argv[1];
-  }
-  std::cout << get_greet(who) << std::endl;
-  print_localtime();
-  return 0;
-}"#
-    .to_string()
-}
+/// Writes `contents` to `path` unless the file already exists, in which case
+/// it is only replaced when `force` is set.
+fn write_file(path: &Path, contents: &str, force: bool) -> Result<(), String> {
+    if path.exists() && !force {
+        style::warning(format!(
+            "`{}` already exists, skipping (use --force to overwrite)",
+            path.display()
+        ));
+        return Ok(());
+    }
 
-fn get_test() -> String {
-    r#"#include <gtest/gtest.h>
-
-// Demonstrate some basic assertions.
-TEST(HelloTest, BasicAssertions) {
-  // Expect two strings not to be equal.
-  EXPECT_STRNE("hello", "world");
-  // Expect equality.
-  EXPECT_EQ(7 * 6, 42);
-}"#
-    .to_string()
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let mut file = File::create(path).map_err(|e| e.to_string())?;
+    file.write_all(contents.as_bytes())
+        .map_err(|e| e.to_string())
 }
 
-pub fn run(path: &str) -> Result<(), String> {
-    if Path::new("Buddy.toml").exists() {
-        Err("`buddy init` cannot be run on existing Buddy packages".to_string())
-    } else {
-        let folder_path = PathBuf::from(path);
-        let path = fs::canonicalize(&folder_path).unwrap();
-
-        if !folder_path.is_dir() {
-            fs::create_dir_all(&path).unwrap();
-        }
+pub fn run(path: &str, name: Option<&str>, force: bool) -> Result<(), String> {
+    let folder_path = PathBuf::from(path);
 
-        let package_name = folder_name_from_path(path.to_str().unwrap());
-
-        let mut file = File::create(folder_path.join("Buddy.toml")).unwrap();
-        file.write_all(get_base_config(&package_name).as_bytes())
-            .unwrap();
+    if folder_path.join("Buddy.toml").exists() {
+        return Err(
+            "`buddy init` cannot be run on existing Buddy packages, use --force to regenerate their files"
+                .to_string(),
+        );
+    }
 
-        if !folder_path.join("WORKSPACE").exists() {
-            File::create(folder_path.join("WORKSPACE")).unwrap();
+    if !folder_path.is_dir() {
+        // An invalid name leaves no directory behind.
+        config::package_name(&folder_path, name)?;
+        fs::create_dir_all(&folder_path).map_err(|e| e.to_string())?;
+    }
+    let path = fs::canonicalize(&folder_path).map_err(|e| e.to_string())?;
+
+    let package_name = config::package_name(&path, name)?;
+
+    let mut vars = Vars::new(&package_name, Language::Cxx, PackageKind::Bin);
+    vars.author = git::author();
+    write_file(
+        &folder_path.join("Buddy.toml"),
+        &template::manifest(&vars),
+        force,
+    )?;
+
+    if !folder_path.join("WORKSPACE").exists() {
+        // Filled in from the manifest by the first build.
+        fs::write(folder_path.join("WORKSPACE"), targets::GENERATED_HEADER)
+            .map_err(|e| e.to_string())?;
+    }
 
-            if !folder_path.join("src").is_dir() {
-                fs::create_dir_all(folder_path.join("src")).unwrap();
+    let existing = targets::collect_sources(&folder_path).map_err(|e| e.to_string())?;
+    if existing.is_empty() {
+        // The BUILD files are derived from the sources below.
+        for (file, contents) in template::sources(&vars) {
+            if !file.ends_with("BUILD") {
+                write_file(&folder_path.join(file), &contents, force)?;
             }
+        }
+    }
 
-            if !folder_path.join("src").join("main.cc").is_file() {
-                let mut file = File::create(folder_path.join("src").join("main.cc")).unwrap();
+    let build_files = targets::scan(&folder_path, &package_name, &TestConfig::default())
+        .map_err(|e| e.to_string())?;
+    for build_file in &build_files {
+        write_file(
+            &folder_path.join(&build_file.dir).join("BUILD"),
+            &build_file.render(),
+            force,
+        )?;
+    }
 
-                file.write_all(get_main().as_bytes()).unwrap();
-            }
+    let kind = if build_files
+        .iter()
+        .flat_map(|b| &b.targets)
+        .any(|t| t.kind == Kind::Binary)
+    {
+        "binary (application)"
+    } else {
+        "library"
+    };
+
+    style::status(
+        "Created",
+        format!("{} `{}` package", kind, path.to_str().unwrap()),
+    );
+    Ok(())
+}
 
-            if !folder_path.join("test").is_dir() {
-                fs::create_dir_all(folder_path.join("test")).unwrap();
+/// `.bazelrc` with the flags of [`bazelrc`] it lacks, keeping the user's
+/// but for another C++ standard than the manifest's.
+fn repair_bazelrc(current: &str, package: &Package) -> String {
+    let expected = bazelrc(package);
+    let mut out: String = current
+        .split_inclusive('\n')
+        .filter(|l| {
+            let l = l.trim();
+            !l.starts_with(CXX_STANDARD_FLAG) || expected.iter().any(|line| line == l)
+        })
+        .collect();
+    for line in expected {
+        if !out.lines().any(|l| l.trim() == line) {
+            if !out.is_empty() && !out.ends_with('\n') {
+                out.push('\n');
             }
+            out.push_str(&line);
+            out.push('\n');
+        }
+    }
+    out
+}
 
-            if !folder_path.join("test").join("test_main.cc").is_file() {
-                let mut file = File::create(folder_path.join("test").join("test_main.cc")).unwrap();
+/// The files buddy derives from `Buddy.toml`, relative to the package root,
+/// and what they should contain.
+fn managed_files(
+    root: &Path,
+    config: &Config,
+    plugins: &[Plugin],
+    global: &GlobalConfig,
+) -> Result<Vec<(PathBuf, String)>, String> {
+    let enabled = features::resolve(&config.features, &[], true)?;
+    let dependencies = features::dependencies(config, &enabled)?;
+    let bazelrc = fs::read_to_string(root.join(".bazelrc")).unwrap_or_default();
+
+    let mut files = vec![
+        (
+            PathBuf::from("WORKSPACE"),
+            workspace::render(
+                &dependencies,
+                &config.sources(),
+                &Lockfile::load(&root.join(LOCKFILE))?,
+                plugins,
+                global,
+                workspace::vendor_dir(root).as_deref(),
+            )?,
+        ),
+        (
+            PathBuf::from(".bazelrc"),
+            repair_bazelrc(&bazelrc, &config.package),
+        ),
+    ];
+    if config.lib.types.contains(&LibType::Shared) {
+        files.push((
+            targets::export_header(config),
+            targets::render_export_header(&config.package.name),
+        ));
+    }
+    let external = ExternalDeps::resolve(config, &enabled, plugins, global)?;
+    for build_file in targets::scan_library(root, config, &external).map_err(|e| e.to_string())? {
+        files.push((build_file.dir.join("BUILD"), build_file.render()));
+    }
+    Ok(files)
+}
 
-                file.write_all(get_test().as_bytes()).unwrap();
+/// Regenerates the files of the package at `root` which drifted from its
+/// `Buddy.toml` or went missing. Files it replaces are shown as a diff and
+/// kept aside with the `.buddy-backup` suffix.
+pub fn repair(root: &Path, plugins: &[Plugin], global: &GlobalConfig) -> Result<(), String> {
+    let manifest = fs::read_to_string(root.join("Buddy.toml")).map_err(|e| e.to_string())?;
+    let config: Config =
+        toml::from_str(&manifest).map_err(|e| format!("failed to parse `Buddy.toml`: {}", e))?;
+
+    let mut repaired = 0;
+    for (file, contents) in managed_files(root, &config, plugins, global)? {
+        let path = root.join(&file);
+        let name = file.to_string_lossy().replace('\\', "/");
+        match fs::read_to_string(&path) {
+            Ok(current) if current == contents => continue,
+            Ok(current) => {
+                print_diff(&name, &current, &contents);
+                let backup = backup_path(&path);
+                fs::copy(&path, &backup).map_err(|e| format!("{}: {}", backup.display(), e))?;
+                style::status(
+                    "Replaced",
+                    format!("{}, backed up to {}", name, backup.display()),
+                );
+            }
+            Err(_) => {
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+                }
+                style::status("Created", name);
             }
         }
+        fs::write(&path, contents).map_err(|e| format!("{}: {}", path.display(), e))?;
+        repaired += 1;
+    }
 
-        println!(
-            "    {} binary (application) `{}` package",
-            "Created".green(),
-            path.to_str().unwrap()
-        );
-        Ok(())
+    if repaired == 0 {
+        style::status("Up to date", "the generated files match Buddy.toml");
+    } else {
+        style::status("Repaired", format!("{} file(s)", repaired));
     }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::template::License;
     use std::fs;
 
+    #[test]
+    fn test_run_with_invalid_name() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("7up");
+
+        // The name is refused before the directory is created
+        assert!(run(path.to_str().unwrap(), None, false).is_err());
+        assert!(!path.exists());
+    }
+
     #[test]
     fn test_run_on_empty_project() {
         let tmp_dir = tempfile::tempdir().unwrap();
@@ -127,7 +282,7 @@ mod tests {
         fs::create_dir_all(&path).unwrap();
 
         // Call the function and check that it returns Ok
-        assert!(run(path.to_str().unwrap()).is_ok());
+        assert!(run(path.to_str().unwrap(), None, false).is_ok());
 
         // Make sure the project has been created
         let buddy_file = path.join("Buddy.toml");
@@ -141,16 +296,26 @@ mod tests {
             .expect("failed to read file");
 
         // Assert that the file contents are equal to "geronimo"
+        let authors = git::author()
+            .map(|author| format!("authors = [\"{}\"]\n", author))
+            .unwrap_or_default();
         assert_eq!(
             file_contents,
-            r#"[package]
+            format!(
+                r#"[package]
 name = "test_project"
 version = "0.1.0"
 edition = "2023"
+buddy-version = "{}"
+{}cxx-standard = "c++17"
 
 [dependencies]
-bazel-toolchain = "0.8.0"
-google-test = "1.13.0""#
+bazel-toolchain = "0.8.2"
+google-test = "1.13.0"
+"#,
+                env!("CARGO_PKG_VERSION"),
+                authors
+            )
         );
 
         assert!(path.join("WORKSPACE").is_file());
@@ -165,7 +330,7 @@ google-test = "1.13.0""#
         let path = tmp_dir.path().join("non-existing");
 
         // Call the function and check that it returns Ok
-        assert!(run(path.to_str().unwrap()).is_ok());
+        assert!(run(path.to_str().unwrap(), None, false).is_ok());
 
         // Make sure the project has been created
         assert!(fs::metadata(path.join("Buddy.toml").to_str().unwrap()).is_ok());
@@ -178,9 +343,154 @@ google-test = "1.13.0""#
         let path = tmp_dir.path().join("bazel-project");
 
         // Call the function and check that it returns Ok
-        assert!(run(path.to_str().unwrap()).is_ok());
+        assert!(run(path.to_str().unwrap(), None, false).is_ok());
 
         // Make sure the project has been created
         assert!(fs::metadata(path.join("Buddy.toml").to_str().unwrap()).is_ok());
     }
+
+    #[test]
+    fn test_run_on_existing_sources() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+
+        let path = tmp_dir.path().join("legacy");
+        fs::create_dir_all(path.join("src")).unwrap();
+        fs::write(path.join("src").join("tool.cc"), "int main() {}").unwrap();
+        fs::write(path.join("src").join("BUILD"), "# hand written").unwrap();
+
+        assert!(run(path.to_str().unwrap(), None, false).is_ok());
+
+        // No hello-world template is dropped next to the existing code
+        assert!(!path.join("src").join("main.cc").exists());
+
+        // Existing files are left alone without --force
+        assert_eq!(
+            fs::read_to_string(path.join("src").join("BUILD")).unwrap(),
+            "# hand written"
+        );
+
+        fs::remove_file(path.join("Buddy.toml")).unwrap();
+        assert!(run(path.to_str().unwrap(), None, true).is_ok());
+
+        let build = fs::read_to_string(path.join("src").join("BUILD")).unwrap();
+        assert!(build.contains("cc_binary("));
+        assert!(build.contains("name = \"tool\""));
+    }
+
+    #[test]
+    fn test_run_with_explicit_name() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+
+        let path = tmp_dir.path().join("2024");
+
+        // The directory name is not a valid package name
+        assert!(run(path.to_str().unwrap(), None, false).is_err());
+
+        assert!(run(path.to_str().unwrap(), Some("experiment"), false).is_ok());
+        let config = fs::read_to_string(path.join("Buddy.toml")).unwrap();
+        assert!(config.contains("name = \"experiment\""));
+    }
+
+    #[test]
+    fn test_repair() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("drifted");
+        run(path.to_str().unwrap(), None, false).unwrap();
+        let plugins = crate::plugins::builtin();
+        let global = GlobalConfig::default();
+
+        assert!(run(path.to_str().unwrap(), None, true).is_err());
+        fs::write(path.join(".bazelrc"), "build --config=asan").unwrap();
+        fs::write(path.join("test").join("BUILD"), "# stale").unwrap();
+        repair(&path, &plugins, &global).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(path.join(".bazelrc")).unwrap(),
+            format!(
+                "build --config=asan\n{}\n",
+                bazelrc(&Package::default()).join("\n")
+            )
+        );
+
+        // The standard of the manifest replaces the one of `.bazelrc`.
+        let package = Package {
+            cxx_standard: Some("c++20".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            repair_bazelrc("build --cxxopt=-std=c++17\nbuild --config=asan\n", &package),
+            format!("build --config=asan\n{}\n", bazelrc(&package).join("\n"))
+        );
+        let workspace = fs::read_to_string(path.join("WORKSPACE")).unwrap();
+        assert!(workspace.contains("googletest"));
+        assert!(fs::read_to_string(path.join("test").join("BUILD"))
+            .unwrap()
+            .contains("cc_test("));
+        assert_eq!(
+            fs::read_to_string(path.join("test").join("BUILD.buddy-backup")).unwrap(),
+            "# stale"
+        );
+    }
+
+    #[test]
+    fn test_library_package() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path();
+        let mut vars = Vars::new("my-lib", Language::Cxx, PackageKind::Lib);
+        vars.cxx_standard = "c++20".to_string();
+        let config: Config = toml::from_str(&template::manifest(&vars)).unwrap();
+        assert_eq!(config.lib.public_headers, ["include/**/*.h"]);
+        assert_eq!(
+            bazel::cxx_standard_flags(config.package.cxx_standard.as_deref()).unwrap(),
+            ["--cxxopt=-std=c++20"]
+        );
+        assert_eq!(bazelrc(&config.package)[0], "build --cxxopt=-std=c++20");
+        assert!(bazel::cxx_standard_flags(Some("c17")).is_err());
+        for (file, contents) in template::sources(&vars) {
+            fs::create_dir_all(root.join(&file).parent().unwrap()).unwrap();
+            fs::write(root.join(file), contents).unwrap();
+        }
+        assert!(fs::read_to_string(root.join("src/my-lib.cc"))
+            .unwrap()
+            .starts_with("#include \"my-lib/my-lib.h\""));
+
+        let build_files = targets::scan_library(root, &config, &ExternalDeps::default()).unwrap();
+        let library = targets::package_library(&build_files).unwrap();
+        assert_eq!(library.deps, ["//include/my-lib:my-lib"]);
+        let test = build_files
+            .iter()
+            .flat_map(|b| &b.targets)
+            .find(|t| t.kind == Kind::Test)
+            .unwrap();
+        assert_eq!(test.name, "my_lib_test");
+        assert!(test.deps.contains(&"//src:my-lib".to_string()));
+    }
+
+    #[test]
+    fn test_c_package() {
+        let mut vars = Vars::new("clib", Language::C, PackageKind::Bin);
+        vars.author = Some("Ada <ada@example.com>".to_string());
+        vars.license = Some(License::Mit);
+        vars.holder = "Ada".to_string();
+        assert!(template::license_text(License::Mit, &vars)
+            .contains(&format!("Copyright (c) {} Ada\n", vars.year)));
+        let manifest = template::manifest(&vars);
+        assert!(manifest.contains("\n\n[dependencies]\nbazel-toolchain = \"0.8.2\"\n\n[test]\n"));
+        let config: Config = toml::from_str(&manifest).unwrap();
+        assert_eq!(config.package.authors, ["Ada <ada@example.com>"]);
+        assert_eq!(config.package.license.as_deref(), Some("MIT"));
+        assert_eq!(config.package.language, Language::C);
+        assert_eq!(
+            crate::bazel::c_standard_flags(config.package.c_standard.as_deref()).unwrap(),
+            ["--conlyopt=-std=c17"]
+        );
+        assert!(config.test.deps.is_empty());
+        assert!(!config.dependencies.contains_key("google-test"));
+        let sources = template::sources(&vars);
+        assert_eq!(sources[1].0, Path::new("src/main.c"));
+        assert_eq!(sources[2].0, Path::new("test/hello_test.c"));
+        assert!(sources[0].1.contains("srcs = [\"main.c\"]"));
+        assert!(targets::glob_match(&config.test.pattern, "hello_test.c"));
+        assert!(crate::bazel::c_standard_flags(Some("c++17")).is_err());
+    }
 }
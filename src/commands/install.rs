@@ -0,0 +1,210 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::bazel;
+use crate::commands::run::Binary;
+use crate::config::Package;
+use crate::global;
+use crate::style;
+
+/// Lists the binaries installed under a root, in it.
+const MANIFEST: &str = "installed.json";
+
+/// What an installed binary comes from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Installed {
+    pub package: String,
+    pub version: String,
+}
+
+/// Where binaries are installed without `--root`: `~/.buddy`, the binaries
+/// going to its `bin/`.
+pub fn default_root() -> PathBuf {
+    global::buddy_home()
+}
+
+fn load(root: &Path) -> Result<BTreeMap<String, Installed>, String> {
+    let path = root.join(MANIFEST);
+    match fs::read_to_string(&path) {
+        Ok(contents) => serde_json::from_str(&contents)
+            .map_err(|e| format!("corrupted {}: {}", path.display(), e)),
+        Err(_) => Ok(BTreeMap::new()),
+    }
+}
+
+fn save(root: &Path, installed: &BTreeMap<String, Installed>) -> Result<(), String> {
+    let contents = serde_json::to_string_pretty(installed).map_err(|e| e.to_string())?;
+    fs::write(root.join(MANIFEST), contents).map_err(|e| e.to_string())
+}
+
+/// Copies the binary at `source` to `bin/<name>` under `root`, executable,
+/// and records it. A binary of another package, or one buddy didn't
+/// install, is only replaced with `force`.
+fn place(
+    source: &Path,
+    root: &Path,
+    name: &str,
+    package: &Package,
+    force: bool,
+) -> Result<(), String> {
+    let mut installed = load(root)?;
+    let bin = root.join("bin");
+    let dest = bin.join(name);
+    let owner = installed.get(name).map(|entry| entry.package.as_str());
+    if !force && dest.exists() && owner != Some(package.name.as_str()) {
+        let owner = match owner {
+            Some(owner) => format!("package `{}`", owner),
+            None => "another program".to_string(),
+        };
+        return Err(format!(
+            "{} was installed by {}, pass --force to replace it",
+            dest.display(),
+            owner
+        ));
+    }
+
+    fs::create_dir_all(&bin).map_err(|e| e.to_string())?;
+    // Bazel's outputs are read-only, replace rather than overwrite.
+    let _ = fs::remove_file(&dest);
+    fs::copy(source, &dest).map_err(|e| format!("{}: {}", dest.display(), e))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&dest, fs::Permissions::from_mode(0o755))
+            .map_err(|e| format!("{}: {}", dest.display(), e))?;
+    }
+
+    installed.insert(
+        name.to_string(),
+        Installed {
+            package: package.name.clone(),
+            version: package.version.clone(),
+        },
+    );
+    save(root, &installed)?;
+    style::status(
+        "Installed",
+        format!("{} v{} to {}", name, package.version, dest.display()),
+    );
+    Ok(())
+}
+
+/// Builds the `binaries` of `package` with the build `flags`, the release
+/// profile's, and installs them under `root`.
+pub fn install(
+    bazel_bin: &Path,
+    binaries: &[Binary],
+    flags: &[String],
+    package: &Package,
+    root: &Path,
+    force: bool,
+) -> Result<(), String> {
+    let mut cmd = bazel::command(bazel_bin, "build");
+    cmd.args(flags)
+        .args(binaries.iter().map(|binary| &binary.label));
+    let status = bazel::stream(&mut cmd).map_err(|e| e.to_string())?;
+    if !status.success() {
+        return Err(format!("failed to build `{}`", package.name));
+    }
+
+    for binary in binaries {
+        let source = bazel::bin_path(&binary.label)
+            .ok_or_else(|| format!("unsupported binary label `{}`", binary.label))?;
+        // A library's package falls back on a binary it doesn't have.
+        if !source.is_file() {
+            return Err(format!(
+                "`{}` has no binary to install, {} is not one",
+                package.name, binary.label
+            ));
+        }
+        place(&source, root, &binary.name, package, force)?;
+    }
+
+    let bin = root.join("bin");
+    let on_path = std::env::var_os("PATH")
+        .is_some_and(|path| std::env::split_paths(&path).any(|dir| dir == bin));
+    if !on_path {
+        style::warning(format!(
+            "{} is not on your PATH, add it to run the installed binaries",
+            bin.display()
+        ));
+    }
+    Ok(())
+}
+
+/// Removes the binary `name` installed under `root`, or every binary of
+/// the package `name`.
+pub fn uninstall(root: &Path, name: &str) -> Result<(), String> {
+    let mut installed = load(root)?;
+    let names: Vec<String> = if installed.contains_key(name) {
+        vec![name.to_string()]
+    } else {
+        installed
+            .iter()
+            .filter(|(_, entry)| entry.package == name)
+            .map(|(binary, _)| binary.clone())
+            .collect()
+    };
+    if names.is_empty() {
+        return Err(format!(
+            "no binary or package named `{}` is installed in {}",
+            name,
+            root.display()
+        ));
+    }
+
+    for binary in names {
+        let path = root.join("bin").join(&binary);
+        if path.exists() {
+            fs::remove_file(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        }
+        installed.remove(&binary);
+        style::status("Removed", path.display());
+    }
+    save(root, &installed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_install_and_uninstall() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path().join("root");
+        let source = tmp_dir.path().join("app");
+        fs::write(&source, "#!/bin/sh\n").unwrap();
+        let package = |name: &str| Package {
+            name: name.to_string(),
+            version: "1.0.0".to_string(),
+            ..Default::default()
+        };
+
+        place(&source, &root, "app", &package("app"), false).unwrap();
+        place(&source, &root, "app-cli", &package("app"), false).unwrap();
+        // Reinstalling replaces the binary, another package needs --force.
+        place(&source, &root, "app", &package("app"), false).unwrap();
+        assert!(place(&source, &root, "app", &package("other"), false)
+            .unwrap_err()
+            .contains("installed by package `app`"));
+        assert!(root.join("bin/app").is_file());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(root.join("bin/app"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o755);
+        }
+
+        uninstall(&root, "app-cli").unwrap();
+        assert!(!root.join("bin/app-cli").exists());
+        uninstall(&root, "app").unwrap();
+        assert!(!root.join("bin/app").exists());
+        assert!(load(&root).unwrap().is_empty());
+        assert!(uninstall(&root, "app").is_err());
+    }
+}
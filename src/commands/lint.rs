@@ -0,0 +1,253 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+use std::thread;
+use which::which;
+
+use crate::compdb;
+use crate::config::LintsConfig;
+use crate::style::{self, Level};
+use crate::{git, targets};
+
+/// Flags used when no `compile_commands.json` is around to tell clang-tidy
+/// how the sources are compiled.
+const FALLBACK_FLAGS: [&str; 4] = ["-std=c++17", "-I.", "-Isrc", "-Iinclude"];
+
+/// A diagnostic of clang-tidy: its `file:line:column: severity: message`
+/// line, then the source excerpt and the notes following it.
+#[derive(Debug, PartialEq)]
+struct Diagnostic {
+    level: Level,
+    header: String,
+    details: Vec<String>,
+}
+
+/// The level of a `file:line:column: warning: message` line, `None` for
+/// notes and every other line.
+fn level(line: &str) -> Option<Level> {
+    let (location, message) = line.split_once(": ")?;
+    let mut parts = location.rsplitn(3, ':');
+    let numbers = parts.next()?.parse::<u32>().is_ok() && parts.next()?.parse::<u32>().is_ok();
+    if !numbers {
+        return None;
+    }
+    match message.split_once(": ")?.0 {
+        "warning" => Some(Level::Warning),
+        "error" | "fatal error" => Some(Level::Error),
+        _ => None,
+    }
+}
+
+/// The summaries clang-tidy prints after the diagnostics of a file.
+fn is_summary(line: &str) -> bool {
+    line.ends_with(" generated.")
+        || line.starts_with("Suppressed ")
+        || line.starts_with("Use -header-filter=")
+        || line.starts_with("Error while processing ")
+        || line.starts_with("Found compiler error")
+}
+
+/// The diagnostics of the `outputs` of clang-tidy, each once although a
+/// header included by several files is reported with each of them.
+fn diagnostics<'a>(outputs: impl IntoIterator<Item = &'a str>) -> Vec<Diagnostic> {
+    let mut seen = HashSet::new();
+    let mut diagnostics = Vec::new();
+    for output in outputs {
+        let mut current: Option<Diagnostic> = None;
+        for line in output.lines().filter(|line| !is_summary(line)) {
+            if let Some(level) = level(line) {
+                diagnostics.extend(current.take());
+                current = Some(Diagnostic {
+                    level,
+                    header: line.to_string(),
+                    details: Vec::new(),
+                });
+            } else if let Some(diagnostic) = current.as_mut() {
+                diagnostic.details.push(line.to_string());
+            }
+        }
+        diagnostics.extend(current);
+    }
+    diagnostics
+        .retain(|diagnostic| seen.insert((diagnostic.header.clone(), diagnostic.details.clone())));
+    diagnostics
+}
+
+fn print(diagnostic: &Diagnostic) {
+    let severity = match diagnostic.level {
+        Level::Error => "error",
+        _ => "warning",
+    };
+    let marker = format!(": {}: ", severity);
+    match diagnostic.header.split_once(&marker) {
+        Some((location, message)) => println!(
+            "{}: {}: {}",
+            location,
+            style::paint(severity, diagnostic.level),
+            message
+        ),
+        None => println!("{}", diagnostic.header),
+    }
+    for line in &diagnostic.details {
+        println!("{}", line);
+    }
+}
+
+/// Runs clang-tidy on `file`, returning whether it passed and what it
+/// printed.
+fn tidy(clang_tidy: &Path, file: &Path, args: &[String]) -> Result<(bool, String), String> {
+    let output = Command::new(clang_tidy)
+        .arg(file)
+        .args(args)
+        .output()
+        .map_err(|e| format!("failed to run clang-tidy: {}", e))?;
+    let mut printed = String::from_utf8_lossy(&output.stdout).into_owned();
+    printed.push_str(&String::from_utf8_lossy(&output.stderr));
+    Ok((output.status.success(), printed))
+}
+
+/// Runs on the given files (ignoring anything that isn't a C/C++ source), on
+/// the files changed since a git ref with `changed`, or on all the project
+/// sources when none are given, the checks of `[lints]` enabled. The files
+/// are checked in parallel, the diagnostics of the headers they share are
+/// reported once.
+pub fn run(
+    files: &[PathBuf],
+    changed: Option<Option<&str>>,
+    lints: &LintsConfig,
+) -> Result<(), String> {
+    let clang_tidy = which("clang-tidy").map_err(|_| {
+        "`clang-tidy` not found. Install it with your LLVM/clang packages (e.g. `apt install clang-tidy`)"
+            .to_string()
+    })?;
+
+    let compile_commands = Path::new(compdb::COMPDB).is_file();
+    let files = match changed {
+        Some(reference) => git::changed_files(reference)?,
+        None if files.is_empty() => {
+            targets::project_sources(Path::new(".")).map_err(|e| e.to_string())?
+        }
+        None => files.to_vec(),
+    };
+    let files: Vec<_> = files
+        .into_iter()
+        .filter(|f| targets::is_translation_unit(f))
+        // Without compile commands the include paths of external
+        // dependencies (gtest, ...) are unknown, so only our own code is
+        // checked.
+        .filter(|f| compile_commands || !f.starts_with("test") && !f.starts_with("tests"))
+        .collect();
+    if files.is_empty() {
+        if changed.is_some() {
+            style::status("Skipped", "no changed C/C++ files");
+        }
+        return Ok(());
+    }
+
+    let mut args = Vec::new();
+    if !lints.checks.is_empty() {
+        args.push(format!("--checks={}", lints.checks.join(",")));
+    }
+    if !lints.warnings_as_errors.is_empty() {
+        args.push(format!(
+            "--warnings-as-errors={}",
+            lints.warnings_as_errors.join(",")
+        ));
+    }
+    if compile_commands {
+        args.extend(["-p".to_string(), ".".to_string()]);
+    } else {
+        args.push("--".to_string());
+        args.extend(FALLBACK_FLAGS.map(String::from));
+    }
+
+    let jobs = thread::available_parallelism()
+        .map_or(1, |jobs| jobs.get())
+        .min(files.len());
+    let queue = Mutex::new(files.iter());
+    let results = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| loop {
+                let Some(file) = queue.lock().unwrap().next() else {
+                    break;
+                };
+                let result = tidy(&clang_tidy, file, &args);
+                results.lock().unwrap().push((file, result));
+            });
+        }
+    });
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(file, _)| *file);
+
+    let mut outputs = Vec::new();
+    let mut failed = 0;
+    for (_, result) in results {
+        let (passed, output) = result?;
+        if !passed {
+            failed += 1;
+        }
+        outputs.push(output);
+    }
+    let diagnostics = diagnostics(outputs.iter().map(String::as_str));
+    for diagnostic in &diagnostics {
+        print(diagnostic);
+    }
+
+    let errors = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.level == Level::Error)
+        .count();
+    style::status(
+        "Checked",
+        format!(
+            "{} files, {} warnings, {} errors",
+            files.len(),
+            diagnostics.len() - errors,
+            errors
+        ),
+    );
+    if failed > 0 {
+        return Err(format!("clang-tidy reported errors in {} file(s)", failed));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diagnostics() {
+        let first = "\
+2 warnings generated.
+/p/include/demo/demo.h:3:1: warning: use 'using' instead of 'typedef' [modernize-use-using]
+typedef int count;
+^~~~~~~~~~~~~~~~~
+/p/src/demo.cc:7:10: error: use of undeclared identifier 'x' [clang-diagnostic-error]
+  return x;
+         ^
+/p/src/demo.cc:2:1: note: declared here
+Suppressed 1 warnings (1 in non-user code).
+";
+        let second = "\
+/p/include/demo/demo.h:3:1: warning: use 'using' instead of 'typedef' [modernize-use-using]
+typedef int count;
+^~~~~~~~~~~~~~~~~
+";
+        let diagnostics = diagnostics([first, second]);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].level, Level::Warning);
+        assert_eq!(diagnostics[0].details.len(), 2);
+        assert_eq!(diagnostics[1].level, Level::Error);
+        assert_eq!(
+            diagnostics[1].details,
+            [
+                "  return x;",
+                "         ^",
+                "/p/src/demo.cc:2:1: note: declared here"
+            ]
+        );
+    }
+}
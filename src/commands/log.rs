@@ -0,0 +1,88 @@
+use colored::*;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::failure;
+use crate::style::{self, Level};
+
+/// How long ago `time`, in seconds since the Unix epoch, was.
+fn ago(time: u64, now: u64) -> String {
+    let seconds = now.saturating_sub(time);
+    match seconds {
+        0..=59 => "just now".to_string(),
+        60..=3599 => format!("{} minutes ago", seconds / 60),
+        3600..=86399 => format!("{} hours ago", seconds / 3600),
+        _ => format!("{} days ago", seconds / 86400),
+    }
+}
+
+/// Shows the report of the last failed build or test: the failed targets
+/// and the diagnostics, or everything bazel printed with `full`.
+pub fn last(full: bool) -> Result<(), String> {
+    let failure = failure::load()?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let exit = match failure.exit_code {
+        Some(code) => format!("exit code {}", code),
+        None => "a signal".to_string(),
+    };
+    println!(
+        "      {} `{}` with {}, {}",
+        style::paint("Failed", Level::Error),
+        failure.command.join(" "),
+        exit,
+        ago(failure.time, now)
+    );
+
+    if full {
+        for line in &failure.log {
+            println!("{}", line);
+        }
+        return Ok(());
+    }
+
+    for target in &failure.failed_targets {
+        match &target.test_log {
+            Some(log) => println!(
+                "      {} {}, log in {}",
+                style::paint("Target", Level::Error),
+                target.label,
+                log.display()
+            ),
+            None => println!(
+                "      {} {}",
+                style::paint("Target", Level::Error),
+                target.label
+            ),
+        }
+    }
+    for diagnostic in &failure.diagnostics {
+        let severity = match diagnostic.severity.as_str() {
+            "warning" => style::paint(&diagnostic.severity, Level::Warning),
+            _ => style::paint(&diagnostic.severity, Level::Error),
+        };
+        match &diagnostic.location {
+            Some(location) => println!("{}: {}: {}", severity, location.bold(), diagnostic.message),
+            None => println!("{}: {}", severity, diagnostic.message),
+        }
+    }
+    println!(
+        "see the {} lines of output with `buddy log last --full`",
+        failure.log.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ago() {
+        assert_eq!(ago(1000, 1010), "just now");
+        assert_eq!(ago(1000, 1000 + 5 * 60), "5 minutes ago");
+        assert_eq!(ago(1000, 1000 + 3 * 3600 + 10), "3 hours ago");
+        assert_eq!(ago(1000, 1000 + 2 * 86400), "2 days ago");
+    }
+}
@@ -0,0 +1,47 @@
+use std::io::{self, BufRead};
+
+use crate::credentials::{self, Storage};
+use crate::global::GlobalConfig;
+use crate::style;
+
+/// Saves the token of `registry`, a registry of `~/.buddy/config.toml` or a
+/// host such as the remote cache's. The token is read from stdin when not
+/// given, which keeps it out of the shell history.
+pub fn login(registry: &str, token: Option<&str>) -> Result<(), String> {
+    let config = GlobalConfig::load()?;
+
+    let token = match token {
+        Some(token) => token.to_string(),
+        None => {
+            println!("please paste the token for `{}` below", registry);
+            let mut line = String::new();
+            io::stdin()
+                .lock()
+                .read_line(&mut line)
+                .map_err(|e| e.to_string())?;
+            line.trim().to_string()
+        }
+    };
+    if token.is_empty() {
+        return Err("please provide a non-empty token".to_string());
+    }
+
+    let location = match credentials::store(registry, config.registries.get(registry), &token)? {
+        Storage::Provider => "the credential provider".to_string(),
+        Storage::Keychain => "the OS keychain".to_string(),
+        Storage::File(path) => format!("`{}`", path.display()),
+    };
+    style::status("Saved", format!("token for `{}` in {}", registry, location));
+    Ok(())
+}
+
+pub fn logout(registry: &str) -> Result<(), String> {
+    let config = GlobalConfig::load()?;
+
+    if credentials::erase(registry, config.registries.get(registry))? {
+        style::status("Removed", format!("token for `{}`", registry));
+    } else {
+        style::warning(format!("not logged in to `{}`", registry));
+    }
+    Ok(())
+}
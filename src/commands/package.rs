@@ -0,0 +1,300 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use which::which;
+
+use crate::config::Package;
+use crate::style;
+
+/// Where `buddy package` puts the archives.
+pub const PACKAGE_DIR: &str = "target/package";
+
+/// Directories of the project root that never go into an archive: the
+/// outputs and the vendored archives.
+const SKIPPED_DIRS: [&str; 2] = ["target", "vendor"];
+
+/// Files of the project root that go into a binary archive next to the
+/// artifacts.
+const BINARY_FILES: [&str; 3] = ["Buddy.toml", "LICENSE", "README.md"];
+
+/// Whether `name`, in the project root when `top` is set, is left out of a
+/// source archive: outputs, bazel's symlinks, hidden directories such as
+/// `.git` and the files buddy writes for the tools.
+fn is_skipped(name: &str, is_dir: bool, top: bool) -> bool {
+    if is_dir {
+        return name.starts_with('.') || top && SKIPPED_DIRS.contains(&name);
+    }
+    name.ends_with(".buddy-backup") || top && name == "compile_commands.json"
+}
+
+fn walk(root: &Path, dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), String> {
+    let mut entries = fs::read_dir(root.join(dir))
+        .and_then(|entries| entries.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("{}: {}", root.join(dir).display(), e))?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let Some(name) = entry.file_name().to_str().map(String::from) else {
+            continue;
+        };
+        let file_type = entry.file_type().map_err(|e| e.to_string())?;
+        // bazel-* and the other symlinks point out of the project.
+        if file_type.is_symlink()
+            || is_skipped(&name, file_type.is_dir(), dir.as_os_str().is_empty())
+        {
+            continue;
+        }
+        if file_type.is_dir() {
+            walk(root, &dir.join(&name), files)?;
+        } else {
+            files.push(dir.join(&name));
+        }
+    }
+    Ok(())
+}
+
+/// The files of the project at `root` that make its source archive, by
+/// their path under `root`, sorted: the manifest, the lockfile, the sources
+/// and the BUILD files, generated ones included.
+pub fn sources(root: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut files = Vec::new();
+    walk(root, Path::new(""), &mut files)?;
+    if !files.iter().any(|file| file == Path::new("Buddy.toml")) {
+        return Err("no Buddy.toml to package".to_string());
+    }
+    Ok(files)
+}
+
+/// `value` in octal, NUL terminated, filling `field`.
+fn octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}\0", value, width = field.len() - 1);
+    field.copy_from_slice(digits.as_bytes());
+}
+
+/// The ustar header of a file at `path` of `size` bytes. Owners and times
+/// are left out for the archive to only depend on the contents.
+fn header(path: &str, size: u64, executable: bool) -> Result<[u8; 512], String> {
+    let mut header = [0u8; 512];
+    // Paths over 100 bytes are split in a prefix and a name at a `/`.
+    let (prefix, name) = match path.len() {
+        0..=100 => ("", path),
+        _ => path
+            .char_indices()
+            .filter(|(i, c)| *c == '/' && *i <= 155 && path.len() - i - 1 <= 100)
+            .map(|(i, _)| (&path[..i], &path[i + 1..]))
+            .next()
+            .ok_or_else(|| format!("path too long to archive: {}", path))?,
+    };
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    octal(
+        &mut header[100..108],
+        if executable { 0o755 } else { 0o644 },
+    );
+    octal(&mut header[108..116], 0);
+    octal(&mut header[116..124], 0);
+    octal(&mut header[124..136], size);
+    octal(&mut header[136..148], 0);
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+
+    // The checksum is computed with its own field as spaces.
+    header[148..156].fill(b' ');
+    let checksum: u64 = header.iter().map(|b| u64::from(*b)).sum();
+    let digits = format!("{:06o}\0 ", checksum);
+    header[148..156].copy_from_slice(digits.as_bytes());
+    Ok(header)
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_metadata: &fs::Metadata) -> bool {
+    false
+}
+
+/// The tarball of `files`, archive paths and the files they are read from,
+/// in that order.
+fn tar(files: &[(String, PathBuf)]) -> Result<Vec<u8>, String> {
+    let mut archive = Vec::new();
+    for (path, source) in files {
+        let contents = fs::read(source).map_err(|e| format!("{}: {}", source.display(), e))?;
+        let metadata = fs::metadata(source).map_err(|e| e.to_string())?;
+        archive.extend(header(
+            path,
+            contents.len() as u64,
+            is_executable(&metadata),
+        )?);
+        archive.extend(&contents);
+        archive.resize(archive.len().next_multiple_of(512), 0);
+    }
+    archive.resize(archive.len() + 1024, 0);
+    Ok(archive)
+}
+
+/// Writes `files`, as `tar` takes them, to the gzipped tarball `output`,
+/// with no name or time in the gzip header either.
+fn write(files: &[(String, PathBuf)], output: &Path) -> Result<(), String> {
+    let archive = tar(files)?;
+    let gzip =
+        which("gzip").map_err(|_| "`gzip` not found, it is needed to compress the archive")?;
+    if let Some(dir) = output.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    }
+    let file = fs::File::create(output).map_err(|e| format!("{}: {}", output.display(), e))?;
+    let mut child = Command::new(gzip)
+        .args(["-n", "-9", "-c"])
+        .stdin(Stdio::piped())
+        .stdout(file)
+        .spawn()
+        .map_err(|e| format!("failed to run gzip: {}", e))?;
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(&archive)
+        .map_err(|e| format!("failed to run gzip: {}", e))?;
+    let status = child.wait().map_err(|e| e.to_string())?;
+    if !status.success() {
+        return Err(format!("failed to write {}", output.display()));
+    }
+    Ok(())
+}
+
+/// Lists `files` with `list`, or archives them to `output` under
+/// `name/`. Returns `output` either way.
+fn archive(
+    name: &str,
+    files: &[(String, PathBuf)],
+    output: PathBuf,
+    list: bool,
+) -> Result<PathBuf, String> {
+    if list {
+        for (path, _) in files {
+            println!("{}", path);
+        }
+        return Ok(output);
+    }
+    let files: Vec<_> = files
+        .iter()
+        .map(|(path, source)| (format!("{}/{}", name, path), source.clone()))
+        .collect();
+    write(&files, &output)?;
+    style::status(
+        "Packaged",
+        format!("{} files into {}", files.len(), output.display()),
+    );
+    Ok(output)
+}
+
+/// Archives the sources of `package`, the project in the current
+/// directory, into `target/package/<name>-<version>.tar.gz`, or lists them
+/// with `list`. Returns the path of the archive.
+pub fn source(package: &Package, list: bool) -> Result<PathBuf, String> {
+    let files: Vec<_> = sources(Path::new("."))?
+        .into_iter()
+        .map(|file| (file.to_string_lossy().replace('\\', "/"), file))
+        .collect();
+    if !files.iter().any(|(path, _)| path == "Buddy.lock") {
+        style::warning("no Buddy.lock to package, the dependencies aren't pinned");
+    }
+    let name = format!("{}-{}", package.name, package.version);
+    let output = Path::new(PACKAGE_DIR).join(format!("{}.tar.gz", name));
+    archive(&name, &files, output, list)
+}
+
+/// Archives the artifacts built into `artifacts`, with the manifest, the
+/// license and the readme, into
+/// `target/package/<name>-<version>-<os>-<arch>.tar.gz`, or lists them
+/// with `list`. Returns the path of the archive.
+pub fn binary(package: &Package, artifacts: &Path, list: bool) -> Result<PathBuf, String> {
+    let mut files: Vec<_> = BINARY_FILES
+        .iter()
+        .filter(|file| Path::new(file).is_file())
+        .map(|file| (file.to_string(), PathBuf::from(file)))
+        .collect();
+    let mut built = Vec::new();
+    walk(artifacts, Path::new(""), &mut built)?;
+    files.extend(built.into_iter().map(|file| {
+        (
+            file.to_string_lossy().replace('\\', "/"),
+            artifacts.join(file),
+        )
+    }));
+    let name = format!(
+        "{}-{}-{}-{}",
+        package.name,
+        package.version,
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    );
+    let output = Path::new(PACKAGE_DIR).join(format!("{}.tar.gz", name));
+    archive(&name, &files, output, list)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sources_archive() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path();
+        for file in [
+            "Buddy.toml",
+            "Buddy.lock",
+            "WORKSPACE",
+            "compile_commands.json",
+            "src/BUILD",
+            "src/demo.cc",
+            "src/demo.cc.buddy-backup",
+            "include/demo/demo.h",
+            "target/bin/demo",
+            ".git/HEAD",
+        ] {
+            fs::create_dir_all(root.join(file).parent().unwrap()).unwrap();
+            fs::write(root.join(file), file).unwrap();
+        }
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(root.join("target"), root.join("bazel-bin")).unwrap();
+
+        let files = sources(root).unwrap();
+        assert_eq!(
+            files,
+            [
+                "Buddy.lock",
+                "Buddy.toml",
+                "WORKSPACE",
+                "include/demo/demo.h",
+                "src/BUILD",
+                "src/demo.cc",
+            ]
+            .map(PathBuf::from)
+        );
+
+        let files: Vec<_> = files
+            .iter()
+            .map(|file| (format!("demo-1.0.0/{}", file.display()), root.join(file)))
+            .collect();
+        let archive = tar(&files).unwrap();
+        assert_eq!(archive, tar(&files).unwrap());
+        // A header, the contents padded to a block, and the end of archive.
+        assert_eq!(archive.len(), 6 * 1024 + 1024);
+        assert_eq!(&archive[..20], b"demo-1.0.0/Buddy.loc");
+        assert_eq!(&archive[257..263], b"ustar\0");
+
+        let long = format!("demo-1.0.0/{}/file.cc", "dir/".repeat(30));
+        let header = header(&long, 0, false).unwrap();
+        let field = |range: std::ops::Range<usize>| {
+            let field = &header[range];
+            let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+            String::from_utf8(field[..end].to_vec()).unwrap()
+        };
+        assert_eq!(format!("{}/{}", field(345..500), field(0..100)), long);
+    }
+}
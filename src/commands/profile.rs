@@ -0,0 +1,374 @@
+use colored::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use which::which;
+
+use crate::bazel;
+use crate::config::Config;
+use crate::flamegraph;
+use crate::heap;
+use crate::style::{self, Level};
+
+const PROFILE_DIR: &str = "target/profile";
+
+/// Number of allocation sites listed in the memory summary.
+const TOP_SITES: usize = 10;
+
+/// Flags giving the profiler usable stacks: optimized code that keeps frame
+/// pointers and debug info, and isn't stripped by Bazel.
+const PROFILE_FLAGS: [&str; 5] = [
+    "-c",
+    "opt",
+    "--strip=never",
+    "--copt=-g",
+    "--copt=-fno-omit-frame-pointer",
+];
+
+/// Turns what the user typed into a label: full labels are kept as they are,
+/// plain names refer to binaries in `src/` (or tests in `test/`).
+fn label(config: &Config, target: Option<&str>, test: Option<&str>) -> String {
+    match (target, test) {
+        (_, Some(test)) if test.starts_with("//") => test.to_string(),
+        (_, Some(test)) => format!("//test:{}", test),
+        (Some(target), None) if target.starts_with("//") => target.to_string(),
+        (Some(target), None) => format!("//src:{}", target),
+        (None, None) => format!("//src:{}", config.package.name),
+    }
+}
+
+fn find_tool(name: &str, install_hint: &str) -> Result<PathBuf, String> {
+    which(name).map_err(|_| format!("`{}` not found. {}", name, install_hint))
+}
+
+fn record_perf(
+    binary: &Path,
+    args: &[String],
+    out_dir: &Path,
+) -> Result<flamegraph::Folded, String> {
+    let perf = find_tool(
+        "perf",
+        "Install it from your distribution's linux-tools package (e.g. `apt install linux-tools-generic`)",
+    )?;
+    let data = out_dir.join("perf.data");
+
+    let status = Command::new(&perf)
+        .args(["record", "-F", "99", "-g", "-o"])
+        .arg(&data)
+        .arg("--")
+        .arg(binary)
+        .args(args)
+        .status()
+        .map_err(|e| format!("failed to run perf: {}", e))?;
+    if !status.success() {
+        style::warning(format!("profiled program exited with {}", status));
+    }
+
+    let output = Command::new(&perf)
+        .arg("script")
+        .arg("-i")
+        .arg(&data)
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|e| format!("failed to run perf script: {}", e))?;
+
+    Ok(flamegraph::fold_perf(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+/// `word` as a single word of the shell.
+fn shell_quote(word: &str) -> String {
+    format!("'{}'", word.replace('\'', r"'\''"))
+}
+
+fn record_dtrace(
+    binary: &Path,
+    args: &[String],
+    out_dir: &Path,
+) -> Result<flamegraph::Folded, String> {
+    let dtrace = find_tool(
+        "dtrace",
+        "DTrace ships with macOS and requires running as root",
+    )?;
+    let stacks = out_dir.join("dtrace.stacks");
+    // dtrace splits the command of `-c` on whitespace, quotes included, so
+    // it runs a script exec'ing the program with its arguments quoted.
+    let script = out_dir.join("dtrace-run.sh");
+    let command = std::iter::once(binary.to_string_lossy().into_owned())
+        .chain(args.iter().cloned())
+        .map(|word| shell_quote(&word))
+        .collect::<Vec<_>>()
+        .join(" ");
+    fs::write(&script, format!("#!/bin/sh\nexec {}\n", command))
+        .map_err(|e| format!("{}: {}", script.display(), e))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755))
+            .map_err(|e| e.to_string())?;
+    }
+
+    let status = Command::new(dtrace)
+        .args(["-x", "ustackframes=100", "-n"])
+        .arg("profile-997 /pid == $target/ { @[ustack()] = count(); }")
+        .arg("-o")
+        .arg(&stacks)
+        .arg("-c")
+        .arg(&script)
+        .status()
+        .map_err(|e| format!("failed to run dtrace: {}", e))?;
+    if !status.success() {
+        return Err(format!("dtrace exited with {}", status));
+    }
+
+    let output = fs::read_to_string(&stacks).map_err(|e| e.to_string())?;
+    Ok(flamegraph::fold_dtrace(&output))
+}
+
+fn record_heaptrack(
+    heaptrack: &Path,
+    binary: &Path,
+    args: &[String],
+    out_dir: &Path,
+) -> Result<(heap::Report, PathBuf), String> {
+    // heaptrack appends the compression extension itself, so clear out old
+    // recordings to know which file is ours.
+    for entry in fs::read_dir(out_dir).map_err(|e| e.to_string())?.flatten() {
+        if entry
+            .file_name()
+            .to_str()
+            .unwrap_or("")
+            .starts_with("heaptrack.")
+        {
+            fs::remove_file(entry.path()).map_err(|e| e.to_string())?;
+        }
+    }
+
+    let status = Command::new(heaptrack)
+        .arg("-o")
+        .arg(out_dir.join("heaptrack"))
+        .arg(binary)
+        .args(args)
+        .status()
+        .map_err(|e| format!("failed to run heaptrack: {}", e))?;
+    if !status.success() {
+        style::warning(format!("profiled program exited with {}", status));
+    }
+
+    let recording = fs::read_dir(out_dir)
+        .map_err(|e| e.to_string())?
+        .flatten()
+        .map(|e| e.path())
+        .find(|p| {
+            p.file_name()
+                .unwrap()
+                .to_str()
+                .unwrap_or("")
+                .starts_with("heaptrack.")
+        })
+        .ok_or("heaptrack did not produce a recording")?;
+
+    let output = Command::new(find_tool("heaptrack_print", "It is part of heaptrack")?)
+        .arg(&recording)
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|e| format!("failed to run heaptrack_print: {}", e))?;
+
+    let root = std::env::current_dir().map_err(|e| e.to_string())?;
+    Ok((
+        heap::parse_heaptrack(&String::from_utf8_lossy(&output.stdout), &root),
+        recording,
+    ))
+}
+
+fn record_massif(
+    valgrind: &Path,
+    binary: &Path,
+    args: &[String],
+    out_dir: &Path,
+) -> Result<(heap::Report, PathBuf), String> {
+    let recording = out_dir.join("massif.out");
+
+    let status = Command::new(valgrind)
+        .args(["--tool=massif", "--time-unit=B"])
+        .arg(format!("--massif-out-file={}", recording.display()))
+        .arg(binary)
+        .args(args)
+        .status()
+        .map_err(|e| format!("failed to run valgrind: {}", e))?;
+    if !status.success() {
+        style::warning(format!("profiled program exited with {}", status));
+    }
+
+    let contents = fs::read_to_string(&recording).map_err(|e| e.to_string())?;
+    let root = std::env::current_dir().map_err(|e| e.to_string())?;
+    Ok((heap::parse_massif(&contents, &root), recording))
+}
+
+fn print_memory_report(report: &heap::Report) {
+    println!();
+    println!(
+        "{:>12} {}",
+        "Peak heap".bold(),
+        heap::format_bytes(report.peak_bytes)
+    );
+    match report.leaked_bytes {
+        Some(0) => println!(
+            "{:>12} {}",
+            "Leaked".bold(),
+            style::paint("none", Level::Info)
+        ),
+        Some(bytes) => println!(
+            "{:>12} {}",
+            "Leaked".bold(),
+            style::paint(&heap::format_bytes(bytes), Level::Error)
+        ),
+        None => println!(
+            "{:>12} {}",
+            "Leaked".bold(),
+            style::dim("n/a (massif does not track leaks, install heaptrack)")
+        ),
+    }
+
+    if !report.sites.is_empty() {
+        println!();
+        println!("{}", "Top allocation sites at peak".bold());
+        for site in report.sites.iter().take(TOP_SITES) {
+            println!(
+                "{:>12}  {}  {}",
+                heap::format_bytes(site.bytes),
+                site.function,
+                style::dim(site.location.as_deref().unwrap_or(""))
+            );
+        }
+    }
+}
+
+/// Launches `viewer` on `file` without waiting for it to be closed.
+pub fn open_viewer(viewer: &str, file: &Path) -> Result<(), String> {
+    let viewer = which(viewer).map_err(|_| format!("`{}` not found", viewer))?;
+    Command::new(viewer)
+        .arg(file)
+        .spawn()
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+fn profile_memory(
+    binary: &Path,
+    args: &[String],
+    out_dir: &Path,
+    open: bool,
+) -> Result<(), String> {
+    let (report, recording, viewer) = if let Ok(heaptrack) = which("heaptrack") {
+        let (report, recording) = record_heaptrack(&heaptrack, binary, args, out_dir)?;
+        (report, recording, "heaptrack_gui")
+    } else if let Ok(valgrind) = which("valgrind") {
+        let (report, recording) = record_massif(&valgrind, binary, args, out_dir)?;
+        (report, recording, "massif-visualizer")
+    } else {
+        return Err(
+            "neither `heaptrack` nor `valgrind` found. Install one of them (e.g. `apt install heaptrack`)"
+                .to_string(),
+        );
+    };
+
+    print_memory_report(&report);
+    println!();
+    style::status("Wrote", recording.display());
+
+    if open {
+        open_viewer(viewer, &recording)?;
+    }
+
+    Ok(())
+}
+
+pub fn run(
+    bazel_bin: &Path,
+    config: &Config,
+    target: Option<&str>,
+    test: Option<&str>,
+    args: &[String],
+    memory: bool,
+    open: bool,
+) -> Result<(), String> {
+    let label = label(config, target, test);
+    let binary = bazel::bin_path(&label).ok_or_else(|| format!("invalid target `{}`", label))?;
+
+    let mut cmd = bazel::command(bazel_bin, "build");
+    cmd.args(PROFILE_FLAGS).arg(&label);
+    let status = bazel::stream(&mut cmd).map_err(|e| e.to_string())?;
+    if !status.success() {
+        return Err(format!("failed to build `{}`", label));
+    }
+
+    let out_dir = Path::new(PROFILE_DIR);
+    fs::create_dir_all(out_dir).map_err(|e| e.to_string())?;
+
+    style::status("Profiling", &label);
+    if memory {
+        return profile_memory(&binary, args, out_dir, open);
+    }
+
+    let folded = if cfg!(target_os = "macos") {
+        record_dtrace(&binary, args, out_dir)?
+    } else {
+        record_perf(&binary, args, out_dir)?
+    };
+
+    if folded.is_empty() {
+        return Err(
+            "no samples were collected, the program may have exited too quickly".to_string(),
+        );
+    }
+
+    fs::write(out_dir.join("stacks.folded"), flamegraph::to_text(&folded))
+        .map_err(|e| e.to_string())?;
+    let svg = out_dir.join("flamegraph.svg");
+    fs::write(&svg, flamegraph::render_svg(&folded, &label)).map_err(|e| e.to_string())?;
+
+    style::status(
+        "Wrote",
+        format!(
+            "{} ({} samples)",
+            svg.display(),
+            folded.values().sum::<u64>()
+        ),
+    );
+
+    if open {
+        open_viewer(
+            if cfg!(target_os = "macos") {
+                "open"
+            } else {
+                "xdg-open"
+            },
+            &svg,
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shell_quote() {
+        let words = ["target/bin/src/my app", "it's", "$HOME;ls"];
+        let quoted: Vec<_> = words.iter().map(|word| shell_quote(word)).collect();
+        assert_eq!(quoted[1], r"'it'\''s'");
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(format!("printf '%s\\n' {}", quoted.join(" ")))
+            .output()
+            .unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "target/bin/src/my app\nit's\n$HOME;ls\n"
+        );
+    }
+}
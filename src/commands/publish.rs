@@ -0,0 +1,314 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use which::which;
+
+use crate::commands::dist;
+use crate::config::{Config, Package};
+use crate::credentials;
+use crate::global::{self, GlobalConfig, Registry};
+use crate::style;
+
+/// Lists what was published from this machine, in `~/.buddy`.
+const MANIFEST: &str = "published.json";
+
+/// A version published to a registry.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Published {
+    pub name: String,
+    pub version: String,
+    pub sha256: String,
+}
+
+/// Whether `version` reads `MAJOR.MINOR.PATCH`, with an optional
+/// `-pre-release` suffix.
+fn is_release_version(version: &str) -> bool {
+    let release = version
+        .split_once('-')
+        .map_or(version, |(release, _)| release);
+    let parts: Vec<_> = release.split('.').collect();
+    parts.len() == 3
+        && parts
+            .iter()
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Checks the manifest has what a registry needs to list the package,
+/// naming everything missing at once.
+pub fn validate(config: &Config) -> Result<(), String> {
+    if config.workspace.is_some() {
+        return Err("a workspace isn't published, publish its members from their directory".into());
+    }
+    let package = &config.package;
+    let mut problems = Vec::new();
+    if !is_release_version(&package.version) {
+        problems.push(format!(
+            "version `{}` is not of the form MAJOR.MINOR.PATCH",
+            package.version
+        ));
+    }
+    if package.description.is_none() {
+        problems.push("`description` is missing from [package]".to_string());
+    }
+    if package.license.is_none() {
+        problems.push("`license` is missing from [package]".to_string());
+    }
+    if package.authors.is_empty() {
+        problems.push("`authors` is missing from [package]".to_string());
+    }
+    if !Path::new("Buddy.lock").is_file() {
+        problems.push("Buddy.lock is missing, build the package first".to_string());
+    }
+    match problems.is_empty() {
+        true => Ok(()),
+        false => Err(format!(
+            "`{}` can't be published:\n  {}",
+            package.name,
+            problems.join("\n  ")
+        )),
+    }
+}
+
+/// The recipe of `package` in a directory registry, for the source
+/// archives published next to it.
+fn recipe(package: &Package) -> toml_edit::Table {
+    let name = &package.name;
+    let mut recipe = toml_edit::Table::new();
+    recipe["build-rule"] = toml_edit::value(format!(
+        r#"http_archive(
+  name = "{{repository}}",
+  urls = ["{{registry}}/{name}-{{version}}.tar.gz"],
+  sha256 = "{{sha}}",
+  strip_prefix = "{name}-{{version}}",
+)"#
+    ));
+    recipe["archive"] = toml_edit::value(format!("{{registry}}/{}-{{version}}.tar.gz", name));
+    recipe["target"] = toml_edit::value(format!("@{{repository}}//src:{}", name));
+    if let Some(description) = &package.description {
+        recipe["description"] = toml_edit::value(description);
+    }
+    if let Some(license) = &package.license {
+        recipe["license"] = toml_edit::value(license);
+    }
+    recipe["versions"] = toml_edit::Item::Table(toml_edit::Table::new());
+    recipe
+}
+
+/// Adds the `archive` of `package`, of checksum `sha256`, to the directory
+/// registry at `dir`: the archive goes next to the recipe, which gets the
+/// version.
+fn publish_directory(
+    dir: &Path,
+    scope: Option<&str>,
+    package: &Package,
+    archive: &Path,
+    sha256: &str,
+) -> Result<(), String> {
+    let dir = match scope {
+        Some(scope) => dir.join(scope),
+        None => dir.to_path_buf(),
+    };
+    let path = dir.join(format!("{}.toml", package.name));
+    let mut recipe = match fs::read_to_string(&path) {
+        Ok(contents) => contents
+            .parse::<toml_edit::Document>()
+            .map_err(|e| format!("failed to parse `{}`: {}", path.display(), e))?,
+        Err(_) => toml_edit::Document::from(recipe(package)),
+    };
+    let versions = recipe["versions"]
+        .or_insert(toml_edit::Item::Table(toml_edit::Table::new()))
+        .as_table_mut()
+        .ok_or_else(|| format!("`versions` of {} is not a table", path.display()))?;
+    if versions.contains_key(&package.version) {
+        return Err(format!(
+            "`{}` {} is already published, bump the version",
+            package.name, package.version
+        ));
+    }
+    versions[package.version.as_str()] = toml_edit::value(sha256);
+
+    fs::create_dir_all(&dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    let dest = dir.join(format!("{}-{}.tar.gz", package.name, package.version));
+    fs::copy(archive, &dest).map_err(|e| format!("{}: {}", dest.display(), e))?;
+    fs::write(&path, recipe.to_string()).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Uploads `archive` to the registry `name` served over HTTP, with its
+/// token.
+fn upload(
+    name: &str,
+    registry: &Registry,
+    package: &Package,
+    archive: &Path,
+    global: &GlobalConfig,
+) -> Result<(), String> {
+    let api = registry.api.as_deref().ok_or_else(|| {
+        format!(
+            "registry `{}` takes no uploads, set its `api` in ~/.buddy/config.toml",
+            name
+        )
+    })?;
+    let token = credentials::token(name, Some(registry))?.ok_or_else(|| {
+        format!(
+            "not logged in to `{}`, run `buddy login {}` first",
+            name, name
+        )
+    })?;
+    let curl = which("curl").map_err(|_| "`curl` not found, it is needed to publish")?;
+    let url = format!(
+        "{}/{}/{}",
+        api.trim_end_matches('/'),
+        package.name,
+        package.version
+    );
+    let headers = credentials::curl_headers(&[("Authorization", &format!("Bearer {}", token))])?;
+    let status = Command::new(curl)
+        .args(["-fsS", "-o", "/dev/null", "-X", "PUT", "-K"])
+        .arg(headers.path())
+        .args(["-H", "Content-Type: application/gzip", "--upload-file"])
+        .arg(archive)
+        .arg(&url)
+        .envs(global.http.env())
+        .status()
+        .map_err(|e| format!("failed to run curl: {}", e))?;
+    if !status.success() {
+        return Err(format!("{} refused the upload", url));
+    }
+    Ok(())
+}
+
+fn load(path: &Path) -> Result<BTreeMap<String, Vec<Published>>, String> {
+    match fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents)
+            .map_err(|e| format!("corrupted {}: {}", path.display(), e)),
+        Err(_) => Ok(BTreeMap::new()),
+    }
+}
+
+/// Records that `published` went to `registry` in the manifest at `path`.
+fn record(path: &Path, registry: &str, published: Published) -> Result<(), String> {
+    let mut manifest = load(path)?;
+    manifest
+        .entry(registry.to_string())
+        .or_default()
+        .push(published);
+    let contents = serde_json::to_string_pretty(&manifest).map_err(|e| e.to_string())?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    }
+    fs::write(path, contents).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Publishes `archive`, the source archive of `package`, to the registry
+/// `name`: a directory registry gets the archive and the version in the
+/// recipe, another one the upload. With `dry_run` nothing is sent.
+pub fn run(
+    global: &GlobalConfig,
+    name: &str,
+    package: &Package,
+    archive: &Path,
+    dry_run: bool,
+) -> Result<(), String> {
+    let registry = global
+        .registries
+        .get(name)
+        .ok_or_else(|| format!("unknown registry `{}`, see ~/.buddy/config.toml", name))?;
+    let manifest = global::buddy_home().join(MANIFEST);
+    let published = load(&manifest)?;
+    if published
+        .get(name)
+        .into_iter()
+        .flatten()
+        .any(|entry| entry.name == package.name && entry.version == package.version)
+    {
+        return Err(format!(
+            "`{}` {} was already published to `{}`, bump the version",
+            package.name, package.version, name
+        ));
+    }
+    let sha256 = dist::sha256(archive)?;
+    if dry_run {
+        style::status(
+            "Skipped",
+            format!("upload of {} to `{}` (dry run)", archive.display(), name),
+        );
+        return Ok(());
+    }
+
+    match &registry.path {
+        Some(dir) => publish_directory(dir, registry.scope.as_deref(), package, archive, &sha256)?,
+        None => upload(name, registry, package, archive, global)?,
+    }
+    record(
+        &manifest,
+        name,
+        Published {
+            name: package.name.clone(),
+            version: package.version.clone(),
+            sha256,
+        },
+    )?;
+    style::status(
+        "Published",
+        format!("{} v{} to `{}`", package.name, package.version, name),
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins;
+
+    #[test]
+    fn test_publish_directory() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let registry = tmp_dir.path().join("registry");
+        let archive = tmp_dir.path().join("demo-1.0.0.tar.gz");
+        fs::write(&archive, "archive").unwrap();
+        let mut package = Package {
+            name: "demo".to_string(),
+            version: "1.0.0".to_string(),
+            ..Default::default()
+        };
+
+        publish_directory(&registry, None, &package, &archive, "abc").unwrap();
+        assert!(
+            publish_directory(&registry, None, &package, &archive, "abc")
+                .unwrap_err()
+                .contains("already published")
+        );
+        package.version = "1.1.0".to_string();
+        publish_directory(&registry, None, &package, &archive, "def").unwrap();
+        assert!(registry.join("demo-1.1.0.tar.gz").is_file());
+
+        let recipes = plugins::load_directory("corp", &registry).unwrap();
+        assert_eq!(recipes.len(), 1);
+        assert_eq!(recipes[0].versions["1.0.0"], "abc");
+        assert_eq!(recipes[0].versions["1.1.0"], "def");
+        assert!(recipes[0]
+            .render("1.1.0")
+            .unwrap()
+            .contains("/demo-1.1.0.tar.gz\"],\n  sha256 = \"def\""));
+        assert_eq!(
+            recipes[0].target.as_deref(),
+            Some("@{repository}//src:demo")
+        );
+
+        let manifest = tmp_dir.path().join(MANIFEST);
+        let published = Published {
+            name: "demo".to_string(),
+            version: "1.0.0".to_string(),
+            sha256: "abc".to_string(),
+        };
+        record(&manifest, "corp", published.clone()).unwrap();
+        assert_eq!(load(&manifest).unwrap()["corp"], [published]);
+
+        assert!(is_release_version("1.2.3"));
+        assert!(is_release_version("1.2.3-rc.1"));
+        assert!(!is_release_version("1.2"));
+        assert!(!is_release_version("v1.2.3"));
+    }
+}
@@ -0,0 +1,259 @@
+use clap::ValueEnum;
+use colored::*;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::bazel;
+use crate::style;
+use crate::targets;
+
+/// The bazel query commands `buddy query` runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Kind {
+    /// The target graph, before configuration
+    Query,
+    /// The configured target graph, the one a build uses
+    Cquery,
+    /// The actions a build runs
+    Aquery,
+}
+
+impl Kind {
+    fn verb(&self) -> &'static str {
+        match self {
+            Kind::Query => "query",
+            Kind::Cquery => "cquery",
+            Kind::Aquery => "aquery",
+        }
+    }
+}
+
+/// Whether `c` ends a word of a query expression.
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || "()[],'\"+^=".contains(c)
+}
+
+/// `expression` with the words naming a package or a dependency, in
+/// `names`, replaced by their label: `deps(app)` becomes
+/// `deps(//src:app)`. Function names are left alone.
+pub fn expand(expression: &str, names: &BTreeMap<String, String>) -> String {
+    let mut expanded = String::new();
+    let mut rest = expression;
+    while !rest.is_empty() {
+        let end = rest.find(is_delimiter).unwrap_or(rest.len());
+        let (word, after) = rest.split_at(end);
+        let call = after.trim_start().starts_with('(');
+        match names.get(word) {
+            Some(label) if !call => expanded.push_str(label),
+            _ => expanded.push_str(word),
+        }
+        let delimiter = after.chars().next().map_or(0, char::len_utf8);
+        expanded.push_str(&after[..delimiter]);
+        rest = &after[delimiter..];
+    }
+    expanded
+}
+
+/// The kind and label of each line of the `label_kind` output, e.g.
+/// `cc_library rule //src:demo`, without the configuration cquery adds.
+fn label_kinds(output: &str) -> Vec<(String, String)> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let start = line.find(" //").or_else(|| line.find(" @"))?;
+            let kind = line[..start].trim_end_matches(" rule").to_string();
+            let label = line[start + 1..].split(' ').next()?.to_string();
+            Some((kind, label))
+        })
+        .collect()
+}
+
+/// Runs `kind` over `expression`, the names of `names` expanded. Results
+/// are listed with their kind, or printed as bazel writes them in the
+/// `output` format asked for and for the actions of aquery. The build
+/// `flags` apply to cquery and aquery, which configure the targets.
+pub fn run(
+    bazel_bin: &Path,
+    kind: Kind,
+    expression: &str,
+    names: &BTreeMap<String, String>,
+    flags: &[String],
+    output: Option<&str>,
+) -> Result<(), String> {
+    let mut cmd = bazel::command(bazel_bin, kind.verb());
+    if kind != Kind::Query {
+        cmd.args(flags);
+    }
+    cmd.arg(expand(expression, names));
+    let pretty = output.is_none() && kind != Kind::Aquery;
+    match output {
+        Some(format) => {
+            cmd.arg(format!("--output={}", format));
+        }
+        None if pretty => {
+            cmd.arg("--output=label_kind");
+        }
+        None => {}
+    }
+    let result = bazel::output(&mut cmd)?;
+    if !pretty {
+        print!("{}", result);
+        return Ok(());
+    }
+
+    let mut results = label_kinds(&result);
+    results.sort_by(|a, b| a.1.cmp(&b.1));
+    let width = results
+        .iter()
+        .map(|(kind, _)| kind.len())
+        .max()
+        .unwrap_or(0);
+    for (kind, label) in &results {
+        println!(
+            "{}  {}",
+            style::dim(&format!("{:<width$}", kind, width = width)),
+            label
+        );
+    }
+    Ok(())
+}
+
+/// The groups `buddy targets` lists, with how each is used.
+const GROUPS: [(&str, &str); 5] = [
+    ("Binaries", "buddy run --bin NAME"),
+    ("Examples", "buddy run --example NAME"),
+    ("Benchmarks", "buddy bench LABEL"),
+    ("Libraries", "buddy build LABEL"),
+    ("Tests", "buddy test LABEL"),
+];
+
+/// The index in `GROUPS` of a target of `kind` at `label`, `None` for the
+/// rules buddy doesn't expose.
+fn group(kind: &str, label: &str) -> Option<usize> {
+    let dir = label
+        .trim_start_matches("//")
+        .split(':')
+        .next()
+        .unwrap_or_default();
+    // Members of a workspace are further down, e.g. //app/examples.
+    let in_dir = |name: &str| {
+        Path::new(dir)
+            .ancestors()
+            .any(|ancestor| ancestor.file_name().is_some_and(|n| n == name))
+    };
+    match kind {
+        "cc_binary" if in_dir(targets::EXAMPLES_DIR) => Some(1),
+        "cc_binary" if in_dir(targets::BENCH_DIR) => Some(2),
+        "cc_binary" => Some(0),
+        "cc_library" | "cc_shared_library" => Some(3),
+        "cc_test" => Some(4),
+        _ => None,
+    }
+}
+
+/// The name and label of the targets of `results`, kinds and labels, in
+/// the groups of `GROUPS`.
+fn grouped(results: &[(String, String)]) -> Vec<Vec<(String, String)>> {
+    let mut groups = vec![Vec::new(); GROUPS.len()];
+    for (kind, label) in results {
+        if let Some(group) = group(kind, label) {
+            let name = label.rsplit(':').next().unwrap_or(label);
+            groups[group].push((name.to_string(), label.clone()));
+        }
+    }
+    for targets in &mut groups {
+        targets.sort();
+    }
+    groups
+}
+
+/// Lists the C/C++ targets of the workspace by what they are, with the
+/// names `buddy run` and the others take.
+pub fn list(bazel_bin: &Path) -> Result<(), String> {
+    let mut cmd = bazel::command(bazel_bin, "query");
+    cmd.arg("kind(\"cc_(binary|library|shared_library|test) rule\", //...)")
+        .arg("--output=label_kind");
+    let groups = grouped(&label_kinds(&bazel::output(&mut cmd)?));
+
+    let width = groups
+        .iter()
+        .flatten()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    let mut first = true;
+    for ((title, usage), targets) in GROUPS.iter().zip(&groups) {
+        if targets.is_empty() {
+            continue;
+        }
+        if !first {
+            println!();
+        }
+        first = false;
+        println!("{} {}", title.bold(), style::dim(&format!("({})", usage)));
+        for (name, label) in targets {
+            println!("  {:<width$}  {}", name, label, width = width);
+        }
+    }
+    if first {
+        style::status("Skipped", "no C/C++ targets in the workspace");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand() {
+        let names = BTreeMap::from([
+            ("app".to_string(), "//src:app".to_string()),
+            (
+                "google-test".to_string(),
+                "@com_google_googletest//:gtest".to_string(),
+            ),
+            ("deps".to_string(), "//deps/src:deps".to_string()),
+        ]);
+        assert_eq!(
+            expand("deps(app) intersect rdeps(//..., google-test, 1)", &names),
+            "deps(//src:app) intersect rdeps(//..., @com_google_googletest//:gtest, 1)"
+        );
+        assert_eq!(expand("deps (deps)", &names), "deps (//deps/src:deps)");
+        assert_eq!(expand("//src:app", &names), "//src:app");
+
+        assert_eq!(
+            label_kinds("cc_library rule //src:app (3e1c0d)\nsource file //src:main.cc\nnoise\n"),
+            [
+                ("cc_library".to_string(), "//src:app".to_string()),
+                ("source file".to_string(), "//src:main.cc".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_grouped() {
+        let results: Vec<_> = [
+            ("cc_library", "//src:demo"),
+            ("cc_binary", "//app/src/bin:tool"),
+            ("cc_binary", "//examples:hello"),
+            ("cc_binary", "//app/bench:sort_bench"),
+            ("cc_test", "//test:demo_test"),
+            ("filegroup", "//:docs"),
+        ]
+        .iter()
+        .map(|(kind, label)| (kind.to_string(), label.to_string()))
+        .collect();
+        let entry = |name: &str, label: &str| vec![(name.to_string(), label.to_string())];
+        assert_eq!(
+            grouped(&results),
+            [
+                entry("tool", "//app/src/bin:tool"),
+                entry("hello", "//examples:hello"),
+                entry("sort_bench", "//app/bench:sort_bench"),
+                entry("demo", "//src:demo"),
+                entry("demo_test", "//test:demo_test"),
+            ]
+        );
+    }
+}
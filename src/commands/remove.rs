@@ -0,0 +1,194 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::{self, Config, Dependency, Source};
+use crate::global::GlobalConfig;
+use crate::lockfile::Lockfile;
+use crate::plugins::{self, Plugin};
+use crate::style;
+use crate::targets;
+use crate::workspace;
+
+/// Drops `name` from the `[dependencies]` of `manifest`, keeping the rest
+/// of it as it is.
+pub fn remove_dependency(manifest: &str, name: &str) -> Result<String, String> {
+    let mut document = manifest
+        .parse::<toml_edit::Document>()
+        .map_err(|e| format!("failed to parse `Buddy.toml`: {}", e))?;
+    let removed = document
+        .get_mut("dependencies")
+        .and_then(|dependencies| dependencies.as_table_like_mut())
+        .and_then(|dependencies| dependencies.remove(name));
+    if removed.is_none() {
+        return Err(format!("`{}` is not a dependency of the package", name));
+    }
+    Ok(document.to_string())
+}
+
+/// The BUILD files under `dir`, relative to `root`.
+fn build_files(root: &Path, dir: &Path, found: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(root.join(dir)) else {
+        return;
+    };
+    let mut entries: Vec<_> = entries.flatten().map(|entry| entry.path()).collect();
+    entries.sort();
+    for path in entries {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if path.is_dir() {
+            if !targets::skip_dir(&name) {
+                build_files(root, &dir.join(&*name), found);
+            }
+        } else if name == "BUILD" || name == "BUILD.bazel" {
+            found.push(dir.join(&*name));
+        }
+    }
+}
+
+/// The files of the project still referring to `repository`, the external
+/// repository of a removed dependency.
+fn references(root: &Path, config: &Config, repository: &str) -> Vec<String> {
+    let prefix = format!("@{}//", repository);
+    let mut files = Vec::new();
+    build_files(root, Path::new(""), &mut files);
+    let mut referencing: Vec<String> = files
+        .into_iter()
+        .filter(|file| {
+            fs::read_to_string(root.join(file)).is_ok_and(|contents| contents.contains(&prefix))
+        })
+        .map(|file| file.display().to_string())
+        .collect();
+    if config.test.deps.iter().any(|dep| dep.starts_with(&prefix)) {
+        referencing.push("the [test] deps of Buddy.toml".to_string());
+    }
+    referencing
+}
+
+/// Removes the dependency `name` from the manifest, the WORKSPACE and the
+/// lockfile, and warns about the BUILD files still using it.
+pub fn run(
+    root: &Path,
+    config: &Config,
+    name: &str,
+    plugins: &[Plugin],
+    global: &GlobalConfig,
+) -> Result<(), String> {
+    let manifest_path = root.join("Buddy.toml");
+    let manifest = fs::read_to_string(&manifest_path)
+        .map_err(|_| "could not find `Buddy.toml` in the current directory".to_string())?;
+    let manifest = remove_dependency(&manifest, name)?;
+
+    let mut dependencies = config.versions();
+    dependencies.remove(name);
+    let mut sources = config.sources();
+    sources.remove(name);
+    let version = match config.dependencies.get(name) {
+        Some(Dependency::Version(version)) => version.clone(),
+        Some(Dependency::Detailed(detailed)) => detailed.version.clone(),
+        Some(Dependency::Source(Source::Git(git))) => {
+            git.reference().unwrap_or_default().to_string()
+        }
+        Some(Dependency::Source(Source::Path(path))) => path.path.clone(),
+        None => String::new(),
+    };
+    workspace::sync(root, &dependencies, &sources, plugins, global)?;
+    fs::write(&manifest_path, manifest).map_err(|e| e.to_string())?;
+
+    let lock_path = root.join("Buddy.lock");
+    if let Ok(contents) = fs::read_to_string(&lock_path) {
+        let edited = Lockfile::remove_package(&contents, name)?;
+        if edited != contents {
+            fs::write(&lock_path, edited).map_err(|e| e.to_string())?;
+        }
+    }
+    style::status(
+        "Removing",
+        format!("`{} {}` from dependencies", name, version),
+    );
+
+    // The repository of the recipe's target, the dependency's own name
+    // otherwise.
+    let repository = plugins::find(plugins, name)
+        .and_then(|plugin| {
+            let version = plugin.select(&version, None).ok()?;
+            plugin.target_label(&version).ok().flatten()
+        })
+        .and_then(|label| Some(label.strip_prefix('@')?.split_once("//")?.0.to_string()))
+        .unwrap_or_else(|| config::repository_name(name));
+    for file in references(root, config, &repository) {
+        style::warning(format!(
+            "{} still refers to `@{}`, remove it from the deps",
+            file, repository
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_remove() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path();
+        let manifest = r#"[package]
+name = "demo"
+version = "0.1.0"
+edition = "2023"
+
+[dependencies]
+bazel-toolchain = "0.8.2"
+google-test = "1.13.0"
+"#;
+        fs::write(root.join("Buddy.toml"), manifest).unwrap();
+        fs::write(
+            root.join("Buddy.lock"),
+            r#"version = 1
+
+[[package]]
+name = "bazel-toolchain"
+version = "0.8.2"
+
+[[package]]
+name = "google-test"
+version = "1.13.0"
+"#,
+        )
+        .unwrap();
+        fs::create_dir_all(root.join("src/net")).unwrap();
+        fs::write(
+            root.join("src/net/BUILD"),
+            "cc_test(name = \"t\", deps = [\"@com_google_googletest//:gtest\"])",
+        )
+        .unwrap();
+        let config: Config = toml::from_str(manifest).unwrap();
+        let plugins = plugins::builtin();
+        let global = GlobalConfig::default();
+        workspace::sync(
+            root,
+            &config.versions(),
+            &BTreeMap::new(),
+            &plugins,
+            &global,
+        )
+        .unwrap();
+
+        run(root, &config, "google-test", &plugins, &global).unwrap();
+        let config: Config =
+            toml::from_str(&fs::read_to_string(root.join("Buddy.toml")).unwrap()).unwrap();
+        assert!(!config.dependencies.contains_key("google-test"));
+        assert!(!fs::read_to_string(root.join("WORKSPACE"))
+            .unwrap()
+            .contains("googletest"));
+        let lockfile = Lockfile::load(&root.join("Buddy.lock")).unwrap();
+        assert_eq!(lockfile.locked_version("google-test"), None);
+        assert_eq!(lockfile.locked_version("bazel-toolchain"), Some("0.8.2"));
+        assert_eq!(
+            references(root, &config, "com_google_googletest"),
+            ["src/net/BUILD", "the [test] deps of Buddy.toml"]
+        );
+
+        assert!(run(root, &config, "google-test", &plugins, &global).is_err());
+    }
+}
@@ -0,0 +1,359 @@
+use colored::*;
+use std::fs::File;
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
+
+use crate::config::Config;
+use crate::notify;
+use crate::style;
+use crate::targets::{self, BuildFile, Kind};
+
+/// A binary `buddy run` can execute.
+#[derive(Debug, PartialEq)]
+pub struct Binary {
+    pub name: String,
+    pub label: String,
+}
+
+/// The binaries of the targets `build_files` declare in the directories
+/// `dirs` selects.
+fn programs(build_files: &[BuildFile], dirs: impl Fn(&Path) -> bool) -> Vec<Binary> {
+    build_files
+        .iter()
+        .filter(|build_file| dirs(&build_file.dir))
+        .flat_map(|build_file| {
+            build_file
+                .targets
+                .iter()
+                .filter(|target| target.kind == Kind::Binary)
+                .map(|target| Binary {
+                    name: target.name.clone(),
+                    label: build_file.label(&target.name),
+                })
+        })
+        .collect()
+}
+
+/// The binaries of the package at `root`: the `[[bin]]` entries, then the
+/// programs found in `src/` and `src/bin/`. A package with neither has its
+/// own binary, `//src:<name>`.
+pub fn binaries(root: &Path, config: &Config) -> Vec<Binary> {
+    let mut binaries: Vec<_> = config
+        .bin
+        .iter()
+        .map(|bin| Binary {
+            name: bin.name.clone(),
+            label: bin.label(),
+        })
+        .collect();
+    // Sources buddy can't read leave the [[bin]] entries.
+    let build_files = targets::scan(root, &config.package.name, &config.test).unwrap_or_default();
+    let found = programs(&build_files, |dir| {
+        dir == Path::new("src") || targets::is_bin_dir(dir)
+    });
+    for binary in found {
+        if !binaries.iter().any(|known| known.name == binary.name) {
+            binaries.push(binary);
+        }
+    }
+    if binaries.is_empty() {
+        binaries.push(Binary {
+            name: config.package.name.clone(),
+            label: format!("//src:{}", config.package.name),
+        });
+    }
+    binaries
+}
+
+fn names(binaries: &[Binary]) -> String {
+    binaries
+        .iter()
+        .map(|binary| binary.name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The label of the example `name`, a binary under `examples/` of the
+/// package at `root`.
+pub fn example(root: &Path, config: &Config, name: &str) -> Result<String, String> {
+    let build_files =
+        targets::scan(root, &config.package.name, &config.test).map_err(|e| e.to_string())?;
+    let examples = programs(&build_files, targets::is_example_dir);
+    match examples.iter().find(|example| example.name == name) {
+        Some(example) => Ok(example.label.clone()),
+        None if examples.is_empty() => Err(format!(
+            "no example named `{}`, the package has none under {}/",
+            name,
+            targets::EXAMPLES_DIR
+        )),
+        None => Err(format!(
+            "no example named `{}`, available examples: {}",
+            name,
+            names(&examples)
+        )),
+    }
+}
+
+/// Asks which of `binaries` to run.
+fn pick(binaries: &[Binary]) -> Result<String, String> {
+    println!("the package has several binaries:");
+    for (i, binary) in binaries.iter().enumerate() {
+        println!("  {}) {} ({})", i + 1, binary.name.bold(), binary.label);
+    }
+
+    let stdin = io::stdin();
+    loop {
+        print!("which one should run? [1-{}] ", binaries.len());
+        io::stdout().flush().map_err(|e| e.to_string())?;
+
+        let mut line = String::new();
+        let read = stdin
+            .lock()
+            .read_line(&mut line)
+            .map_err(|e| e.to_string())?;
+        if read == 0 {
+            return Err("no binary selected".to_string());
+        }
+        let line = line.trim();
+        let chosen = match line.parse::<usize>() {
+            Ok(n) if (1..=binaries.len()).contains(&n) => binaries.get(n - 1),
+            _ => binaries.iter().find(|binary| binary.name == line),
+        };
+        if let Some(binary) = chosen {
+            return Ok(binary.label.clone());
+        }
+    }
+}
+
+/// Selects the label `buddy run` executes in the package at `root`: `bin`
+/// when given, then the package's `default-run`, otherwise the only binary
+/// of the package. With several binaries the user picks one when
+/// `interactive`, scripts get an error rather than a guess.
+pub fn select(
+    root: &Path,
+    config: &Config,
+    bin: Option<&str>,
+    interactive: bool,
+) -> Result<String, String> {
+    let binaries = binaries(root, config);
+
+    if let Some(name) = bin.or(config.package.default_run.as_deref()) {
+        return binaries
+            .iter()
+            .find(|binary| binary.name == name)
+            .map(|binary| binary.label.clone())
+            .ok_or_else(|| {
+                let setting = if bin.is_none() { " (default-run)" } else { "" };
+                format!(
+                    "no binary named `{}`{}, available binaries: {}",
+                    name,
+                    setting,
+                    names(&binaries)
+                )
+            });
+    }
+
+    match binaries.as_slice() {
+        [binary] => Ok(binary.label.clone()),
+        _ if interactive => pick(&binaries),
+        _ => Err(format!(
+            "`buddy run` could not determine which binary to run, use --bin to pick one of: {}",
+            names(&binaries)
+        )),
+    }
+}
+
+/// Whether the user can be asked to pick a binary.
+pub fn is_interactive() -> bool {
+    io::stdin().is_terminal() && io::stdout().is_terminal()
+}
+
+/// Copies `input` to `output` and to the `log`, if any, as it comes.
+fn tee(mut input: impl Read, mut output: impl Write, log: &Mutex<Option<File>>) -> io::Result<()> {
+    let mut buffer = [0; 8192];
+    loop {
+        let read = input.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(());
+        }
+        output.write_all(&buffer[..read])?;
+        output.flush()?;
+        if let Some(file) = log.lock().unwrap().as_mut() {
+            file.write_all(&buffer[..read])?;
+        }
+    }
+}
+
+/// Runs the program with its output piped through buddy, echoed and, with
+/// `capture`, saved to that file.
+fn run_captured(cmd: &mut Command, capture: Option<&Path>) -> Result<ExitStatus, String> {
+    let log = match capture {
+        Some(path) => Some(File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?),
+        None => None,
+    };
+    let log = Mutex::new(log);
+    let mut child = cmd
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to run the program: {}", e))?;
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+    thread::scope(|scope| {
+        let errors = scope.spawn(|| tee(stderr, io::stderr(), &log));
+        let copied = tee(stdout, io::stdout(), &log);
+        errors.join().unwrap().and(copied)
+    })
+    .map_err(|e| e.to_string())?;
+    child.wait().map_err(|e| e.to_string())
+}
+
+/// Runs the program of `label` and reports how it exited, returning its
+/// exit code. Unless its output is captured or redirected, the program
+/// inherits buddy's stdin and terminal, for REPLs and full screen programs.
+pub fn execute(cmd: &mut Command, label: &str, capture: Option<&Path>) -> Result<i32, String> {
+    let start = Instant::now();
+    let status = if capture.is_none() && io::stdout().is_terminal() {
+        cmd.status()
+            .map_err(|e| format!("failed to run the program: {}", e))?
+    } else {
+        run_captured(cmd, capture)?
+    };
+
+    let duration = notify::format_duration(start.elapsed());
+    match status.code() {
+        Some(0) => style::status("Finished", format!("`{}` in {}", label, duration)),
+        Some(code) => style::warning(format!(
+            "`{}` exited with code {} after {}",
+            label, code, duration
+        )),
+        None => style::warning(format!(
+            "`{}` was killed by a signal after {}",
+            label, duration
+        )),
+    }
+    if let Some(path) = capture {
+        style::status("Captured", path.display());
+    }
+    Ok(status.code().unwrap_or(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path();
+        let mut config: Config = toml::from_str(
+            r#"
+[package]
+name = "demo"
+version = "0.1.0"
+edition = "2023"
+
+[dependencies]
+"#,
+        )
+        .unwrap();
+        assert_eq!(select(root, &config, None, false).unwrap(), "//src:demo");
+
+        config = toml::from_str(
+            r#"
+[package]
+name = "demo"
+version = "0.1.0"
+edition = "2023"
+
+[dependencies]
+
+[[bin]]
+name = "server"
+
+[[bin]]
+name = "client"
+path = "tools/client/main.cc"
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            select(root, &config, Some("client"), false).unwrap(),
+            "//tools/client:client"
+        );
+        assert!(select(root, &config, Some("demo"), false)
+            .unwrap_err()
+            .contains("available binaries: server, client"));
+        assert!(select(root, &config, None, false)
+            .unwrap_err()
+            .contains("use --bin to pick one of: server, client"));
+
+        config.package.default_run = Some("server".to_string());
+        assert_eq!(select(root, &config, None, false).unwrap(), "//src:server");
+        assert_eq!(
+            select(root, &config, Some("client"), false).unwrap(),
+            "//tools/client:client"
+        );
+
+        // Without [[bin]], the programs of src/ and src/bin/.
+        config.bin.clear();
+        config.package.default_run = None;
+        std::fs::create_dir_all(root.join("src/bin")).unwrap();
+        std::fs::write(root.join("src/main.cc"), "int main() {}").unwrap();
+        std::fs::write(root.join("src/bin/tool2.cc"), "int main() {}").unwrap();
+        assert_eq!(
+            select(root, &config, Some("tool2"), false).unwrap(),
+            "//src/bin:tool2"
+        );
+        assert!(select(root, &config, None, false)
+            .unwrap_err()
+            .contains("use --bin to pick one of: demo, tool2"));
+    }
+
+    #[test]
+    fn test_example() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path();
+        let config: Config =
+            toml::from_str("[package]\nname = \"demo\"\nversion = \"0.1.0\"\nedition = \"2023\"\n")
+                .unwrap();
+        assert!(example(root, &config, "hello")
+            .unwrap_err()
+            .contains("the package has none under examples/"));
+
+        std::fs::create_dir_all(root.join("examples/client")).unwrap();
+        std::fs::write(root.join("examples/hello.cc"), "int main() {}").unwrap();
+        std::fs::write(root.join("examples/client/main.cc"), "int main() {}").unwrap();
+        assert_eq!(example(root, &config, "hello").unwrap(), "//examples:hello");
+        assert_eq!(
+            example(root, &config, "client").unwrap(),
+            "//examples/client:client"
+        );
+        assert!(example(root, &config, "server")
+            .unwrap_err()
+            .contains("available examples: hello, client"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_capture() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let log = tmp_dir.path().join("run.log");
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "echo out; echo err >&2; exit 3"]);
+        let status = run_captured(&mut cmd, Some(&log)).unwrap();
+        assert_eq!(status.code(), Some(3));
+
+        let mut lines: Vec<_> = std::fs::read_to_string(&log)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect();
+        lines.sort();
+        assert_eq!(lines, ["err", "out"]);
+    }
+}
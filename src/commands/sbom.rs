@@ -0,0 +1,309 @@
+use clap::ValueEnum;
+use serde_json::{json, Value};
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::commands::dist;
+use crate::config::Package;
+use crate::lockfile::{LockedPackage, Lockfile, LOCKFILE};
+use crate::plugins::{self, Plugin};
+use crate::style;
+
+/// The SBOM formats `buddy sbom` writes, both as JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// CycloneDX 1.5
+    Cyclonedx,
+    /// SPDX 2.3
+    Spdx,
+}
+
+/// What the SBOM says of a locked dependency.
+struct Component<'a> {
+    locked: &'a LockedPackage,
+    purl: String,
+    /// The sha256 of the archive, when the dependency is pinned to one.
+    sha256: Option<&'a str>,
+    /// Where the archive or the repository is downloaded from.
+    location: Option<String>,
+    license: Option<&'a str>,
+}
+
+fn purl(name: &str, version: &str) -> String {
+    format!("pkg:buddy/{}@{}", name, version)
+}
+
+fn components<'a>(lockfile: &'a Lockfile, plugins: &'a [Plugin]) -> Vec<Component<'a>> {
+    lockfile
+        .package
+        .iter()
+        .map(|locked| {
+            let sha256 = locked.sha.as_deref();
+            // The source is the registry's name when the recipe names no
+            // archive, which locates nothing.
+            let location = locked.source.as_deref().and_then(|source| {
+                match (source.strip_prefix("git+"), &locked.commit) {
+                    (Some(url), Some(commit)) => Some(format!("git+{}@{}", url, commit)),
+                    _ if source.contains("://") && !source.starts_with("path+") => {
+                        Some(source.to_string())
+                    }
+                    _ => None,
+                }
+            });
+            Component {
+                locked,
+                purl: purl(&locked.name, &locked.version),
+                sha256,
+                location,
+                license: plugins::find(plugins, &locked.name)
+                    .and_then(|plugin| plugin.license.as_deref()),
+            }
+        })
+        .collect()
+}
+
+/// The CycloneDX document of `package` depending on `components`.
+fn cyclonedx(package: &Package, components: &[Component], timestamp: &str) -> Value {
+    let root = purl(&package.name, &package.version);
+    let entries: Vec<Value> = components
+        .iter()
+        .map(|component| {
+            let mut entry = json!({
+                "type": "library",
+                "bom-ref": component.purl,
+                "name": component.locked.name,
+                "version": component.locked.version,
+                "purl": component.purl,
+            });
+            if let Some(sha256) = component.sha256 {
+                entry["hashes"] = json!([{ "alg": "SHA-256", "content": sha256 }]);
+            }
+            if let Some(license) = component.license {
+                entry["licenses"] = json!([{ "expression": license }]);
+            }
+            if let Some(location) = &component.location {
+                let kind = match location.starts_with("git+") {
+                    true => "vcs",
+                    false => "distribution",
+                };
+                entry["externalReferences"] = json!([{ "type": kind, "url": location }]);
+            }
+            entry
+        })
+        .collect();
+    let mut metadata_component = json!({
+        "type": "application",
+        "bom-ref": root,
+        "name": package.name,
+        "version": package.version,
+        "purl": root,
+    });
+    if let Some(license) = &package.license {
+        metadata_component["licenses"] = json!([{ "expression": license }]);
+    }
+
+    json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.5",
+        "version": 1,
+        "metadata": {
+            "timestamp": timestamp,
+            "tools": {
+                "components": [{
+                    "type": "application",
+                    "name": "buddy",
+                    "version": env!("CARGO_PKG_VERSION"),
+                }],
+            },
+            "component": metadata_component,
+        },
+        "components": entries,
+        "dependencies": [{
+            "ref": root,
+            "dependsOn": components.iter().map(|component| &component.purl).collect::<Vec<_>>(),
+        }],
+    })
+}
+
+/// An SPDX identifier made of `name`, which only takes letters, digits,
+/// `.` and `-`.
+fn spdx_id(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    format!("SPDXRef-Package-{}", name)
+}
+
+/// The SPDX document of `package` depending on `components`.
+fn spdx(package: &Package, components: &[Component], timestamp: &str) -> Value {
+    let root = spdx_id(&package.name);
+    let spdx_package = |name: &str, version: &str, purl: &str, license: Option<&str>| {
+        json!({
+            "SPDXID": spdx_id(name),
+            "name": name,
+            "versionInfo": version,
+            "downloadLocation": "NOASSERTION",
+            "filesAnalyzed": false,
+            "licenseConcluded": "NOASSERTION",
+            "licenseDeclared": license.unwrap_or("NOASSERTION"),
+            "copyrightText": "NOASSERTION",
+            "externalRefs": [{
+                "referenceCategory": "PACKAGE-MANAGER",
+                "referenceType": "purl",
+                "referenceLocator": purl,
+            }],
+        })
+    };
+
+    let mut packages = vec![spdx_package(
+        &package.name,
+        &package.version,
+        &purl(&package.name, &package.version),
+        package.license.as_deref(),
+    )];
+    let mut relationships = vec![json!({
+        "spdxElementId": "SPDXRef-DOCUMENT",
+        "relationshipType": "DESCRIBES",
+        "relatedSpdxElement": root,
+    })];
+    for component in components {
+        let mut entry = spdx_package(
+            &component.locked.name,
+            &component.locked.version,
+            &component.purl,
+            component.license,
+        );
+        if let Some(location) = &component.location {
+            entry["downloadLocation"] = json!(location);
+        }
+        if let Some(sha256) = component.sha256 {
+            entry["checksums"] = json!([{ "algorithm": "SHA256", "checksumValue": sha256 }]);
+        }
+        packages.push(entry);
+        relationships.push(json!({
+            "spdxElementId": root,
+            "relationshipType": "DEPENDS_ON",
+            "relatedSpdxElement": spdx_id(&component.locked.name),
+        }));
+    }
+
+    json!({
+        "spdxVersion": "SPDX-2.3",
+        "dataLicense": "CC0-1.0",
+        "SPDXID": "SPDXRef-DOCUMENT",
+        "name": format!("{}-{}", package.name, package.version),
+        "documentNamespace": format!(
+            "https://github.com/cppbuddy/buddy/spdx/{}-{}",
+            package.name, package.version
+        ),
+        "creationInfo": {
+            "created": timestamp,
+            "creators": [format!("Tool: buddy-{}", env!("CARGO_PKG_VERSION"))],
+        },
+        "packages": packages,
+        "relationships": relationships,
+    })
+}
+
+/// Writes the SBOM of `package` and the dependencies locked in
+/// `Buddy.lock` in `format`, to `output` or stdout.
+pub fn run(
+    package: &Package,
+    plugins: &[Plugin],
+    format: Format,
+    output: Option<&Path>,
+) -> Result<(), String> {
+    if !Path::new(LOCKFILE).is_file() {
+        return Err(format!(
+            "no {} to describe, `buddy fetch` resolves the dependencies",
+            LOCKFILE
+        ));
+    }
+    let lockfile = Lockfile::load(Path::new(LOCKFILE))?;
+    let components = components(&lockfile, plugins);
+    let timestamp = dist::rfc3339(SystemTime::now());
+    let document = match format {
+        Format::Cyclonedx => cyclonedx(package, &components, &timestamp),
+        Format::Spdx => spdx(package, &components, &timestamp),
+    };
+    let contents = serde_json::to_string_pretty(&document).map_err(|e| e.to_string())? + "\n";
+    match output {
+        Some(path) => {
+            fs::write(path, contents).map_err(|e| format!("{}: {}", path.display(), e))?;
+            style::status("Wrote", path.display());
+        }
+        None => print!("{}", contents),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_documents() {
+        let lockfile: Lockfile = toml::from_str(
+            r#"
+[[package]]
+name = "bazel-toolchain"
+version = "0.8.2"
+source = "https://github.com/grailbio/bazel-toolchain/archive/refs/tags/0.8.2.tar.gz"
+sha = "0fc3a2b0c9c929920f4bed8f2b446a8274cad41f5ee823fd3faa0d7641f20db0"
+
+[[package]]
+name = "corp/logging"
+version = "main"
+source = "git+https://git.corp/logging"
+commit = "b796f7d44681514f58a683a3a71ff17c94edb0c1"
+"#,
+        )
+        .unwrap();
+        let plugins = plugins::builtin();
+        let components = components(&lockfile, &plugins);
+        let package = Package {
+            name: "demo".to_string(),
+            version: "1.0.0".to_string(),
+            license: Some("MIT".to_string()),
+            ..Default::default()
+        };
+
+        let bom = cyclonedx(&package, &components, "2024-01-01T00:00:00Z");
+        let toolchain = &bom["components"][0];
+        assert_eq!(toolchain["purl"], "pkg:buddy/bazel-toolchain@0.8.2");
+        assert_eq!(
+            toolchain["hashes"][0]["content"],
+            "0fc3a2b0c9c929920f4bed8f2b446a8274cad41f5ee823fd3faa0d7641f20db0"
+        );
+        assert_eq!(toolchain["licenses"][0]["expression"], "Apache-2.0");
+        let logging = &bom["components"][1];
+        assert!(logging.get("hashes").is_none());
+        assert_eq!(logging["externalReferences"][0]["type"], "vcs");
+        assert_eq!(
+            bom["dependencies"][0]["dependsOn"][1],
+            "pkg:buddy/corp/logging@main"
+        );
+
+        let document = spdx(&package, &components, "2024-01-01T00:00:00Z");
+        let packages = document["packages"].as_array().unwrap();
+        assert_eq!(packages[0]["licenseDeclared"], "MIT");
+        assert_eq!(packages[1]["checksums"][0]["algorithm"], "SHA256");
+        assert_eq!(packages[2]["SPDXID"], "SPDXRef-Package-corp-logging");
+        assert_eq!(
+            packages[2]["downloadLocation"],
+            "git+https://git.corp/logging@b796f7d44681514f58a683a3a71ff17c94edb0c1"
+        );
+        assert_eq!(
+            document["relationships"][2]["relationshipType"],
+            "DEPENDS_ON"
+        );
+    }
+}
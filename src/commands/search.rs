@@ -0,0 +1,144 @@
+use colored::*;
+
+use crate::plugins::Plugin;
+use crate::style;
+
+/// How well a recipe matches the search, the best first. `None` when it
+/// doesn't.
+fn rank(plugin: &Plugin, term: &str) -> Option<u8> {
+    let name = plugin.name.to_lowercase();
+    // Scoped names match on their last part too, `corp/zlib` on `zlib`.
+    let short = name.rsplit('/').next().unwrap_or(&name);
+    if name == term || short == term {
+        Some(0)
+    } else if name.contains(term) {
+        Some(1)
+    } else if plugin
+        .keywords
+        .iter()
+        .any(|keyword| keyword.to_lowercase() == term)
+    {
+        Some(2)
+    } else if plugin
+        .description
+        .as_ref()
+        .is_some_and(|description| description.to_lowercase().contains(term))
+    {
+        Some(3)
+    } else {
+        None
+    }
+}
+
+/// The recipes matching `term` by name, keyword or description, best
+/// matches first then by name. A name shadowed by an earlier recipe, e.g.
+/// of a plugin directory, is listed once.
+pub fn search<'a>(plugins: &'a [Plugin], term: &str) -> Vec<&'a Plugin> {
+    let term = term.to_lowercase();
+    let mut found: Vec<(u8, &Plugin)> = Vec::new();
+    for plugin in plugins {
+        if found.iter().any(|(_, seen)| seen.name == plugin.name) {
+            continue;
+        }
+        if let Some(rank) = rank(plugin, &term) {
+            found.push((rank, plugin));
+        }
+    }
+    found.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.name.cmp(&b.1.name)));
+    found.into_iter().map(|(_, plugin)| plugin).collect()
+}
+
+/// Prints the recipes matching `term`, the `limit` first ones, with their
+/// latest version and description, as `buddy add` takes them.
+pub fn run(plugins: &[Plugin], term: &str, limit: usize) -> Result<(), String> {
+    let found = search(plugins, term);
+    if found.is_empty() {
+        return Err(format!(
+            "no package matches `{}`, `buddy update-index` fetches the latest ones of the registries",
+            term
+        ));
+    }
+
+    let shown = &found[..found.len().min(limit)];
+    let width = shown
+        .iter()
+        .map(|plugin| {
+            plugin.name.len() + plugin.latest_version().map_or(0, |version| version.len())
+        })
+        .max()
+        .unwrap_or(0);
+    for plugin in shown {
+        let version = plugin.latest_version().unwrap_or("-");
+        let entry = format!("{} = \"{}\"", plugin.name.bold(), version);
+        // The bold codes don't take room on the terminal.
+        let padding = width + 5 - plugin.name.len() - version.len();
+        let mut about: Vec<String> = Vec::new();
+        if plugin.deprecated.is_some() {
+            about.push("deprecated".to_string());
+        }
+        about.extend(plugin.description.clone());
+        if let Some(registry) = &plugin.registry {
+            about.push(format!("({})", registry));
+        }
+        let about = about.join(" ");
+        println!(
+            "{}{:padding$}{}",
+            entry,
+            "",
+            style::dim(&format!("# {}", about)),
+            padding = padding
+        );
+    }
+    if found.len() > shown.len() {
+        println!(
+            "... and {} more, use --limit to show them",
+            found.len() - shown.len()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins;
+
+    #[test]
+    fn test_search() {
+        let mut recipes = plugins::builtin();
+        recipes.insert(
+            0,
+            Plugin {
+                name: "corp/google-test".to_string(),
+                versions: [("2.0.0".to_string(), "abc".to_string())].into(),
+                build_rule: String::new(),
+                archive: None,
+                target: None,
+                signing_key: None,
+                registry: Some("internal".to_string()),
+                yanked: Default::default(),
+                deprecated: None,
+                description: Some("Test helpers of the corp".to_string()),
+                keywords: vec!["Testing".to_string()],
+                license: None,
+                advisories: Vec::new(),
+            },
+        );
+        let names = |term: &str| -> Vec<String> {
+            search(&recipes, term)
+                .iter()
+                .map(|plugin| plugin.name.clone())
+                .collect()
+        };
+
+        assert_eq!(names("google-test"), ["corp/google-test", "google-test"]);
+        assert_eq!(
+            names("GOOGLE"),
+            ["corp/google-test", "google-benchmark", "google-test"]
+        );
+        assert_eq!(names("testing"), ["corp/google-test", "google-test"]);
+        assert_eq!(names("llvm"), ["bazel-toolchain"]);
+        assert_eq!(names("snippets"), ["google-benchmark"]);
+        assert!(names("rust").is_empty());
+    }
+}
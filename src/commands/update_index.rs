@@ -0,0 +1,105 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use which::which;
+
+use crate::credentials;
+use crate::global::{GlobalConfig, Registry};
+use crate::index;
+use crate::plugins;
+use crate::style;
+
+/// The archive of recipes a registry serves at the root of its index, laid
+/// out like a directory registry.
+const INDEX_ARCHIVE: &str = "index.tar.gz";
+
+/// Downloads and unpacks the index of `registry` into `dir`, replacing the
+/// previous one only once the new one is complete.
+fn fetch(name: &str, registry: &Registry, dir: &Path, global: &GlobalConfig) -> Result<(), String> {
+    let curl = which("curl").map_err(|_| "`curl` not found, it is needed to fetch the index")?;
+    let tar = which("tar").map_err(|_| "`tar` not found, it is needed to unpack the index")?;
+
+    let root = dir.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(root).map_err(|e| format!("{}: {}", root.display(), e))?;
+    let partial = root.join(format!(".{}.partial", name));
+    let _ = fs::remove_dir_all(&partial);
+    fs::create_dir_all(&partial).map_err(|e| format!("{}: {}", partial.display(), e))?;
+    let archive = partial.join(INDEX_ARCHIVE);
+
+    let url = format!("{}/{}", registry.index.trim_end_matches('/'), INDEX_ARCHIVE);
+    let mut cmd = Command::new(curl);
+    cmd.args(["-fsSL", "-o"]).arg(&archive).arg(&url);
+    let headers = match credentials::token(name, Some(registry))? {
+        Some(token) => Some(credentials::curl_headers(&[(
+            "Authorization",
+            &format!("Bearer {}", token),
+        )])?),
+        None => None,
+    };
+    if let Some(headers) = &headers {
+        cmd.arg("-K").arg(headers.path());
+    }
+    let status = cmd
+        .envs(global.http.env())
+        .status()
+        .map_err(|e| format!("failed to run curl: {}", e))?;
+    if !status.success() {
+        return Err(format!("failed to download {}", url));
+    }
+
+    let status = Command::new(tar)
+        .arg("-xzf")
+        .arg(&archive)
+        .arg("-C")
+        .arg(&partial)
+        .status()
+        .map_err(|e| format!("failed to run tar: {}", e))?;
+    if !status.success() {
+        return Err(format!("{} is not a valid index archive", url));
+    }
+    fs::remove_file(&archive).map_err(|e| e.to_string())?;
+    index::mark_fetched(&partial)?;
+
+    let _ = fs::remove_dir_all(dir);
+    fs::rename(&partial, dir).map_err(|e| format!("{}: {}", dir.display(), e))
+}
+
+/// Fetches the latest index of `registry`, or of every registry served
+/// over HTTP, into `~/.buddy/index`.
+pub fn run(global: &GlobalConfig, registry: Option<&str>, offline: bool) -> Result<(), String> {
+    if offline {
+        return Err("cannot update the index in offline mode".to_string());
+    }
+    let names: Vec<&str> = match registry {
+        Some(name) => {
+            let found = global
+                .registries
+                .get(name)
+                .ok_or_else(|| format!("unknown registry `{}`, see ~/.buddy/config.toml", name))?;
+            if found.path.is_some() {
+                return Err(format!(
+                    "registry `{}` is a directory, its recipes are always up to date",
+                    name
+                ));
+            }
+            vec![name]
+        }
+        None => index::remote_registries(global).collect(),
+    };
+    if names.is_empty() {
+        style::warning("no registry with an index to fetch");
+        return Ok(());
+    }
+
+    for name in names {
+        style::status("Updating", format!("index of `{}`", name));
+        let dir = index::root().join(name);
+        fetch(name, &global.registries[name], &dir, global)?;
+        let recipes = plugins::load_directory(name, &dir)?.len();
+        style::status(
+            "Updated",
+            format!("index of `{}`, {} recipe(s)", name, recipes),
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,234 @@
+use colored::*;
+use similar::TextDiff;
+use std::cmp::Ordering;
+use std::fs;
+use std::path::Path;
+
+use crate::config::Config;
+use crate::plugins::compare_versions;
+use crate::style::{self, Level};
+use crate::targets;
+
+/// `test/BUILD` as written by `buddy new` before test targets were generated.
+const LEGACY_TEST_BUILD: &str = r#"cc_test(
+  name = "hello_test",
+  size = "small",
+  srcs = ["hello_test.cc"],
+  deps = ["@com_google_googletest//:gtest_main"],
+)"#;
+
+/// A change to a file generated by an older buddy.
+struct Migration {
+    /// The buddy version which started generating the file this way,
+    /// projects recorded as generated by an older one are migrated.
+    version: &'static str,
+    file: &'static str,
+    description: &'static str,
+    /// Returns the migrated contents, `None` when the file doesn't need it.
+    apply: fn(root: &Path, config: &Config, contents: &str) -> Option<String>,
+}
+
+const MIGRATIONS: [Migration; 3] = [
+    Migration {
+        version: "0.0.3",
+        file: "Buddy.toml",
+        description: "depend on bazel-toolchain 0.8.2, the version the WORKSPACE was pinned to",
+        apply: |_, _, contents| pin_bazel_toolchain(contents),
+    },
+    Migration {
+        version: "0.0.3",
+        file: "test/BUILD",
+        description: "generate the test targets so that `buddy test` keeps them in sync",
+        apply: generate_test_build,
+    },
+    Migration {
+        version: "0.0.3",
+        file: ".bazelrc",
+        description: "end the file with a newline",
+        apply: |_, _, contents| {
+            (!contents.is_empty() && !contents.ends_with('\n')).then(|| format!("{}\n", contents))
+        },
+    },
+];
+
+/// `bazel-toolchain = "0.8.0"` of the manifests of the first `buddy new`
+/// becomes the 0.8.2 their WORKSPACE was pinned to, which the recipe knows.
+fn pin_bazel_toolchain(contents: &str) -> Option<String> {
+    let mut document = contents.parse::<toml_edit::Document>().ok()?;
+    let requirement = document
+        .get_mut("dependencies")?
+        .get_mut("bazel-toolchain")?;
+    if requirement.as_str()? != "0.8.0" {
+        return None;
+    }
+    let decor = requirement.as_value()?.decor().clone();
+    *requirement = toml_edit::value("0.8.2");
+    *requirement.as_value_mut()?.decor_mut() = decor;
+    Some(document.to_string())
+}
+
+fn generate_test_build(root: &Path, config: &Config, contents: &str) -> Option<String> {
+    // Only the untouched template is replaced, edited files are the user's.
+    if contents.trim() != LEGACY_TEST_BUILD {
+        return None;
+    }
+    targets::scan(root, &config.package.name, &config.test)
+        .ok()?
+        .into_iter()
+        .find(|build_file| build_file.dir == Path::new("test"))
+        .map(|build_file| build_file.render())
+}
+
+/// Sets `buddy-version` in the `[package]` table, keeping the rest of the
+/// manifest as it is.
+pub fn record_version(manifest: &str, version: &str) -> Result<String, String> {
+    let mut document = manifest
+        .parse::<toml_edit::Document>()
+        .map_err(|e| format!("failed to parse `Buddy.toml`: {}", e))?;
+    let package = document["package"]
+        .as_table_mut()
+        .ok_or("`Buddy.toml` has no [package] table")?;
+    package["buddy-version"] = toml_edit::value(version);
+    Ok(document.to_string())
+}
+
+/// Prints the changes from `old` to `new` as a colored unified diff.
+pub fn print_diff(file: &str, old: &str, new: &str) {
+    let diff = TextDiff::from_lines(old, new);
+    let diff = diff
+        .unified_diff()
+        .context_radius(2)
+        .header(&format!("a/{}", file), &format!("b/{}", file))
+        .to_string();
+    for line in diff.lines() {
+        if line.starts_with("+++") || line.starts_with("---") {
+            println!("{}", line.bold());
+        } else if line.starts_with('+') {
+            println!("{}", style::paint(line, Level::Info));
+        } else if line.starts_with('-') {
+            println!("{}", style::paint(line, Level::Error));
+        } else {
+            println!("{}", line);
+        }
+    }
+}
+
+fn update(root: &Path, file: &str, old: &str, new: &str, dry_run: bool) -> Result<(), String> {
+    print_diff(file, old, new);
+    if !dry_run {
+        fs::write(root.join(file), new).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Migrates the files generated by the buddy version recorded in
+/// `Buddy.toml` to what the running one generates, then records the running
+/// version. Projects without a recorded version predate this command and
+/// go through every migration.
+pub fn upgrade(root: &Path, config: &Config, dry_run: bool) -> Result<(), String> {
+    let manifest_path = root.join("Buddy.toml");
+    let mut manifest = fs::read_to_string(&manifest_path)
+        .map_err(|_| "could not find `Buddy.toml` in the current directory".to_string())?;
+
+    let current = env!("CARGO_PKG_VERSION");
+    let from = config.package.buddy_version.as_deref();
+    if let Some(from) = from {
+        if compare_versions(from, current) == Ordering::Greater {
+            return Err(format!(
+                "the project was generated by buddy {}, which is newer than this one ({})",
+                from, current
+            ));
+        }
+    }
+
+    let mut changed = 0;
+    for migration in &MIGRATIONS {
+        if from.is_some_and(|from| compare_versions(from, migration.version) != Ordering::Less) {
+            continue;
+        }
+        // The manifest as migrated so far, files are only written when
+        // it isn't a dry run.
+        let contents = match migration.file {
+            "Buddy.toml" => manifest.clone(),
+            file => match fs::read_to_string(root.join(file)) {
+                Ok(contents) => contents,
+                Err(_) => continue,
+            },
+        };
+        let Some(migrated) = (migration.apply)(root, config, &contents) else {
+            continue;
+        };
+
+        style::status(
+            "Migrating",
+            format!("{}: {}", migration.file, migration.description),
+        );
+        update(root, migration.file, &contents, &migrated, dry_run)?;
+        if migration.file == "Buddy.toml" {
+            manifest = migrated;
+        }
+        changed += 1;
+    }
+
+    let recorded = record_version(&manifest, current)?;
+    if recorded != manifest {
+        style::status("Migrating", format!("Buddy.toml: record buddy {}", current));
+        update(root, "Buddy.toml", &manifest, &recorded, dry_run)?;
+        changed += 1;
+    }
+
+    if changed == 0 {
+        println!("the project is up to date with buddy {}", current);
+    } else if dry_run {
+        style::warning("dry run, no file was changed");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_version_keeps_formatting() {
+        let manifest =
+            "[package]\nname = \"demo\" # the name\nversion = \"0.1.0\"\n\n[dependencies]\n";
+        let recorded = record_version(manifest, "0.0.3").unwrap();
+        assert_eq!(
+            recorded,
+            "[package]\nname = \"demo\" # the name\nversion = \"0.1.0\"\nbuddy-version = \"0.0.3\"\n\n[dependencies]\n"
+        );
+    }
+
+    #[test]
+    fn test_upgrade_legacy_project() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path();
+        fs::create_dir_all(root.join("test")).unwrap();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src").join("main.cc"), "int main() {}\n").unwrap();
+        fs::write(root.join("test").join("hello_test.cc"), "").unwrap();
+        fs::write(root.join("test").join("BUILD"), LEGACY_TEST_BUILD).unwrap();
+        fs::write(root.join(".bazelrc"), "build --cxxopt=-std=c++17").unwrap();
+        // The manifest of the first `buddy new`.
+        let manifest = "[package]\nname = \"demo\"\nversion = \"0.1.0\"\nedition = \"2023\"\n\n[dependencies]\nbazel-toolchain = \"0.8.0\"\ngoogle-test = \"1.13.0\"";
+        fs::write(root.join("Buddy.toml"), manifest).unwrap();
+
+        let config: Config = toml::from_str(manifest).unwrap();
+        upgrade(root, &config, false).unwrap();
+
+        let build = fs::read_to_string(root.join("test").join("BUILD")).unwrap();
+        assert!(build.contains("name = \"hello_test\""));
+        assert!(build.starts_with("# This file is automatically @generated by Buddy."));
+        assert_eq!(
+            fs::read_to_string(root.join(".bazelrc")).unwrap(),
+            "build --cxxopt=-std=c++17\n"
+        );
+        let manifest = fs::read_to_string(root.join("Buddy.toml")).unwrap();
+        assert!(manifest.contains(&format!(
+            "buddy-version = \"{}\"",
+            env!("CARGO_PKG_VERSION")
+        )));
+        assert!(manifest.contains("\nbazel-toolchain = \"0.8.2\"\ngoogle-test = \"1.13.0\""));
+    }
+}
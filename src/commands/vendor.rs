@@ -0,0 +1,158 @@
+use std::fs;
+use std::path::Path;
+
+use crate::config::Config;
+use crate::global::{self, GlobalConfig};
+use crate::lockfile::{Lockfile, LOCKFILE};
+use crate::plugins::Plugin;
+use crate::style;
+use crate::workspace::{self, VENDOR_DIR};
+
+use super::fetch;
+
+/// Copies the archives of the packages locked in `root`'s lockfile into
+/// its vendor directory, from the distdir when `buddy fetch` already got
+/// them, downloading them otherwise. Returns how many were added.
+fn vendor(
+    root: &Path,
+    global: &GlobalConfig,
+    distdir: &Path,
+    offline: bool,
+) -> Result<usize, String> {
+    let lockfile = Lockfile::load(&root.join(LOCKFILE))?;
+    if lockfile.package.is_empty() {
+        return Err(format!(
+            "no package locked in {}, build the project once to resolve its dependencies",
+            LOCKFILE
+        ));
+    }
+
+    let vendor = root.join(VENDOR_DIR);
+    fs::create_dir_all(&vendor).map_err(|e| format!("{}: {}", vendor.display(), e))?;
+    let mut added = 0;
+    for package in &lockfile.package {
+        let Some(url) = package
+            .source
+            .as_deref()
+            .filter(|source| source.starts_with("https://") || source.starts_with("http://"))
+        else {
+            continue;
+        };
+        let archive = workspace::vendor_path(&vendor, &package.name, url);
+        if archive.exists() {
+            continue;
+        }
+        let cached = fetch::archive_path(distdir, &package.name, url);
+        if cached.is_file() {
+            fs::copy(&cached, &archive).map_err(|e| format!("{}: {}", archive.display(), e))?;
+        } else if offline {
+            return Err(format!(
+                "`{} {}` is not in the local archive cache and --offline was passed",
+                package.name, package.version
+            ));
+        } else {
+            fetch::download(url, &archive, global)?;
+        }
+        style::status("Vendored", format!("{} {}", package.name, package.version));
+        added += 1;
+    }
+    Ok(added)
+}
+
+/// Vendors the locked dependencies of the package in `root` and points its
+/// WORKSPACE at them, so that it builds with no network.
+pub fn run(
+    root: &Path,
+    config: &Config,
+    plugins: &[Plugin],
+    global: &GlobalConfig,
+    offline: bool,
+) -> Result<(), String> {
+    let added = vendor(root, global, &global::distdir(), offline)?;
+    workspace::sync(root, &config.versions(), &config.sources(), plugins, global)?;
+    if added == 0 {
+        style::status("Fresh", format!("{}/ is up to date", VENDOR_DIR));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lockfile;
+    use crate::plugins;
+    use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+    #[test]
+    fn test_vendor() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path().join("demo");
+        let distdir = tmp_dir.path().join("distdir");
+        fs::create_dir_all(&root).unwrap();
+        fs::create_dir_all(&distdir).unwrap();
+        let global = GlobalConfig::default();
+        assert!(vendor(&root, &global, &distdir, true).is_err());
+
+        let plugins = plugins::builtin();
+        let dependencies: HashMap<String, String> =
+            [("google-test".to_string(), "1.13.0".to_string())].into();
+        lockfile::sync(
+            &root,
+            &dependencies,
+            &BTreeMap::new(),
+            &BTreeSet::new(),
+            &plugins,
+            &global,
+            false,
+        )
+        .unwrap();
+        assert!(vendor(&root, &global, &distdir, true)
+            .unwrap_err()
+            .contains("--offline"));
+
+        let archive = "b796f7d44681514f58a683a3a71ff17c94edb0c1.zip";
+        fs::create_dir_all(distdir.join("google_test")).unwrap();
+        fs::write(distdir.join("google_test").join(archive), "zip").unwrap();
+        assert_eq!(vendor(&root, &global, &distdir, true).unwrap(), 1);
+        assert_eq!(vendor(&root, &global, &distdir, true).unwrap(), 0);
+        let archive = format!("google_test-{}", archive);
+        assert!(root.join(VENDOR_DIR).join(&archive).is_file());
+
+        workspace::sync(&root, &dependencies, &BTreeMap::new(), &plugins, &global).unwrap();
+        assert!(fs::read_to_string(root.join("WORKSPACE"))
+            .unwrap()
+            .contains(&format!(
+                "file://{}",
+                fs::canonicalize(root.join(VENDOR_DIR))
+                    .unwrap()
+                    .join(archive)
+                    .display()
+            )));
+    }
+
+    #[test]
+    fn test_vendor_same_named_archives() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path().join("demo");
+        let distdir = tmp_dir.path().join("distdir");
+        fs::create_dir_all(&root).unwrap();
+        let lockfile = "[[package]]\nname = \"fmt\"\nversion = \"1.0.0\"\nsource = \"https://github.com/fmtlib/fmt/archive/refs/tags/v1.0.0.tar.gz\"\n\n[[package]]\nname = \"spdlog\"\nversion = \"1.0.0\"\nsource = \"https://github.com/gabime/spdlog/archive/refs/tags/v1.0.0.tar.gz\"\n";
+        fs::write(root.join(LOCKFILE), lockfile).unwrap();
+        for name in ["fmt", "spdlog"] {
+            fs::create_dir_all(distdir.join(name)).unwrap();
+            fs::write(distdir.join(name).join("v1.0.0.tar.gz"), name).unwrap();
+        }
+
+        let global = GlobalConfig::default();
+        assert_eq!(vendor(&root, &global, &distdir, true).unwrap(), 2);
+        let vendor = root.join(VENDOR_DIR);
+        assert_eq!(
+            fs::read_to_string(vendor.join("fmt-v1.0.0.tar.gz")).unwrap(),
+            "fmt"
+        );
+        assert_eq!(
+            fs::read_to_string(vendor.join("spdlog-v1.0.0.tar.gz")).unwrap(),
+            "spdlog"
+        );
+    }
+}
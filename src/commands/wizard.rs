@@ -0,0 +1,191 @@
+use clap::ValueEnum;
+use std::io::{BufRead, Write};
+
+use crate::template::{License, Vars};
+
+/// Shows `question` and its `choices`, names and descriptions, and asks
+/// until the answer is one of them, by number or name. Returns its index;
+/// an empty answer or the end of the input picks `default`.
+fn choose(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    question: &str,
+    choices: &[(&str, &str)],
+    default: usize,
+) -> Result<usize, String> {
+    writeln!(output, "{}", question).map_err(|e| e.to_string())?;
+    for (i, (name, description)) in choices.iter().enumerate() {
+        let line = if description.is_empty() {
+            name.to_string()
+        } else {
+            format!("{:<14}{}", name, description)
+        };
+        writeln!(output, "  {}) {}", i + 1, line).map_err(|e| e.to_string())?;
+    }
+    loop {
+        write!(output, "Choose [{}]: ", choices[default].0).map_err(|e| e.to_string())?;
+        output.flush().map_err(|e| e.to_string())?;
+        let mut line = String::new();
+        if input.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+            writeln!(output).map_err(|e| e.to_string())?;
+            return Ok(default);
+        }
+        let answer = line.trim();
+        if answer.is_empty() {
+            return Ok(default);
+        }
+        let by_number = answer
+            .parse::<usize>()
+            .ok()
+            .filter(|n| (1..=choices.len()).contains(n))
+            .map(|n| n - 1);
+        let by_name = || {
+            choices
+                .iter()
+                .position(|(name, _)| name.eq_ignore_ascii_case(answer))
+        };
+        match by_number.or_else(by_name) {
+            Some(choice) => return Ok(choice),
+            None => writeln!(output, "`{}` is not one of the choices", answer)
+                .map_err(|e| e.to_string())?,
+        }
+    }
+}
+
+/// Like `choose` among `names` and `current`, which is the default.
+fn choose_name(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    question: &str,
+    names: &[&str],
+    current: &str,
+) -> Result<String, String> {
+    let mut choices: Vec<_> = names.iter().map(|name| (*name, "")).collect();
+    let default = match names.iter().position(|name| *name == current) {
+        Some(default) => default,
+        None => {
+            choices.push((current, ""));
+            names.len()
+        }
+    };
+    let choice = choose(input, output, question, &choices, default)?;
+    Ok(choices[choice].0.to_string())
+}
+
+/// Asks what `buddy new --interactive` creates, the answers to the
+/// questions being the flags given so far: the package kind, the language
+/// standard, the test framework of C++ packages, the toolchain and the
+/// license.
+pub fn ask(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    vars: &mut Vars,
+) -> Result<(), String> {
+    let kinds = [
+        ("bin", "a program"),
+        ("lib", "a library with public headers under include/"),
+    ];
+    vars.lib = choose(
+        input,
+        output,
+        "What does the package build?",
+        &kinds,
+        vars.lib as usize,
+    )? == 1;
+
+    if vars.c {
+        vars.c_standard = choose_name(
+            input,
+            output,
+            "Which C standard?",
+            &["c11", "c17", "c23"],
+            &vars.c_standard,
+        )?;
+    } else {
+        vars.cxx_standard = choose_name(
+            input,
+            output,
+            "Which C++ standard?",
+            &["c++14", "c++17", "c++20", "c++23"],
+            &vars.cxx_standard,
+        )?;
+        let frameworks = [
+            ("googletest", "GoogleTest"),
+            ("none", "plain programs failing with a non-zero exit code"),
+        ];
+        let framework = choose(
+            input,
+            output,
+            "Which test framework?",
+            &frameworks,
+            !vars.googletest as usize,
+        )?;
+        vars.googletest = framework == 0;
+    }
+
+    let toolchains = [
+        ("llvm", "a hermetic LLVM toolchain bazel downloads"),
+        ("system", "the compiler installed on this machine"),
+    ];
+    let toolchain = choose(
+        input,
+        output,
+        "Which toolchain?",
+        &toolchains,
+        !vars.llvm_toolchain as usize,
+    )?;
+    vars.llvm_toolchain = toolchain == 0;
+
+    let licenses: Vec<_> = License::value_variants()
+        .iter()
+        .filter_map(|license| license.to_possible_value())
+        .collect();
+    let mut choices = vec![("none", "")];
+    choices.extend(licenses.iter().map(|license| (license.get_name(), "")));
+    let default = vars.license.map_or(0, |current| {
+        1 + License::value_variants()
+            .iter()
+            .position(|license| *license == current)
+            .unwrap_or_default()
+    });
+    let license = choose(input, output, "Which license?", &choices, default)?;
+    vars.license = license.checked_sub(1).map(|i| License::value_variants()[i]);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::init::PackageKind;
+    use crate::config::Language;
+    use crate::template;
+
+    #[test]
+    fn test_ask() {
+        let mut vars = Vars::new("app", Language::Cxx, PackageKind::Bin);
+        let mut input = "lib\n3\nnope\nnone\nsystem\nApache-2.0\n".as_bytes();
+        let mut output = Vec::new();
+        ask(&mut input, &mut output, &mut vars).unwrap();
+        assert!(vars.lib);
+        assert_eq!(vars.cxx_standard, "c++20");
+        assert!(!vars.googletest);
+        assert!(!vars.llvm_toolchain);
+        assert_eq!(vars.license, Some(License::Apache2));
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("  2) c++17\n"));
+        assert!(output.contains("`nope` is not one of the choices"));
+
+        let manifest = template::manifest(&vars);
+        assert!(!manifest.contains("bazel-toolchain"));
+        assert!(manifest.contains("pattern = \"*_test.cc\"\ndeps = []\n"));
+        assert!(!template::sources(&vars)[2].1.contains("gtest"));
+
+        // The end of the input keeps the answers given by the flags.
+        let mut vars = Vars::new("clib", Language::C, PackageKind::Lib);
+        ask(&mut "".as_bytes(), &mut Vec::new(), &mut vars).unwrap();
+        assert!(vars.lib);
+        assert_eq!(vars.c_standard, "c17");
+        assert!(vars.llvm_toolchain);
+        assert_eq!(vars.license, None);
+    }
+}
@@ -0,0 +1,168 @@
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+use crate::bazel;
+
+/// Where the compilation database is written, at the root of the project
+/// where clang-tidy and clangd look for it.
+pub const COMPDB: &str = "compile_commands.json";
+
+/// An entry of `compile_commands.json`.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct CompileCommand {
+    pub directory: String,
+    pub file: String,
+    pub arguments: Vec<String>,
+}
+
+/// `arg` pointing at the output base rather than at the `external/`
+/// directory of the execution root, which only holds the repositories of
+/// the actions bazel ran.
+fn rewrite_external(arg: &str, output_base: &Path) -> String {
+    for prefix in ["-isystem", "-iquote", "-I", ""] {
+        if let Some(path) = arg
+            .strip_prefix(prefix)
+            .and_then(|rest| rest.strip_prefix("external/"))
+        {
+            return format!(
+                "{}{}",
+                prefix,
+                output_base.join("external").join(path).display()
+            );
+        }
+    }
+    arg.to_string()
+}
+
+/// The compile commands of the project's own sources, from the JSON
+/// output of `bazel aquery` over the `CppCompile` actions. Sources are
+/// given by their path under `root`, the commands run in `execution_root`.
+pub fn parse(
+    aquery: &str,
+    root: &Path,
+    execution_root: &Path,
+    output_base: &Path,
+) -> Result<Vec<CompileCommand>, String> {
+    let aquery: serde_json::Value =
+        serde_json::from_str(aquery).map_err(|e| format!("invalid aquery output: {}", e))?;
+
+    let mut commands = Vec::new();
+    for action in aquery["actions"].as_array().into_iter().flatten() {
+        let arguments: Vec<&str> = action["arguments"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|arg| arg.as_str())
+            .collect();
+        let Some(source) = arguments
+            .iter()
+            .position(|arg| *arg == "-c")
+            .and_then(|i| arguments.get(i + 1))
+        else {
+            continue;
+        };
+        // Dependencies and generated sources aren't the project's to lint.
+        if source.starts_with("external/") || source.starts_with("bazel-out/") {
+            continue;
+        }
+        let file = root.join(source).display().to_string();
+        if commands
+            .iter()
+            .any(|command: &CompileCommand| command.file == file)
+        {
+            continue;
+        }
+        commands.push(CompileCommand {
+            directory: execution_root.display().to_string(),
+            file,
+            arguments: arguments
+                .iter()
+                .map(|arg| rewrite_external(arg, output_base))
+                .collect(),
+        });
+    }
+    Ok(commands)
+}
+
+/// The value of `key` in the `key: value` lines of `bazel info`.
+fn info<'a>(output: &'a str, key: &str) -> Result<&'a str, String> {
+    output
+        .lines()
+        .find_map(|line| line.strip_prefix(key)?.strip_prefix(": "))
+        .ok_or_else(|| format!("`bazel info` did not report the {}", key))
+}
+
+/// Writes `compile_commands.json` for the C/C++ sources of the project,
+/// compiled with the build `flags`. Returns the number of sources.
+pub fn generate(bazel_bin: &Path, flags: &[String]) -> Result<usize, String> {
+    let mut cmd = bazel::command(bazel_bin, "info");
+    cmd.args(flags).args(["execution_root", "output_base"]);
+    let output = bazel::output(&mut cmd)?;
+    let execution_root = Path::new(info(&output, "execution_root")?);
+    let output_base = Path::new(info(&output, "output_base")?);
+
+    let mut cmd = bazel::command(bazel_bin, "aquery");
+    cmd.args(flags)
+        .arg("mnemonic(\"CppCompile\", //...)")
+        .arg("--output=jsonproto");
+    let aquery = bazel::output(&mut cmd)?;
+
+    let root = std::env::current_dir().map_err(|e| e.to_string())?;
+    let commands = parse(&aquery, &root, execution_root, output_base)?;
+    let contents = serde_json::to_string_pretty(&commands).map_err(|e| e.to_string())?;
+    fs::write(COMPDB, contents).map_err(|e| format!("{}: {}", COMPDB, e))?;
+    Ok(commands.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let aquery = r#"{
+  "actions": [{
+    "mnemonic": "CppCompile",
+    "arguments": ["/usr/bin/clang", "-iquote", "external/com_google_googletest", "-Iexternal/com_google_googletest/googletest/include", "-std=c++17", "-c", "test/demo_test.cc", "-o", "bazel-out/k8-fastbuild/bin/test/_objs/demo_test/demo_test.o"]
+  }, {
+    "mnemonic": "CppCompile",
+    "arguments": ["/usr/bin/clang", "-c", "external/com_google_googletest/googletest/src/gtest-all.cc"]
+  }, {
+    "mnemonic": "CppCompile",
+    "arguments": ["/usr/bin/clang", "-fPIC", "-c", "test/demo_test.cc"]
+  }]
+}"#;
+        let commands = parse(
+            aquery,
+            Path::new("/home/me/demo"),
+            Path::new("/cache/execroot/demo"),
+            Path::new("/cache"),
+        )
+        .unwrap();
+        assert_eq!(
+            commands,
+            [CompileCommand {
+                directory: "/cache/execroot/demo".to_string(),
+                file: "/home/me/demo/test/demo_test.cc".to_string(),
+                arguments: [
+                    "/usr/bin/clang",
+                    "-iquote",
+                    "/cache/external/com_google_googletest",
+                    "-I/cache/external/com_google_googletest/googletest/include",
+                    "-std=c++17",
+                    "-c",
+                    "test/demo_test.cc",
+                    "-o",
+                    "bazel-out/k8-fastbuild/bin/test/_objs/demo_test/demo_test.o",
+                ]
+                .map(String::from)
+                .to_vec(),
+            }]
+        );
+        assert_eq!(
+            info("execution_root: /x\noutput_base: /y\n", "output_base").unwrap(),
+            "/y"
+        );
+    }
+}
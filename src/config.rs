@@ -0,0 +1,965 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+use crate::features::Features;
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct Package {
+    pub name: String,
+    pub version: String,
+    pub edition: String,
+    /// One line telling what the package is, e.g. on its documentation.
+    pub description: Option<String>,
+    /// Version of buddy which generated or last upgraded the project.
+    pub buddy_version: Option<String>,
+    /// The `[[bin]]` a bare `buddy run` executes.
+    pub default_run: Option<String>,
+    #[serde(default)]
+    pub language: Language,
+    /// Standard the C sources are compiled against, e.g. `c11` or `c17`.
+    pub c_standard: Option<String>,
+    /// Standard the C++ sources are compiled against, e.g. `c++20`.
+    pub cxx_standard: Option<String>,
+    /// `Name <email>` of each author.
+    #[serde(default)]
+    pub authors: Vec<String>,
+    /// SPDX expression of the license, e.g. `MIT OR Apache-2.0`.
+    pub license: Option<String>,
+}
+
+/// The language of a package's sources.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Language {
+    #[default]
+    #[serde(rename = "c++")]
+    #[value(name = "c++")]
+    Cxx,
+    #[serde(rename = "c")]
+    C,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct Config {
+    /// Absent from the manifest at the root of a workspace.
+    #[serde(default)]
+    pub package: Package,
+    #[serde(default)]
+    pub dependencies: HashMap<String, Dependency>,
+    /// Dependencies only used when a feature enables them with
+    /// `"dep:<name>"`.
+    #[serde(default)]
+    pub optional_dependencies: HashMap<String, String>,
+    #[serde(default)]
+    pub test: TestConfig,
+    #[serde(default)]
+    pub ci: CiConfig,
+    #[serde(default)]
+    pub hooks: HooksConfig,
+    #[serde(default)]
+    pub features: Features,
+    #[serde(default)]
+    pub build: BuildConfig,
+    #[serde(default)]
+    pub profile: Profiles,
+    #[serde(default)]
+    pub bin: Vec<BinConfig>,
+    #[serde(default)]
+    pub doc: DocConfig,
+    #[serde(default)]
+    pub lints: LintsConfig,
+    #[serde(default)]
+    pub lib: LibConfig,
+    #[serde(default)]
+    pub modules: BTreeMap<String, ModuleConfig>,
+    /// The `[env]` table: the variables set for the build actions, the
+    /// tests and `buddy run`, by name.
+    #[serde(default)]
+    pub env: BTreeMap<String, EnvVar>,
+    /// The `[patch]` table: dependencies temporarily built from a git
+    /// repository or a local package in place of their recipe, e.g. to
+    /// test a fix.
+    #[serde(default)]
+    pub patch: BTreeMap<String, Source>,
+    /// The `[target.'cfg(<os>)']` tables, by their `cfg(...)` key.
+    #[serde(default)]
+    pub target: BTreeMap<String, PlatformConfig>,
+    /// The `[workspace]` table of the manifest at the root of several
+    /// packages.
+    #[serde(default)]
+    pub workspace: Option<WorkspaceConfig>,
+}
+
+/// The `[workspace]` table: `members = ["app", "libfoo"]`, the directories
+/// of the packages built together, sharing the lockfile and the WORKSPACE
+/// of the root.
+#[derive(Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct WorkspaceConfig {
+    pub members: Vec<String>,
+}
+
+/// A `[target.'cfg(linux)']` table: what only applies on one platform.
+#[derive(Debug, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct PlatformConfig {
+    /// Dependencies only linked when building for the platform.
+    #[serde(default)]
+    pub dependencies: HashMap<String, String>,
+}
+
+/// The operating systems `cfg(...)` may name, the `@platforms//os` ones.
+const PLATFORM_OSES: [&str; 4] = ["linux", "macos", "windows", "freebsd"];
+
+/// The Bazel condition of a `[target]` key, `cfg(linux)` or
+/// `cfg(target_os = "linux")`, e.g. `@platforms//os:linux`.
+pub fn platform_condition(cfg: &str) -> Result<String, String> {
+    let os = cfg
+        .trim()
+        .strip_prefix("cfg(")
+        .and_then(|cfg| cfg.strip_suffix(')'))
+        .map(|os| match os.split_once('=') {
+            Some((key, value)) if key.trim() == "target_os" => value.trim().trim_matches('"'),
+            _ => os.trim(),
+        })
+        .filter(|os| PLATFORM_OSES.contains(os))
+        .ok_or_else(|| {
+            format!(
+                "unsupported target `{}`, expected `cfg(<os>)` with one of: {}",
+                cfg,
+                PLATFORM_OSES.join(", ")
+            )
+        })?;
+    Ok(format!("@platforms//os:{}", os))
+}
+
+/// A variable of the `[env]` table, a value (`LOG_LEVEL = "debug"`) or the
+/// one of the host (`JAVA_HOME = { from-host = true }`). Only the variables
+/// listed are passed on, the rest of the host environment stays out of the
+/// build.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum EnvVar {
+    Value(String),
+    #[serde(rename_all = "kebab-case")]
+    Host {
+        from_host: bool,
+        /// Value used when the host doesn't set the variable.
+        default: Option<String>,
+    },
+}
+
+impl EnvVar {
+    /// The value of the variable `name`, `None` when it is read from a host
+    /// not setting it and has no default.
+    pub fn resolve(&self, name: &str) -> Option<String> {
+        match self {
+            EnvVar::Value(value) => Some(value.clone()),
+            EnvVar::Host { from_host, default } => from_host
+                .then(|| std::env::var(name).ok())
+                .flatten()
+                .or_else(|| default.clone()),
+        }
+    }
+}
+
+/// A dependency of `[dependencies]`, a version of its recipe
+/// (`fmt = "10.1.1"`), possibly optional, or a source of its own.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Dependency {
+    Version(String),
+    Detailed(DetailedDependency),
+    Source(Source),
+}
+
+/// A version of a recipe given as a table, which may mark it optional:
+/// `json = { version = "3.11.2", optional = true }`. Optional dependencies
+/// are only used when a feature enables them, like the ones of
+/// `[optional-dependencies]`.
+#[derive(Debug, Deserialize, Clone, PartialEq, Default)]
+#[serde(deny_unknown_fields)]
+pub struct DetailedDependency {
+    pub version: String,
+    #[serde(default)]
+    pub optional: bool,
+}
+
+/// Where a dependency without a recipe is built from.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Source {
+    Git(GitDependency),
+    Path(PathDependency),
+}
+
+/// A dependency built from a git repository, at a commit, a branch or a
+/// tag: `mylib = { git = "https://github.com/me/mylib", tag = "v1.2.0" }`.
+#[derive(Debug, Deserialize, Clone, PartialEq, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct GitDependency {
+    pub git: String,
+    pub rev: Option<String>,
+    pub branch: Option<String>,
+    pub tag: Option<String>,
+    /// BUILD file of a repository which has none, relative to the package.
+    pub build_file: Option<String>,
+}
+
+impl GitDependency {
+    /// The reference followed, as written in the manifest, `HEAD` when none
+    /// is.
+    pub fn reference(&self) -> Result<&str, String> {
+        match (&self.rev, &self.branch, &self.tag) {
+            (None, None, None) => Ok("HEAD"),
+            (Some(reference), None, None)
+            | (None, Some(reference), None)
+            | (None, None, Some(reference)) => Ok(reference),
+            _ => Err(format!(
+                "`{}` may only set one of `rev`, `branch` and `tag`",
+                self.git
+            )),
+        }
+    }
+}
+
+/// A buddy package next to this one on disk, developed along with it:
+/// `mylib = { path = "../mylib" }`.
+#[derive(Debug, Deserialize, Clone, PartialEq, Default)]
+#[serde(deny_unknown_fields)]
+pub struct PathDependency {
+    /// Directory of the package, relative to the one depending on it.
+    pub path: String,
+}
+
+impl PathDependency {
+    /// The label of the library of the package, `@<name>//src:<package>`,
+    /// read from its manifest.
+    pub fn label(&self, root: &Path, name: &str) -> Result<String, String> {
+        let manifest = root.join(&self.path).join("Buddy.toml");
+        let contents = std::fs::read_to_string(&manifest).map_err(|_| {
+            format!(
+                "`{}` depends on `{}`, which has no `Buddy.toml`",
+                name, self.path
+            )
+        })?;
+        let config: Config = toml::from_str(&contents)
+            .map_err(|e| format!("failed to parse {}: {}", manifest.display(), e))?;
+        Ok(format!(
+            "@{}//src:{}",
+            repository_name(name),
+            config.package.name
+        ))
+    }
+}
+
+impl Config {
+    /// The `[dependencies]` on versions of recipes, the patched ones left
+    /// out.
+    pub fn versions(&self) -> HashMap<String, String> {
+        self.dependencies
+            .iter()
+            .filter(|(name, _)| !self.patch.contains_key(*name))
+            .filter_map(|(name, dependency)| match dependency {
+                Dependency::Version(version) => Some((name.clone(), version.clone())),
+                Dependency::Detailed(detailed) if !detailed.optional => {
+                    Some((name.clone(), detailed.version.clone()))
+                }
+                Dependency::Detailed(_) | Dependency::Source(_) => None,
+            })
+            .collect()
+    }
+
+    /// The optional dependencies, of `[optional-dependencies]` and the ones
+    /// of `[dependencies]` marked `optional = true`.
+    pub fn optional_versions(&self) -> HashMap<String, String> {
+        let mut optional = self.optional_dependencies.clone();
+        for (name, dependency) in &self.dependencies {
+            if let Dependency::Detailed(detailed) = dependency {
+                if detailed.optional {
+                    optional.insert(name.clone(), detailed.version.clone());
+                }
+            }
+        }
+        optional
+    }
+
+    /// The `[dependencies]` on git repositories and local packages, along
+    /// with the sources of the patched dependencies.
+    pub fn sources(&self) -> BTreeMap<String, Source> {
+        let mut sources: BTreeMap<String, Source> = self
+            .dependencies
+            .iter()
+            .filter_map(|(name, dependency)| match dependency {
+                Dependency::Source(source) => Some((name.clone(), source.clone())),
+                Dependency::Version(_) | Dependency::Detailed(_) => None,
+            })
+            .collect();
+        for name in self.patched() {
+            sources.insert(name.clone(), self.patch[&name].clone());
+        }
+        sources
+    }
+
+    /// The dependencies, optional and platform ones included, a `[patch]`
+    /// entry replaces.
+    pub fn patched(&self) -> BTreeSet<String> {
+        self.patch
+            .keys()
+            .filter(|name| {
+                self.dependencies.contains_key(*name)
+                    || self.optional_dependencies.contains_key(*name)
+                    || self
+                        .target
+                        .values()
+                        .any(|platform| platform.dependencies.contains_key(*name))
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// The local packages of `[dependencies]` which aren't patched.
+    pub fn path_dependencies(&self) -> BTreeMap<String, PathDependency> {
+        self.dependencies
+            .iter()
+            .filter(|(name, _)| !self.patch.contains_key(*name))
+            .filter_map(|(name, dependency)| match dependency {
+                Dependency::Source(Source::Path(path)) => Some((name.clone(), path.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The dependencies of the `[target]` tables, by the condition of their
+    /// platform, the patched ones left out.
+    pub fn platform_dependencies(
+        &self,
+    ) -> Result<BTreeMap<String, HashMap<String, String>>, String> {
+        let mut platforms: BTreeMap<String, HashMap<String, String>> = BTreeMap::new();
+        for (cfg, platform) in &self.target {
+            platforms
+                .entry(platform_condition(cfg)?)
+                .or_default()
+                .extend(
+                    platform
+                        .dependencies
+                        .iter()
+                        .filter(|(name, _)| !self.patch.contains_key(*name))
+                        .map(|(name, version)| (name.clone(), version.clone())),
+                );
+        }
+        Ok(platforms)
+    }
+
+    /// The variables of `[env]` with their value, the unset ones left out.
+    pub fn env_vars(&self) -> Vec<(String, String)> {
+        self.env
+            .iter()
+            .filter_map(|(name, var)| Some((name.clone(), var.resolve(name)?)))
+            .collect()
+    }
+}
+
+/// A `[modules.<name>]` table: the subdirectory `src/<name>` of a large
+/// package, built as a library of its own.
+#[derive(Debug, Deserialize, Default)]
+pub struct ModuleConfig {
+    /// Modules this one uses, by name (`net`, `net/http`), or Bazel labels.
+    #[serde(default)]
+    pub deps: Vec<String>,
+}
+
+/// An output of the package's library.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LibType {
+    /// The `.a` archive of the `cc_library`.
+    Static,
+    /// A versioned `.so` built by a `cc_shared_library`.
+    Shared,
+}
+
+/// Symbols a shared library exports by default.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    /// Only the symbols marked with the generated export macro.
+    #[default]
+    Hidden,
+    /// Every symbol, the compiler's default.
+    Default,
+}
+
+/// The `[lib]` table, how the package's library is distributed.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LibConfig {
+    #[serde(rename = "type", default = "default_lib_types")]
+    pub types: Vec<LibType>,
+    #[serde(default)]
+    pub visibility: Visibility,
+    /// Linker version script of the shared library, for ELF platforms.
+    pub version_script: Option<String>,
+    /// File listing the symbols the shared library exports on macOS.
+    pub exported_symbols_list: Option<String>,
+    /// Patterns of the headers making up the public API, e.g.
+    /// `include/mylib/**.h`. The other headers are private to the library.
+    #[serde(default)]
+    pub public_headers: Vec<String>,
+    /// Directory public headers are included from, e.g. `mylib` for
+    /// `#include <mylib/foo.h>`, prepended to their path relative to the
+    /// pattern.
+    pub include_prefix: Option<String>,
+}
+
+impl Default for LibConfig {
+    fn default() -> Self {
+        LibConfig {
+            types: default_lib_types(),
+            visibility: Visibility::default(),
+            version_script: None,
+            exported_symbols_list: None,
+            public_headers: Vec::new(),
+            include_prefix: None,
+        }
+    }
+}
+
+fn default_lib_types() -> Vec<LibType> {
+    vec![LibType::Static]
+}
+
+/// The `[lints]` table: the clang-tidy checks `buddy lint` runs, as
+/// clang-tidy globs, e.g. `["bugprone-*", "-bugprone-macro-parentheses"]`.
+/// Without checks clang-tidy reads its `.clang-tidy` files.
+#[derive(Debug, Deserialize, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct LintsConfig {
+    #[serde(default)]
+    pub checks: Vec<String>,
+    /// Checks failing `buddy lint` rather than only warning.
+    #[serde(default)]
+    pub warnings_as_errors: Vec<String>,
+}
+
+/// The `[doc]` table, what `buddy doc` hands to Doxygen.
+#[derive(Debug, Deserialize)]
+pub struct DocConfig {
+    /// Directories to document, the ones missing are skipped.
+    #[serde(default = "default_doc_input")]
+    pub input: Vec<String>,
+    /// Doxygen patterns of files to leave out, e.g. `*/internal/*`.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Where `buddy doc --publish` pushes the documentation: a git branch
+    /// served by GitHub Pages, or an `s3://bucket/prefix` URL.
+    #[serde(default = "default_doc_publish")]
+    pub publish: String,
+}
+
+impl Default for DocConfig {
+    fn default() -> Self {
+        DocConfig {
+            input: default_doc_input(),
+            exclude: Vec::new(),
+            publish: default_doc_publish(),
+        }
+    }
+}
+
+fn default_doc_publish() -> String {
+    "gh-pages".to_string()
+}
+
+fn default_doc_input() -> Vec<String> {
+    vec!["include".to_string(), "src".to_string()]
+}
+
+/// A `[[bin]]` entry, one of the binaries `buddy run` can pick from.
+#[derive(Debug, Deserialize)]
+pub struct BinConfig {
+    pub name: String,
+    /// Source file defining `main`, `src/<name>.cc` by default.
+    pub path: Option<PathBuf>,
+}
+
+impl BinConfig {
+    /// The label of the binary, a target of the package holding its source.
+    pub fn label(&self) -> String {
+        let dir = self
+            .path
+            .as_deref()
+            .and_then(Path::parent)
+            .and_then(Path::to_str)
+            .filter(|dir| !dir.is_empty())
+            .unwrap_or("src");
+        format!("//{}:{}", dir.replace('\\', "/"), self.name)
+    }
+}
+
+/// The `[build]` table, defaults of `buddy build` and `buddy test`.
+#[derive(Debug, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct BuildConfig {
+    /// Keep building the other targets when one fails, reporting every
+    /// error in a single run.
+    #[serde(default)]
+    pub keep_going: bool,
+    /// Number of concurrent jobs, defaults to the number of cores.
+    pub jobs: Option<u32>,
+    /// RAM Bazel may schedule local actions against, e.g. `8G`, `512M` or
+    /// `50%` of the machine's.
+    pub local_ram: Option<String>,
+    /// Linker to use instead of the toolchain's default: `lld`, `mold`,
+    /// `gold`, or `auto` for the fastest one installed.
+    pub linker: Option<String>,
+    /// Apple frameworks linked on macOS, e.g. `Foundation` or `AppKit`.
+    #[serde(default)]
+    pub frameworks: Vec<String>,
+    /// Compiler flags of the project's sources, e.g. `-Wall`.
+    #[serde(default)]
+    pub copts: Vec<String>,
+    /// Linker flags of the project's binaries, tests and shared libraries.
+    #[serde(default)]
+    pub linkopts: Vec<String>,
+    /// Include directories of the project's sources, relative to its root.
+    #[serde(default)]
+    pub includes: Vec<String>,
+    /// Preprocessor definitions of the project's sources, `NAME` or
+    /// `NAME=value`.
+    #[serde(default)]
+    pub defines: Vec<String>,
+}
+
+/// The `[profile.dev]` and `[profile.release]` tables: how the builds are
+/// compiled by default, and with `--release`.
+#[derive(Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Profiles {
+    #[serde(default)]
+    pub dev: ProfileConfig,
+    #[serde(default)]
+    pub release: ProfileConfig,
+}
+
+impl Profiles {
+    /// The release profile with `release`, the dev one otherwise.
+    pub fn get(&self, release: bool) -> &ProfileConfig {
+        if release {
+            &self.release
+        } else {
+            &self.dev
+        }
+    }
+}
+
+/// A `[profile.<name>]` table.
+#[derive(Debug, Deserialize, Default, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ProfileConfig {
+    /// Bazel's `--compilation_mode`: `dbg`, `opt` or `fastbuild`. Defaults to
+    /// `dbg` for dev and `opt` for release.
+    pub compilation_mode: Option<String>,
+    /// Options passed to the compiler, e.g. `-march=native`.
+    #[serde(default)]
+    pub copts: Vec<String>,
+    /// Options passed to the linker.
+    #[serde(default)]
+    pub linkopts: Vec<String>,
+    /// Preprocessor macros, `NAME` or `NAME=VALUE`.
+    #[serde(default)]
+    pub defines: Vec<String>,
+    /// Compile and link with link-time optimization.
+    #[serde(default)]
+    pub lto: bool,
+}
+
+/// The `[test]` table, driving the `cc_test` targets generated for `test/`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TestConfig {
+    /// File name pattern of test sources, one `cc_test` is generated per match.
+    #[serde(default = "default_test_pattern")]
+    pub pattern: String,
+    /// Dependencies shared by every generated test target.
+    #[serde(default = "default_test_deps")]
+    pub deps: Vec<String>,
+    /// Settings of single tests, by target name.
+    #[serde(default)]
+    pub targets: BTreeMap<String, TestTarget>,
+}
+
+impl Default for TestConfig {
+    fn default() -> Self {
+        TestConfig {
+            pattern: default_test_pattern(),
+            deps: default_test_deps(),
+            targets: BTreeMap::new(),
+        }
+    }
+}
+
+/// A `[test.targets.<name>]` table.
+#[derive(Debug, Deserialize)]
+pub struct TestTarget {
+    /// Whether bazel may reuse the result of a previous run, off for tests
+    /// that aren't hermetic, e.g. because they use the network.
+    #[serde(default = "default_test_cache")]
+    pub cache: bool,
+    /// Bazel tags of the `cc_test`, selected with `buddy test --tag` and
+    /// `--exclude-tag`, e.g. `integration`.
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+fn default_test_pattern() -> String {
+    "*_test.cc".to_string()
+}
+
+fn default_test_deps() -> Vec<String> {
+    vec!["@com_google_googletest//:gtest_main".to_string()]
+}
+
+fn default_test_cache() -> bool {
+    true
+}
+
+/// The `[ci]` table, read by the pipeline generators.
+#[derive(Debug, Deserialize)]
+pub struct CiConfig {
+    /// Platforms the project supports, each one becomes a CI runner.
+    #[serde(default = "default_ci_platforms")]
+    pub platforms: Vec<String>,
+}
+
+impl Default for CiConfig {
+    fn default() -> Self {
+        CiConfig {
+            platforms: default_ci_platforms(),
+        }
+    }
+}
+
+fn default_ci_platforms() -> Vec<String> {
+    vec!["linux".to_string()]
+}
+
+/// The `[hooks]` table: which checks each git hook runs. Supported checks
+/// are `fmt`, `lint`, `build` and `test`; an empty list means no hook.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct HooksConfig {
+    #[serde(default = "default_pre_commit")]
+    pub pre_commit: Vec<String>,
+    #[serde(default)]
+    pub pre_push: Vec<String>,
+}
+
+impl Default for HooksConfig {
+    fn default() -> Self {
+        HooksConfig {
+            pre_commit: default_pre_commit(),
+            pre_push: Vec::new(),
+        }
+    }
+}
+
+fn default_pre_commit() -> Vec<String> {
+    vec!["fmt".to_string(), "lint".to_string()]
+}
+
+/// Reads the manifest at `path`, the one of a package or of the root of a
+/// workspace, which has no package of its own.
+pub fn load(path: &Path) -> Result<Config, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    let config: Config = toml::from_str(&contents)
+        .map_err(|e| format!("failed to parse {}: {}", path.display(), e))?;
+    match &config.workspace {
+        Some(_) if !config.package.name.is_empty() => Err(format!(
+            "{} declares both [package] and [workspace], move the package into a member",
+            path.display()
+        )),
+        None if config.package.name.is_empty() => {
+            Err(format!("{} has no [package] table", path.display()))
+        }
+        _ => Ok(config),
+    }
+}
+
+/// Checks that `name` can be used as a package name, which also ends up as a
+/// Bazel target name: ASCII letters, digits, `-` and `_`, starting with a
+/// letter.
+pub fn validate_package_name(name: &str) -> Result<(), String> {
+    let mut chars = name.chars();
+
+    match chars.next() {
+        None => return Err("package name cannot be empty".to_string()),
+        Some(c) if !c.is_ascii_alphabetic() => {
+            return Err(format!(
+                "invalid package name `{}`: names must start with a letter",
+                name
+            ))
+        }
+        _ => {}
+    }
+
+    if let Some(c) = chars.find(|c| !(c.is_ascii_alphanumeric() || *c == '-' || *c == '_')) {
+        return Err(format!(
+            "invalid character `{}` in package name `{}`: only letters, digits, `-` and `_` are allowed",
+            c, name
+        ));
+    }
+
+    Ok(())
+}
+
+/// Splits a dependency name into its optional scope and bare name, e.g.
+/// `corp/logging` into `corp` and `logging`.
+pub fn split_scope(name: &str) -> (Option<&str>, &str) {
+    match name.split_once('/') {
+        Some((scope, name)) => (Some(scope), name),
+        None => (None, name),
+    }
+}
+
+/// Checks a dependency name, which may be scoped to an organisation
+/// (`"corp/logging" = "2.1"`, quoted in TOML). The scope and the name both
+/// follow the package name rules.
+pub fn validate_dependency_name(name: &str) -> Result<(), String> {
+    let (scope, bare) = split_scope(name);
+    if let Some(scope) = scope {
+        validate_package_name(scope)
+            .map_err(|error| format!("invalid scope in `{}`: {}", name, error))?;
+    }
+    validate_package_name(bare).map_err(|error| format!("invalid dependency `{}`: {}", name, error))
+}
+
+/// The Bazel repository a dependency is fetched into. The scope is joined
+/// with a double underscore so that `corp/my_lib` and `corp_my/lib` don't
+/// collide.
+pub fn repository_name(name: &str) -> String {
+    let sanitize = |part: &str| part.replace('-', "_");
+    match split_scope(name) {
+        (Some(scope), bare) => format!("{}__{}", sanitize(scope), sanitize(bare)),
+        (None, bare) => sanitize(bare),
+    }
+}
+
+/// Picks the package name for a project created at `path`: the explicit
+/// `name` when given, otherwise the last component of the path as long as it
+/// is a valid package name.
+pub fn package_name(path: &Path, name: Option<&str>) -> Result<String, String> {
+    if let Some(name) = name {
+        validate_package_name(name)?;
+        return Ok(name.to_string());
+    }
+
+    let folder_name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+
+    validate_package_name(folder_name).map_err(|error| {
+        format!(
+            "cannot use the directory name as package name ({}), use --name to set one explicitly",
+            error
+        )
+    })?;
+
+    Ok(folder_name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_validate_package_name() {
+        assert!(validate_package_name("hello").is_ok());
+        assert!(validate_package_name("hello-world_2").is_ok());
+        assert!(validate_package_name("").is_err());
+        assert!(validate_package_name("7up").is_err());
+        assert!(validate_package_name("my.app").is_err());
+    }
+
+    #[test]
+    fn test_scoped_dependency_names() {
+        assert_eq!(split_scope("corp/logging"), (Some("corp"), "logging"));
+        assert_eq!(split_scope("zlib"), (None, "zlib"));
+        assert!(validate_dependency_name("corp/logging").is_ok());
+        assert!(validate_dependency_name("corp/").is_err());
+        assert!(validate_dependency_name("corp/a/b").is_err());
+        assert_eq!(repository_name("corp/my-lib"), "corp__my_lib");
+        assert_ne!(
+            repository_name("corp/my_lib"),
+            repository_name("corp_my/lib")
+        );
+    }
+
+    #[test]
+    fn test_package_name() {
+        let path = Path::new("repos/2024/experiment-7");
+        assert_eq!(package_name(path, None).unwrap(), "experiment-7");
+        assert_eq!(package_name(path, Some("exp")).unwrap(), "exp");
+        assert!(package_name(Path::new("repos/2024"), None).is_err());
+        assert!(package_name(path, Some("bad name")).is_err());
+    }
+
+    #[test]
+    fn test_env_vars() {
+        let config: Config = toml::from_str(
+            r#"
+dependencies = {}
+
+[package]
+name = "demo"
+version = "0.1.0"
+edition = "2024"
+
+[env]
+LOG_LEVEL = "debug"
+BUDDY_TEST_UNSET_VAR = { from-host = true }
+BUDDY_TEST_DEFAULT_VAR = { from-host = true, default = "fallback" }
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.env_vars(),
+            [
+                ("BUDDY_TEST_DEFAULT_VAR".to_string(), "fallback".to_string()),
+                ("LOG_LEVEL".to_string(), "debug".to_string()),
+            ]
+        );
+        assert!(toml::from_str::<Config>(
+            "dependencies = {}\n[package]\nname = \"demo\"\nversion = \"0.1.0\"\nedition = \"2024\"\n[env]\nHOME = { from_host = true }\n"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_sources() {
+        let config: Config = toml::from_str(
+            r#"
+[package]
+name = "demo"
+version = "0.1.0"
+edition = "2024"
+
+[dependencies]
+fmt = "10.1.1"
+mylib = { git = "https://github.com/me/mylib", tag = "v1.2.0" }
+core = { path = "../core" }
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.versions()["fmt"], "10.1.1");
+        let sources = config.sources();
+        assert!(!sources.contains_key("fmt"));
+        let Source::Git(git) = &sources["mylib"] else {
+            panic!("`mylib` isn't a git dependency");
+        };
+        assert_eq!(git.reference(), Ok("v1.2.0"));
+        let Source::Path(path) = &sources["core"] else {
+            panic!("`core` isn't a path dependency");
+        };
+        assert_eq!(path.path, "../core");
+
+        let both = GitDependency {
+            rev: Some("abc123".to_string()),
+            branch: Some("main".to_string()),
+            ..Default::default()
+        };
+        assert!(both.reference().is_err());
+        assert!(toml::from_str::<Config>(
+            "[package]\nname = \"demo\"\nversion = \"0.1.0\"\nedition = \"2024\"\n[dependencies]\nmylib = { git = \"x\", commit = \"abc\" }\n"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_load() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let manifest = tmp_dir.path().join("Buddy.toml");
+        fs::write(&manifest, "[workspace]\nmembers = [\"app\", \"libfoo\"]\n").unwrap();
+        let config = load(&manifest).unwrap();
+        assert_eq!(config.workspace.unwrap().members, ["app", "libfoo"]);
+
+        fs::write(
+            &manifest,
+            "[workspace]\nmembers = [\"app\"]\n[package]\nname = \"demo\"\nversion = \"0.1.0\"\nedition = \"2024\"\n",
+        )
+        .unwrap();
+        assert!(load(&manifest)
+            .unwrap_err()
+            .contains("declares both [package] and [workspace]"));
+
+        fs::write(&manifest, "[dependencies]\nfmt = \"10.1.1\"\n").unwrap();
+        assert!(load(&manifest)
+            .unwrap_err()
+            .contains("has no [package] table"));
+    }
+
+    #[test]
+    fn test_platform_dependencies() {
+        assert_eq!(
+            platform_condition("cfg(linux)").unwrap(),
+            "@platforms//os:linux"
+        );
+        assert_eq!(
+            platform_condition("cfg(target_os = \"macos\")").unwrap(),
+            "@platforms//os:macos"
+        );
+        assert!(platform_condition("cfg(beos)").is_err());
+        assert!(platform_condition("linux").is_err());
+
+        let config: Config = toml::from_str(
+            r#"
+[package]
+name = "demo"
+version = "0.1.0"
+edition = "2024"
+
+[dependencies]
+
+[target.'cfg(linux)'.dependencies]
+liburing = "2.5"
+"#,
+        )
+        .unwrap();
+        assert!(config.versions().is_empty());
+        assert_eq!(
+            config.platform_dependencies().unwrap()["@platforms//os:linux"]["liburing"],
+            "2.5"
+        );
+    }
+
+    #[test]
+    fn test_path_dependency_label() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path().join("app");
+        let core = tmp_dir.path().join("core");
+        fs::create_dir_all(&root).unwrap();
+        fs::create_dir_all(&core).unwrap();
+        let dependency = PathDependency {
+            path: "../core".to_string(),
+        };
+        assert!(dependency.label(&root, "my-core").is_err());
+
+        fs::write(
+            core.join("Buddy.toml"),
+            "[package]\nname = \"core\"\nversion = \"0.1.0\"\nedition = \"2024\"\n[dependencies]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            dependency.label(&root, "my-core").unwrap(),
+            "@my_core//src:core"
+        );
+    }
+}
@@ -0,0 +1,274 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use which::which;
+
+use crate::artifacts;
+use crate::commands::profile::open_viewer;
+use crate::style::{self, Level};
+
+/// Where `buddy test --coverage` writes the merged report.
+pub const COVERAGE_DIR: &str = "target/coverage";
+
+/// Only instruments the project's own code, not its dependencies nor its
+/// tests.
+pub const INSTRUMENTATION_FLAG: &str = "--instrumentation_filter=^//";
+
+/// The line and function coverage of a source file.
+#[derive(Debug, Default, PartialEq)]
+pub struct FileCoverage {
+    /// Hits by line.
+    pub lines: BTreeMap<u32, u64>,
+    /// Line and hits by function.
+    pub functions: BTreeMap<String, (u32, u64)>,
+}
+
+impl FileCoverage {
+    fn lines_hit(&self) -> usize {
+        self.lines.values().filter(|hits| **hits > 0).count()
+    }
+
+    fn functions_hit(&self) -> usize {
+        self.functions
+            .values()
+            .filter(|(_, hits)| *hits > 0)
+            .count()
+    }
+}
+
+/// The coverage of every source file, by path.
+pub type Coverage = BTreeMap<String, FileCoverage>;
+
+/// Adds the LCOV tracefile `lcov` to `coverage`, summing the hits of the
+/// lines and functions seen in several tests. Sources of external
+/// repositories are left out.
+pub fn merge(coverage: &mut Coverage, lcov: &str) {
+    let mut current: Option<&mut FileCoverage> = None;
+    for line in lcov.lines() {
+        let (record, value) = line.split_once(':').unwrap_or((line, ""));
+        match record {
+            "SF" => {
+                current = match value.starts_with("external/") {
+                    true => None,
+                    false => Some(coverage.entry(value.to_string()).or_default()),
+                }
+            }
+            "DA" => {
+                let mut fields = value.split(',');
+                let (Some(Ok(number)), Some(Ok(hits))) = (
+                    fields.next().map(str::parse::<u32>),
+                    fields.next().map(str::parse::<u64>),
+                ) else {
+                    continue;
+                };
+                if let Some(file) = current.as_mut() {
+                    *file.lines.entry(number).or_default() += hits;
+                }
+            }
+            "FN" => {
+                let Some((number, name)) = value.split_once(',') else {
+                    continue;
+                };
+                if let (Some(file), Ok(number)) = (current.as_mut(), number.parse()) {
+                    file.functions
+                        .entry(name.to_string())
+                        .or_insert((number, 0));
+                }
+            }
+            "FNDA" => {
+                let Some((hits, name)) = value.split_once(',') else {
+                    continue;
+                };
+                if let (Some(file), Ok(hits)) = (current.as_mut(), hits.parse::<u64>()) {
+                    file.functions.entry(name.to_string()).or_insert((0, 0)).1 += hits;
+                }
+            }
+            "end_of_record" => current = None,
+            _ => {}
+        }
+    }
+}
+
+/// `coverage` as an LCOV tracefile.
+pub fn render(coverage: &Coverage) -> String {
+    let mut out = String::new();
+    for (path, file) in coverage {
+        out.push_str(&format!("SF:{}\n", path));
+        for (name, (number, _)) in &file.functions {
+            out.push_str(&format!("FN:{},{}\n", number, name));
+        }
+        for (name, (_, hits)) in &file.functions {
+            out.push_str(&format!("FNDA:{},{}\n", hits, name));
+        }
+        out.push_str(&format!("FNF:{}\n", file.functions.len()));
+        out.push_str(&format!("FNH:{}\n", file.functions_hit()));
+        for (number, hits) in &file.lines {
+            out.push_str(&format!("DA:{},{}\n", number, hits));
+        }
+        out.push_str(&format!("LF:{}\n", file.lines.len()));
+        out.push_str(&format!("LH:{}\n", file.lines_hit()));
+        out.push_str("end_of_record\n");
+    }
+    out
+}
+
+/// The coverage files of the tests the JSON build events report.
+fn tracefiles(events: &str) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for line in events.lines() {
+        let Ok(event) = serde_json::from_str::<serde_json::Value>(line) else {
+            continue;
+        };
+        let outputs = event["testResult"]["testActionOutput"].as_array();
+        for output in outputs.into_iter().flatten() {
+            if output["name"] == "test.lcov" {
+                files.extend(output["uri"].as_str().and_then(artifacts::file_path));
+            }
+        }
+    }
+    files
+}
+
+fn percent(hit: usize, total: usize) -> f64 {
+    match total {
+        0 => 100.0,
+        _ => hit as f64 * 100.0 / total as f64,
+    }
+}
+
+fn paint_percent(percent: f64) -> String {
+    let text = format!("{:>6.1}%", percent);
+    let level = if percent >= 80.0 {
+        Level::Info
+    } else if percent >= 50.0 {
+        Level::Warning
+    } else {
+        Level::Error
+    };
+    style::paint(&text, level).to_string()
+}
+
+/// Prints the line and function coverage of every file, and the total.
+pub fn print_summary(coverage: &Coverage) {
+    let width = coverage
+        .keys()
+        .map(String::len)
+        .chain([5])
+        .max()
+        .unwrap_or(0);
+    println!(
+        "{:width$}  {:>13}  {:>7}  {:>13}  {:>7}",
+        "File",
+        "Lines",
+        "",
+        "Functions",
+        "",
+        width = width
+    );
+    let row = |name: &str, lines: (usize, usize), functions: (usize, usize)| {
+        println!(
+            "{:width$}  {:>13}  {}  {:>13}  {}",
+            name,
+            format!("{}/{}", lines.0, lines.1),
+            paint_percent(percent(lines.0, lines.1)),
+            format!("{}/{}", functions.0, functions.1),
+            paint_percent(percent(functions.0, functions.1)),
+            width = width
+        );
+    };
+    let mut lines = (0, 0);
+    let mut functions = (0, 0);
+    for (path, file) in coverage {
+        let file_lines = (file.lines_hit(), file.lines.len());
+        let file_functions = (file.functions_hit(), file.functions.len());
+        row(path, file_lines, file_functions);
+        lines = (lines.0 + file_lines.0, lines.1 + file_lines.1);
+        functions = (
+            functions.0 + file_functions.0,
+            functions.1 + file_functions.1,
+        );
+    }
+    row("Total", lines, functions);
+}
+
+/// Merges the coverage of the tests recorded in the build event file into
+/// `target/coverage/lcov.info` and prints its summary. With `open`, also
+/// generates the HTML report with `genhtml` and opens it.
+pub fn report(event_file: &Path, open: bool) -> Result<(), String> {
+    let events = fs::read_to_string(event_file).unwrap_or_default();
+    let mut coverage = Coverage::new();
+    for path in tracefiles(&events) {
+        // Tests which ran nothing instrumented leave no file.
+        if let Ok(lcov) = fs::read_to_string(&path) {
+            merge(&mut coverage, &lcov);
+        }
+    }
+    if coverage.is_empty() {
+        return Err("the tests reported no coverage".to_string());
+    }
+
+    let dir = Path::new(COVERAGE_DIR);
+    fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    let tracefile = dir.join("lcov.info");
+    fs::write(&tracefile, render(&coverage))
+        .map_err(|e| format!("{}: {}", tracefile.display(), e))?;
+    print_summary(&coverage);
+    style::status("Wrote", tracefile.display());
+
+    if open {
+        let genhtml = which("genhtml")
+            .map_err(|_| "`genhtml` not found, install lcov for the HTML report")?;
+        let html = dir.join("html");
+        let status = Command::new(genhtml)
+            .args(["--quiet", "--output-directory"])
+            .arg(&html)
+            .arg(&tracefile)
+            .status()
+            .map_err(|e| format!("failed to run genhtml: {}", e))?;
+        if !status.success() {
+            return Err(format!("genhtml exited with {}", status));
+        }
+        let index = html.join("index.html");
+        style::status("Generated", index.display());
+        open_viewer(
+            if cfg!(target_os = "macos") {
+                "open"
+            } else {
+                "xdg-open"
+            },
+            &index,
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge() {
+        let mut coverage = Coverage::new();
+        merge(
+            &mut coverage,
+            "SF:src/demo.cc\nFN:3,_Z3addii\nFNDA:1,_Z3addii\nFN:7,_Z3subii\nFNDA:0,_Z3subii\nDA:3,1\nDA:4,1\nDA:7,0\nDA:8,0\nend_of_record\nSF:external/fmt/format.cc\nDA:1,5\nend_of_record\n",
+        );
+        merge(
+            &mut coverage,
+            "SF:src/demo.cc\nFN:7,_Z3subii\nFNDA:2,_Z3subii\nDA:7,2\nDA:8,2\nend_of_record\n",
+        );
+
+        assert_eq!(coverage.len(), 1);
+        let demo = &coverage["src/demo.cc"];
+        assert_eq!(demo.lines_hit(), 4);
+        assert_eq!(demo.functions["_Z3subii"], (7, 2));
+        assert_eq!(
+            render(&coverage),
+            "SF:src/demo.cc\nFN:3,_Z3addii\nFN:7,_Z3subii\nFNDA:1,_Z3addii\nFNDA:2,_Z3subii\nFNF:2\nFNH:2\nDA:3,1\nDA:4,1\nDA:7,2\nDA:8,2\nLF:4\nLH:4\nend_of_record\n"
+        );
+
+        let events = r#"{"id":{"testResult":{"label":"//test:a_test","run":1}},"testResult":{"status":"PASSED","testActionOutput":[{"name":"test.log","uri":"file:///out/a/test.log"},{"name":"test.lcov","uri":"file:///out/a/coverage.dat"}]}}"#;
+        assert_eq!(tracefiles(events), [PathBuf::from("/out/a/coverage.dat")]);
+    }
+}
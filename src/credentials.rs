@@ -0,0 +1,433 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use which::which;
+
+use crate::global::{self, GlobalConfig, Registry};
+
+/// Keychain service the tokens are stored under.
+const SERVICE: &str = "buddy";
+
+/// Where `store` put a token.
+pub enum Storage {
+    Provider,
+    Keychain,
+    File(PathBuf),
+}
+
+/// Name of the environment variable overriding the token of `registry`.
+pub fn env_var(registry: &str) -> String {
+    let name: String = registry
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("BUDDY_REGISTRY_{}_TOKEN", name)
+}
+
+/// The host part of `url`, credentials of unnamed registries and caches are
+/// keyed on it.
+pub fn host(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = rest.split('/').next().unwrap_or(rest);
+    authority.rsplit('@').next().unwrap_or(authority)
+}
+
+fn run_provider(
+    command: &str,
+    action: &str,
+    name: &str,
+    registry: &Registry,
+    token: Option<&str>,
+) -> Result<String, String> {
+    let mut words = command.split_whitespace();
+    let program = words
+        .next()
+        .ok_or_else(|| format!("empty credential-provider for registry `{}`", name))?;
+
+    let mut child = Command::new(program)
+        .args(words)
+        .arg(action)
+        .env("BUDDY_REGISTRY_NAME", name)
+        .env("BUDDY_REGISTRY_INDEX", &registry.index)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to run credential provider `{}`: {}", program, e))?;
+    if let Some(token) = token {
+        writeln!(child.stdin.take().unwrap(), "{}", token).map_err(|e| e.to_string())?;
+    }
+    drop(child.stdin.take());
+
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(format!(
+            "credential provider `{}` failed to {} the token of `{}`",
+            program, action, name
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+enum Keychain {
+    SecretTool(PathBuf),
+    Security(PathBuf),
+}
+
+impl Keychain {
+    fn find() -> Option<Keychain> {
+        if cfg!(target_os = "macos") {
+            which("security").ok().map(Keychain::Security)
+        } else {
+            which("secret-tool").ok().map(Keychain::SecretTool)
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<String> {
+        let output = match self {
+            Keychain::SecretTool(bin) => Command::new(bin)
+                .args(["lookup", "service", SERVICE, "registry", name])
+                .stderr(Stdio::null())
+                .output(),
+            Keychain::Security(bin) => Command::new(bin)
+                .args(["find-generic-password", "-s", SERVICE, "-a", name, "-w"])
+                .stderr(Stdio::null())
+                .output(),
+        }
+        .ok()?;
+        let token = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        (output.status.success() && !token.is_empty()).then_some(token)
+    }
+
+    fn store(&self, name: &str, token: &str) -> bool {
+        match self {
+            Keychain::SecretTool(bin) => {
+                let child = Command::new(bin)
+                    .arg("store")
+                    .arg(format!("--label=buddy registry {}", name))
+                    .args(["service", SERVICE, "registry", name])
+                    .stdin(Stdio::piped())
+                    .stderr(Stdio::null())
+                    .spawn();
+                let Ok(mut child) = child else {
+                    return false;
+                };
+                let written = child.stdin.take().unwrap().write_all(token.as_bytes());
+                written.is_ok() && child.wait().is_ok_and(|s| s.success())
+            }
+            Keychain::Security(bin) => {
+                // The token goes through the commands `security -i` reads,
+                // as anyone can see the arguments. Words needing quotes are
+                // left to the file.
+                let plain = |word: &str| {
+                    !word.is_empty()
+                        && word
+                            .chars()
+                            .all(|c| c.is_ascii_graphic() && !"\"'\\".contains(c))
+                };
+                if !plain(name) || !plain(token) {
+                    return false;
+                }
+                let child = Command::new(bin)
+                    .arg("-i")
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .spawn();
+                let Ok(mut child) = child else {
+                    return false;
+                };
+                let written = writeln!(
+                    child.stdin.take().unwrap(),
+                    "add-generic-password -U -s {} -a {} -w {}",
+                    SERVICE,
+                    name,
+                    token
+                );
+                // Its exit status doesn't tell whether the commands failed.
+                written.is_ok() && child.wait().is_ok() && self.get(name).as_deref() == Some(token)
+            }
+        }
+    }
+
+    fn erase(&self, name: &str) -> bool {
+        match self {
+            Keychain::SecretTool(bin) => Command::new(bin)
+                .args(["clear", "service", SERVICE, "registry", name])
+                .stderr(Stdio::null())
+                .status(),
+            Keychain::Security(bin) => Command::new(bin)
+                .args(["delete-generic-password", "-s", SERVICE, "-a", name])
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status(),
+        }
+        .is_ok_and(|s| s.success())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    token: String,
+}
+
+/// The plaintext fallback, `~/.buddy/credentials.toml`.
+#[derive(Debug, Serialize, Deserialize, Default)]
+struct CredentialsFile {
+    #[serde(default)]
+    registries: BTreeMap<String, Entry>,
+}
+
+fn credentials_path() -> PathBuf {
+    global::buddy_home().join("credentials.toml")
+}
+
+fn read_file(path: &Path) -> Result<CredentialsFile, String> {
+    match fs::read_to_string(path) {
+        Ok(contents) => toml::from_str(&contents)
+            .map_err(|e| format!("failed to parse `{}`: {}", path.display(), e)),
+        Err(_) => Ok(CredentialsFile::default()),
+    }
+}
+
+fn write_file(path: &Path, credentials: &CredentialsFile) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let contents = toml::to_string(credentials).map_err(|e| e.to_string())?;
+    write_private(path, &contents, 0o600)
+}
+
+/// Writes `contents` holding secrets to `path`, only ever accessible to
+/// the user, with `mode`, from its creation on.
+pub fn write_private(path: &Path, contents: &str, mode: u32) -> Result<(), String> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(mode);
+    }
+    let mut file = options
+        .open(path)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    // Files written before keep their mode on opening.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(mode))
+            .map_err(|e| e.to_string())?;
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    file.write_all(contents.as_bytes())
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// The curl config file setting `headers`, values quoted.
+fn curl_config(headers: &[(&str, &str)]) -> String {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let header = format!("{}: {}", name, value);
+            format!(
+                "header = \"{}\"\n",
+                header.replace('\\', "\\\\").replace('"', "\\\"")
+            )
+        })
+        .collect()
+}
+
+/// Writes `headers`, which hold secrets, to a temporary curl config file
+/// only the user can read, for `curl -K`: anyone can see the arguments of
+/// curl. It is removed when dropped.
+pub fn curl_headers(headers: &[(&str, &str)]) -> Result<tempfile::NamedTempFile, String> {
+    let mut file = tempfile::NamedTempFile::new().map_err(|e| e.to_string())?;
+    file.write_all(curl_config(headers).as_bytes())
+        .map_err(|e| e.to_string())?;
+    Ok(file)
+}
+
+fn file_store(path: &Path, name: &str, token: &str) -> Result<(), String> {
+    let mut credentials = read_file(path)?;
+    credentials.registries.insert(
+        name.to_string(),
+        Entry {
+            token: token.to_string(),
+        },
+    );
+    write_file(path, &credentials)
+}
+
+fn file_erase(path: &Path, name: &str) -> Result<bool, String> {
+    let mut credentials = read_file(path)?;
+    if credentials.registries.remove(name).is_none() {
+        return Ok(false);
+    }
+    write_file(path, &credentials)?;
+    Ok(true)
+}
+
+/// Looks up the token of the registry (or host) `name`. Sources are tried in
+/// order:
+///
+/// 1. the `BUDDY_REGISTRY_<NAME>_TOKEN` environment variable, for CI;
+/// 2. the registry's `credential-provider`, a command called with `get`,
+///    `store` or `erase` (the token is exchanged on stdin/stdout and the
+///    registry is described by `BUDDY_REGISTRY_NAME`/`BUDDY_REGISTRY_INDEX`);
+/// 3. the OS keychain (`secret-tool` on Linux, `security` on macOS);
+/// 4. the plaintext `~/.buddy/credentials.toml`.
+pub fn token(name: &str, registry: Option<&Registry>) -> Result<Option<String>, String> {
+    if let Ok(token) = std::env::var(env_var(name)) {
+        return Ok(Some(token));
+    }
+
+    if let Some(registry) = registry {
+        if let Some(provider) = &registry.credential_provider {
+            let token = run_provider(provider, "get", name, registry, None)?;
+            return Ok((!token.is_empty()).then_some(token));
+        }
+    }
+
+    if let Some(token) = Keychain::find().and_then(|keychain| keychain.get(name)) {
+        return Ok(Some(token));
+    }
+
+    Ok(read_file(&credentials_path())?
+        .registries
+        .remove(name)
+        .map(|entry| entry.token))
+}
+
+/// Finds the token to send along requests to `url`: the one of the
+/// registry served from the same host, or the one stored for the host.
+pub fn token_for_url(config: &GlobalConfig, url: &str) -> Result<Option<String>, String> {
+    let host = host(url);
+    match config
+        .registries
+        .iter()
+        .find(|(_, registry)| self::host(&registry.index) == host)
+    {
+        Some((name, registry)) => token(name, Some(registry)),
+        None => token(host, None),
+    }
+}
+
+/// Saves the token of `name`, preferring the credential provider, then the
+/// keychain.
+pub fn store(name: &str, registry: Option<&Registry>, token: &str) -> Result<Storage, String> {
+    if let Some(registry) = registry {
+        if let Some(provider) = &registry.credential_provider {
+            run_provider(provider, "store", name, registry, Some(token))?;
+            return Ok(Storage::Provider);
+        }
+    }
+
+    if let Some(keychain) = Keychain::find() {
+        if keychain.store(name, token) {
+            // Don't leave an outdated copy behind.
+            file_erase(&credentials_path(), name)?;
+            return Ok(Storage::Keychain);
+        }
+    }
+
+    let path = credentials_path();
+    file_store(&path, name, token)?;
+    Ok(Storage::File(path))
+}
+
+/// Forgets the token of `name` wherever it is stored, returns whether there
+/// was one.
+pub fn erase(name: &str, registry: Option<&Registry>) -> Result<bool, String> {
+    if let Some(registry) = registry {
+        if let Some(provider) = &registry.credential_provider {
+            run_provider(provider, "erase", name, registry, None)?;
+            return Ok(true);
+        }
+    }
+
+    let keychain = Keychain::find().is_some_and(|keychain| keychain.erase(name));
+    let file = file_erase(&credentials_path(), name)?;
+    Ok(keychain || file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env_var_and_host() {
+        assert_eq!(env_var("my-corp"), "BUDDY_REGISTRY_MY_CORP_TOKEN");
+        assert_eq!(host("https://buddy.example.com/index"), "buddy.example.com");
+        assert_eq!(
+            host("grpcs://user@cache.example.com:443"),
+            "cache.example.com:443"
+        );
+        assert_eq!(host("cache.example.com"), "cache.example.com");
+    }
+
+    #[test]
+    fn test_file_store_and_erase() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("credentials.toml");
+
+        file_store(&path, "internal", "secret").unwrap();
+        file_store(&path, "cache.example.com", "other").unwrap();
+        let credentials = read_file(&path).unwrap();
+        assert_eq!(credentials.registries["internal"].token, "secret");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        assert!(file_erase(&path, "internal").unwrap());
+        assert!(!file_erase(&path, "internal").unwrap());
+        let credentials = read_file(&path).unwrap();
+        assert_eq!(credentials.registries.len(), 1);
+    }
+
+    #[test]
+    fn test_curl_config() {
+        assert_eq!(
+            curl_config(&[("Authorization", "Bearer abc"), ("X-Quote", r#"a"b\c"#)]),
+            "header = \"Authorization: Bearer abc\"\nheader = \"X-Quote: a\\\"b\\\\c\"\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_security_store_keeps_token_off_argv() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let dir = tmp_dir.path();
+        // Keeps the last word of the command read by `-i` as the password,
+        // and logs its arguments.
+        let bin = dir.join("security");
+        fs::write(
+            &bin,
+            "#!/bin/sh\ndir=$(dirname \"$0\")\necho \"$@\" >> \"$dir/args\"\nif [ \"$1\" = -i ]; then read -r line; echo \"${line##* }\" > \"$dir/password\"; else cat \"$dir/password\"; fi\n",
+        )
+        .unwrap();
+        fs::set_permissions(&bin, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let keychain = Keychain::Security(bin);
+        assert!(keychain.store("internal", "s3cr3t-token"));
+        assert_eq!(keychain.get("internal").as_deref(), Some("s3cr3t-token"));
+        assert!(!fs::read_to_string(dir.join("args"))
+            .unwrap()
+            .contains("s3cr3t"));
+        // Left to the file rather than quoted.
+        assert!(!keychain.store("internal", "with space"));
+    }
+}
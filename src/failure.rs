@@ -0,0 +1,264 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::artifacts;
+
+/// Where the report of the last failed bazel invocation is kept.
+pub const LAST_FAILURE: &str = "target/buddy/last-failure.json";
+
+/// An error or warning found in bazel's output, from bazel itself or from
+/// the compiler.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub severity: String,
+    /// `file:line:column` when known.
+    pub location: Option<String>,
+    pub message: String,
+}
+
+/// A target which failed to build, or a test which didn't pass.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct FailedTarget {
+    pub label: String,
+    /// The log of a failed test.
+    pub test_log: Option<PathBuf>,
+}
+
+/// What went wrong in a bazel invocation.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Failure {
+    pub command: Vec<String>,
+    pub exit_code: Option<i32>,
+    /// Seconds since the Unix epoch.
+    pub time: u64,
+    pub failed_targets: Vec<FailedTarget>,
+    pub diagnostics: Vec<Diagnostic>,
+    /// Everything bazel printed.
+    pub log: Vec<String>,
+}
+
+/// Whether `text` looks like `file:line` or `file:line:column`.
+fn is_location(text: &str) -> bool {
+    let numeric = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+    let Some((rest, last)) = text.rsplit_once(':') else {
+        return false;
+    };
+    let file = match rest.rsplit_once(':') {
+        Some((file, line)) if numeric(line) => file,
+        _ => rest,
+    };
+    numeric(last) && !file.is_empty() && !file.contains(' ')
+}
+
+/// Splits `location: message`, when the text starts with a location.
+fn split_location(text: &str) -> (Option<String>, &str) {
+    for (i, _) in text.match_indices(": ") {
+        if is_location(&text[..i]) {
+            return (Some(text[..i].to_string()), &text[i + 2..]);
+        }
+    }
+    (None, text)
+}
+
+/// Recognizes bazel's `ERROR:`/`WARNING:` lines and the compilers'
+/// `file:line:column: error: message` ones.
+pub fn parse_diagnostic(line: &str) -> Option<Diagnostic> {
+    for (prefix, severity) in [("ERROR: ", "error"), ("WARNING: ", "warning")] {
+        if let Some(rest) = line.strip_prefix(prefix) {
+            let (location, message) = split_location(rest);
+            return Some(Diagnostic {
+                severity: severity.to_string(),
+                location,
+                message: message.to_string(),
+            });
+        }
+    }
+    for severity in ["fatal error", "error", "warning"] {
+        if let Some((location, message)) = line.split_once(&format!(": {}: ", severity)) {
+            if is_location(location) {
+                return Some(Diagnostic {
+                    severity: severity.to_string(),
+                    location: Some(location.to_string()),
+                    message: message.to_string(),
+                });
+            }
+        }
+    }
+    None
+}
+
+/// The targets the JSON build events report as failed: aborted or
+/// unsuccessful targets, and tests which didn't pass.
+pub fn failed_targets(events: &str) -> Vec<FailedTarget> {
+    let mut failed: Vec<FailedTarget> = Vec::new();
+    let mut test_logs = Vec::new();
+    for line in events.lines() {
+        let Ok(event) = serde_json::from_str::<serde_json::Value>(line) else {
+            continue;
+        };
+        let id = &event["id"];
+        let label = id["targetCompleted"]["label"]
+            .as_str()
+            .or(id["targetConfigured"]["label"].as_str());
+
+        if let Some(label) = id["testResult"]["label"].as_str() {
+            let result = &event["testResult"];
+            if result["status"] != "PASSED" {
+                let log = result["testActionOutput"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .find(|output| output["name"] == "test.log")
+                    .and_then(|output| artifacts::file_path(output["uri"].as_str()?));
+                test_logs.push((label.to_string(), log));
+            }
+        }
+
+        let label = match label {
+            Some(label) if !event["aborted"].is_null() => Some(label),
+            Some(label)
+                if id["targetCompleted"]["aspect"].is_null()
+                    && event["completed"]["success"] == false =>
+            {
+                Some(label)
+            }
+            _ => id["testSummary"]["label"]
+                .as_str()
+                .filter(|_| event["testSummary"]["overallStatus"] != "PASSED"),
+        };
+        if let Some(label) = label {
+            if !failed.iter().any(|target| target.label == label) {
+                failed.push(FailedTarget {
+                    label: label.to_string(),
+                    test_log: None,
+                });
+            }
+        }
+    }
+
+    for target in failed.iter_mut() {
+        target.test_log = test_logs
+            .iter()
+            .rev()
+            .find(|(label, _)| *label == target.label)
+            .and_then(|(_, log)| log.clone());
+    }
+    failed
+}
+
+/// The arguments of `cmd`, without the credentials they may carry.
+fn command_line(cmd: &Command) -> Vec<String> {
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|arg| {
+            let arg = arg.to_string_lossy();
+            match arg.split_once('=') {
+                Some((flag, _)) if flag == "--remote_header" => format!("{}=<redacted>", flag),
+                _ => arg.to_string(),
+            }
+        })
+        .collect()
+}
+
+/// Writes the report of the failed invocation `cmd` to
+/// `target/buddy/last-failure.json`.
+pub fn record(
+    cmd: &Command,
+    status: ExitStatus,
+    log: Vec<String>,
+    event_file: Option<&Path>,
+) -> Result<(), String> {
+    let mut diagnostics = Vec::new();
+    for diagnostic in log.iter().filter_map(|line| parse_diagnostic(line)) {
+        if !diagnostics.contains(&diagnostic) {
+            diagnostics.push(diagnostic);
+        }
+    }
+    let failed_targets = event_file
+        .and_then(|path| fs::read_to_string(path).ok())
+        .map(|events| failed_targets(&events))
+        .unwrap_or_default();
+
+    let failure = Failure {
+        command: command_line(cmd),
+        exit_code: status.code(),
+        time: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+        failed_targets,
+        diagnostics,
+        log,
+    };
+
+    let path = Path::new(LAST_FAILURE);
+    fs::create_dir_all(path.parent().unwrap()).map_err(|e| e.to_string())?;
+    let contents = serde_json::to_string_pretty(&failure).map_err(|e| e.to_string())?;
+    fs::write(path, contents).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Reads the report of the last failure.
+pub fn load() -> Result<Failure, String> {
+    let contents = fs::read_to_string(LAST_FAILURE)
+        .map_err(|_| "no failure recorded in target/buddy, nothing failed yet".to_string())?;
+    serde_json::from_str(&contents).map_err(|e| format!("invalid {}: {}", LAST_FAILURE, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_failures() {
+        assert_eq!(
+            parse_diagnostic("src/foo.cc:3:14: error: expected ';' after expression"),
+            Some(Diagnostic {
+                severity: "error".to_string(),
+                location: Some("src/foo.cc:3:14".to_string()),
+                message: "expected ';' after expression".to_string(),
+            })
+        );
+        assert_eq!(
+            parse_diagnostic(
+                "ERROR: /home/me/demo/src/BUILD:3:10: Compiling src/foo.cc failed: (Exit 1)"
+            ),
+            Some(Diagnostic {
+                severity: "error".to_string(),
+                location: Some("/home/me/demo/src/BUILD:3:10".to_string()),
+                message: "Compiling src/foo.cc failed: (Exit 1)".to_string(),
+            })
+        );
+        assert_eq!(
+            parse_diagnostic("ERROR: Build did NOT complete successfully"),
+            Some(Diagnostic {
+                severity: "error".to_string(),
+                location: None,
+                message: "Build did NOT complete successfully".to_string(),
+            })
+        );
+        assert_eq!(parse_diagnostic("INFO: Found 1 target..."), None);
+
+        let events = r#"{"id":{"targetCompleted":{"label":"//src:demo"}},"completed":{"success":false}}
+{"id":{"targetCompleted":{"label":"//src:ok"}},"completed":{"success":true}}
+{"id":{"testResult":{"label":"//test:a_test","run":1}},"testResult":{"status":"FAILED","testActionOutput":[{"name":"test.log","uri":"file:///out/testlogs/test/a_test/test.log"}]}}
+{"id":{"testSummary":{"label":"//test:a_test"}},"testSummary":{"overallStatus":"FAILED"}}
+{"id":{"testSummary":{"label":"//test:b_test"}},"testSummary":{"overallStatus":"PASSED"}}
+"#;
+        assert_eq!(
+            failed_targets(events),
+            [
+                FailedTarget {
+                    label: "//src:demo".to_string(),
+                    test_log: None,
+                },
+                FailedTarget {
+                    label: "//test:a_test".to_string(),
+                    test_log: Some(PathBuf::from("/out/testlogs/test/a_test/test.log")),
+                },
+            ]
+        );
+    }
+}
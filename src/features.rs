@@ -0,0 +1,269 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::config::Config;
+use crate::global::GlobalConfig;
+use crate::plugins::{self, Plugin};
+
+/// The `[features]` table: each feature lists the features it enables, and
+/// the optional dependencies it pulls in as `"dep:<name>"`. `default` is the
+/// set enabled unless `--no-default-features` is given.
+pub type Features = BTreeMap<String, Vec<String>>;
+
+const DEPENDENCY_PREFIX: &str = "dep:";
+
+/// Unifies the requested features with everything they transitively
+/// enable.
+pub fn resolve(
+    features: &Features,
+    requested: &[String],
+    default_features: bool,
+) -> Result<BTreeSet<String>, String> {
+    let mut pending: Vec<&str> = requested.iter().map(String::as_str).collect();
+    if default_features {
+        if let Some(default) = features.get("default") {
+            pending.extend(default.iter().map(String::as_str));
+        }
+    }
+
+    let mut enabled = BTreeSet::new();
+    while let Some(feature) = pending.pop() {
+        let implied = features.get(feature).ok_or_else(|| {
+            let known: Vec<_> = features
+                .keys()
+                .filter(|name| *name != "default")
+                .map(String::as_str)
+                .collect();
+            format!(
+                "unknown feature `{}`, the [features] table declares: {}",
+                feature,
+                known.join(", ")
+            )
+        })?;
+        if feature != "default" && enabled.insert(feature.to_string()) {
+            pending.extend(
+                implied
+                    .iter()
+                    .map(String::as_str)
+                    .filter(|name| !name.starts_with(DEPENDENCY_PREFIX)),
+            );
+        }
+    }
+    Ok(enabled)
+}
+
+/// The optional dependencies enabled by a feature of `enabled`, with their
+/// version, the patched ones left out.
+pub fn optional_dependencies(
+    config: &Config,
+    enabled: &BTreeSet<String>,
+) -> Result<HashMap<String, String>, String> {
+    let declared = config.optional_versions();
+    let mut dependencies = HashMap::new();
+    for feature in enabled {
+        let optional = config.features[feature]
+            .iter()
+            .filter_map(|name| name.strip_prefix(DEPENDENCY_PREFIX));
+        for name in optional {
+            let version = declared.get(name).ok_or_else(|| {
+                format!(
+                    "feature `{}` enables `{}`, which is not in [optional-dependencies] nor marked `optional = true`",
+                    feature, name
+                )
+            })?;
+            if !config.patch.contains_key(name) {
+                dependencies.insert(name.to_string(), version.clone());
+            }
+        }
+    }
+    Ok(dependencies)
+}
+
+/// The versions the build depends on: the required ones, the ones of every
+/// platform, Bazel only fetching the ones it builds for, plus the optional
+/// ones enabled by a feature of `enabled`. Git and path dependencies, and
+/// the patched ones, are left out.
+pub fn dependencies(
+    config: &Config,
+    enabled: &BTreeSet<String>,
+) -> Result<HashMap<String, String>, String> {
+    let mut dependencies = config.versions();
+    for platform in config.platform_dependencies()?.into_values() {
+        dependencies.extend(platform);
+    }
+    dependencies.extend(optional_dependencies(config, enabled)?);
+    Ok(dependencies)
+}
+
+/// The targets of the optional dependencies enabled by a feature of
+/// `enabled`, which the generated `BUILD` files link against.
+pub fn optional_labels(
+    config: &Config,
+    enabled: &BTreeSet<String>,
+    plugins: &[Plugin],
+    global: &GlobalConfig,
+) -> Result<Vec<String>, String> {
+    let optional = optional_dependencies(config, enabled)?;
+    let mut names: Vec<_> = optional.keys().collect();
+    names.sort();
+
+    let mut labels = Vec::new();
+    for name in names {
+        let plugin = plugins::resolve(plugins, name, global)?;
+        let version = plugin.select(&optional[name], None)?;
+        labels.extend(plugin.target_label(&version)?);
+    }
+    Ok(labels)
+}
+
+/// The preprocessor define of `feature`, e.g. `MYAPP_FEATURE_FAST_MATH`.
+pub fn define(package: &str, feature: &str) -> String {
+    let upper = |name: &str| {
+        name.chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>()
+    };
+    format!("{}_FEATURE_{}", upper(package), upper(feature))
+}
+
+/// The bazel flags enabling `features`: a compilation define, and a
+/// `--define` of the same name which BUILD files can `select` optional
+/// sources or targets on, with
+/// `config_setting(name = "...", define_values = {"MYAPP_FEATURE_X": "1"})`.
+pub fn flags(package: &str, features: &BTreeSet<String>) -> Vec<String> {
+    features
+        .iter()
+        .flat_map(|feature| {
+            let define = define(package, feature);
+            [
+                format!("--copt=-D{}", define),
+                format!("--define={}=1", define),
+            ]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn features() -> Features {
+        toml::from_str(
+            r#"
+default = ["logging"]
+logging = []
+simd = ["fast-math"]
+fast-math = []
+"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_resolve() {
+        let features = features();
+        let all = |set: BTreeSet<String>| set.into_iter().collect::<Vec<_>>();
+
+        assert_eq!(all(resolve(&features, &[], true).unwrap()), ["logging"]);
+        assert_eq!(
+            all(resolve(&features, &["simd".to_string()], false).unwrap()),
+            ["fast-math", "simd"]
+        );
+        assert!(resolve(&features, &["gpu".to_string()], true)
+            .unwrap_err()
+            .contains("unknown feature `gpu`"));
+        assert!(resolve(&Features::new(), &[], true).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_optional_dependencies() {
+        let config: Config = toml::from_str(
+            r#"
+[package]
+name = "demo"
+version = "0.1.0"
+edition = "2023"
+
+[dependencies]
+google-test = "1.13.0"
+
+[optional-dependencies]
+opentelemetry-cpp = "1.9.1"
+
+[features]
+telemetry = ["dep:opentelemetry-cpp"]
+tracing = ["telemetry"]
+broken = ["dep:zlib"]
+"#,
+        )
+        .unwrap();
+
+        let enabled = resolve(&config.features, &[], true).unwrap();
+        let default = dependencies(&config, &enabled).unwrap();
+        assert_eq!(default.len(), 1);
+
+        let enabled = resolve(&config.features, &["tracing".to_string()], true).unwrap();
+        let traced = dependencies(&config, &enabled).unwrap();
+        assert_eq!(traced["opentelemetry-cpp"], "1.9.1");
+
+        let enabled = resolve(&config.features, &["broken".to_string()], true).unwrap();
+        assert!(dependencies(&config, &enabled)
+            .unwrap_err()
+            .contains("`zlib`, which is not in [optional-dependencies]"));
+    }
+
+    #[test]
+    fn test_optional_labels() {
+        let config: Config = toml::from_str(
+            r#"
+[package]
+name = "demo"
+version = "0.1.0"
+edition = "2023"
+
+[dependencies]
+google-test = { version = "^1.12", optional = true }
+
+[features]
+testing = ["dep:google-test"]
+"#,
+        )
+        .unwrap();
+        assert!(config.versions().is_empty());
+        let plugins = plugins::builtin();
+        let global = GlobalConfig::default();
+
+        let enabled = resolve(&config.features, &[], true).unwrap();
+        assert!(dependencies(&config, &enabled).unwrap().is_empty());
+        assert!(optional_labels(&config, &enabled, &plugins, &global)
+            .unwrap()
+            .is_empty());
+
+        let enabled = resolve(&config.features, &["testing".to_string()], true).unwrap();
+        assert_eq!(
+            dependencies(&config, &enabled).unwrap()["google-test"],
+            "^1.12"
+        );
+        assert_eq!(
+            optional_labels(&config, &enabled, &plugins, &global).unwrap(),
+            ["@com_google_googletest//:gtest"]
+        );
+    }
+
+    #[test]
+    fn test_flags() {
+        let enabled = ["fast-math".to_string()].into_iter().collect();
+        assert_eq!(
+            flags("my-app", &enabled),
+            [
+                "--copt=-DMY_APP_FEATURE_FAST_MATH",
+                "--define=MY_APP_FEATURE_FAST_MATH=1"
+            ]
+        );
+    }
+}
@@ -0,0 +1,306 @@
+use std::collections::BTreeMap;
+
+const WIDTH: f64 = 1200.0;
+const FRAME_HEIGHT: f64 = 16.0;
+const PADDING: f64 = 10.0;
+const HEADER: f64 = 30.0;
+
+/// Folded stacks: `root;caller;callee` mapped to the number of samples.
+pub type Folded = BTreeMap<String, u64>;
+
+fn clean_frame(frame: &str) -> String {
+    // `func+0x1f` -> `func`
+    let frame = match frame.rfind("+0x") {
+        Some(i) => &frame[..i],
+        None => frame,
+    };
+    frame.trim().to_string()
+}
+
+/// Folds the output of `perf script`. Every sample is a header line followed
+/// by one line per frame, leaf first, and terminated by an empty line.
+pub fn fold_perf(script: &str) -> Folded {
+    let mut folded = Folded::new();
+    let mut frames: Vec<String> = Vec::new();
+    let mut in_sample = false;
+
+    let mut flush = |frames: &mut Vec<String>| {
+        if !frames.is_empty() {
+            frames.reverse();
+            *folded.entry(frames.join(";")).or_insert(0) += 1;
+            frames.clear();
+        }
+    };
+
+    for line in script.lines() {
+        if line.trim().is_empty() {
+            flush(&mut frames);
+            in_sample = false;
+        } else if !line.starts_with(char::is_whitespace) {
+            flush(&mut frames);
+            in_sample = true;
+        } else if in_sample {
+            // `    7f12ab func_a+0x12 (/path/to/binary)`
+            let line = line.trim();
+            let line = match line.rfind(" (") {
+                Some(i) => &line[..i],
+                None => line,
+            };
+            if let Some((_, symbol)) = line.split_once(' ') {
+                frames.push(clean_frame(symbol));
+            }
+        }
+    }
+    flush(&mut frames);
+
+    folded
+}
+
+/// Folds dtrace `ustack()` aggregations: blocks of `module`func+0x..` frames,
+/// leaf first, each followed by the sample count on its own line.
+pub fn fold_dtrace(output: &str) -> Folded {
+    let mut folded = Folded::new();
+    let mut frames: Vec<String> = Vec::new();
+
+    for line in output.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Ok(count) = line.parse::<u64>() {
+            if !frames.is_empty() {
+                frames.reverse();
+                *folded.entry(frames.join(";")).or_insert(0) += count;
+                frames.clear();
+            }
+        } else {
+            let symbol = line.split_once('`').map(|(_, s)| s).unwrap_or(line);
+            frames.push(clean_frame(symbol));
+        }
+    }
+
+    folded
+}
+
+/// Serializes folded stacks in the format understood by other flamegraph
+/// tools, one `stack count` pair per line.
+pub fn to_text(folded: &Folded) -> String {
+    folded
+        .iter()
+        .map(|(stack, count)| format!("{} {}\n", stack, count))
+        .collect()
+}
+
+#[derive(Default)]
+struct Node {
+    value: u64,
+    children: BTreeMap<String, Node>,
+}
+
+fn build_tree(folded: &Folded) -> Node {
+    let mut root = Node::default();
+    for (stack, count) in folded {
+        root.value += count;
+        let mut node = &mut root;
+        for frame in stack.split(';') {
+            node = node.children.entry(frame.to_string()).or_default();
+            node.value += count;
+        }
+    }
+    root
+}
+
+fn depth(node: &Node) -> usize {
+    node.children
+        .values()
+        .map(|c| 1 + depth(c))
+        .max()
+        .unwrap_or(0)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Warm flamegraph palette, stable per function name.
+fn color(name: &str) -> String {
+    let hash = name
+        .bytes()
+        .fold(5381u32, |h, b| h.wrapping_mul(33) ^ b as u32);
+    let r = 205 + (hash % 50);
+    let g = (hash / 50) % 230;
+    let b = (hash / 11500) % 55;
+    format!("rgb({},{},{})", r, g, b)
+}
+
+struct Layout<'a> {
+    out: &'a mut String,
+    total: u64,
+    height: f64,
+}
+
+impl Layout<'_> {
+    fn frame(&mut self, name: &str, node: &Node, x: f64, level: usize) {
+        let width = node.value as f64 / self.total as f64 * (WIDTH - 2.0 * PADDING);
+        let y = self.height - PADDING - (level as f64 + 1.0) * FRAME_HEIGHT;
+        let percent = node.value as f64 / self.total as f64 * 100.0;
+        let label = if width > 20.0 {
+            let chars = ((width - 6.0) / 7.0) as usize;
+            if name.chars().count() > chars {
+                format!(
+                    "{}..",
+                    name.chars()
+                        .take(chars.saturating_sub(2))
+                        .collect::<String>()
+                )
+            } else {
+                name.to_string()
+            }
+        } else {
+            String::new()
+        };
+
+        self.out.push_str(&format!(
+            "<g class=\"frame\" data-x=\"{x:.2}\" data-w=\"{width:.2}\"><title>{name} ({samples} samples, {percent:.2}%)</title>\
+<rect x=\"{x:.2}\" y=\"{y:.2}\" width=\"{width:.2}\" height=\"{h:.2}\" fill=\"{fill}\" rx=\"2\"/>\
+<text x=\"{tx:.2}\" y=\"{ty:.2}\">{label}</text></g>\n",
+            name = escape(name),
+            samples = node.value,
+            h = FRAME_HEIGHT - 1.0,
+            fill = color(name),
+            tx = x + 3.0,
+            ty = y + FRAME_HEIGHT - 4.0,
+            label = escape(&label),
+        ));
+
+        let mut child_x = x;
+        for (child_name, child) in &node.children {
+            self.frame(child_name, child, child_x, level + 1);
+            child_x += child.value as f64 / self.total as f64 * (WIDTH - 2.0 * PADDING);
+        }
+    }
+}
+
+const SCRIPT: &str = r##"<script><![CDATA[
+var frames = document.querySelectorAll("g.frame");
+function zoom(x, w) {
+  var scale = (WIDTH - 2 * PADDING) / w;
+  frames.forEach(function (g) {
+    var fx = parseFloat(g.dataset.x), fw = parseFloat(g.dataset.w);
+    var visible = fx + fw > x + 0.001 && fx < x + w - 0.001;
+    g.style.display = visible ? "" : "none";
+    var nx = PADDING + (fx - x) * scale, nw = fw * scale;
+    g.querySelector("rect").setAttribute("x", nx);
+    g.querySelector("rect").setAttribute("width", nw);
+    g.querySelector("text").setAttribute("x", nx + 3);
+    g.querySelector("text").style.display = nw > 20 ? "" : "none";
+  });
+}
+frames.forEach(function (g) {
+  g.addEventListener("click", function () {
+    zoom(parseFloat(g.dataset.x), parseFloat(g.dataset.w));
+  });
+});
+document.querySelector("#reset").addEventListener("click", function () {
+  zoom(PADDING, WIDTH - 2 * PADDING);
+});
+]]></script>
+"##;
+
+/// Renders folded stacks as a self-contained SVG flamegraph. Hovering shows
+/// sample counts, clicking a frame zooms into it and "Reset zoom" zooms out.
+pub fn render_svg(folded: &Folded, title: &str) -> String {
+    let root = build_tree(folded);
+    let height = HEADER + (depth(&root) as f64) * FRAME_HEIGHT + 2.0 * PADDING;
+
+    let mut out = format!(
+        "<?xml version=\"1.0\" standalone=\"no\"?>\n\
+<svg version=\"1.1\" width=\"{WIDTH}\" height=\"{height}\" viewBox=\"0 0 {WIDTH} {height}\" xmlns=\"http://www.w3.org/2000/svg\">\n\
+<style>text {{ font-family: monospace; font-size: 12px; pointer-events: none; }} g.frame:hover rect {{ stroke: black; stroke-width: 0.5; }} #reset {{ cursor: pointer; }}</style>\n\
+<rect width=\"100%\" height=\"100%\" fill=\"rgb(248,248,248)\"/>\n\
+<text x=\"{center}\" y=\"20\" text-anchor=\"middle\" style=\"font-size: 16px\">{title}</text>\n\
+<text id=\"reset\" x=\"{PADDING}\" y=\"20\" style=\"pointer-events: all\">Reset zoom</text>\n",
+        center = WIDTH / 2.0,
+        title = escape(title),
+    );
+
+    if root.value > 0 {
+        let mut layout = Layout {
+            out: &mut out,
+            total: root.value,
+            height,
+        };
+        let mut x = PADDING;
+        for (name, child) in &root.children {
+            layout.frame(name, child, x, 0);
+            x += child.value as f64 / root.value as f64 * (WIDTH - 2.0 * PADDING);
+        }
+    }
+
+    out.push_str(
+        &SCRIPT
+            .replace("WIDTH", &WIDTH.to_string())
+            .replace("PADDING", &PADDING.to_string()),
+    );
+    out.push_str("</svg>\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fold_perf() {
+        let script = "app  1234 100.000001:     10101 cpu-clock:
+\t    55d1 compute+0x12 (/work/target/bin/src/app)
+\t    55d2 main+0x20 (/work/target/bin/src/app)
+\t    7f00 __libc_start_main+0xf3 (/usr/lib/libc.so.6)
+
+app  1234 100.000002:     10101 cpu-clock:
+\t    55d1 compute+0x14 (/work/target/bin/src/app)
+\t    55d2 main+0x20 (/work/target/bin/src/app)
+\t    7f00 __libc_start_main+0xf3 (/usr/lib/libc.so.6)
+
+app  1234 100.000003:     10101 cpu-clock:
+\t    55d2 main+0x28 (/work/target/bin/src/app)
+\t    7f00 __libc_start_main+0xf3 (/usr/lib/libc.so.6)
+";
+
+        let folded = fold_perf(script);
+        assert_eq!(folded["__libc_start_main;main;compute"], 2);
+        assert_eq!(folded["__libc_start_main;main"], 1);
+    }
+
+    #[test]
+    fn test_fold_dtrace() {
+        let output = "
+              app`compute(int)+0x1c
+              app`main+0x20
+              libdyld.dylib`start+0x1
+               7
+
+              app`main+0x24
+              libdyld.dylib`start+0x1
+               3
+";
+
+        let folded = fold_dtrace(output);
+        assert_eq!(folded["start;main;compute(int)"], 7);
+        assert_eq!(folded["start;main"], 3);
+    }
+
+    #[test]
+    fn test_render_svg() {
+        let folded = Folded::from([("main;a<int>".to_string(), 3), ("main;b".to_string(), 1)]);
+        let svg = render_svg(&folded, "app");
+        assert!(svg.starts_with("<?xml"));
+        assert!(svg.contains("<title>main (4 samples, 100.00%)</title>"));
+        assert!(svg.contains("<title>a&lt;int&gt; (3 samples, 75.00%)</title>"));
+        assert!(svg.trim_end().ends_with("</svg>"));
+    }
+}
@@ -0,0 +1,221 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Runs git with `args` in the current directory and returns its trimmed
+/// standard output.
+pub fn output(args: &[&str]) -> Result<String, String> {
+    output_in(Path::new("."), args)
+}
+
+/// Like `output`, in `dir`.
+pub fn output_in(dir: &Path, args: &[&str]) -> Result<String, String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|e| format!("failed to run git: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("`git {}` failed", args.join(" ")));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// The name the user commits with.
+pub fn user_name() -> Option<String> {
+    output(&["config", "user.name"])
+        .ok()
+        .filter(|name| !name.is_empty())
+}
+
+/// `Name <email>` of the user as git knows them, for the manifest of new
+/// packages.
+pub fn author() -> Option<String> {
+    let name = user_name()?;
+    match output(&["config", "user.email"]) {
+        Ok(email) if !email.is_empty() => Some(format!("{} <{}>", name, email)),
+        _ => Some(name),
+    }
+}
+
+/// Whether `dir` lies in the work tree of a git repository.
+pub fn in_work_tree(dir: &Path) -> bool {
+    output_in(dir, &["rev-parse", "--is-inside-work-tree"]).is_ok()
+}
+
+/// Makes `dir` a git repository and commits its files. Fails without a
+/// git identity to commit with, once the files are staged.
+pub fn init_repository(dir: &Path) -> Result<(), String> {
+    output_in(dir, &["init", "-q"])?;
+    output_in(dir, &["add", "-A"])?;
+    output_in(dir, &["commit", "-q", "-m", "Initial commit"])
+        .map(|_| ())
+        .map_err(|_| "no initial commit, configure git's user.name and user.email".to_string())
+}
+
+/// Finds the repository's default branch: the remote's HEAD when known,
+/// otherwise a local `main` or `master`.
+pub fn default_branch() -> Result<String, String> {
+    default_branch_in(Path::new("."))
+}
+
+fn default_branch_in(dir: &Path) -> Result<String, String> {
+    if let Ok(head) = output_in(
+        dir,
+        &["symbolic-ref", "--short", "refs/remotes/origin/HEAD"],
+    ) {
+        return Ok(head);
+    }
+    for branch in ["main", "master"] {
+        if output_in(dir, &["rev-parse", "--verify", "--quiet", branch]).is_ok() {
+            return Ok(branch.to_string());
+        }
+    }
+    Err("cannot determine the default branch, pass a ref explicitly".to_string())
+}
+
+fn lines(output: &str) -> impl Iterator<Item = PathBuf> + '_ {
+    output
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Lists the files modified since `reference`, defaulting to the merge-base
+/// with the default branch. Uncommitted and untracked files are included,
+/// deleted ones are not.
+pub fn changed_files(reference: Option<&str>) -> Result<Vec<PathBuf>, String> {
+    changed_files_in(Path::new("."), reference)
+}
+
+fn changed_files_in(dir: &Path, reference: Option<&str>) -> Result<Vec<PathBuf>, String> {
+    let base = match reference {
+        Some(reference) => reference.to_string(),
+        None => output_in(dir, &["merge-base", "HEAD", &default_branch_in(dir)?])?,
+    };
+
+    // `--relative` keeps the paths relative to the project when it lives in
+    // a subdirectory of the repository, like `ls-files` does.
+    let diff = output_in(
+        dir,
+        &[
+            "diff",
+            "--relative",
+            "--name-only",
+            "--diff-filter=ACMR",
+            &base,
+        ],
+    )?;
+    let untracked = output_in(dir, &["ls-files", "--others", "--exclude-standard"])?;
+
+    let mut files: Vec<PathBuf> = lines(&diff).chain(lines(&untracked)).collect();
+    files.sort();
+    files.dedup();
+    files.retain(|f| dir.join(f).is_file());
+    Ok(files)
+}
+
+/// Resolves `reference`, a branch, a tag or `HEAD`, of the repository at
+/// `url` to its commit, peeling annotated tags.
+pub fn remote_commit(url: &str, reference: &str) -> Result<String, String> {
+    let peeled = format!("{}^{{}}", reference);
+    let refs = output(&["ls-remote", url, reference, &peeled])
+        .map_err(|_| format!("cannot list the references of `{}`", url))?;
+    let commit = |suffix: &str| {
+        refs.lines().find_map(|line| {
+            let (commit, name) = line.split_once('\t')?;
+            name.ends_with(suffix).then(|| commit.to_string())
+        })
+    };
+    commit("^{}")
+        .or_else(|| commit(reference))
+        .ok_or_else(|| format!("`{}` has no branch or tag `{}`", url, reference))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn git(dir: &Path, args: &[&str]) {
+        output_in(
+            dir,
+            &[
+                &["-c", "user.name=buddy", "-c", "user.email=buddy@localhost"],
+                args,
+            ]
+            .concat(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_changed_files_since_merge_base() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let dir = tmp_dir.path();
+        git(dir, &["init", "-q", "-b", "main"]);
+        for file in ["a.cc", "b.cc", "c.cc"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        git(dir, &["add", "."]);
+        git(dir, &["commit", "-q", "-m", "initial"]);
+
+        git(dir, &["checkout", "-q", "-b", "feature"]);
+        fs::write(dir.join("a.cc"), "int a;").unwrap();
+        git(dir, &["commit", "-q", "-am", "change a"]);
+        fs::write(dir.join("b.cc"), "int b;").unwrap();
+        fs::remove_file(dir.join("c.cc")).unwrap();
+        fs::write(dir.join("d.cc"), "").unwrap();
+
+        assert_eq!(
+            changed_files_in(dir, None).unwrap(),
+            vec![
+                PathBuf::from("a.cc"),
+                PathBuf::from("b.cc"),
+                PathBuf::from("d.cc")
+            ]
+        );
+        assert_eq!(
+            changed_files_in(dir, Some("HEAD")).unwrap(),
+            vec![PathBuf::from("b.cc"), PathBuf::from("d.cc")]
+        );
+    }
+
+    #[test]
+    fn test_init_repository() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let dir = tmp_dir.path().join("app");
+        fs::create_dir(&dir).unwrap();
+        assert!(!in_work_tree(&dir));
+        // The identity of the test, `init_repository` keeps it.
+        git(&dir, &["init", "-q"]);
+        git(&dir, &["config", "user.name", "buddy"]);
+        git(&dir, &["config", "user.email", "buddy@localhost"]);
+        fs::write(dir.join("main.cc"), "int main() {}").unwrap();
+
+        init_repository(&dir).unwrap();
+        assert!(in_work_tree(&dir));
+        assert_eq!(output_in(&dir, &["ls-files"]).unwrap(), "main.cc");
+        assert_eq!(
+            output_in(&dir, &["log", "--format=%s"]).unwrap(),
+            "Initial commit"
+        );
+    }
+
+    #[test]
+    fn test_remote_commit() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let dir = tmp_dir.path();
+        git(dir, &["init", "-q", "-b", "main"]);
+        git(dir, &["commit", "-q", "--allow-empty", "-m", "initial"]);
+        git(dir, &["tag", "-a", "v1.0.0", "-m", "release"]);
+        let head = output_in(dir, &["rev-parse", "HEAD"]).unwrap();
+
+        let url = dir.to_str().unwrap();
+        assert_eq!(remote_commit(url, "main").unwrap(), head);
+        assert_eq!(remote_commit(url, "v1.0.0").unwrap(), head);
+        assert_eq!(remote_commit(url, "HEAD").unwrap(), head);
+        assert!(remote_commit(url, "v2.0.0").is_err());
+    }
+}
@@ -0,0 +1,326 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::signature::SigningKey;
+
+/// Buddy's per-user directory, `$BUDDY_HOME` or `~/.buddy`.
+pub fn buddy_home() -> PathBuf {
+    if let Some(home) = std::env::var_os("BUDDY_HOME") {
+        return PathBuf::from(home);
+    }
+    let home = std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .unwrap_or_default();
+    PathBuf::from(home).join(".buddy")
+}
+
+/// Where `buddy fetch` puts verified archives, a directory per dependency.
+pub fn distdir() -> PathBuf {
+    buddy_home().join("distdir")
+}
+
+/// The directories of the distdir, each handed to Bazel as a `--distdir`.
+pub fn distdirs() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = fs::read_dir(distdir())
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    dirs.sort();
+    dirs
+}
+
+/// The public registry of C/C++ recipes, known without any configuration.
+/// `[registries.buddy]` replaces it, an empty `index` turns it off.
+pub const DEFAULT_REGISTRY: &str = "buddy";
+
+/// Where the index of the public registry is served.
+const DEFAULT_INDEX: &str = "https://github.com/cppbuddy/index/releases/latest/download";
+
+/// A registry declared in the user configuration.
+#[derive(Debug, Deserialize, Default, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct Registry {
+    /// Base URL of the registry index.
+    #[serde(default)]
+    pub index: String,
+    /// Directory of recipe TOMLs and archives, for registries living on
+    /// disk rather than behind a server.
+    pub path: Option<PathBuf>,
+    /// Command run to obtain the registry token, see `credentials`.
+    pub credential_provider: Option<String>,
+    /// Key the archives of the registry's recipes are signed with.
+    pub signing_key: Option<SigningKey>,
+    /// Organisation namespace served by the registry: dependencies named
+    /// `<scope>/<name>` are only looked up there.
+    pub scope: Option<String>,
+    /// URL `buddy publish` uploads the archives to, as
+    /// `<api>/<name>/<version>`, for registries accepting them.
+    pub api: Option<String>,
+}
+
+/// A Bazel remote cache shared by every project of the user.
+#[derive(Debug, Deserialize, Default)]
+pub struct RemoteCache {
+    pub url: String,
+}
+
+/// The `[source]` table. `replace` maps URL prefixes onto the mirror
+/// serving them, e.g. an internal Artifactory.
+#[derive(Debug, Deserialize, Default)]
+pub struct SourceConfig {
+    #[serde(default)]
+    pub replace: BTreeMap<String, String>,
+}
+
+/// The `[http]` table, proxy settings for everything buddy and Bazel
+/// download.
+#[derive(Debug, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct HttpConfig {
+    pub proxy: Option<String>,
+    pub no_proxy: Option<String>,
+}
+
+impl HttpConfig {
+    /// The proxy environment variables to pass to child processes. Variables
+    /// already set in the environment take precedence over the config.
+    pub fn env(&self) -> Vec<(&'static str, String)> {
+        let is_set = |name: &str| {
+            std::env::var_os(name).is_some() || std::env::var_os(name.to_lowercase()).is_some()
+        };
+
+        let mut vars = Vec::new();
+        if let Some(proxy) = &self.proxy {
+            for name in ["HTTP_PROXY", "HTTPS_PROXY"] {
+                if !is_set(name) {
+                    vars.push((name, proxy.clone()));
+                }
+            }
+        }
+        if let Some(no_proxy) = &self.no_proxy {
+            if !is_set("NO_PROXY") {
+                vars.push(("NO_PROXY", no_proxy.clone()));
+            }
+        }
+        vars
+    }
+}
+
+/// The `[policy]` table, organisation wide rules on dependencies.
+#[derive(Debug, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct PolicyConfig {
+    /// Refuse dependencies whose archives aren't signed.
+    #[serde(default)]
+    pub require_signatures: bool,
+}
+
+/// The `[notify]` table, reporting the end of long builds and tests.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct NotifyConfig {
+    /// Show a desktop notification.
+    #[serde(default)]
+    pub desktop: bool,
+    /// URL receiving a Slack-compatible JSON payload.
+    pub webhook: Option<String>,
+    /// Commands shorter than this, in seconds, don't notify unless run with
+    /// `--notify`.
+    #[serde(default = "default_notify_after")]
+    pub after: u64,
+}
+
+impl Default for NotifyConfig {
+    fn default() -> Self {
+        NotifyConfig {
+            desktop: false,
+            webhook: None,
+            after: default_notify_after(),
+        }
+    }
+}
+
+fn default_notify_after() -> u64 {
+    60
+}
+
+/// The `[telemetry]` table, exporting the metrics of builds and tests to
+/// an OpenTelemetry collector.
+#[derive(Debug, Deserialize, Default)]
+pub struct TelemetryConfig {
+    /// Base URL of the OTLP/HTTP receiver, e.g. `http://localhost:4318`.
+    pub endpoint: String,
+    /// Headers sent with every export, e.g. an API key.
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+/// How status lines are printed: the verb right-aligned before the
+/// message, or a symbol and the verb, unaligned.
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum StatusLines {
+    #[default]
+    Verbose,
+    Compact,
+}
+
+/// The symbols of the output, for terminals or fonts without unicode.
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Symbols {
+    #[default]
+    Unicode,
+    Ascii,
+}
+
+/// The colors of the `[style]` table: a name such as `green` or
+/// `bright red`, or a `#rrggbb` hex code.
+#[derive(Debug, Deserialize, Default)]
+pub struct StyleColors {
+    pub info: Option<String>,
+    pub warning: Option<String>,
+    pub error: Option<String>,
+}
+
+/// The `[style]` table, how buddy's output looks.
+#[derive(Debug, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct StyleConfig {
+    #[serde(default)]
+    pub status: StatusLines,
+    #[serde(default)]
+    pub symbols: Symbols,
+    #[serde(default)]
+    pub colors: StyleColors,
+    /// Bright, bold colors and no dimmed text.
+    #[serde(default)]
+    pub high_contrast: bool,
+}
+
+/// The user configuration, `~/.buddy/config.toml`. Unlike `Buddy.toml` it
+/// holds machine and organisation specific settings that don't belong in a
+/// project.
+#[derive(Debug, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct GlobalConfig {
+    #[serde(default)]
+    pub registries: BTreeMap<String, Registry>,
+    pub remote_cache: Option<RemoteCache>,
+    #[serde(default)]
+    pub source: SourceConfig,
+    #[serde(default)]
+    pub http: HttpConfig,
+    #[serde(default)]
+    pub policy: PolicyConfig,
+    #[serde(default)]
+    pub notify: NotifyConfig,
+    pub telemetry: Option<TelemetryConfig>,
+    #[serde(default)]
+    pub style: StyleConfig,
+}
+
+impl GlobalConfig {
+    /// Loads the user configuration, which is optional.
+    pub fn load() -> Result<GlobalConfig, String> {
+        GlobalConfig::load_from(&buddy_home().join("config.toml"))
+    }
+
+    /// The registry serving the packages of `scope`.
+    pub fn registry_for_scope(&self, scope: &str) -> Option<&str> {
+        self.registries
+            .iter()
+            .find(|(_, registry)| registry.scope.as_deref() == Some(scope))
+            .map(|(name, _)| name.as_str())
+    }
+
+    pub fn load_from(path: &Path) -> Result<GlobalConfig, String> {
+        let mut config: GlobalConfig = match fs::read_to_string(path) {
+            Ok(contents) => toml::from_str(&contents)
+                .map_err(|e| format!("failed to parse `{}`: {}", path.display(), e))?,
+            Err(_) => GlobalConfig::default(),
+        };
+        config
+            .registries
+            .entry(DEFAULT_REGISTRY.to_string())
+            .or_insert_with(|| Registry {
+                index: DEFAULT_INDEX.to_string(),
+                ..Default::default()
+            });
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_from() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("config.toml");
+        let config = GlobalConfig::load_from(&path).unwrap();
+        assert_eq!(
+            config.registries.keys().collect::<Vec<_>>(),
+            [DEFAULT_REGISTRY]
+        );
+        assert_eq!(config.registries[DEFAULT_REGISTRY].index, DEFAULT_INDEX);
+
+        fs::write(
+            &path,
+            r##"
+[registries.internal]
+index = "https://buddy.example.com/index"
+credential-provider = "vault-buddy --role ci"
+
+[registries.buddy]
+index = ""
+
+[remote-cache]
+url = "grpcs://cache.example.com"
+
+[source.replace]
+"https://github.com/" = "https://artifactory.corp/github/"
+
+[http]
+proxy = "http://proxy.corp:3128"
+no-proxy = "localhost,.corp"
+
+[telemetry]
+endpoint = "http://otel.corp:4318"
+headers = { "x-api-key" = "secret" }
+
+[style]
+status = "compact"
+symbols = "ascii"
+colors = { error = "#ff5555" }
+"##,
+        )
+        .unwrap();
+        let config = GlobalConfig::load_from(&path).unwrap();
+        assert_eq!(
+            config.registries["internal"].credential_provider.as_deref(),
+            Some("vault-buddy --role ci")
+        );
+        assert!(config.registries[DEFAULT_REGISTRY].index.is_empty());
+        assert_eq!(
+            config.remote_cache.unwrap().url,
+            "grpcs://cache.example.com"
+        );
+        assert_eq!(
+            config.source.replace["https://github.com/"],
+            "https://artifactory.corp/github/"
+        );
+        assert_eq!(config.http.no_proxy.as_deref(), Some("localhost,.corp"));
+        assert_eq!(config.telemetry.unwrap().headers["x-api-key"], "secret");
+        assert_eq!(config.style.status, StatusLines::Compact);
+        assert_eq!(config.style.symbols, Symbols::Ascii);
+        assert_eq!(config.style.colors.error.as_deref(), Some("#ff5555"));
+        assert!(!config.style.high_contrast);
+    }
+}
@@ -0,0 +1,248 @@
+use std::path::Path;
+
+/// An allocation site as reported by the heap profiler.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Site {
+    pub bytes: u64,
+    pub function: String,
+    /// `file:line`, relative to the workspace when it lives inside it.
+    pub location: Option<String>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Report {
+    pub peak_bytes: u64,
+    /// Not every profiler tracks leaks (massif doesn't).
+    pub leaked_bytes: Option<u64>,
+    /// Largest allocation sites at the time of the peak, biggest first.
+    pub sites: Vec<Site>,
+}
+
+/// Parses sizes as printed by heaptrack, e.g. `512B`, `4.00K`, `1.23M`.
+pub fn parse_size(size: &str) -> Option<u64> {
+    let size = size.trim();
+    let (number, multiplier) = match size.chars().last()? {
+        'B' => (&size[..size.len() - 1], 1.0),
+        'K' => (&size[..size.len() - 1], 1024.0),
+        'M' => (&size[..size.len() - 1], 1024.0 * 1024.0),
+        'G' => (&size[..size.len() - 1], 1024.0 * 1024.0 * 1024.0),
+        _ => (size, 1.0),
+    };
+    number
+        .parse::<f64>()
+        .ok()
+        .map(|n| (n * multiplier).round() as u64)
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64;
+    let mut unit = "B";
+    for u in UNITS {
+        if value < 1024.0 {
+            break;
+        }
+        value /= 1024.0;
+        unit = u;
+    }
+    format!("{:.2} {}", value, unit)
+}
+
+/// Maps paths recorded in debug info back onto the workspace: Bazel compiles
+/// inside `execroot/<workspace>/` (possibly within a sandbox), so everything
+/// up to there is dropped, as is the workspace root itself.
+pub fn workspace_path(path: &str, root: &Path) -> String {
+    if let Some(i) = path.find("/execroot/") {
+        let rest = &path[i + "/execroot/".len()..];
+        if let Some((_, relative)) = rest.split_once('/') {
+            return relative.to_string();
+        }
+    }
+    Path::new(path)
+        .strip_prefix(root)
+        .map(|p| p.to_str().unwrap().to_string())
+        .unwrap_or_else(|_| path.to_string())
+}
+
+/// Parses a `massif.out` file. The peak snapshot carries the detailed heap
+/// tree whose first level lists the allocation sites.
+pub fn parse_massif(contents: &str, root: &Path) -> Report {
+    let mut report = Report::default();
+    let mut heap = 0;
+    let mut in_peak = false;
+
+    for line in contents.lines() {
+        if let Some(value) = line.strip_prefix("mem_heap_B=") {
+            heap = value.trim().parse().unwrap_or(0);
+        } else if line.starts_with("heap_tree=") {
+            in_peak = line == "heap_tree=peak";
+            if in_peak {
+                report.peak_bytes = heap;
+            }
+        } else if line.starts_with("#-----------") {
+            in_peak = false;
+        } else if in_peak && line.starts_with(" n") && !line.starts_with("  ") {
+            // ` n1: 3000 0x10916B: allocate(int) (main.cc:7)`
+            let Some((_, rest)) = line.trim().split_once(": ") else {
+                continue;
+            };
+            let Some((bytes, rest)) = rest.split_once(' ') else {
+                continue;
+            };
+            let Some((_, frame)) = rest.split_once(": ") else {
+                // "in N places, all below massif's threshold"
+                continue;
+            };
+            let (function, location) = match frame.rfind(" (") {
+                Some(i) if frame.ends_with(')') => (
+                    &frame[..i],
+                    Some(workspace_path(&frame[i + 2..frame.len() - 1], root)),
+                ),
+                _ => (frame, None),
+            };
+            report.sites.push(Site {
+                bytes: bytes.parse().unwrap_or(0),
+                function: function.to_string(),
+                location,
+            });
+        }
+    }
+
+    report.sites.sort_by_key(|s| std::cmp::Reverse(s.bytes));
+    report
+}
+
+/// Parses the text output of `heaptrack_print`: the `PEAK MEMORY CONSUMERS`
+/// section for the sites and the trailing summary for peak and leak totals.
+pub fn parse_heaptrack(output: &str, root: &Path) -> Report {
+    let mut report = Report::default();
+    let mut section = "";
+    let mut lines = output.lines().peekable();
+
+    while let Some(line) = lines.next() {
+        if let Some(value) = line.strip_prefix("peak heap memory consumption:") {
+            report.peak_bytes = parse_size(value).unwrap_or(0);
+        } else if let Some(value) = line.strip_prefix("total memory leaked:") {
+            report.leaked_bytes = parse_size(value);
+        } else if !line.is_empty() && line.chars().all(|c| c.is_ascii_uppercase() || c == ' ') {
+            section = line;
+        } else if section == "PEAK MEMORY CONSUMERS" && line.contains(" peak memory consumed over ")
+        {
+            let bytes = line
+                .split_whitespace()
+                .next()
+                .and_then(parse_size)
+                .unwrap_or(0);
+            let function = lines.next().unwrap_or("").trim().to_string();
+            let location = lines
+                .peek()
+                .and_then(|l| l.trim().strip_prefix("at "))
+                .map(|l| workspace_path(l, root));
+            report.sites.push(Site {
+                bytes,
+                function,
+                location,
+            });
+        }
+    }
+
+    report.sites.sort_by_key(|s| std::cmp::Reverse(s.bytes));
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_massif() {
+        let massif = "desc: --time-unit=B
+cmd: ./app
+time_unit: B
+#-----------
+snapshot=0
+#-----------
+time=0
+mem_heap_B=0
+mem_heap_extra_B=0
+mem_stacks_B=0
+heap_tree=empty
+#-----------
+snapshot=1
+#-----------
+time=1234
+mem_heap_B=4000
+mem_heap_extra_B=16
+mem_stacks_B=0
+heap_tree=peak
+n3: 4000 (heap allocation functions) malloc/new/new[], --alloc-fns, etc.
+ n1: 3000 0x10916B: allocate(int) (/home/me/.cache/bazel/execroot/__main__/src/main.cc:7)
+  n0: 3000 0x1091A0: main (main.cc:12)
+ n0: 900 0x1091B0: other() (/work/app/src/other.cc:3)
+ n0: 100 in 2 places, all below massif's threshold (1.00%)
+";
+
+        let report = parse_massif(massif, Path::new("/work/app"));
+        assert_eq!(report.peak_bytes, 4000);
+        assert_eq!(report.leaked_bytes, None);
+        assert_eq!(
+            report.sites,
+            vec![
+                Site {
+                    bytes: 3000,
+                    function: "allocate(int)".to_string(),
+                    location: Some("src/main.cc:7".to_string()),
+                },
+                Site {
+                    bytes: 900,
+                    function: "other()".to_string(),
+                    location: Some("src/other.cc:3".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_heaptrack() {
+        let output =
+            "reading file \"heaptrack.app.1.zst\" - please wait, this might take some time...
+Debuggee command was: ./app
+finished reading file, now analyzing data:
+
+PEAK MEMORY CONSUMERS
+4.00K peak memory consumed over 1 calls from
+allocate(int)
+  at /work/app/src/main.cc:7
+  in /work/app/target/bin/src/app
+4.00K consumed over 1 calls from:
+    main
+      at /work/app/src/main.cc:12
+
+MEMORY LEAKS
+1.00K leaked over 1 calls from
+leaky()
+
+total runtime: 0.01s.
+calls to allocation functions: 3 (300/s)
+peak heap memory consumption: 4.00K
+total memory leaked: 1.00K
+";
+
+        let report = parse_heaptrack(output, Path::new("/work/app"));
+        assert_eq!(report.peak_bytes, 4096);
+        assert_eq!(report.leaked_bytes, Some(1024));
+        assert_eq!(report.sites.len(), 1);
+        assert_eq!(report.sites[0].function, "allocate(int)");
+        assert_eq!(report.sites[0].location.as_deref(), Some("src/main.cc:7"));
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(4096), "4.00 KiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024 / 2), "1.50 MiB");
+    }
+}
@@ -0,0 +1,127 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::global::{self, GlobalConfig};
+use crate::style;
+
+/// Age after which a cached index is reported as stale.
+pub const STALE_AFTER: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// File recording when an index was fetched, in seconds since the epoch.
+const FETCHED_FILE: &str = ".fetched";
+
+/// Where `buddy update-index` keeps the indexes of the registries served
+/// over HTTP, one directory of recipes per registry.
+pub fn root() -> PathBuf {
+    global::buddy_home().join("index")
+}
+
+/// The registries with an index to fetch: the ones not living on disk.
+pub fn remote_registries(global: &GlobalConfig) -> impl Iterator<Item = &str> {
+    global
+        .registries
+        .iter()
+        .filter(|(_, registry)| registry.path.is_none() && !registry.index.is_empty())
+        .map(|(name, _)| name.as_str())
+}
+
+/// Records that the index in `dir` was just fetched.
+pub fn mark_fetched(dir: &Path) -> Result<(), String> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    fs::write(dir.join(FETCHED_FILE), now.to_string()).map_err(|e| e.to_string())
+}
+
+/// How long ago the index in `dir` was fetched, `None` if it never was.
+pub fn age(dir: &Path, now: SystemTime) -> Option<Duration> {
+    let fetched: u64 = fs::read_to_string(dir.join(FETCHED_FILE))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    now.duration_since(UNIX_EPOCH + Duration::from_secs(fetched))
+        .ok()
+        .or(Some(Duration::ZERO))
+}
+
+/// Describes the cached indexes of `root` which are missing or older than
+/// `STALE_AFTER`.
+pub fn stale(global: &GlobalConfig, root: &Path, now: SystemTime) -> Vec<String> {
+    remote_registries(global)
+        .filter_map(|name| match age(&root.join(name), now) {
+            None => Some(format!(
+                "the index of registry `{}` was never fetched",
+                name
+            )),
+            Some(age) if age > STALE_AFTER => Some(format!(
+                "the index of registry `{}` is {} days old",
+                name,
+                age.as_secs() / (24 * 60 * 60)
+            )),
+            Some(_) => None,
+        })
+        .collect()
+}
+
+/// Warns about the indexes needing a `buddy update-index`. Offline, the
+/// cached ones are used as they are, silently.
+pub fn warn_if_stale(global: &GlobalConfig, offline: bool) {
+    if offline {
+        return;
+    }
+    for message in stale(global, &root(), SystemTime::now()) {
+        style::warning(format!(
+            "{}, run `buddy update-index` to refresh it",
+            message
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stale() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path();
+        let global: GlobalConfig = toml::from_str(
+            r#"
+[registries.corp]
+index = "https://buddy.corp/index"
+
+[registries.fresh]
+index = "https://buddy.example.com/index"
+
+[registries.local]
+path = "/srv/recipes"
+
+[registries.new]
+index = "https://new.example.com/index"
+"#,
+        )
+        .unwrap();
+
+        let now = UNIX_EPOCH + Duration::from_secs(100 * 24 * 60 * 60);
+        for (name, day) in [("corp", 55), ("fresh", 99)] {
+            fs::create_dir(root.join(name)).unwrap();
+            let fetched = day * 24 * 60 * 60;
+            fs::write(root.join(name).join(FETCHED_FILE), fetched.to_string()).unwrap();
+        }
+
+        assert_eq!(
+            stale(&global, root, now),
+            [
+                "the index of registry `corp` is 45 days old",
+                "the index of registry `new` was never fetched",
+            ]
+        );
+        assert_eq!(
+            age(&root.join("fresh"), now),
+            Some(Duration::from_secs(24 * 60 * 60))
+        );
+    }
+}
@@ -0,0 +1,115 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config::{Config, LibType};
+use crate::targets::{self, BuildFile, ExternalDeps};
+
+/// Where `buddy build` copies the libraries.
+pub const LIB_DIR: &str = "target/lib";
+
+/// Lists what installing the package puts where, written by `buddy build`.
+pub const MANIFEST: &str = "target/install_manifest.json";
+
+/// A file to install: where it's taken from, relative to the project, and
+/// where it goes, relative to the install prefix.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct Entry {
+    pub source: PathBuf,
+    pub destination: PathBuf,
+}
+
+/// File names of the library outputs requested by `[lib]`.
+pub fn library_outputs(config: &Config, build_files: &[BuildFile]) -> Vec<String> {
+    let Some(library) = targets::package_library(build_files) else {
+        return Vec::new();
+    };
+    let mut outputs = Vec::new();
+    if config.lib.types.contains(&LibType::Static) {
+        outputs.push(format!("lib{}.a", library.name));
+    }
+    if config.lib.types.contains(&LibType::Shared) {
+        outputs.push(targets::shared_lib_name(&config.package));
+    }
+    outputs
+}
+
+/// The install manifest of the package at `root`: the public headers under
+/// `include/`, at their include path, and the libraries copied to
+/// `target/lib` under `lib/`.
+pub fn manifest(root: &Path, config: &Config) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for header in targets::project_sources(root)? {
+        if let Some(path) = targets::public_header_path(&config.lib, &header) {
+            entries.push(Entry {
+                source: header,
+                destination: Path::new("include").join(path),
+            });
+        }
+    }
+    entries.sort_by(|a, b| a.destination.cmp(&b.destination));
+
+    let build_files = targets::scan_library(root, config, &ExternalDeps::default())?;
+    for output in library_outputs(config, &build_files) {
+        let source = Path::new(LIB_DIR).join(&output);
+        if root.join(&source).is_file() {
+            entries.push(Entry {
+                source,
+                destination: Path::new("lib").join(output),
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// Writes the install manifest to `target/install_manifest.json`.
+pub fn write_manifest(root: &Path, config: &Config) -> io::Result<()> {
+    let entries = manifest(root, config)?;
+    let contents = serde_json::to_string_pretty(&entries).map_err(io::Error::other)?;
+    fs::write(root.join(MANIFEST), contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path();
+        fs::create_dir_all(root.join("include/mylib/detail")).unwrap();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(root.join(LIB_DIR)).unwrap();
+        fs::write(root.join("include/mylib/foo.h"), "").unwrap();
+        fs::write(root.join("include/mylib/detail/bar.h"), "").unwrap();
+        fs::write(root.join("src/foo.cc"), "int foo() { return 1; }").unwrap();
+        fs::write(root.join(LIB_DIR).join("libdemo.a"), "").unwrap();
+
+        let config: Config = toml::from_str(
+            r#"
+[package]
+name = "demo"
+version = "1.2.0"
+edition = "2023"
+
+[dependencies]
+
+[lib]
+public-headers = ["include/**.h"]
+"#,
+        )
+        .unwrap();
+
+        let entries = manifest(root, &config).unwrap();
+        let destinations: Vec<_> = entries.iter().map(|e| e.destination.clone()).collect();
+        assert_eq!(
+            destinations,
+            [
+                PathBuf::from("include/mylib/detail/bar.h"),
+                PathBuf::from("include/mylib/foo.h"),
+                PathBuf::from("lib/libdemo.a"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,546 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::Path;
+
+use crate::config::{GitDependency, Source};
+use crate::git;
+use crate::global::GlobalConfig;
+use crate::plugins::{self, Plugin};
+use crate::style;
+use crate::targets::GENERATED_HEADER;
+
+/// The lockfile of a package, next to its `Buddy.toml`.
+pub const LOCKFILE: &str = "Buddy.lock";
+
+/// Version of the lockfile format.
+const FORMAT_VERSION: u32 = 1;
+
+/// A `[[package]]` entry of `Buddy.lock`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LockedPackage {
+    pub name: String,
+    /// The version, the reference a git dependency follows, or the path of
+    /// a local package patching a dependency.
+    pub version: String,
+    /// The archive the version is downloaded from, the registry serving it
+    /// when its recipe names no archive, `git+<url>` or `path+<path>`.
+    pub source: Option<String>,
+    /// The sha256 of the archive, when the recipe pins one.
+    pub sha: Option<String>,
+    /// The commit the recipe pins the version to, or the one a git
+    /// dependency resolved to.
+    pub commit: Option<String>,
+    /// Whether `[patch]` replaces the dependency.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub patched: bool,
+}
+
+impl LockedPackage {
+    /// What the version is pinned to, the sha256 of its archive or a
+    /// commit.
+    pub fn pin(&self) -> Option<&str> {
+        self.sha.as_deref().or(self.commit.as_deref())
+    }
+}
+
+/// Whether `sha` is a sha256 rather than a commit.
+fn is_sha256(sha: &str) -> bool {
+    sha.len() == 64 && sha.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// `Buddy.lock`, the versions the dependencies were resolved to.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Lockfile {
+    #[serde(default = "format_version")]
+    pub version: u32,
+    #[serde(default)]
+    pub package: Vec<LockedPackage>,
+}
+
+impl Default for Lockfile {
+    fn default() -> Self {
+        Lockfile {
+            version: FORMAT_VERSION,
+            package: Vec::new(),
+        }
+    }
+}
+
+fn format_version() -> u32 {
+    FORMAT_VERSION
+}
+
+impl Lockfile {
+    /// Loads the lockfile, a missing one being empty. The commits the
+    /// lockfiles written before `commit` kept in `sha` are moved to it.
+    pub fn load(path: &Path) -> Result<Lockfile, String> {
+        let mut lockfile: Lockfile = match fs::read_to_string(path) {
+            Ok(contents) => toml::from_str(&contents)
+                .map_err(|e| format!("failed to parse `{}`: {}", path.display(), e))?,
+            Err(_) => return Ok(Lockfile::default()),
+        };
+        for package in &mut lockfile.package {
+            if package.commit.is_none() && package.sha.as_deref().is_some_and(|sha| !is_sha256(sha))
+            {
+                package.commit = package.sha.take();
+            }
+        }
+        Ok(lockfile)
+    }
+
+    /// Drops the `[[package]]` entries of `name` from the lockfile
+    /// `contents`, keeping the rest of it as it is.
+    pub fn remove_package(contents: &str, name: &str) -> Result<String, String> {
+        let mut document = contents
+            .parse::<toml_edit::Document>()
+            .map_err(|e| format!("failed to parse `Buddy.lock`: {}", e))?;
+        if let Some(packages) = document
+            .get_mut("package")
+            .and_then(|packages| packages.as_array_of_tables_mut())
+        {
+            packages.retain(|package| package.get("name").and_then(|n| n.as_str()) != Some(name));
+        }
+        Ok(document.to_string())
+    }
+
+    pub fn locked_version(&self, name: &str) -> Option<&str> {
+        self.package
+            .iter()
+            .find(|package| package.name == name)
+            .map(|package| package.version.as_str())
+    }
+
+    fn locked(&self, name: &str, version: &str) -> Option<&LockedPackage> {
+        self.package
+            .iter()
+            .find(|package| package.name == name && package.version == version)
+    }
+
+    pub fn render(&self) -> Result<String, String> {
+        let contents = toml::to_string(self).map_err(|e| e.to_string())?;
+        Ok(format!("{}{}", GENERATED_HEADER, contents))
+    }
+}
+
+/// The commit of the git dependency following `reference`: a `rev` is one
+/// already, branches and tags are resolved on the remote.
+fn git_commit(dependency: &GitDependency, reference: &str) -> Result<String, String> {
+    if dependency.rev.is_some() {
+        return Ok(reference.to_string());
+    }
+    git::remote_commit(&dependency.git, reference)
+}
+
+/// Resolves the requirements of `dependencies` to versions their recipes
+/// know of, and the git `sources` to a commit, local packages being left
+/// out. A version already in `previous` is kept while it satisfies the
+/// requirement and must still be pinned to the same sha, so that a recipe
+/// changing under a locked version is noticed, and a git reference already
+/// locked keeps its commit until it is changed in the manifest.
+/// The `patched` dependencies are marked as such. With `locked`, a git
+/// reference missing from `previous` is an error, the remote isn't asked.
+pub fn resolve(
+    dependencies: &HashMap<String, String>,
+    sources: &BTreeMap<String, Source>,
+    patched: &BTreeSet<String>,
+    plugins: &[Plugin],
+    global: &GlobalConfig,
+    previous: &Lockfile,
+    locked: bool,
+) -> Result<Lockfile, String> {
+    let mut names: Vec<_> = dependencies.keys().collect();
+    names.sort();
+
+    let mut lockfile = Lockfile::default();
+    for name in names {
+        let plugin = plugins::resolve(plugins, name, global)?;
+        let version = plugin.select(&dependencies[name], previous.locked_version(name))?;
+        // Fails with the available versions when this one is unknown.
+        plugin
+            .render(&version)
+            .map_err(|error| format!("cannot resolve dependency `{}`: {}", name, error))?;
+        let pinned = plugin.versions.get(&version).cloned();
+        if let Some(locked) = previous.locked(name, &version) {
+            if locked.pin().is_some() && locked.pin() != pinned.as_deref() {
+                return Err(format!(
+                    "the recipe of `{} {}` changed its sha from {} to {}, remove the entry from {} if the change is expected",
+                    name,
+                    version,
+                    locked.pin().unwrap_or_default(),
+                    pinned.as_deref().unwrap_or_default(),
+                    LOCKFILE
+                ));
+            }
+        }
+        // The other recipes pin the commit the archive is made of.
+        let (sha, commit) = if plugin.pins_checksum() {
+            (pinned, None)
+        } else {
+            (None, pinned)
+        };
+        let source = match plugin.archive_url(&version)? {
+            Some(url) => Some(url),
+            None => plugin
+                .registry
+                .as_ref()
+                .map(|registry| format!("registry+{}", registry)),
+        };
+        lockfile.package.push(LockedPackage {
+            name: name.clone(),
+            version,
+            source,
+            sha,
+            commit,
+            patched: false,
+        });
+    }
+
+    for (name, source) in sources {
+        let patched = patched.contains(name);
+        let dependency = match source {
+            Source::Git(dependency) => dependency,
+            // Local packages have nothing to lock, a patch is still listed
+            // so that the lockfile shows it.
+            Source::Path(dependency) => {
+                if patched {
+                    lockfile.package.push(LockedPackage {
+                        name: name.clone(),
+                        version: dependency.path.clone(),
+                        source: Some(format!("path+{}", dependency.path)),
+                        sha: None,
+                        commit: None,
+                        patched,
+                    });
+                }
+                continue;
+            }
+        };
+        let reference = dependency.reference()?;
+        let source = format!("git+{}", dependency.git);
+        let locked_commit = previous
+            .locked(name, reference)
+            .filter(|locked| locked.source.as_deref() == Some(source.as_str()))
+            .and_then(|locked| locked.commit.clone());
+        let commit = match locked_commit {
+            Some(commit) => commit,
+            None if locked => return Err(needs_update()),
+            None => git_commit(dependency, reference)
+                .map_err(|error| format!("cannot resolve dependency `{}`: {}", name, error))?,
+        };
+        lockfile.package.push(LockedPackage {
+            name: name.clone(),
+            version: reference.to_string(),
+            source: Some(source),
+            sha: None,
+            commit: Some(commit),
+            patched,
+        });
+    }
+    lockfile.package.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(lockfile)
+}
+
+fn needs_update() -> String {
+    format!(
+        "{} needs to be updated but --locked was passed, run without it to update the lockfile",
+        LOCKFILE
+    )
+}
+
+/// Brings `root`'s lockfile up to date with `dependencies`, reusing what is
+/// already locked. With `locked`, a lockfile needing an update is an error
+/// instead.
+pub fn sync(
+    root: &Path,
+    dependencies: &HashMap<String, String>,
+    sources: &BTreeMap<String, Source>,
+    patched: &BTreeSet<String>,
+    plugins: &[Plugin],
+    global: &GlobalConfig,
+    locked: bool,
+) -> Result<(), String> {
+    let path = root.join(LOCKFILE);
+    let previous = Lockfile::load(&path)?;
+    let lockfile = resolve(
+        dependencies,
+        sources,
+        patched,
+        plugins,
+        global,
+        &previous,
+        locked,
+    )?;
+    let contents = lockfile.render()?;
+    let current = fs::read_to_string(&path).ok();
+    if current.as_deref() != Some(contents.as_str()) {
+        if locked {
+            // Only the format of the lockfile changed, what it locks didn't.
+            if current.is_some() && previous.render()? == contents {
+                return Ok(());
+            }
+            return Err(needs_update());
+        }
+        fs::write(&path, contents).map_err(|e| format!("{}: {}", path.display(), e))?;
+        style::status("Locking", format!("{} package(s)", lockfile.package.len()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PathDependency;
+
+    #[test]
+    fn test_sync() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path();
+        let mut plugins = plugins::builtin();
+        let global = GlobalConfig::default();
+        let sources = BTreeMap::new();
+        let dependencies: HashMap<String, String> =
+            [("google-test".to_string(), "1.13.0".to_string())].into();
+
+        assert!(sync(
+            root,
+            &dependencies,
+            &sources,
+            &BTreeSet::new(),
+            &plugins,
+            &global,
+            true
+        )
+        .is_err());
+        sync(
+            root,
+            &dependencies,
+            &sources,
+            &BTreeSet::new(),
+            &plugins,
+            &global,
+            false,
+        )
+        .unwrap();
+        sync(
+            root,
+            &dependencies,
+            &sources,
+            &BTreeSet::new(),
+            &plugins,
+            &global,
+            true,
+        )
+        .unwrap();
+        let contents = fs::read_to_string(root.join(LOCKFILE)).unwrap();
+        assert!(contents.starts_with(GENERATED_HEADER));
+        let lockfile = Lockfile::load(&root.join(LOCKFILE)).unwrap();
+        let package = &lockfile.package[0];
+        assert_eq!(package.version, "1.13.0");
+        assert_eq!(package.sha, None);
+        assert_eq!(
+            package.commit.as_deref(),
+            Some("b796f7d44681514f58a683a3a71ff17c94edb0c1")
+        );
+        assert_eq!(
+            package.source.as_deref(),
+            Some("https://github.com/google/googletest/archive/b796f7d44681514f58a683a3a71ff17c94edb0c1.zip")
+        );
+
+        plugins[0]
+            .versions
+            .insert("1.13.0".to_string(), "0000".to_string());
+        assert!(sync(
+            root,
+            &dependencies,
+            &sources,
+            &BTreeSet::new(),
+            &plugins,
+            &global,
+            false
+        )
+        .unwrap_err()
+        .contains("changed its sha"));
+
+        let dependencies: HashMap<String, String> =
+            [("google-test".to_string(), "1.12.1".to_string())].into();
+        sync(
+            root,
+            &dependencies,
+            &sources,
+            &BTreeSet::new(),
+            &plugins,
+            &global,
+            false,
+        )
+        .unwrap();
+        let lockfile = Lockfile::load(&root.join(LOCKFILE)).unwrap();
+        assert_eq!(lockfile.locked_version("google-test"), Some("1.12.1"));
+
+        // A requirement keeps the locked version while it satisfies it.
+        let mut dependencies: HashMap<String, String> =
+            [("google-test".to_string(), "^1.12".to_string())].into();
+        let patched = BTreeSet::new();
+        sync(
+            root,
+            &dependencies,
+            &sources,
+            &patched,
+            &plugins,
+            &global,
+            true,
+        )
+        .unwrap();
+        dependencies.insert("google-test".to_string(), "^1.13".to_string());
+        sync(
+            root,
+            &dependencies,
+            &sources,
+            &patched,
+            &plugins,
+            &global,
+            false,
+        )
+        .unwrap();
+        let lockfile = Lockfile::load(&root.join(LOCKFILE)).unwrap();
+        assert_eq!(lockfile.locked_version("google-test"), Some("1.13.0"));
+    }
+
+    #[test]
+    fn test_sync_legacy_sha() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path();
+        let legacy = format!(
+            "{}version = 1\n\n[[package]]\nname = \"google-test\"\nversion = \"1.13.0\"\nsource = \"https://github.com/google/googletest/archive/b796f7d44681514f58a683a3a71ff17c94edb0c1.zip\"\nsha = \"b796f7d44681514f58a683a3a71ff17c94edb0c1\"\n",
+            GENERATED_HEADER
+        );
+        fs::write(root.join(LOCKFILE), &legacy).unwrap();
+        let lockfile = Lockfile::load(&root.join(LOCKFILE)).unwrap();
+        assert_eq!(lockfile.package[0].sha, None);
+        assert_eq!(
+            lockfile.package[0].commit.as_deref(),
+            Some("b796f7d44681514f58a683a3a71ff17c94edb0c1")
+        );
+
+        let dependencies: HashMap<String, String> =
+            [("google-test".to_string(), "1.13.0".to_string())].into();
+        let sync_with = |locked| {
+            sync(
+                root,
+                &dependencies,
+                &BTreeMap::new(),
+                &BTreeSet::new(),
+                &plugins::builtin(),
+                &GlobalConfig::default(),
+                locked,
+            )
+        };
+        // Still up to date for --locked, rewritten otherwise.
+        sync_with(true).unwrap();
+        assert_eq!(fs::read_to_string(root.join(LOCKFILE)).unwrap(), legacy);
+        sync_with(false).unwrap();
+        assert!(fs::read_to_string(root.join(LOCKFILE))
+            .unwrap()
+            .ends_with("commit = \"b796f7d44681514f58a683a3a71ff17c94edb0c1\"\n"));
+    }
+
+    #[test]
+    fn test_sync_git() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path();
+        let global = GlobalConfig::default();
+        let rev = "4f2a6a2e0f3e1c7a9d0b8c5e6f7a8b9c0d1e2f3a";
+        let mut sources: BTreeMap<String, Source> = [(
+            "mylib".to_string(),
+            Source::Git(GitDependency {
+                git: "https://github.com/me/mylib".to_string(),
+                rev: Some(rev.to_string()),
+                ..Default::default()
+            }),
+        )]
+        .into();
+
+        sync(
+            root,
+            &HashMap::new(),
+            &sources,
+            &BTreeSet::new(),
+            &[],
+            &global,
+            false,
+        )
+        .unwrap();
+        let lockfile = Lockfile::load(&root.join(LOCKFILE)).unwrap();
+        let package = &lockfile.package[0];
+        assert_eq!(package.version, rev);
+        assert_eq!(package.commit.as_deref(), Some(rev));
+        assert_eq!(
+            package.source.as_deref(),
+            Some("git+https://github.com/me/mylib")
+        );
+
+        // A locked branch keeps its commit without asking the remote.
+        let mut contents = fs::read_to_string(root.join(LOCKFILE)).unwrap();
+        contents = contents.replace(&format!("version = \"{}\"", rev), "version = \"main\"");
+        fs::write(root.join(LOCKFILE), contents).unwrap();
+        let Some(Source::Git(dependency)) = sources.get_mut("mylib") else {
+            unreachable!();
+        };
+        dependency.rev = None;
+        dependency.branch = Some("main".to_string());
+        sync(
+            root,
+            &HashMap::new(),
+            &sources,
+            &BTreeSet::new(),
+            &[],
+            &global,
+            true,
+        )
+        .unwrap();
+
+        // An unlocked one fails without asking the remote either.
+        let Some(Source::Git(dependency)) = sources.get_mut("mylib") else {
+            unreachable!();
+        };
+        dependency.git = "https://unreachable.invalid/mylib".to_string();
+        let error = sync(
+            root,
+            &HashMap::new(),
+            &sources,
+            &BTreeSet::new(),
+            &[],
+            &global,
+            true,
+        )
+        .unwrap_err();
+        assert!(error.contains("--locked"));
+    }
+
+    #[test]
+    fn test_sync_patch() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path();
+        let sources: BTreeMap<String, Source> = [(
+            "google-test".to_string(),
+            Source::Path(PathDependency {
+                path: "../googletest".to_string(),
+            }),
+        )]
+        .into();
+        let patched = ["google-test".to_string()].into();
+
+        sync(
+            root,
+            &HashMap::new(),
+            &sources,
+            &patched,
+            &plugins::builtin(),
+            &GlobalConfig::default(),
+            false,
+        )
+        .unwrap();
+        let contents = fs::read_to_string(root.join(LOCKFILE)).unwrap();
+        assert!(contents.contains("source = \"path+../googletest\"\npatched = true\n"));
+    }
+}
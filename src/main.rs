@@ -1,296 +1,772 @@
-use clap::{Parser, Subcommand};
-use colored::*;
-use serde::Deserialize;
-use std::collections::HashMap;
+use clap::{Args, Parser, Subcommand};
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
 use std::fs;
-use std::fs::File;
-use std::io::prelude::*;
-use std::io::{self, BufRead};
 use std::path::Path;
 use std::path::PathBuf;
-use std::process::{Command, Stdio};
+use std::process::Command;
+use std::time::{Instant, SystemTime};
 use which::which;
 
+pub mod affected;
+pub mod artifacts;
+pub mod bazel;
 pub mod commands;
+pub mod compdb;
+pub mod config;
+pub mod coverage;
+pub mod credentials;
+pub mod failure;
+pub mod features;
+pub mod flamegraph;
+pub mod git;
+pub mod global;
+pub mod heap;
+pub mod index;
+pub mod install;
+pub mod lockfile;
+pub mod members;
+pub mod mirror;
+pub mod notify;
+pub mod plugins;
+pub mod progress;
+pub mod requirement;
+pub mod results;
+pub mod runtime;
+pub mod sanitizer;
+pub mod signature;
+pub mod snapshots;
+pub mod style;
+pub mod targets;
+pub mod telemetry;
+pub mod template;
+pub mod workspace;
+
+use commands::init::{PackageKind, Vcs};
+use config::{Config, Language, LibType};
+use global::GlobalConfig;
+use lockfile::{Lockfile, LOCKFILE};
+use members::Member;
+use plugins::Plugin;
+use sanitizer::Sanitizer;
+use targets::{BuildFile, ExternalDeps};
+use template::{License, Template, Vars};
+
+/// Creates the package at `path`, from `template` when given, a directory
+/// or a git repository, otherwise from the built-in one, rendered with
+/// `vars`. The package is put under `vcs`, by default git unless it lies
+/// in a repository already.
+fn new_package(
+    path: &str,
+    vars: &Vars,
+    template: Option<&str>,
+    vcs: Option<Vcs>,
+    plugins: &[Plugin],
+    global: &GlobalConfig,
+) -> Result<(), String> {
+    if Path::new(path).exists() {
+        return Err(format!("destination `{}` already exists", path));
+    }
+    let root = Path::new(path);
+    let package_name = &vars.name;
+    // Decided before the package makes its own directory a work tree.
+    let vcs = vcs.unwrap_or(match root.parent() {
+        Some(parent) if git::in_work_tree(&parent.join(".")) => Vcs::None,
+        _ => Vcs::Git,
+    });
+    match template {
+        Some(source) => {
+            let template = Template::fetch(source)?;
+            style::status(
+                "Created",
+                format!("`{}` package from template `{}`", package_name, source),
+            );
+            if let Some(description) = &template.manifest.description {
+                style::status("Template", description);
+            }
+            fs::create_dir_all(root).map_err(|e| e.to_string())?;
+            template.generate(root, vars)?;
+            if !root.join("Buddy.toml").is_file() {
+                fs::write(root.join("Buddy.toml"), template::manifest(vars))
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+        None => {
+            let description = if vars.lib {
+                "library"
+            } else {
+                "binary (application)"
+            };
+            style::status(
+                "Created",
+                format!("{} `{}` package", description, package_name),
+            );
+            write_builtin(root, vars).map_err(|e| e.to_string())?;
+        }
+    }
 
-fn new_package(package_name: &str, plugins: &[Plugin]) -> std::io::Result<()> {
-    if !Path::new(package_name).exists() {
-        println!(
-            "    {} binary (application) `{}` package",
-            "Created".green(),
-            package_name
-        );
-        fs::create_dir(package_name)?;
-        fs::create_dir(PathBuf::from(package_name).join("src"))?;
-        fs::create_dir(PathBuf::from(package_name).join("test"))?;
-
-        let mut file = File::create(PathBuf::from(package_name).join("WORKSPACE"))?;
-
-        write!(
-            file,
-            r#"# This file is automatically @generated by Buddy.
-# It is not intended for manual editing.
-load("@bazel_tools//tools/build_defs/repo:http.bzl", "http_archive")
-
-"#
-        )?;
-
-        let build_rule = &plugins[0].build_rule;
-        let build_rule = build_rule.replace("{version}", &plugins[0].versions["1.13.0"]);
-
-        write!(file, "{}", build_rule)?;
-
-        write!(file, "\n")?;
-
-        let build_rule = &plugins[1].build_rule;
+    let config = config::load(&root.join("Buddy.toml"))?;
+    let sources = config.sources();
+    lockfile::sync(
+        root,
+        &config.versions(),
+        &sources,
+        &config.patched(),
+        plugins,
+        global,
+        false,
+    )?;
+    workspace::sync(root, &config.versions(), &sources, plugins, global)?;
+
+    if !root.join(".bazelrc").exists() {
+        let mut bazelrc = String::new();
+        for line in commands::init::bazelrc(&config.package) {
+            bazelrc.push_str(&line);
+            bazelrc.push('\n');
+        }
+        fs::write(root.join(".bazelrc"), bazelrc).map_err(|e| e.to_string())?;
+    }
+    // The library and its public headers are laid out after [lib].
+    if !config.lib.public_headers.is_empty() {
+        targets::sync_library(root, Path::new(""), &config, &ExternalDeps::default())
+            .map_err(|e| e.to_string())?;
+    }
+    targets::sync_tests(root, Path::new(""), &config.package.name, &config.test)
+        .map_err(|e| e.to_string())?;
+    sync_programs(root, Path::new(""), &config)?;
 
-        write!(file, "{}", build_rule)?;
+    // Templates may bring their own style.
+    if !root.join(".clang-format").exists() {
+        fs::write(root.join(".clang-format"), template::CLANG_FORMAT).map_err(|e| e.to_string())?;
+    }
+    // Editors find the compile commands once `buddy ide` wrote them.
+    if !root.join(commands::ide::CLANGD).exists() {
+        commands::ide::write_clangd(&config, root)?;
+    }
+    if let Some(license) = vars.license {
+        if !root.join("LICENSE").exists() {
+            fs::write(root.join("LICENSE"), template::license_text(license, vars))
+                .map_err(|e| e.to_string())?;
+        }
+    }
 
-        let mut file = File::create(PathBuf::from(package_name).join("Buddy.toml"))?;
-        write!(
-            file,
-            r#"[package]
-name = "{}"
-version = "0.1.0"
-edition = "2023"
+    if vcs == Vcs::Git {
+        if !root.join(".gitignore").exists() {
+            fs::write(root.join(".gitignore"), template::GITIGNORE).map_err(|e| e.to_string())?;
+        }
+        // The package is there all the same.
+        if let Err(e) = git::init_repository(root) {
+            style::warning(e);
+        }
+    }
+    Ok(())
+}
 
-[dependencies]
-bazel-toolchain = "0.8.0"
-google-test = "1.13.0""#,
-            package_name
-        )?;
+/// Writes the manifest and the sources of the built-in template rendered
+/// with `vars` into `root`.
+fn write_builtin(root: &Path, vars: &Vars) -> std::io::Result<()> {
+    fs::create_dir_all(root.join("test"))?;
+    fs::write(root.join("Buddy.toml"), template::manifest(vars))?;
+    for (file, contents) in template::sources(vars) {
+        let file = root.join(file);
+        fs::create_dir_all(file.parent().unwrap())?;
+        fs::write(file, contents)?;
+    }
+    Ok(())
+}
 
-        let mut file = File::create(PathBuf::from(package_name).join("Buddy.lock"))?;
-        write!(
-            file,
-            r#"# This file is automatically @generated by Buddy.
-# It is not intended for manual editing.
-version = 1
-
-[[package]]
-name = "google-test"
-version = "1.13.0"
-source = "https://github.com/google/googletest"
-"#
-        )?;
+/// Copies the library outputs requested by `[lib]` into `target/lib`.
+fn copy_libraries(config: &Config) -> std::io::Result<()> {
+    let bin = Path::new("target").join("bin").join("src");
+    let lib = Path::new(install::LIB_DIR);
+    let build_files = targets::scan_library(Path::new("."), config, &ExternalDeps::default())?;
 
-        let mut file = File::create(PathBuf::from(package_name).join(".bazelrc"))?;
-        write!(file, r#"build --cxxopt=-std=c++17"#)?;
-        write!(file, "\n")?;
-        write!(
-            file,
-            r#"build --incompatible_enable_cc_toolchain_resolution"#
-        )?;
+    let shared = targets::shared_lib_name(&config.package);
+    for output in install::library_outputs(config, &build_files) {
+        if !bin.join(&output).is_file() {
+            continue;
+        }
+        fs::create_dir_all(lib)?;
+        let dest = lib.join(&output);
+        // Bazel's outputs are read-only, replace rather than overwrite.
+        let _ = fs::remove_file(&dest);
+        fs::copy(bin.join(&output), &dest)?;
+        style::status("Copied", dest.display());
+    }
 
-        let mut file = File::create(PathBuf::from(package_name).join("src").join("BUILD"))?;
+    // The links a shared library is found through at build and run time.
+    #[cfg(unix)]
+    if lib.join(&shared).is_file() {
+        for link in [
+            targets::soname(&config.package),
+            format!("lib{}.so", config.package.name),
+        ] {
+            let _ = fs::remove_file(lib.join(&link));
+            std::os::unix::fs::symlink(&shared, lib.join(link))?;
+        }
+    }
 
-        write!(
-            file,
-            r#"load("@rules_cc//cc:defs.bzl", "cc_binary")
+    Ok(())
+}
 
-cc_binary(
-    name = "{}",
-    srcs = ["main.cc"],
-)"#,
-            package_name
-        )?;
+fn build(
+    bazel_bin: &Path,
+    args: &[String],
+    flags: &[String],
+    config: &Config,
+    out_dir: Option<&Path>,
+) -> Result<bool, Box<dyn Error>> {
+    let mut cmd = bazel::command(bazel_bin, "build");
+    cmd.args(flags);
+
+    if !args.is_empty() {
+        for arg in args {
+            cmd.arg(arg);
+        }
+    } else {
+        cmd.arg("//src/...");
+    }
 
-        let mut file = File::create(PathBuf::from(package_name).join("src").join("main.cc"))?;
-
-        write!(
-            file,
-            r#"#include <ctime>
-#include <string>
-#include <iostream>
-
-std::string get_greet(const std::string& who) {{
-  return "Hello " + who;
-}}
-
-void print_localtime() {{
-  std::time_t result = std::time(nullptr);
-  std::cout << std::asctime(std::localtime(&result));
-}}
-
-int main(int argc, char** argv) {{
-  std::string who = "world";
-  if (argc > 1) {{
-    who = argv[1];
-  }}
-  std::cout << get_greet(who) << std::endl;
-  print_localtime();
-  return 0;
-}}"#
-        )?;
+    if !bazel::stream(&mut cmd)?.success() {
+        return Ok(false);
+    }
+    // The members of a workspace install nothing at its root.
+    if Path::new("Buddy.toml").is_file() && config.workspace.is_none() {
+        copy_libraries(config)?;
+        install::write_manifest(Path::new("."), config)?;
+    }
+    if let Some(out_dir) = out_dir {
+        let events = fs::read_to_string(bazel::EVENT_FILE)?;
+        artifacts::copy(&artifacts::parse(&events), out_dir)?;
+    }
 
-        let mut file = File::create(PathBuf::from(package_name).join("test").join("BUILD"))?;
+    Ok(true)
+}
 
-        write!(
-            file,
-            r#"cc_test(
-  name = "hello_test",
-  size = "small",
-  srcs = ["hello_test.cc"],
-  deps = ["@com_google_googletest//:gtest_main"],
-)"#
-        )?;
+/// Runs bazel with `args`, its startup options, command and the command's
+/// arguments, in buddy's setup, and returns its exit code. The flags of the
+/// features are only passed to the commands building.
+fn passthrough(bazel_bin: &Path, args: &[String], flags: &[String]) -> Result<i32, String> {
+    let verb = args
+        .iter()
+        .position(|arg| !arg.starts_with('-'))
+        .ok_or("missing the bazel command to run, e.g. `buddy bazel -- cquery //src/...`")?;
+    let (startup_options, args) = args.split_at(verb);
+
+    let mut cmd = bazel::command_with(bazel_bin, startup_options, &args[0]);
+    if bazel::builds(&args[0]) {
+        cmd.args(flags);
+    }
+    cmd.args(&args[1..]);
+    let status = cmd
+        .status()
+        .map_err(|e| format!("failed to run bazel: {}", e))?;
+    Ok(status.code().unwrap_or(1))
+}
 
-        let mut file = File::create(
-            PathBuf::from(package_name)
-                .join("test")
-                .join("hello_test.cc"),
-        )?;
+/// Where `buddy run` has bazel write the command running the binary.
+const RUN_SCRIPT: &str = "target/run.sh";
+
+/// Builds the binary and runs it through the script bazel writes, with the
+/// directories of the shared libraries its rpath misses, e.g. the ones of
+/// the toolchain, on the library path, and the variables of `[env]` set.
+fn run(
+    bazel_bin: &Path,
+    args: &[String],
+    flags: &[String],
+    env: &[(String, String)],
+    capture: Option<&Path>,
+) -> Result<i32, Box<dyn Error>> {
+    let mut cmd = bazel::command(bazel_bin, "run");
+    cmd.args(flags);
+    cmd.arg(format!("--script_path={}", RUN_SCRIPT));
+    cmd.args(args);
+
+    if !bazel::stream(&mut cmd)?.success() {
+        return Ok(1);
+    }
 
-        write!(
-            file,
-            r#"#include <gtest/gtest.h>
-
-// Demonstrate some basic assertions.
-TEST(HelloTest, BasicAssertions) {{
-  // Expect two strings not to be equal.
-  EXPECT_STRNE("hello", "world");
-  // Expect equality.
-  EXPECT_EQ(7 * 6, 42);
-}}"#
-        )?;
+    // Only needed to find the libraries, running goes on without them.
+    let events = fs::read_to_string(bazel::EVENT_FILE).unwrap_or_default();
+    let mut dirs = Vec::new();
+    for artifact in artifacts::parse(&events) {
+        for library in runtime::libraries(&artifact.path) {
+            let dir = library.path.parent().map(Path::to_path_buf);
+            if !library.resolved && !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+    }
+    let dirs: Vec<PathBuf> = dirs.into_iter().flatten().collect();
 
-        Ok(())
-    } else {
-        println!(
-            "{}: destination `{}` already exixts",
-            "error".red(),
-            package_name
-        );
-        Ok(())
+    let mut script = Command::new(RUN_SCRIPT);
+    script.envs(env.iter().map(|(name, value)| (name, value)));
+    if !dirs.is_empty() {
+        let (variable, value) = runtime::library_path(&dirs);
+        script.env(variable, value);
     }
+    let label = args
+        .iter()
+        .find(|arg| !arg.starts_with('-'))
+        .map_or("", String::as_str);
+    Ok(commands::run::execute(&mut script, label, capture)?)
 }
 
-fn build(bazel_bin: &PathBuf, args: &[String]) -> Result<(), Box<dyn Error>> {
-    let mut cmd = Command::new(bazel_bin);
-
-    // cmd.arg("--output_base=target/build");
-    cmd.arg("build");
-    cmd.arg("--symlink_prefix=target/");
+fn test(
+    bazel_bin: &Path,
+    args: &[String],
+    flags: &[String],
+    config: &Config,
+    members: &[Member],
+    update_snapshots: bool,
+    coverage: bool,
+) -> Result<bool, Box<dyn Error>> {
+    let root = Path::new(".");
+    sync_tests(config, members)?;
+
+    let verb = if coverage { "coverage" } else { "test" };
+    let mut cmd = bazel::command(bazel_bin, verb);
+    cmd.arg("--test_output=all");
+    cmd.args(flags);
+    if coverage {
+        cmd.arg(coverage::INSTRUMENTATION_FLAG);
+    }
+    if update_snapshots {
+        // Keeps the recorded snapshots readable under target/testlogs.
+        cmd.arg("--zip_undeclared_test_outputs=false");
+    }
 
-    if args.len() != 0 {
+    if !args.is_empty() {
         for arg in args {
             cmd.arg(arg);
         }
     } else {
-        cmd.arg("//src/...");
+        cmd.arg("//test/...");
     }
 
-    let mut child = cmd
-        .stderr(Stdio::piped())
-        .spawn()
-        .expect("Failed to execute command");
-
-    let stderr = child.stderr.take().unwrap();
-    let reader = io::BufReader::new(stderr);
+    let mut success = bazel::stream(&mut cmd)?.success();
+    results::report(Path::new(bazel::EVENT_FILE));
 
-    for line in reader.lines() {
-        let line = line.unwrap();
-        if line.starts_with("INFO:") {
-            let (_, message) = line.split_at(6);
-            println!("{} {}", "INFO:".green(), message);
-        } else {
-            println!("{}", line);
+    if update_snapshots {
+        let build_files = test_build_files(config, members)?;
+        let pending = snapshots::pending(root, &build_files)?;
+        if pending.is_empty() {
+            return Ok(success);
+        }
+        snapshots::accept(root, &pending)?;
+
+        // Run the tests again with their new snapshots.
+        sync_tests(config, members)?;
+        let mut tests: Vec<_> = pending.iter().map(|p| p.test.as_str()).collect();
+        tests.dedup();
+        let mut cmd = bazel::command(bazel_bin, verb);
+        cmd.arg("--test_output=all");
+        cmd.args(flags);
+        if coverage {
+            cmd.arg(coverage::INSTRUMENTATION_FLAG);
         }
+        cmd.args(tests);
+        success = bazel::stream(&mut cmd)?.success();
+        results::report(Path::new(bazel::EVENT_FILE));
     }
 
-    // Not sure why is still being generated. Eitherway, we get rid of it.
-    let folder_path = Path::new("bazel-out");
-    if folder_path.exists() {
-        fs::remove_dir_all(folder_path).expect("Failed to delete folder");
-    }
+    Ok(success)
+}
 
-    Ok(())
+/// The tests affected by the files changed since `reference`, `None` when
+/// the changes may affect any test.
+fn affected_tests(
+    bazel_bin: &Path,
+    config: &Config,
+    members: &[Member],
+    reference: Option<&str>,
+) -> Result<Option<Vec<String>>, String> {
+    let root = Path::new(".");
+    // The query needs the test targets of the current sources.
+    sync_tests(config, members).map_err(|e| e.to_string())?;
+
+    let files = git::changed_files(reference)?;
+    match affected::labels(root, &files) {
+        Some(labels) if labels.is_empty() => Ok(Some(Vec::new())),
+        Some(labels) => affected::tests(bazel_bin, &labels).map(Some),
+        None => Ok(None),
+    }
 }
 
-fn run(bazel_bin: &PathBuf, args: &[String], config: &Config) -> Result<(), Box<dyn Error>> {
-    let mut cmd = Command::new(bazel_bin);
+/// Whether the package has libraries laid out by buddy, beyond the
+/// hand-written `src/BUILD` of a new package.
+fn generates_layout(config: &Config) -> bool {
+    config.lib.types.contains(&LibType::Shared)
+        || !config.lib.public_headers.is_empty()
+        || !config.build.frameworks.is_empty()
+        || !config.modules.is_empty()
+        || !config.path_dependencies().is_empty()
+        || !config.optional_versions().is_empty()
+        || !config.target.is_empty()
+}
 
-    // cmd.arg("--output_base=target/build");
-    cmd.arg("run");
-    cmd.arg("--symlink_prefix=target/");
+/// Regenerates the `BUILD` files of the programs under `src/bin/` and
+/// `examples/` of the package at `root`, its labels relocated below
+/// `prefix` in a workspace.
+fn sync_programs(root: &Path, prefix: &Path, config: &Config) -> Result<(), String> {
+    targets::sync_binaries(root, prefix, &config.package.name, &config.test)
+        .and_then(|_| targets::sync_examples(root, prefix, &config.package.name, &config.test))
+        .and_then(|_| targets::sync_benches(root, prefix, &config.package.name, &config.test))
+        .and_then(|_| targets::sync_fuzzers(root, prefix, &config.package.name, &config.test))
+        .map_err(|e| e.to_string())
+}
 
-    if args.len() != 0 {
-        for arg in args {
-            cmd.arg(arg);
+/// Locks every dependency `config` declares, brings the WORKSPACE up to
+/// date with the `dependencies` the build uses and, when the policy or
+/// `--offline` ask for it, checks the archives of those.
+fn sync_dependencies(
+    config: &Config,
+    dependencies: &HashMap<String, String>,
+    plugins: &[Plugin],
+    global: &GlobalConfig,
+    cli: &Cli,
+) -> Result<(), String> {
+    let sources = config.sources();
+    let mut declared = config.versions();
+    declared.extend(config.optional_versions());
+    for platform in config.platform_dependencies()?.into_values() {
+        declared.extend(platform);
+    }
+    declared.retain(|name, _| !config.patch.contains_key(name));
+    let patched = config.patched();
+    for name in config.patch.keys() {
+        if !patched.contains(name) {
+            style::warning(format!(
+                "[patch] replaces `{}`, which is not a dependency of the package",
+                name
+            ));
         }
-    } else {
-        cmd.arg(format!("//src:{}", config.package.name));
     }
+    lockfile::sync(
+        Path::new("."),
+        &declared,
+        &sources,
+        &patched,
+        plugins,
+        global,
+        cli.is_locked(),
+    )?;
+    // After the lockfile, which pins the commits of the git dependencies.
+    workspace::sync(Path::new("."), dependencies, &sources, plugins, global)?;
+    if cli.is_offline() {
+        let lockfile = Lockfile::load(Path::new(LOCKFILE))?;
+        let versions = plugins::select_versions(dependencies, plugins, global, &lockfile)?;
+        commands::fetch::check_cached(
+            &versions,
+            plugins,
+            global,
+            &global::distdir(),
+            workspace::vendor_dir(Path::new(".")).as_deref(),
+        )?;
+    }
+    commands::fetch::verify_if_required(dependencies, plugins)
+}
 
-    let mut child = cmd
-        .stderr(Stdio::piped())
-        .spawn()
-        .expect("Failed to execute command");
+/// `prepare` at the root of a workspace: brings the `BUILD` files of every
+/// member up to date, with the features of `args` it declares, and the
+/// shared lockfile and WORKSPACE with the dependencies of all of them.
+/// Returns the bazel flags enabling the features.
+fn prepare_members(
+    config: &Config,
+    members: &[Member],
+    args: &FeatureArgs,
+    plugins: &[Plugin],
+    global: &GlobalConfig,
+    cli: &Cli,
+) -> Result<Vec<String>, String> {
+    if let Some(unknown) = args.features.iter().find(|feature| {
+        !members
+            .iter()
+            .any(|member| member.config.features.contains_key(*feature))
+    }) {
+        return Err(format!(
+            "unknown feature `{}`, no workspace member declares it",
+            unknown
+        ));
+    }
+    let merged = members::merge(config, members)?;
 
-    let stderr = child.stderr.take().unwrap();
-    let reader = io::BufReader::new(stderr);
+    let mut dependencies = HashMap::new();
+    let mut flags = Vec::new();
+    for member in members {
+        let requested: Vec<_> = args
+            .features
+            .iter()
+            .filter(|feature| member.config.features.contains_key(*feature))
+            .cloned()
+            .collect();
+        let enabled = features::resolve(
+            &member.config.features,
+            &requested,
+            !args.no_default_features,
+        )?;
+        if !enabled.is_empty() {
+            let names: Vec<_> = enabled.iter().map(String::as_str).collect();
+            style::status(
+                "Features",
+                format!("{} ({})", names.join(", "), member.config.package.name),
+            );
+        }
 
-    for line in reader.lines() {
-        let line = line.unwrap();
-        if line.starts_with("INFO:") {
-            let (_, message) = line.split_at(6);
-            println!("{} {}", "INFO:".green(), message);
-        } else {
-            println!("{}", line);
+        let mut external = ExternalDeps::resolve(&member.config, &enabled, plugins, global)?;
+        external.members = member.member_deps.clone();
+        let layout = generates_layout(&member.config) || !external.members.is_empty();
+        if layout
+            && !targets::sync_library(&member.dir, &member.dir, &member.config, &external)
+                .map_err(|e| e.to_string())?
+        {
+            style::warning(format!(
+                "{} is not generated by buddy, declare the cc_shared_library of [lib] in it",
+                member.dir.join("src").join("BUILD").display()
+            ));
         }
+        sync_programs(&member.dir, &member.dir, &member.config)?;
+        dependencies.extend(features::dependencies(&member.config, &enabled)?);
+        flags.extend(features::flags(&member.config.package.name, &enabled));
     }
+    dependencies.retain(|name, _| !merged.patch.contains_key(name));
+    sync_dependencies(&merged, &dependencies, plugins, global, cli)?;
+    Ok(flags)
+}
 
-    // Not sure why is still being generated. Eitherway, we get rid of it.
-    let folder_path = Path::new("bazel-out");
-    if folder_path.exists() {
-        fs::remove_dir_all(folder_path).expect("Failed to delete folder");
-    }
+/// Resolves the features to build with, brings the WORKSPACE up to date
+/// with the dependencies they need, locks every declared dependency and,
+/// when the policy or `--offline` ask for it, checks the archives of those
+/// before handing over to bazel. At the root of a workspace, does so for
+/// all its `members`. Returns the bazel flags enabling the features, the
+/// configured linker and the `[env]` variables.
+fn prepare(
+    config: &Config,
+    members: &[Member],
+    args: &FeatureArgs,
+    plugins: &[Plugin],
+    global: &GlobalConfig,
+    cli: &Cli,
+) -> Result<Vec<String>, String> {
+    let mut flags = if config.workspace.is_some() {
+        prepare_members(config, members, args, plugins, global, cli)?
+    } else {
+        let enabled =
+            features::resolve(&config.features, &args.features, !args.no_default_features)?;
+        if !enabled.is_empty() {
+            let names: Vec<_> = enabled.iter().map(String::as_str).collect();
+            style::status("Features", names.join(", "));
+        }
 
-    Ok(())
+        if Path::new("Buddy.toml").is_file() {
+            let external = ExternalDeps::resolve(config, &enabled, plugins, global)?;
+            if generates_layout(config)
+                && !targets::sync_library(Path::new("."), Path::new(""), config, &external)
+                    .map_err(|e| e.to_string())?
+            {
+                style::warning(
+                    "src/BUILD is not generated by buddy, declare the cc_shared_library of [lib] in it",
+                );
+            }
+            sync_programs(Path::new("."), Path::new(""), config)?;
+            let dependencies = features::dependencies(config, &enabled)?;
+            sync_dependencies(config, &dependencies, plugins, global, cli)?;
+        }
+        features::flags(&config.package.name, &enabled)
+    };
+    flags.extend(bazel::linker_flags(config.build.linker.as_deref())?);
+    flags.extend(bazel::build_flags(&config.build));
+    flags.extend(bazel::c_standard_flags(
+        config.package.c_standard.as_deref(),
+    )?);
+    flags.extend(bazel::cxx_standard_flags(
+        config.package.cxx_standard.as_deref(),
+    )?);
+    flags.extend(bazel::env_flags(&config.env_vars()));
+    if cli.is_offline() {
+        flags.push(bazel::OFFLINE_FLAG.to_string());
+    }
+    Ok(flags)
 }
 
-fn test(bazel_bin: &PathBuf, args: &[String]) -> Result<(), Box<dyn Error>> {
-    let mut cmd = Command::new(bazel_bin);
-
-    // cmd.arg("--output_base=target/build");
-    cmd.arg("test");
-    cmd.arg("--test_output=all");
-    cmd.arg("--symlink_prefix=target/");
+/// The targets to build or test in a workspace: the ones given, relative to
+/// the member buddy runs in when `selected`, or every target under `dir` of
+/// the selected member, of all the members at the root.
+fn member_targets(
+    targets: &[String],
+    members: &[Member],
+    selected: Option<&Path>,
+    dir: &str,
+) -> Vec<String> {
+    let selected: Vec<_> = members
+        .iter()
+        .filter(|member| selected.is_none_or(|selected| member.dir == selected))
+        .collect();
+    match selected.as_slice() {
+        _ if members.is_empty() => targets.to_vec(),
+        _ if targets.is_empty() => selected.iter().map(|member| member.pattern(dir)).collect(),
+        [member] if selected.len() < members.len() => targets
+            .iter()
+            .map(|target| targets::relocate_label(target, &member.package()))
+            .collect(),
+        _ => targets.to_vec(),
+    }
+}
 
-    if args.len() != 0 {
-        for arg in args {
-            cmd.arg(arg);
+/// The labels `buddy query` puts in place of the names of the packages, the
+/// members' in a workspace, and of the dependencies with a target.
+fn query_names(
+    config: &Config,
+    members: &[Member],
+    plugins: &[Plugin],
+) -> BTreeMap<String, String> {
+    let mut names = BTreeMap::new();
+    let lockfile = Lockfile::load(Path::new(LOCKFILE)).ok();
+    let configs = std::iter::once(config).chain(members.iter().map(|member| &member.config));
+    for config in configs {
+        for (name, requirement) in config.versions() {
+            let version = lockfile
+                .as_ref()
+                .and_then(|lockfile| lockfile.locked_version(&name))
+                .unwrap_or(&requirement);
+            if let Some(Ok(Some(label))) =
+                plugins::find(plugins, &name).map(|plugin| plugin.target_label(version))
+            {
+                names.insert(name, label);
+            }
         }
-    } else {
-        cmd.arg("//test/...");
     }
+    if config.workspace.is_none() {
+        names.insert(
+            config.package.name.clone(),
+            format!("//src:{}", config.package.name),
+        );
+    }
+    for member in members {
+        let name = &member.config.package.name;
+        names.insert(
+            name.clone(),
+            targets::relocate_label(&format!("//src:{}", name), &member.package()),
+        );
+    }
+    names
+}
 
-    let mut child = cmd
-        .stderr(Stdio::piped())
-        .spawn()
-        .expect("Failed to execute command");
+/// The member of the workspace `buddy <command>` runs or installs a binary
+/// of: the selected one, or the only member. `None` outside a workspace.
+fn run_member<'a>(
+    command: &str,
+    config: &Config,
+    members: &'a [Member],
+    selected: Option<&Path>,
+) -> Result<Option<&'a Member>, String> {
+    if config.workspace.is_none() {
+        return Ok(None);
+    }
+    if let Some(selected) = selected {
+        return Ok(members.iter().find(|member| member.dir == selected));
+    }
+    match members {
+        [member] => Ok(Some(member)),
+        _ => Err(format!(
+            "`buddy {}` at the root of a workspace needs -p to pick the member, one of: {}",
+            command,
+            members::names(members)
+        )),
+    }
+}
 
-    let stderr = child.stderr.take().unwrap();
-    let reader = io::BufReader::new(stderr);
+/// Brings the test `BUILD` files and the snapshot headers of the package,
+/// or of every member of the workspace, up to date.
+fn sync_tests(config: &Config, members: &[Member]) -> std::io::Result<()> {
+    if config.workspace.is_none() {
+        let root = Path::new(".");
+        snapshots::sync_header(root)?;
+        return targets::sync_tests(root, Path::new(""), &config.package.name, &config.test);
+    }
+    for member in members {
+        snapshots::sync_header(&member.dir)?;
+        targets::sync_tests(
+            &member.dir,
+            &member.dir,
+            &member.config.package.name,
+            &member.config.test,
+        )?;
+    }
+    Ok(())
+}
 
-    for line in reader.lines() {
-        let line = line.unwrap();
-        if line.starts_with("INFO:") {
-            let (_, message) = line.split_at(6);
-            println!("{} {}", "INFO:".green(), message);
-        } else {
-            println!("{}", line);
+/// The test `BUILD` files of the package, or of every member of the
+/// workspace, their directories relative to the root.
+fn test_build_files(config: &Config, members: &[Member]) -> std::io::Result<Vec<BuildFile>> {
+    if config.workspace.is_none() {
+        return targets::scan(Path::new("."), &config.package.name, &config.test);
+    }
+    let mut build_files = Vec::new();
+    for member in members {
+        for mut build_file in targets::scan(
+            &member.dir,
+            &member.config.package.name,
+            &member.config.test,
+        )? {
+            build_file.dir = member.dir.join(&build_file.dir);
+            build_files.push(build_file);
         }
     }
+    Ok(build_files)
+}
+
+/// The flags of the profile `args` select, see `[profile]`.
+fn profile_flags(config: &Config, args: &ProfileArgs) -> Result<Vec<String>, String> {
+    bazel::profile_flags(config.profile.get(args.release), args.release)
+}
 
-    // Not sure why is still being generated. Eitherway, we get rid of it.
-    let folder_path = Path::new("bazel-out");
-    if folder_path.exists() {
-        fs::remove_dir_all(folder_path).expect("Failed to delete folder");
+/// The bazel flags of the build options, the command line taking
+/// precedence over `[build]`.
+fn build_flags(config: &Config, args: &BuildArgs) -> Result<Vec<String>, String> {
+    let mut flags = bazel::resource_flags(
+        args.jobs.or(config.build.jobs),
+        args.local_ram
+            .as_deref()
+            .or(config.build.local_ram.as_deref()),
+    )?;
+    if args.keep_going || config.build.keep_going {
+        flags.push("--keep_going".to_string());
     }
+    Ok(flags)
+}
 
-    Ok(())
+/// Reports the end of `command`, started at `start`, as `[notify]`,
+/// `--notify` and `[telemetry]` ask.
+fn finished(
+    global: &GlobalConfig,
+    args: &BuildArgs,
+    config: &Config,
+    command: &str,
+    success: bool,
+    start: Instant,
+) {
+    let duration = start.elapsed();
+    let outcome = notify::Outcome {
+        command,
+        package: &config.package.name,
+        success,
+        duration,
+    };
+    notify::send(global, args.notify, &outcome);
+    telemetry::export(
+        global,
+        command,
+        &config.package.name,
+        success,
+        duration,
+        Path::new(bazel::EVENT_FILE),
+    );
+}
+
+fn exit_with_error<T>(error: String) -> T {
+    style::error(error);
+    std::process::exit(1);
 }
 
 #[derive(Parser)]
@@ -299,138 +775,1495 @@ fn test(bazel_bin: &PathBuf, args: &[String]) -> Result<(), Box<dyn Error>> {
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    /// Never access the network: use the cached registry indexes as they
+    /// are and only the dependency archives already fetched
+    #[arg(long, global = true)]
+    offline: bool,
+
+    /// Fail instead of updating Buddy.lock
+    #[arg(long, global = true)]
+    locked: bool,
+
+    /// Both --locked and --offline
+    #[arg(long, global = true)]
+    frozen: bool,
+}
+
+impl Commands {
+    /// The member of the workspace `-p` selects.
+    fn package(&self) -> Option<&str> {
+        match self {
+            Commands::Build { package, .. }
+            | Commands::Check { package, .. }
+            | Commands::Install { package, .. }
+            | Commands::Run { package, .. }
+            | Commands::Test { package, .. } => package.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Whether the command builds the package, or every member of the
+    /// workspace at its root.
+    fn builds(&self) -> bool {
+        matches!(
+            self,
+            Commands::Build { .. }
+                | Commands::Check { .. }
+                | Commands::Dist { .. }
+                | Commands::Install { .. }
+                | Commands::Ide { .. }
+                | Commands::Lint { .. }
+                | Commands::Package { .. }
+                | Commands::Publish { .. }
+                | Commands::Query { .. }
+                | Commands::Targets { .. }
+                | Commands::Run { .. }
+                | Commands::Test { .. }
+                | Commands::Bazel { .. }
+        )
+    }
+}
+
+impl Cli {
+    fn is_locked(&self) -> bool {
+        self.locked || self.frozen
+    }
+
+    fn is_offline(&self) -> bool {
+        self.offline || self.frozen
+    }
+}
+
+#[derive(Args)]
+struct ProfileArgs {
+    /// Build with the release profile, optimized
+    #[arg(short, long)]
+    release: bool,
+}
+
+#[derive(Args)]
+struct SanitizeArgs {
+    /// Instrument the code with sanitizers, comma separated
+    #[arg(long, value_name = "SANITIZER", value_delimiter = ',')]
+    sanitize: Vec<Sanitizer>,
+}
+
+#[derive(Args)]
+struct BuildArgs {
+    /// Build as much as possible instead of stopping at the first failing
+    /// target
+    #[arg(long)]
+    keep_going: bool,
+
+    /// Number of parallel jobs, defaults to the number of cores
+    #[arg(short, long, value_name = "N")]
+    jobs: Option<u32>,
+
+    /// RAM available to the build, e.g. 8G, 512M or 50%
+    #[arg(long, value_name = "SIZE")]
+    local_ram: Option<String>,
+
+    /// Show a desktop notification when done, and call the webhook of
+    /// [notify] however long it took
+    #[arg(long)]
+    notify: bool,
+}
+
+#[derive(Args, Default)]
+struct FeatureArgs {
+    /// Features of the [features] table to enable, comma separated
+    #[arg(long, value_delimiter = ',')]
+    features: Vec<String>,
+
+    /// Do not enable the `default` feature set
+    #[arg(long)]
+    no_default_features: bool,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// Create a new buddy package
-    New { path: String },
+    New {
+        path: String,
+
+        /// Set the package name, defaults to the directory name
+        #[arg(long)]
+        name: Option<String>,
+
+        /// Language of the package's sources
+        #[arg(long, value_enum, default_value_t = Language::Cxx)]
+        lang: Language,
+
+        /// Standard of the language, e.g. `c++20`, or `c11` with `--lang c`
+        #[arg(long, value_name = "STD")]
+        std: Option<String>,
+
+        /// Create a library, with its public headers under include/
+        #[arg(long)]
+        lib: bool,
+
+        /// Add a Google Benchmark program under bench/
+        #[arg(long)]
+        bench: bool,
+
+        /// Create the package from a template: a directory or a git
+        /// repository with a buddy-template.toml
+        #[arg(long, value_name = "PATH|URL", conflicts_with_all = ["lang", "lib", "bench"])]
+        template: Option<String>,
+
+        /// License the package under, writing its text to LICENSE
+        #[arg(long, value_enum)]
+        license: Option<License>,
+
+        /// Ask for the package kind, standard, test framework, toolchain and
+        /// license, the flags given being the default answers
+        #[arg(short, long, conflicts_with = "template")]
+        interactive: bool,
+
+        /// Put the package under version control, by default a git
+        /// repository unless it lies in one already
+        #[arg(long, value_enum)]
+        vcs: Option<Vcs>,
+    },
 
     /// Create a new buddy package in an existing directory
     Init {
         #[clap(default_value = ".")]
         path: String,
+
+        /// Set the package name, defaults to the directory name
+        #[arg(long)]
+        name: Option<String>,
+
+        /// Overwrite existing files instead of skipping them. In an existing
+        /// package, regenerate the files derived from Buddy.toml, backing up
+        /// the ones replaced
+        #[arg(long)]
+        force: bool,
     },
 
     /// Compile the current package
-    Build { targets: Vec<String> },
+    Build {
+        targets: Vec<String>,
+
+        /// Member of the workspace to build, by package name
+        #[arg(short, long, value_name = "NAME")]
+        package: Option<String>,
+
+        /// Copy the built binaries and libraries into DIR
+        #[arg(long, value_name = "DIR")]
+        out_dir: Option<PathBuf>,
+
+        #[command(flatten)]
+        options: BuildArgs,
+
+        #[command(flatten)]
+        profile: ProfileArgs,
+
+        #[command(flatten)]
+        sanitize: SanitizeArgs,
+
+        #[command(flatten)]
+        features: FeatureArgs,
+    },
+
+    /// Compile the current package without linking it, for quick feedback
+    Check {
+        /// Labels, or source files to compile the targets of, e.g. a header
+        targets: Vec<String>,
+
+        /// Member of the workspace to check, by package name
+        #[arg(short, long, value_name = "NAME")]
+        package: Option<String>,
+
+        #[command(flatten)]
+        options: BuildArgs,
+
+        #[command(flatten)]
+        profile: ProfileArgs,
+
+        #[command(flatten)]
+        features: FeatureArgs,
+    },
+
+    /// Build the package's binaries optimized and install them into
+    /// ~/.buddy/bin
+    Install {
+        /// Only install this binary
+        #[arg(long, value_name = "NAME")]
+        bin: Option<String>,
+
+        /// Member of the workspace to install, by package name
+        #[arg(short, long, value_name = "NAME")]
+        package: Option<String>,
+
+        /// Install into DIR/bin instead, tracking the binaries in DIR
+        #[arg(long, value_name = "DIR")]
+        root: Option<PathBuf>,
+
+        /// Replace binaries installed by other packages or by hand
+        #[arg(long)]
+        force: bool,
+
+        #[command(flatten)]
+        features: FeatureArgs,
+    },
+
+    /// Remove binaries installed with `buddy install`
+    Uninstall {
+        /// Binary to remove, or package whose binaries to remove
+        name: String,
+
+        /// Remove from DIR/bin, as installed with --root
+        #[arg(long, value_name = "DIR")]
+        root: Option<PathBuf>,
+    },
+
+    /// Build the release artifacts into target/dist
+    Dist {
+        targets: Vec<String>,
+
+        /// Write the SLSA provenance of each artifact next to it, as
+        /// <artifact>.intoto.json
+        #[arg(long)]
+        attest: bool,
+
+        /// Sign the provenance with minisign:<secret key file> or
+        /// cosign:<key>
+        #[arg(long, value_name = "KEY", requires = "attest")]
+        sign: Option<String>,
+
+        #[command(flatten)]
+        options: BuildArgs,
+
+        #[command(flatten)]
+        features: FeatureArgs,
+    },
+
+    /// Archive the sources, or the built artifacts, into target/package
+    Package {
+        /// Archive the release artifacts instead of the sources
+        #[arg(long)]
+        binary: bool,
+
+        /// List the files of the archive without writing it
+        #[arg(long)]
+        list: bool,
+
+        #[command(flatten)]
+        options: BuildArgs,
+
+        #[command(flatten)]
+        features: FeatureArgs,
+    },
+
+    /// Check, package and upload the sources to a registry
+    Publish {
+        /// Registry from ~/.buddy/config.toml to publish to
+        #[arg(long, default_value = global::DEFAULT_REGISTRY)]
+        registry: String,
+
+        /// Skip building the package and running its tests first
+        #[arg(long)]
+        no_verify: bool,
+
+        /// Check and package without uploading
+        #[arg(long)]
+        dry_run: bool,
+
+        #[command(flatten)]
+        options: BuildArgs,
+
+        #[command(flatten)]
+        features: FeatureArgs,
+    },
+
+    /// Search the registries for packages `buddy add` can install
+    Search {
+        /// Part of the name, a keyword or words of the description
+        term: String,
+
+        /// Number of packages to show
+        #[arg(long, default_value_t = 10)]
+        limit: usize,
+    },
+
+    /// Report the locked dependencies with known vulnerabilities
+    Audit {
+        /// Advisory to leave out of the report, e.g. a CVE that doesn't
+        /// apply to how the dependency is used
+        #[arg(long, value_name = "ID")]
+        ignore: Vec<String>,
+
+        /// Fail on warnings too, e.g. in CI
+        #[arg(long, value_enum)]
+        deny: Option<commands::audit::Deny>,
+    },
+
+    /// Write the software bill of materials of the locked dependencies
+    Sbom {
+        #[arg(long, value_enum, default_value_t = commands::sbom::Format::Cyclonedx)]
+        format: commands::sbom::Format,
+
+        /// Write to FILE instead of stdout
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+
+    /// Add a dependency to Buddy.toml, the WORKSPACE and a BUILD target
+    Add {
+        /// The dependency, NAME or NAME@VERSION, the latest one by default
+        dependency: String,
+
+        /// Target linking the dependency, defaults to the package's binary
+        #[arg(long, value_name = "LABEL", conflicts_with = "dev")]
+        target: Option<String>,
+
+        /// Link the dependency into the tests instead
+        #[arg(long)]
+        dev: bool,
+
+        /// Add a version withdrawn from its registry
+        #[arg(long)]
+        allow_yanked: bool,
+    },
+
+    /// Remove a dependency from Buddy.toml, the WORKSPACE and Buddy.lock
+    Remove {
+        /// Name of the dependency
+        dependency: String,
+    },
+
+    /// Download the archives of the locked dependencies into vendor/ for
+    /// builds without network
+    Vendor,
+
+    /// Download the dependencies and verify their signatures
+    Fetch {
+        /// Use dependency versions withdrawn from their registry
+        #[arg(long)]
+        allow_yanked: bool,
+
+        #[command(flatten)]
+        features: FeatureArgs,
+    },
+
+    /// Fetch the latest index of the registries served over HTTP
+    UpdateIndex {
+        /// Only update this registry of ~/.buddy/config.toml
+        registry: Option<String>,
+    },
 
     /// Run a binary or example of the local package
-    Run { targets: Vec<String> },
+    Run {
+        targets: Vec<String>,
+
+        /// Member of the workspace to run, by package name
+        #[arg(short, long, value_name = "NAME")]
+        package: Option<String>,
+
+        /// Name of the [[bin]] to run
+        #[arg(long, value_name = "NAME", conflicts_with = "targets")]
+        bin: Option<String>,
+
+        /// Name of the example to run, a program under examples/
+        #[arg(long, value_name = "NAME", conflicts_with_all = ["targets", "bin"])]
+        example: Option<String>,
+
+        /// Pipe the program's output through buddy and save it to FILE,
+        /// rather than giving the program the terminal
+        #[arg(long, value_name = "FILE")]
+        capture: Option<PathBuf>,
+
+        #[command(flatten)]
+        profile: ProfileArgs,
+
+        #[command(flatten)]
+        sanitize: SanitizeArgs,
+
+        #[command(flatten)]
+        features: FeatureArgs,
+    },
 
     /// Run the tests
-    Test { targets: Vec<String> },
-}
+    Test {
+        targets: Vec<String>,
+
+        /// Member of the workspace to test, by package name
+        #[arg(short, long, value_name = "NAME")]
+        package: Option<String>,
+
+        /// Accept the output of failing snapshot assertions as their new
+        /// snapshots
+        #[arg(long)]
+        update_snapshots: bool,
+
+        /// Only run the tests affected by the files changed since REF
+        /// (default: the merge-base with the default branch)
+        #[arg(long, value_name = "REF", num_args = 0..=1, conflicts_with = "targets")]
+        affected: Option<Option<String>>,
+
+        /// Run the tests even when bazel has their result cached
+        #[arg(long, visible_alias = "force-rerun")]
+        no_cache: bool,
+
+        /// Only run the tests with one of these tags, comma separated
+        #[arg(long = "tag", value_name = "TAG", value_delimiter = ',')]
+        tags: Vec<String>,
+
+        /// Skip the tests with any of these tags, comma separated
+        #[arg(long = "exclude-tag", value_name = "TAG", value_delimiter = ',')]
+        exclude_tags: Vec<String>,
+
+        /// Measure the code coverage of the tests, merged into
+        /// target/coverage/lcov.info
+        #[arg(long)]
+        coverage: bool,
+
+        /// Generate the HTML coverage report and open it in a browser
+        #[arg(long, requires = "coverage")]
+        open: bool,
+
+        #[command(flatten)]
+        options: BuildArgs,
+
+        #[command(flatten)]
+        profile: ProfileArgs,
+
+        #[command(flatten)]
+        sanitize: SanitizeArgs,
+
+        #[command(flatten)]
+        features: FeatureArgs,
+    },
+
+    /// Run the benchmarks
+    Bench {
+        targets: Vec<String>,
+
+        /// Save the results as a named baseline under target/benches
+        #[arg(long, value_name = "NAME")]
+        save_baseline: Option<String>,
+
+        /// Compare the results against a previously saved baseline
+        #[arg(long, value_name = "NAME")]
+        baseline: Option<String>,
+
+        /// Fail when a benchmark got slower than the baseline by more than this
+        #[arg(long, value_name = "PERCENT", requires = "baseline")]
+        fail_on_regression: Option<String>,
+
+        #[command(flatten)]
+        profile: ProfileArgs,
+
+        #[command(flatten)]
+        features: FeatureArgs,
+    },
+
+    /// Profile a binary or test and generate a flamegraph
+    Profile {
+        /// Binary to profile, defaults to the package binary
+        target: Option<String>,
+
+        /// Profile a test instead of a binary
+        #[arg(long, conflicts_with = "target")]
+        test: Option<String>,
+
+        /// Profile heap usage instead of CPU time
+        #[arg(long)]
+        memory: bool,
+
+        /// Open the result in a viewer
+        #[arg(long)]
+        open: bool,
+
+        /// Arguments passed to the profiled program
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
+
+    /// Generate the API documentation with Doxygen
+    Doc {
+        /// Open the documentation in a browser
+        #[arg(long)]
+        open: bool,
+
+        /// Publish the documentation to the destination set in [doc]
+        #[arg(long)]
+        publish: bool,
+    },
+
+    /// Remove the build outputs: bazel's and the target/ directory
+    Clean {
+        /// Remove bazel's whole output base, the fetched external
+        /// repositories included
+        #[arg(long)]
+        expunge: bool,
+
+        /// Also remove the archives downloaded by `buddy fetch`
+        #[arg(long)]
+        cache: bool,
+    },
+
+    /// Run any bazel command with buddy's setup: the generated workspace,
+    /// the symlinks under target/ and the user configuration
+    Bazel {
+        /// Startup options, in the --name=value form, the bazel command and
+        /// its arguments
+        #[arg(last = true, required = true)]
+        args: Vec<String>,
+
+        #[command(flatten)]
+        features: FeatureArgs,
+    },
+
+    /// Query the build graph with bazel, where the names of packages and
+    /// dependencies stand for their targets
+    Query {
+        /// The query expression, e.g. `deps(app)`
+        expression: String,
+
+        /// Which bazel query runs
+        #[arg(long, value_enum, default_value_t = commands::query::Kind::Query)]
+        kind: commands::query::Kind,
+
+        /// Print bazel's output in this format, e.g. `graph`, instead of
+        /// listing the targets
+        #[arg(long, value_name = "FORMAT")]
+        output: Option<String>,
+
+        #[command(flatten)]
+        features: FeatureArgs,
+    },
+
+    /// List the binaries, examples, benchmarks, libraries and tests of the
+    /// workspace
+    Targets {
+        #[command(flatten)]
+        features: FeatureArgs,
+    },
+
+    /// Render the resolved dependency graph
+    Graph {
+        #[arg(long, value_enum, default_value_t = commands::graph::Format::Dot)]
+        format: commands::graph::Format,
+
+        /// Include the targets of the workspace and their dependencies,
+        /// queried from bazel
+        #[arg(long)]
+        targets: bool,
+
+        /// Write the graph to FILE instead of stdout
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+
+        #[command(flatten)]
+        features: FeatureArgs,
+    },
+
+    /// Check the shared library for ABI breaking changes since a baseline
+    AbiCheck {
+        /// Git ref or library file to compare against, defaults to the
+        /// latest tag
+        #[arg(long, value_name = "REF|PATH")]
+        baseline: Option<String>,
+
+        #[command(flatten)]
+        features: FeatureArgs,
+    },
+
+    /// Format the sources with clang-format
+    Fmt {
+        /// Files to format, defaults to all the project sources
+        files: Vec<PathBuf>,
+
+        /// Only report unformatted files instead of rewriting them
+        #[arg(long)]
+        check: bool,
+
+        /// Only format the files changed since REF (default: the merge-base
+        /// with the default branch)
+        #[arg(long, value_name = "REF", num_args = 0..=1, conflicts_with = "files")]
+        changed: Option<Option<String>>,
+    },
+
+    /// Generate compile_commands.json and .clangd for the editors, the
+    /// compile commands refreshed by every `buddy build` from then on
+    Ide {
+        /// Also set up this editor's tasks, debugging and extensions
+        #[arg(long)]
+        editor: Option<commands::ide::Editor>,
+
+        /// Overwrite the editor's existing files
+        #[arg(long, requires = "editor")]
+        force: bool,
 
-#[derive(Debug, Deserialize, Default)]
-struct Package {
-    name: String,
-    version: String,
-    edition: String,
+        #[command(flatten)]
+        features: FeatureArgs,
+    },
+
+    /// Check the sources with clang-tidy
+    Lint {
+        /// Files to check, defaults to all the project sources
+        files: Vec<PathBuf>,
+
+        /// Only check the files changed since REF (default: the merge-base
+        /// with the default branch)
+        #[arg(long, value_name = "REF", num_args = 0..=1, conflicts_with = "files")]
+        changed: Option<Option<String>>,
+    },
+
+    /// Show the report of a failed build or test
+    Log {
+        #[command(subcommand)]
+        command: LogCommands,
+    },
+
+    /// Bundle the versions, configuration and last failure into an archive
+    /// to attach to a bug report
+    Report {
+        /// Where to write the archive
+        #[arg(long, short, value_name = "FILE", default_value = commands::report::DEFAULT_ARCHIVE)]
+        output: PathBuf,
+    },
+
+    /// Save the token of a registry or remote cache host
+    Login {
+        /// Registry name from ~/.buddy/config.toml, or a host
+        registry: String,
+
+        /// Token to save, read from stdin when omitted
+        #[arg(long)]
+        token: Option<String>,
+    },
+
+    /// Remove the token of a registry or remote cache host
+    Logout { registry: String },
+
+    /// Migrate a project created by an older buddy
+    Upgrade {
+        /// Show the changes without writing them
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Manage the git hooks running buddy's checks
+    Hooks {
+        #[command(subcommand)]
+        command: HooksCommands,
+    },
+
+    /// Manage continuous integration pipelines
+    Ci {
+        #[command(subcommand)]
+        command: CiCommands,
+    },
+
+    /// Fuzz the package with libFuzzer
+    Fuzz {
+        #[command(subcommand)]
+        command: FuzzCommands,
+    },
 }
 
-#[derive(Debug, Deserialize, Default)]
-struct Config {
-    package: Package,
-    dependencies: HashMap<String, String>,
+#[derive(Subcommand)]
+enum LogCommands {
+    /// Show what failed in the last build or test
+    Last {
+        /// Print everything bazel printed instead of a summary
+        #[arg(long)]
+        full: bool,
+    },
 }
 
-#[derive(Debug)]
-struct Plugin {
-    name: String,
-    versions: HashMap<String, String>,
-    build_rule: String,
+#[derive(Subcommand)]
+enum HooksCommands {
+    /// Install the hooks configured in the [hooks] table
+    Install {
+        /// Replace existing hooks, keeping a backup of them
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Remove buddy's hooks and restore the ones they replaced
+    Uninstall,
 }
 
-fn main() {
-    let cli = Cli::parse();
+#[derive(Subcommand)]
+enum CiCommands {
+    /// Generate a CI pipeline for the project
+    Init {
+        #[arg(required_unless_present = "format")]
+        provider: Option<commands::ci::Provider>,
 
-    let bazel_bin = match which("bazelisk") {
-        Ok(path) => path,
-        Err(_) => panic!("Bazelisk binary not found. See https://docs.bazel.build/versions/5.4.1/install-bazelisk.html"),
-    };
+        /// Print the pipeline in a provider-neutral format instead
+        #[arg(long, conflicts_with = "provider")]
+        format: Option<commands::ci::Format>,
 
-    let file_path = "Buddy.toml";
-    let config: Config = match fs::read_to_string(file_path) {
-        Ok(content) => toml::from_str(&content).unwrap(),
-        Err(_) => Config::default(),
-    };
+        /// Overwrite an existing pipeline
+        #[arg(long)]
+        force: bool,
+    },
+}
 
-    println!("{:#?}", config);
-
-    let plugins = vec![
-        Plugin {
-            name: "google-test".to_string(),
-            versions: [
-                (
-                    "1.13.0".to_string(),
-                    "b796f7d44681514f58a683a3a71ff17c94edb0c1".to_string(),
-                ),
-                (
-                    "1.12.1".to_string(),
-                    "58d77fa8070e8cec2dc1ed015d66b454c8d78850".to_string(),
-                ),
-            ]
-            .iter()
-            .cloned()
-            .collect(),
-            build_rule:  r#"http_archive(
-  name = "com_google_googletest",
-  urls = ["https://github.com/google/googletest/archive/5ab508a01f9eb089207ee87fd547d290da39d015.zip"],
-  strip_prefix = "googletest-5ab508a01f9eb089207ee87fd547d290da39d015",
-)"#.to_string(),
-        },
-        Plugin {
-            name: "bazel-toolchain".to_string(),
-            versions: [
-                (
-                    "0.8.2".to_string(),
-                    "b796f7d44681514f58a683a3a71ff17c94edb0c1".to_string(),
-                ),
-                (
-                    "1.12.1".to_string(),
-                    "58d77fa8070e8cec2dc1ed015d66b454c8d78850".to_string(),
-                ),
-            ]
-            .iter()
-            .cloned()
-            .collect(),
-            build_rule:  r#"BAZEL_TOOLCHAIN_TAG = "0.8.2"
-BAZEL_TOOLCHAIN_SHA = "0fc3a2b0c9c929920f4bed8f2b446a8274cad41f5ee823fd3faa0d7641f20db0"
+#[derive(Subcommand)]
+enum FuzzCommands {
+    /// Create a fuzz target under fuzz/
+    Init {
+        /// Name of the fuzz target, defaults to the package's
+        name: Option<String>,
+    },
+
+    /// Build a fuzz target with libFuzzer and AddressSanitizer and fuzz it,
+    /// growing its corpus under target/fuzz/corpus
+    Run {
+        target: String,
+
+        /// Stop fuzzing after SECS seconds
+        #[arg(long, value_name = "SECS")]
+        max_total_time: Option<u64>,
 
-http_archive(
-    name = "com_grail_bazel_toolchain",
-    sha256 = BAZEL_TOOLCHAIN_SHA,
-    strip_prefix = "bazel-toolchain-{tag}".format(tag = BAZEL_TOOLCHAIN_TAG),
-    canonical_id = BAZEL_TOOLCHAIN_TAG,
-    url = "https://github.com/grailbio/bazel-toolchain/archive/refs/tags/{tag}.tar.gz".format(tag = BAZEL_TOOLCHAIN_TAG),
-)
+        #[command(flatten)]
+        features: FeatureArgs,
 
-load("@com_grail_bazel_toolchain//toolchain:deps.bzl", "bazel_toolchain_dependencies")
+        /// Arguments passed to libFuzzer, or crashing inputs to reproduce
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
 
-bazel_toolchain_dependencies()
+    /// List the fuzz targets
+    List,
+}
 
-load("@com_grail_bazel_toolchain//toolchain:rules.bzl", "llvm_toolchain")
+fn main() {
+    let cli = Cli::parse();
 
-llvm_toolchain(
-    name = "llvm_toolchain",
-    llvm_version = "15.0.6",
-)
+    let file_path = Path::new("Buddy.toml");
+    let mut config = if file_path.is_file() {
+        config::load(file_path).unwrap_or_else(exit_with_error)
+    } else {
+        Config::default()
+    };
 
-load("@llvm_toolchain//:toolchains.bzl", "llvm_register_toolchains")
+    // Members build through their workspace, with its shared lockfile and
+    // WORKSPACE, from its root.
+    let mut selected = None;
+    if config.workspace.is_none()
+        && matches!(
+            cli.command,
+            Commands::Build { .. }
+                | Commands::Check { .. }
+                | Commands::Install { .. }
+                | Commands::Run { .. }
+                | Commands::Test { .. }
+        )
+    {
+        let root = std::env::current_dir()
+            .ok()
+            .and_then(|dir| members::find_root(&dir));
+        if let Some((root, member)) = root {
+            std::env::set_current_dir(&root).unwrap_or_else(|e| exit_with_error(e.to_string()));
+            config = config::load(file_path).unwrap_or_else(exit_with_error);
+            selected = Some(member);
+        }
+    }
+    let members = match &config.workspace {
+        Some(workspace) if cli.command.builds() => {
+            members::load(Path::new("."), workspace).unwrap_or_else(exit_with_error)
+        }
+        _ => Vec::new(),
+    };
+    if let Some(name) = cli.command.package() {
+        match config.workspace {
+            Some(_) => {
+                let member = members::find(&members, name).unwrap_or_else(exit_with_error);
+                selected = Some(member.dir.clone());
+            }
+            None if name != config.package.name => exit_with_error(format!(
+                "package `{}` not found, this is `{}` and not a workspace",
+                name, config.package.name
+            )),
+            None => {}
+        }
+    }
 
-llvm_register_toolchains()"#.to_string(),
+    let bazel_bin = || {
+        let path = match which("bazelisk") {
+            Ok(path) => path,
+            Err(_) => panic!("Bazelisk binary not found. See https://docs.bazel.build/versions/5.4.1/install-bazelisk.html"),
+        };
+        if let Some(version) = bazel::version(&path) {
+            bazel::check_version(&config.package.edition, version).unwrap_or_else(exit_with_error);
         }
-    ];
+        path
+    };
 
-    match &cli.command {
-        Commands::New { path } => new_package(&path, &plugins).unwrap(),
-        Commands::Init { path } => commands::init::run(&path)
-            .unwrap_or_else(|error| println!("{}: {}", "error".red(), error)),
-        Commands::Build { targets } => build(&bazel_bin, &targets).unwrap(),
-        Commands::Run { targets } => run(&bazel_bin, &targets, &config).unwrap(),
-        Commands::Test { targets } => test(&bazel_bin, &targets).unwrap(),
+    let global = GlobalConfig::load().unwrap_or_else(|error| {
+        style::warning(error);
+        GlobalConfig::default()
+    });
+    if let Err(error) = style::init(&global.style) {
+        style::warning(error);
     }
+    bazel::set_offline(cli.is_offline());
+    let plugins = plugins::available(&global);
 
-    println!("{:#?}", plugins);
+    match &cli.command {
+        Commands::New {
+            path,
+            name,
+            lang,
+            std,
+            lib,
+            bench,
+            template,
+            license,
+            interactive,
+            vcs,
+        } => {
+            let kind = if *lib {
+                PackageKind::Lib
+            } else {
+                PackageKind::Bin
+            };
+            config::package_name(Path::new(path), name.as_deref())
+                .and_then(|package_name| {
+                    let mut vars = Vars::new(&package_name, *lang, kind);
+                    vars.author = git::author();
+                    vars.license = *license;
+                    if *bench {
+                        if *lang == Language::C {
+                            return Err(
+                                "--bench needs C++, Google Benchmark is a C++ library".to_string()
+                            );
+                        }
+                        vars.bench = true;
+                    }
+                    if let Some(holder) = git::user_name() {
+                        vars.holder = holder;
+                    }
+                    if let Some(std) = std {
+                        match lang {
+                            Language::Cxx => bazel::cxx_standard_flags(Some(std))
+                                .map(|_| vars.cxx_standard = std.clone()),
+                            Language::C => bazel::c_standard_flags(Some(std))
+                                .map(|_| vars.c_standard = std.clone()),
+                        }?;
+                    }
+                    if *interactive {
+                        commands::wizard::ask(
+                            &mut std::io::stdin().lock(),
+                            &mut std::io::stdout(),
+                            &mut vars,
+                        )?;
+                    }
+                    new_package(path, &vars, template.as_deref(), *vcs, &plugins, &global)
+                })
+                .unwrap_or_else(exit_with_error)
+        }
+        Commands::Init { path, name, force } => {
+            let result = if *force && Path::new(path).join("Buddy.toml").is_file() {
+                commands::init::repair(Path::new(path), &plugins, &global)
+            } else {
+                commands::init::run(path, name.as_deref(), *force)
+            };
+            result.unwrap_or_else(style::error)
+        }
+        Commands::Build {
+            targets,
+            package: _,
+            out_dir,
+            options,
+            profile,
+            sanitize,
+            features,
+        } => {
+            let mut flags = prepare(&config, &members, features, &plugins, &global, &cli)
+                .unwrap_or_else(exit_with_error);
+            flags.extend(profile_flags(&config, profile).unwrap_or_else(exit_with_error));
+            flags.extend(sanitizer::flags(&sanitize.sanitize).unwrap_or_else(exit_with_error));
+            flags.extend(build_flags(&config, options).unwrap_or_else(exit_with_error));
+            let targets = member_targets(targets, &members, selected.as_deref(), "src");
+            let start = Instant::now();
+            let success = build(&bazel_bin(), &targets, &flags, &config, out_dir.as_deref())
+                .map_err(|e| e.to_string())
+                .unwrap_or_else(exit_with_error);
+            // Kept up to date for the editors once `buddy ide` wrote it.
+            if success && Path::new(compdb::COMPDB).is_file() {
+                if let Err(error) = compdb::generate(&bazel_bin(), &flags) {
+                    style::warning(format!("{} not refreshed, {}", compdb::COMPDB, error));
+                }
+            }
+            finished(&global, options, &config, "build", success, start);
+            if !success {
+                std::process::exit(1);
+            }
+        }
+        Commands::Check {
+            targets,
+            package: _,
+            options,
+            profile,
+            features,
+        } => {
+            let mut flags = prepare(&config, &members, features, &plugins, &global, &cli)
+                .unwrap_or_else(exit_with_error);
+            flags.extend(profile_flags(&config, profile).unwrap_or_else(exit_with_error));
+            flags.extend(build_flags(&config, options).unwrap_or_else(exit_with_error));
+            let targets = member_targets(targets, &members, selected.as_deref(), "src");
+            let base = selected.clone().unwrap_or_default();
+            flags.extend(bazel::check_args(&targets, &base).unwrap_or_else(exit_with_error));
+            let start = Instant::now();
+            let mut cmd = bazel::command(&bazel_bin(), "build");
+            cmd.args(&flags);
+            if targets.is_empty() {
+                cmd.arg("//src/...");
+            }
+            let success = bazel::stream(&mut cmd)
+                .map_err(|e| e.to_string())
+                .unwrap_or_else(exit_with_error)
+                .success();
+            finished(&global, options, &config, "check", success, start);
+            if !success {
+                std::process::exit(1);
+            }
+        }
+        Commands::Install {
+            bin,
+            package: _,
+            root,
+            force,
+            features,
+        } => {
+            let member = run_member("install", &config, &members, selected.as_deref())
+                .unwrap_or_else(exit_with_error);
+            let package = member.map_or(&config, |member| &member.config);
+            let dir = member.map_or(Path::new("."), |member| member.dir.as_path());
+            let prefix = member.map(Member::package).unwrap_or_default();
+            let mut binaries = commands::run::binaries(dir, package);
+            if let Some(name) = bin {
+                binaries.retain(|binary| binary.name == *name);
+                if binaries.is_empty() {
+                    exit_with_error::<()>(format!(
+                        "no binary named `{}` in package `{}`",
+                        name, package.package.name
+                    ));
+                }
+            }
+            for binary in &mut binaries {
+                binary.label = targets::relocate_label(&binary.label, &prefix);
+            }
+            let mut flags = prepare(&config, &members, features, &plugins, &global, &cli)
+                .unwrap_or_else(exit_with_error);
+            flags.extend(
+                bazel::profile_flags(&config.profile.release, true).unwrap_or_else(exit_with_error),
+            );
+            let root = root.clone().unwrap_or_else(commands::install::default_root);
+            commands::install::install(
+                &bazel_bin(),
+                &binaries,
+                &flags,
+                &package.package,
+                &root,
+                *force,
+            )
+            .unwrap_or_else(exit_with_error)
+        }
+        Commands::Uninstall { name, root } => {
+            let root = root.clone().unwrap_or_else(commands::install::default_root);
+            commands::install::uninstall(&root, name).unwrap_or_else(exit_with_error)
+        }
+        Commands::Dist {
+            targets,
+            attest,
+            sign,
+            options,
+            features,
+        } => {
+            let mut flags = prepare(&config, &members, features, &plugins, &global, &cli)
+                .unwrap_or_else(exit_with_error);
+            flags.extend(build_flags(&config, options).unwrap_or_else(exit_with_error));
+            flags.extend(
+                bazel::profile_flags(&config.profile.release, true).unwrap_or_else(exit_with_error),
+            );
+            let bazel_bin = bazel_bin();
+            let out_dir = Path::new(commands::dist::DIST_DIR);
+            let targets = &member_targets(targets, &members, None, "src");
+            let started = SystemTime::now();
+            let start = Instant::now();
+            let success = build(&bazel_bin, targets, &flags, &config, Some(out_dir)).unwrap();
+            finished(&global, options, &config, "dist", success, start);
+            if !success {
+                std::process::exit(1);
+            }
+            if *attest {
+                let mut build = commands::dist::Build {
+                    config: &config,
+                    targets,
+                    flags: &flags,
+                    bazel_version: bazel::version(&bazel_bin),
+                    started,
+                    finished: SystemTime::now(),
+                    resolved: Vec::new(),
+                };
+                commands::dist::attest(&mut build, &plugins, out_dir, sign.as_deref())
+                    .unwrap_or_else(exit_with_error);
+            }
+        }
+        Commands::Package {
+            binary,
+            list,
+            options,
+            features,
+        } => {
+            // The generated BUILD files go into the archive up to date.
+            let mut flags = prepare(&config, &members, features, &plugins, &global, &cli)
+                .unwrap_or_else(exit_with_error);
+            if !*binary {
+                commands::package::source(&config.package, *list).unwrap_or_else(exit_with_error);
+                return;
+            }
+            flags.extend(build_flags(&config, options).unwrap_or_else(exit_with_error));
+            flags.extend(
+                bazel::profile_flags(&config.profile.release, true).unwrap_or_else(exit_with_error),
+            );
+            let bazel_bin = bazel_bin();
+            let staging = tempfile::tempdir().unwrap_or_else(|e| exit_with_error(e.to_string()));
+            let targets = &member_targets(&[], &members, None, "src");
+            let start = Instant::now();
+            let success =
+                build(&bazel_bin, targets, &flags, &config, Some(staging.path())).unwrap();
+            finished(&global, options, &config, "package", success, start);
+            if !success {
+                std::process::exit(1);
+            }
+            commands::package::binary(&config.package, staging.path(), *list)
+                .unwrap_or_else(exit_with_error);
+        }
+        Commands::Publish {
+            registry,
+            no_verify,
+            dry_run,
+            options,
+            features,
+        } => {
+            let mut flags = prepare(&config, &members, features, &plugins, &global, &cli)
+                .unwrap_or_else(exit_with_error);
+            commands::publish::validate(&config).unwrap_or_else(exit_with_error);
+            if !*no_verify {
+                flags.extend(
+                    bazel::profile_flags(&config.profile.dev, false)
+                        .unwrap_or_else(exit_with_error),
+                );
+                flags.extend(build_flags(&config, options).unwrap_or_else(exit_with_error));
+                let bazel_bin = bazel_bin();
+                let start = Instant::now();
+                let mut success = build(&bazel_bin, &[], &flags, &config, None).unwrap();
+                if success && Path::new("test").is_dir() {
+                    success =
+                        test(&bazel_bin, &[], &flags, &config, &members, false, false).unwrap();
+                }
+                finished(&global, options, &config, "publish", success, start);
+                if !success {
+                    exit_with_error::<()>(
+                        "the package must build and pass its tests to be published".to_string(),
+                    );
+                }
+            }
+            let archive =
+                commands::package::source(&config.package, false).unwrap_or_else(exit_with_error);
+            commands::publish::run(&global, registry, &config.package, &archive, *dry_run)
+                .unwrap_or_else(exit_with_error);
+        }
+        Commands::Search { term, limit } => {
+            index::warn_if_stale(&global, cli.is_offline());
+            commands::search::run(&plugins, term, *limit).unwrap_or_else(exit_with_error)
+        }
+        Commands::Audit { ignore, deny } => {
+            index::warn_if_stale(&global, cli.is_offline());
+            commands::audit::run(&plugins, ignore, *deny).unwrap_or_else(exit_with_error)
+        }
+        Commands::Sbom { format, output } => {
+            commands::sbom::run(&config.package, &plugins, *format, output.as_deref())
+                .unwrap_or_else(exit_with_error)
+        }
+        Commands::Add {
+            dependency,
+            target,
+            dev,
+            allow_yanked,
+        } => {
+            index::warn_if_stale(&global, cli.is_offline());
+            commands::add::run(
+                Path::new("."),
+                &config,
+                dependency,
+                target.as_deref(),
+                *dev,
+                *allow_yanked,
+                &plugins,
+                &global,
+            )
+            .unwrap_or_else(exit_with_error)
+        }
+        Commands::Remove { dependency } => {
+            commands::remove::run(Path::new("."), &config, dependency, &plugins, &global)
+                .unwrap_or_else(exit_with_error)
+        }
+        Commands::Vendor => {
+            commands::vendor::run(Path::new("."), &config, &plugins, &global, cli.is_offline())
+                .unwrap_or_else(exit_with_error)
+        }
+        Commands::Fetch {
+            allow_yanked,
+            features,
+        } => {
+            index::warn_if_stale(&global, cli.is_offline());
+            features::resolve(
+                &config.features,
+                &features.features,
+                !features.no_default_features,
+            )
+            .and_then(|enabled| features::dependencies(&config, &enabled))
+            .and_then(|dependencies| commands::fetch::run(&dependencies, &plugins, *allow_yanked))
+            .unwrap_or_else(exit_with_error)
+        }
+        Commands::UpdateIndex { registry } => {
+            commands::update_index::run(&global, registry.as_deref(), cli.is_offline())
+                .unwrap_or_else(exit_with_error)
+        }
+        Commands::Run {
+            targets,
+            package: _,
+            bin,
+            example,
+            capture,
+            profile,
+            sanitize,
+            features,
+        } => {
+            let member = run_member("run", &config, &members, selected.as_deref())
+                .unwrap_or_else(exit_with_error);
+            let package = member.map_or(&config, |member| &member.config);
+            let targets = if targets.is_empty() {
+                let root = member.map_or(Path::new("."), |member| member.dir.as_path());
+                let label = match example {
+                    Some(name) => commands::run::example(root, package, name),
+                    None => commands::run::select(
+                        root,
+                        package,
+                        bin.as_deref(),
+                        commands::run::is_interactive(),
+                    ),
+                }
+                .unwrap_or_else(exit_with_error);
+                let prefix = member.map(Member::package).unwrap_or_default();
+                vec![targets::relocate_label(&label, &prefix)]
+            } else {
+                member_targets(targets, &members, selected.as_deref(), "src")
+            };
+            let mut flags = prepare(&config, &members, features, &plugins, &global, &cli)
+                .unwrap_or_else(exit_with_error);
+            flags.extend(profile_flags(&config, profile).unwrap_or_else(exit_with_error));
+            flags.extend(sanitizer::flags(&sanitize.sanitize).unwrap_or_else(exit_with_error));
+            let mut env = config.env_vars();
+            if let Some(member) = member {
+                env.extend(member.config.env_vars());
+            }
+            // The run script changes directory, the reports need an absolute
+            // path.
+            let report_dir = std::env::current_dir()
+                .unwrap_or_default()
+                .join(sanitizer::REPORT_DIR);
+            if !sanitize.sanitize.is_empty() {
+                fs::create_dir_all(&report_dir)
+                    .map_err(|e| e.to_string())
+                    .unwrap_or_else(exit_with_error);
+                env.extend(sanitizer::env(&sanitize.sanitize, &report_dir));
+            }
+            let code = run(&bazel_bin(), &targets, &flags, &env, capture.as_deref()).unwrap();
+            sanitizer::report_logs(&targets.join(" "), &report_dir);
+            if code != 0 {
+                std::process::exit(code);
+            }
+        }
+        Commands::Test {
+            targets,
+            package: _,
+            update_snapshots,
+            affected,
+            no_cache,
+            tags,
+            exclude_tags,
+            coverage,
+            open,
+            options,
+            profile,
+            sanitize,
+            features,
+        } => {
+            let mut flags = prepare(&config, &members, features, &plugins, &global, &cli)
+                .unwrap_or_else(exit_with_error);
+            flags.extend(profile_flags(&config, profile).unwrap_or_else(exit_with_error));
+            flags.extend(sanitizer::flags(&sanitize.sanitize).unwrap_or_else(exit_with_error));
+            flags.extend(build_flags(&config, options).unwrap_or_else(exit_with_error));
+            if *no_cache {
+                flags.push("--cache_test_results=no".to_string());
+            }
+            flags.extend(bazel::test_tag_filters(tags, exclude_tags));
+            let bazel_bin = bazel_bin();
+            let targets = match affected {
+                Some(reference) => {
+                    match affected_tests(&bazel_bin, &config, &members, reference.as_deref())
+                        .unwrap_or_else(exit_with_error)
+                    {
+                        Some(tests) if tests.is_empty() => {
+                            style::status("Skipped", "no test affected by the changes");
+                            return;
+                        }
+                        Some(tests) => tests,
+                        None => member_targets(targets, &members, selected.as_deref(), "test"),
+                    }
+                }
+                None => member_targets(targets, &members, selected.as_deref(), "test"),
+            };
+            let start = Instant::now();
+            let success = test(
+                &bazel_bin,
+                &targets,
+                &flags,
+                &config,
+                &members,
+                *update_snapshots,
+                *coverage,
+            )
+            .map_err(|e| e.to_string())
+            .unwrap_or_else(exit_with_error);
+            if !success && !sanitize.sanitize.is_empty() {
+                sanitizer::report_tests(Path::new(bazel::EVENT_FILE));
+            }
+            if *coverage {
+                coverage::report(Path::new(bazel::EVENT_FILE), *open).unwrap_or_else(style::error);
+            }
+            finished(&global, options, &config, "test", success, start);
+            if !success {
+                std::process::exit(1);
+            }
+        }
+        Commands::Bench {
+            targets,
+            save_baseline,
+            baseline,
+            fail_on_regression,
+            profile,
+            features,
+        } => {
+            let mut flags = prepare(&config, &members, features, &plugins, &global, &cli)
+                .unwrap_or_else(exit_with_error);
+            flags.extend(profile_flags(&config, profile).unwrap_or_else(exit_with_error));
+            commands::bench::run(
+                &bazel_bin(),
+                targets,
+                &flags,
+                save_baseline.as_deref(),
+                baseline.as_deref(),
+                fail_on_regression.as_deref(),
+            )
+            .unwrap_or_else(exit_with_error)
+        }
+        Commands::Profile {
+            target,
+            test,
+            memory,
+            open,
+            args,
+        } => commands::profile::run(
+            &bazel_bin(),
+            &config,
+            target.as_deref(),
+            test.as_deref(),
+            args,
+            *memory,
+            *open,
+        )
+        .unwrap_or_else(exit_with_error),
+        Commands::Doc { open, publish } => {
+            commands::doc::run(&config, *open, *publish).unwrap_or_else(exit_with_error)
+        }
+        Commands::Clean { expunge, cache } => {
+            commands::clean::run(&bazel_bin(), *expunge, *cache).unwrap_or_else(exit_with_error)
+        }
+        Commands::Bazel { args, features } => {
+            let flags = prepare(&config, &members, features, &plugins, &global, &cli)
+                .unwrap_or_else(exit_with_error);
+            let code = passthrough(&bazel_bin(), args, &flags).unwrap_or_else(exit_with_error);
+            std::process::exit(code);
+        }
+        Commands::Query {
+            expression,
+            kind,
+            output,
+            features,
+        } => {
+            let flags = prepare(&config, &members, features, &plugins, &global, &cli)
+                .unwrap_or_else(exit_with_error);
+            commands::query::run(
+                &bazel_bin(),
+                *kind,
+                expression,
+                &query_names(&config, &members, &plugins),
+                &flags,
+                output.as_deref(),
+            )
+            .unwrap_or_else(exit_with_error)
+        }
+        Commands::Targets { features } => {
+            prepare(&config, &members, features, &plugins, &global, &cli)
+                .unwrap_or_else(exit_with_error);
+            commands::query::list(&bazel_bin()).unwrap_or_else(exit_with_error)
+        }
+        Commands::Graph {
+            format,
+            targets,
+            output,
+            features,
+        } => {
+            let dependencies = features::resolve(
+                &config.features,
+                &features.features,
+                !features.no_default_features,
+            )
+            .and_then(|enabled| features::dependencies(&config, &enabled))
+            .and_then(|dependencies| {
+                let lockfile = Lockfile::load(Path::new(LOCKFILE))?;
+                plugins::select_versions(&dependencies, &plugins, &global, &lockfile)
+            })
+            .unwrap_or_else(exit_with_error);
+            let bazel_bin = targets.then(|| {
+                workspace::sync(
+                    Path::new("."),
+                    &dependencies,
+                    &config.sources(),
+                    &plugins,
+                    &global,
+                )
+                .unwrap_or_else(exit_with_error);
+                bazel_bin()
+            });
+            commands::graph::run(
+                &config,
+                &dependencies,
+                bazel_bin.as_deref(),
+                *format,
+                output.as_deref(),
+            )
+            .unwrap_or_else(exit_with_error)
+        }
+        Commands::AbiCheck { baseline, features } => commands::abi_check::run(
+            &bazel_bin(),
+            &config,
+            baseline.as_deref(),
+            &features.features,
+            !features.no_default_features,
+            &plugins,
+            &global,
+        )
+        .unwrap_or_else(exit_with_error),
+        Commands::Fmt {
+            files,
+            check,
+            changed,
+        } => commands::fmt::run(*check, files, changed.as_ref().map(|r| r.as_deref()))
+            .unwrap_or_else(exit_with_error),
+        Commands::Ide {
+            editor,
+            force,
+            features,
+        } => {
+            let mut flags = prepare(&config, &members, features, &plugins, &global, &cli)
+                .unwrap_or_else(exit_with_error);
+            flags.extend(
+                bazel::profile_flags(&config.profile.dev, false).unwrap_or_else(exit_with_error),
+            );
+            commands::ide::run(&bazel_bin(), &flags, &config, *editor, *force)
+                .unwrap_or_else(exit_with_error)
+        }
+        Commands::Lint { files, changed } => {
+            // Without bazel, clang-tidy guesses how the sources compile.
+            if file_path.is_file() && which("bazelisk").is_ok() {
+                let flags = prepare(
+                    &config,
+                    &members,
+                    &FeatureArgs::default(),
+                    &plugins,
+                    &global,
+                    &cli,
+                )
+                .unwrap_or_else(exit_with_error);
+                if let Err(error) = compdb::generate(&bazel_bin(), &flags) {
+                    style::warning(format!("no compile commands for clang-tidy, {}", error));
+                }
+            }
+            commands::lint::run(files, changed.as_ref().map(|r| r.as_deref()), &config.lints)
+                .unwrap_or_else(exit_with_error)
+        }
+        Commands::Log {
+            command: LogCommands::Last { full },
+        } => commands::log::last(*full).unwrap_or_else(exit_with_error),
+        Commands::Report { output } => {
+            commands::report::run(&config, output).unwrap_or_else(exit_with_error)
+        }
+        Commands::Login { registry, token } => {
+            commands::login::login(registry, token.as_deref()).unwrap_or_else(exit_with_error)
+        }
+        Commands::Logout { registry } => {
+            commands::login::logout(registry).unwrap_or_else(exit_with_error)
+        }
+        Commands::Upgrade { dry_run } => {
+            commands::upgrade::upgrade(Path::new("."), &config, *dry_run)
+                .unwrap_or_else(exit_with_error)
+        }
+        Commands::Hooks { command } => match command {
+            HooksCommands::Install { force } => {
+                commands::hooks::install(&config, *force).unwrap_or_else(exit_with_error)
+            }
+            HooksCommands::Uninstall => {
+                commands::hooks::uninstall().unwrap_or_else(exit_with_error)
+            }
+        },
+        Commands::Ci {
+            command:
+                CiCommands::Init {
+                    provider,
+                    format,
+                    force,
+                },
+        } => {
+            commands::ci::init(&config, *provider, *format, *force).unwrap_or_else(exit_with_error)
+        }
+        Commands::Fuzz { command } => match command {
+            FuzzCommands::Init { name } => {
+                commands::fuzz::init(name.as_deref().unwrap_or(&config.package.name))
+                    .unwrap_or_else(exit_with_error)
+            }
+            FuzzCommands::Run {
+                target,
+                max_total_time,
+                features,
+                args,
+            } => {
+                let mut flags = prepare(&config, &members, features, &plugins, &global, &cli)
+                    .unwrap_or_else(exit_with_error);
+                flags.extend(commands::fuzz::flags());
+                commands::fuzz::run(&bazel_bin(), target, &flags, *max_total_time, args)
+                    .unwrap_or_else(exit_with_error)
+            }
+            FuzzCommands::List => {
+                for name in commands::fuzz::list(Path::new(".")).unwrap_or_else(exit_with_error) {
+                    println!("{}", name);
+                }
+            }
+        },
+    }
 }
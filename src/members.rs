@@ -0,0 +1,384 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+use crate::config::{self, Config, Dependency, PathDependency, Source, WorkspaceConfig};
+
+/// A package of a workspace.
+#[derive(Debug)]
+pub struct Member {
+    /// Directory of the package, relative to the root of the workspace.
+    pub dir: PathBuf,
+    /// Its manifest, without the path dependencies on other members.
+    pub config: Config,
+    /// The libraries of the other members it depends on with a path, e.g.
+    /// `//libfoo/src:libfoo`.
+    pub member_deps: Vec<String>,
+}
+
+impl Member {
+    /// The directory of the package relative to the root, as bazel labels
+    /// spell it.
+    pub fn package(&self) -> String {
+        self.dir.to_string_lossy().replace('\\', "/")
+    }
+
+    /// The bazel pattern of every target under `dir` of the package, e.g.
+    /// `//app/src/...`.
+    pub fn pattern(&self, dir: &str) -> String {
+        format!("//{}/{}/...", self.package(), dir)
+    }
+}
+
+/// `path` without its `.` components and with the `..` ones applied, as
+/// far as they don't leave the path.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Reads the manifests of the members of the workspace at `root`, linking
+/// the path dependencies between them as packages of the same bazel
+/// workspace.
+pub fn load(root: &Path, workspace: &WorkspaceConfig) -> Result<Vec<Member>, String> {
+    let mut members: Vec<Member> = Vec::new();
+    for member in &workspace.members {
+        let dir = normalize(Path::new(member));
+        if dir.as_os_str().is_empty()
+            || !dir
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(format!(
+                "workspace member `{}` must be a directory below the root of the workspace",
+                member
+            ));
+        }
+        let manifest = root.join(&dir).join("Buddy.toml");
+        if !manifest.is_file() {
+            return Err(format!("workspace member `{}` has no Buddy.toml", member));
+        }
+        let config = config::load(&manifest)?;
+        if config.workspace.is_some() {
+            return Err(format!(
+                "workspace member `{}` is a workspace itself, they can't be nested",
+                member
+            ));
+        }
+        if !config.patch.is_empty() {
+            return Err(format!(
+                "[patch] of workspace member `{}` belongs in the Buddy.toml of the workspace",
+                member
+            ));
+        }
+        // Bazel stops at nested workspaces, the member would be invisible.
+        if root.join(&dir).join("WORKSPACE").exists() {
+            return Err(format!(
+                "{} makes `{}` a bazel workspace of its own, remove it to build it with the workspace",
+                dir.join("WORKSPACE").display(),
+                member
+            ));
+        }
+        if let Some(other) = members
+            .iter()
+            .find(|other| other.config.package.name == config.package.name)
+        {
+            return Err(format!(
+                "workspace members `{}` and `{}` are both named `{}`",
+                other.package(),
+                member,
+                config.package.name
+            ));
+        }
+        members.push(Member {
+            dir,
+            config,
+            member_deps: Vec::new(),
+        });
+    }
+
+    let libraries: HashMap<PathBuf, String> = members
+        .iter()
+        .map(|member| {
+            let label = format!("//{}/src:{}", member.package(), member.config.package.name);
+            (member.dir.clone(), label)
+        })
+        .collect();
+    for member in &mut members {
+        let mut member_deps = Vec::new();
+        let dir = member.dir.clone();
+        member.config.dependencies.retain(|_, dependency| {
+            let Dependency::Source(Source::Path(path)) = dependency else {
+                return true;
+            };
+            match libraries.get(&normalize(&dir.join(&path.path))) {
+                Some(label) => {
+                    member_deps.push(label.clone());
+                    false
+                }
+                None => true,
+            }
+        });
+        member_deps.sort();
+        member.member_deps = member_deps;
+    }
+    Ok(members)
+}
+
+/// The package names of the members, comma separated.
+pub fn names(members: &[Member]) -> String {
+    members
+        .iter()
+        .map(|member| member.config.package.name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The member named `name`, as `-p` selects it.
+pub fn find<'a>(members: &'a [Member], name: &str) -> Result<&'a Member, String> {
+    members
+        .iter()
+        .find(|member| member.config.package.name == name)
+        .ok_or_else(|| {
+            format!(
+                "no workspace member named `{}`, the members are: {}",
+                name,
+                names(members)
+            )
+        })
+}
+
+/// The workspace the package in `dir`, an absolute path, is a member of:
+/// the closest directory above it whose manifest lists it. Returns the root
+/// of the workspace and the directory of the member relative to it.
+pub fn find_root(dir: &Path) -> Option<(PathBuf, PathBuf)> {
+    for root in dir.ancestors().skip(1) {
+        let manifest = root.join("Buddy.toml");
+        if !manifest.is_file() {
+            continue;
+        }
+        let workspace = config::load(&manifest).ok()?.workspace?;
+        let member = dir.strip_prefix(root).ok()?;
+        return workspace
+            .members
+            .iter()
+            .any(|listed| normalize(Path::new(listed)) == member)
+            .then(|| (root.to_path_buf(), member.to_path_buf()));
+    }
+    None
+}
+
+/// Adds the entries of `from`, declared by `member`, to `into`, failing on
+/// the ones another member declares differently.
+fn merge_table<T: Clone + PartialEq>(
+    into: &mut HashMap<String, (T, String)>,
+    from: impl IntoIterator<Item = (String, T)>,
+    member: &str,
+) -> Result<(), String> {
+    for (name, value) in from {
+        match into.get(&name) {
+            Some((existing, other)) if *existing != value => {
+                return Err(format!(
+                    "workspace members `{}` and `{}` declare `{}` differently, they share one version of each dependency",
+                    other, member, name
+                ))
+            }
+            Some(_) => {}
+            None => {
+                into.insert(name, (value, member.to_string()));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The entries of a table `merge_table` filled, without their member.
+fn unzip<T>(table: HashMap<String, (T, String)>) -> HashMap<String, T> {
+    table
+        .into_iter()
+        .map(|(name, (value, _))| (name, value))
+        .collect()
+}
+
+/// The dependencies of every member, as one manifest locked and fetched at
+/// the root: the paths of their local packages relative to the root, and
+/// the `[patch]` of the root's manifest applied.
+pub fn merge(root: &Config, members: &[Member]) -> Result<Config, String> {
+    let mut dependencies = HashMap::new();
+    let mut optional = HashMap::new();
+    let mut platforms: HashMap<String, HashMap<String, (String, String)>> = HashMap::new();
+    for member in members {
+        let package = member.package();
+        let rerooted = member.config.dependencies.iter().map(|(name, dependency)| {
+            let dependency = match dependency {
+                Dependency::Source(Source::Path(path)) => {
+                    Dependency::Source(Source::Path(PathDependency {
+                        path: normalize(&member.dir.join(&path.path))
+                            .to_string_lossy()
+                            .into_owned(),
+                    }))
+                }
+                Dependency::Source(Source::Git(git)) => {
+                    let mut git = git.clone();
+                    git.build_file = git.build_file.map(|build_file| {
+                        if build_file.starts_with("//") || build_file.starts_with('@') {
+                            build_file
+                        } else {
+                            format!("{}/{}", package, build_file)
+                        }
+                    });
+                    Dependency::Source(Source::Git(git))
+                }
+                dependency => dependency.clone(),
+            };
+            (name.clone(), dependency)
+        });
+        merge_table(&mut dependencies, rerooted, &package)?;
+        merge_table(
+            &mut optional,
+            member.config.optional_dependencies.clone(),
+            &package,
+        )?;
+        for (cfg, platform) in &member.config.target {
+            let merged = platforms.entry(cfg.clone()).or_default();
+            merge_table(merged, platform.dependencies.clone(), &package)?;
+        }
+    }
+
+    let mut merged = Config {
+        dependencies: unzip(dependencies),
+        optional_dependencies: unzip(optional),
+        patch: root.patch.clone(),
+        ..Default::default()
+    };
+    for (cfg, platform) in platforms {
+        merged.target.entry(cfg).or_default().dependencies = unzip(platform);
+    }
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn write_member(root: &Path, dir: &str, manifest: &str) {
+        fs::create_dir_all(root.join(dir)).unwrap();
+        fs::write(root.join(dir).join("Buddy.toml"), manifest).unwrap();
+    }
+
+    #[test]
+    fn test_load() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path();
+        write_member(
+            root,
+            "app",
+            r#"
+[package]
+name = "app"
+version = "0.1.0"
+edition = "2024"
+
+[dependencies]
+fmt = "10.1.1"
+libfoo = { path = "../libs/foo" }
+core = { path = "../../core" }
+"#,
+        );
+        write_member(
+            root,
+            "libs/foo",
+            r#"
+[package]
+name = "foo"
+version = "0.1.0"
+edition = "2024"
+
+[dependencies]
+fmt = "10.1.1"
+google-test = "1.13.0"
+"#,
+        );
+        let workspace = WorkspaceConfig {
+            members: vec!["app".to_string(), "./libs/foo/".to_string()],
+        };
+        let members = load(root, &workspace).unwrap();
+        assert_eq!(members[1].dir, Path::new("libs/foo"));
+        assert_eq!(members[0].member_deps, ["//libs/foo/src:foo"]);
+        assert!(!members[0].config.dependencies.contains_key("libfoo"));
+        assert_eq!(members[1].pattern("test"), "//libs/foo/test/...");
+        assert_eq!(find(&members, "foo").unwrap().dir, Path::new("libs/foo"));
+        assert!(find(&members, "bar")
+            .unwrap_err()
+            .ends_with("the members are: app, foo"));
+
+        let merged = merge(&Config::default(), &members).unwrap();
+        assert_eq!(merged.versions().len(), 2);
+        let Source::Path(core) = &merged.sources()["core"] else {
+            panic!("`core` isn't a path dependency");
+        };
+        assert_eq!(core.path, "../core");
+
+        fs::write(root.join("app").join("WORKSPACE"), "").unwrap();
+        assert!(load(root, &workspace)
+            .unwrap_err()
+            .contains("a bazel workspace of its own"));
+
+        let outside = WorkspaceConfig {
+            members: vec!["../app".to_string()],
+        };
+        assert!(load(root, &outside).is_err());
+    }
+
+    #[test]
+    fn test_merge_conflict() {
+        let member = |dir: &str, manifest: &str| Member {
+            dir: PathBuf::from(dir),
+            config: toml::from_str(manifest).unwrap(),
+            member_deps: Vec::new(),
+        };
+        let members = [
+            member("app", "[dependencies]\nfmt = \"10.1.1\"\n"),
+            member("libfoo", "[dependencies]\nfmt = \"9.1.0\"\n"),
+        ];
+        assert!(merge(&Config::default(), &members)
+            .unwrap_err()
+            .contains("`app` and `libfoo` declare `fmt` differently"));
+    }
+
+    #[test]
+    fn test_find_root() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path();
+        fs::write(
+            root.join("Buddy.toml"),
+            "[workspace]\nmembers = [\"app\"]\n",
+        )
+        .unwrap();
+        write_member(
+            root,
+            "app",
+            "[package]\nname = \"app\"\nversion = \"0.1.0\"\nedition = \"2024\"\n",
+        );
+        fs::create_dir_all(root.join("other")).unwrap();
+        assert_eq!(
+            find_root(&root.join("app")),
+            Some((root.to_path_buf(), PathBuf::from("app")))
+        );
+        assert_eq!(find_root(&root.join("other")), None);
+    }
+}
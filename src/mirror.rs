@@ -0,0 +1,126 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+/// Where Bazel releases are downloaded from by bazelisk, in the format of
+/// `BAZELISK_BASE_URL`.
+const BAZEL_RELEASES: &str = "https://github.com/bazelbuild/bazel/releases/download";
+
+/// Rewrites `url` with the `[source.replace]` rule of the longest matching
+/// prefix, `None` when no rule applies.
+pub fn rewrite_url(rules: &BTreeMap<String, String>, url: &str) -> Option<String> {
+    rules
+        .iter()
+        .filter(|(from, _)| url.starts_with(from.as_str()))
+        .max_by_key(|(from, _)| from.len())
+        .map(|(from, to)| format!("{}{}", to, &url[from.len()..]))
+}
+
+/// The `BAZELISK_BASE_URL` making bazelisk download Bazel from the mirror.
+pub fn bazelisk_base_url(rules: &BTreeMap<String, String>) -> Option<String> {
+    rewrite_url(rules, BAZEL_RELEASES)
+}
+
+fn strip_scheme(url: &str) -> &str {
+    url.split_once("://").map_or(url, |(_, rest)| rest)
+}
+
+fn escape_regex(text: &str) -> String {
+    let mut out = String::new();
+    for c in text.chars() {
+        if "\\.^$|?*+()[]{}".contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Renders the rules as a Bazel downloader config, which applies them to
+/// every archive Bazel fetches, including the ones of transitive
+/// dependencies. Checksums are left alone, so mirrored archives are still
+/// verified. Bazel matches URLs without their scheme and keeps the original
+/// one, so mirrors are expected to use the same scheme as what they replace.
+pub fn downloader_config(rules: &BTreeMap<String, String>) -> String {
+    let mut rules: Vec<_> = rules.iter().collect();
+    // Bazel tries every matching rule in order, the most specific first.
+    rules.sort_by_key(|(from, _)| std::cmp::Reverse(from.len()));
+
+    let mut out = String::from(
+        "# This file is automatically @generated by Buddy.\n# It is not intended for manual editing.\n",
+    );
+    for (from, to) in rules {
+        out.push_str(&format!(
+            "rewrite {}(.*) {}$1\n",
+            escape_regex(strip_scheme(from)),
+            strip_scheme(to)
+        ));
+    }
+    out
+}
+
+/// Writes the downloader config under `target/` and returns its absolute
+/// path, as Bazel resolves relative ones against its own directories.
+pub fn write_downloader_config(rules: &BTreeMap<String, String>) -> Result<PathBuf, String> {
+    let dir = std::env::current_dir()
+        .map_err(|e| e.to_string())?
+        .join("target");
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+
+    let path = dir.join("downloader.cfg");
+    let contents = downloader_config(rules);
+    if fs::read_to_string(&path).ok().as_deref() != Some(contents.as_str()) {
+        fs::write(&path, contents).map_err(|e| e.to_string())?;
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules() -> BTreeMap<String, String> {
+        [
+            ("https://github.com/", "https://artifactory.corp/github/"),
+            (
+                "https://github.com/bazelbuild/",
+                "https://artifactory.corp/bazel/",
+            ),
+        ]
+        .iter()
+        .map(|(from, to)| (from.to_string(), to.to_string()))
+        .collect()
+    }
+
+    #[test]
+    fn test_rewrite_url_longest_prefix() {
+        let rules = rules();
+        assert_eq!(
+            rewrite_url(
+                &rules,
+                "https://github.com/google/googletest/archive/v1.zip"
+            )
+            .unwrap(),
+            "https://artifactory.corp/github/google/googletest/archive/v1.zip"
+        );
+        assert_eq!(
+            bazelisk_base_url(&rules).unwrap(),
+            "https://artifactory.corp/bazel/bazel/releases/download"
+        );
+        assert!(rewrite_url(&rules, "https://example.com/a.zip").is_none());
+    }
+
+    #[test]
+    fn test_downloader_config() {
+        assert_eq!(
+            downloader_config(&rules())
+                .lines()
+                .skip(2)
+                .collect::<Vec<_>>(),
+            vec![
+                "rewrite github\\.com/bazelbuild/(.*) artifactory.corp/bazel/$1",
+                "rewrite github\\.com/(.*) artifactory.corp/github/$1",
+            ]
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+use which::which;
+
+use crate::global::{GlobalConfig, NotifyConfig};
+use crate::style;
+
+/// How a build or test ended.
+pub struct Outcome<'a> {
+    /// The buddy command, e.g. `build`.
+    pub command: &'a str,
+    pub package: &'a str,
+    pub success: bool,
+    pub duration: Duration,
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m {}s", seconds / 60, seconds % 60),
+        _ => format!("{}h {}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
+
+fn message(outcome: &Outcome) -> String {
+    format!(
+        "buddy {} of {} {} in {}",
+        outcome.command,
+        outcome.package,
+        if outcome.success {
+            "succeeded"
+        } else {
+            "failed"
+        },
+        format_duration(outcome.duration)
+    )
+}
+
+/// The webhook payload: Slack shows `text`, other receivers can use the
+/// structured fields.
+fn payload(outcome: &Outcome) -> serde_json::Value {
+    let icon = if outcome.success { "✅" } else { "❌" };
+    serde_json::json!({
+        "text": format!("{} {}", icon, message(outcome)),
+        "command": outcome.command,
+        "package": outcome.package,
+        "status": if outcome.success { "success" } else { "failure" },
+        "duration_seconds": outcome.duration.as_secs(),
+    })
+}
+
+/// Which notifications to send: `--notify` always shows one on the
+/// desktop, the configured ones are sent once the command ran long enough.
+fn channels(config: &NotifyConfig, forced: bool, duration: Duration) -> (bool, bool) {
+    let long = duration.as_secs() >= config.after;
+    let desktop = forced || (config.desktop && long);
+    let webhook = config.webhook.is_some() && (forced || long);
+    (desktop, webhook)
+}
+
+fn desktop(title: &str, body: &str) -> Result<(), String> {
+    let status = if cfg!(target_os = "macos") {
+        let script = format!(
+            "display notification {:?} with title {:?}",
+            body.replace('"', "'"),
+            title
+        );
+        Command::new("osascript").args(["-e", &script]).status()
+    } else {
+        let notify_send = which("notify-send").map_err(|_| {
+            "`notify-send` not found, it is needed for desktop notifications (e.g. `apt install libnotify-bin`)"
+        })?;
+        Command::new(notify_send).args([title, body]).status()
+    }
+    .map_err(|e| format!("failed to show the notification: {}", e))?;
+    if !status.success() {
+        return Err("failed to show the notification".to_string());
+    }
+    Ok(())
+}
+
+fn webhook(url: &str, payload: &serde_json::Value, global: &GlobalConfig) -> Result<(), String> {
+    let curl = which("curl").map_err(|_| "`curl` not found, it is needed to call the webhook")?;
+    let mut child = Command::new(curl)
+        .args([
+            "-fsS",
+            "-o",
+            "/dev/null",
+            "-X",
+            "POST",
+            "-H",
+            "Content-Type: application/json",
+            "--data-binary",
+            "@-",
+        ])
+        .arg(url)
+        .envs(global.http.env())
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to run curl: {}", e))?;
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(payload.to_string().as_bytes())
+        .map_err(|e| e.to_string())?;
+    let status = child.wait().map_err(|e| e.to_string())?;
+    if !status.success() {
+        return Err("the notification webhook failed".to_string());
+    }
+    Ok(())
+}
+
+/// Reports the end of a build or test as configured in `[notify]`, or on
+/// the desktop when `forced` by `--notify`. Failing to notify only warns.
+pub fn send(global: &GlobalConfig, forced: bool, outcome: &Outcome) {
+    let (show, call) = channels(&global.notify, forced, outcome.duration);
+    if show {
+        let title = format!("buddy {}", outcome.command);
+        if let Err(error) = desktop(&title, &message(outcome)) {
+            style::warning(error);
+        }
+    }
+    if let (true, Some(url)) = (call, &global.notify.webhook) {
+        if let Err(error) = webhook(url, &payload(outcome), global) {
+            style::warning(error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notifications() {
+        let outcome = Outcome {
+            command: "build",
+            package: "demo",
+            success: false,
+            duration: Duration::from_secs(20 * 60 + 3),
+        };
+        assert_eq!(message(&outcome), "buddy build of demo failed in 20m 3s");
+        let payload = payload(&outcome);
+        assert_eq!(payload["status"], "failure");
+        assert_eq!(payload["duration_seconds"], 1203);
+        assert_eq!(format_duration(Duration::from_secs(3725)), "1h 2m");
+
+        let mut config = NotifyConfig::default();
+        let short = Duration::from_secs(10);
+        let long = Duration::from_secs(600);
+        assert_eq!(channels(&config, false, long), (false, false));
+        assert_eq!(channels(&config, true, short), (true, false));
+        config.desktop = true;
+        config.webhook = Some("https://hooks.slack.com/services/T/B/X".to_string());
+        assert_eq!(channels(&config, false, short), (false, false));
+        assert_eq!(channels(&config, false, long), (true, true));
+    }
+}
@@ -0,0 +1,716 @@
+use serde::Deserialize;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config;
+use crate::global::{self, GlobalConfig};
+use crate::index;
+use crate::lockfile::Lockfile;
+use crate::requirement::VersionReq;
+use crate::signature::SigningKey;
+use crate::style;
+
+/// Versions of the builtin recipes which are no longer pinned, by package,
+/// and the known version standing in for each: the manifests of the first
+/// `buddy new` asked for `bazel-toolchain` 0.8.0, their WORKSPACE was
+/// pinned to 0.8.2.
+const VERSION_ALIASES: [(&str, &str, &str); 1] = [("bazel-toolchain", "0.8.0", "0.8.2")];
+
+/// A recipe describing how to bring a dependency into the WORKSPACE.
+#[derive(Debug)]
+pub struct Plugin {
+    pub name: String,
+    /// Known versions and the sha each one is pinned to, a commit or an
+    /// archive checksum depending on the recipe.
+    pub versions: HashMap<String, String>,
+    /// WORKSPACE rules, where `{version}`, `{sha}` and `{repository}` are
+    /// replaced by the requested version, its sha and the Bazel repository
+    /// name of the dependency.
+    pub build_rule: String,
+    /// The archive the build rule downloads, prefetched by `buddy fetch`.
+    /// Takes the same placeholders as the build rule.
+    pub archive: Option<String>,
+    /// The target packages depending on it link against, e.g.
+    /// `@{repository}//:fmt`, `None` for toolchains and rules. Takes the
+    /// same placeholders as the build rule.
+    pub target: Option<String>,
+    /// Key the archive's detached signature is checked against, when the
+    /// upstream project signs its releases.
+    pub signing_key: Option<SigningKey>,
+    /// Registry of `~/.buddy/config.toml` the recipe comes from, `None` for
+    /// the builtin ones and the ones of the plugin directories.
+    pub registry: Option<String>,
+    /// Versions withdrawn by the registry, with the reason it gave.
+    pub yanked: HashMap<String, String>,
+    /// Set when the whole package is deprecated, says what to use instead.
+    pub deprecated: Option<String>,
+    /// What the package is, shown by `buddy search`.
+    pub description: Option<String>,
+    /// Words `buddy search` finds the package by besides its name.
+    pub keywords: Vec<String>,
+    /// SPDX expression of the package's license, reported in the SBOM.
+    pub license: Option<String>,
+    /// Known vulnerabilities and problems of versions, for `buddy audit`.
+    pub advisories: Vec<Advisory>,
+}
+
+/// An advisory of a recipe, an `[[advisory]]` of its TOML.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Advisory {
+    /// The CVE, GHSA or registry identifier.
+    pub id: String,
+    pub summary: String,
+    /// Requirements matching the affected versions, e.g. `<1.2.13` or
+    /// `>=1.3, <1.3.1`.
+    pub affected: Vec<String>,
+    /// `low`, `medium`, `high` or `critical`.
+    pub severity: Option<String>,
+    pub url: Option<String>,
+    /// Set for advisories which aren't vulnerabilities, e.g. a package no
+    /// longer maintained.
+    #[serde(default)]
+    pub informational: bool,
+}
+
+impl Advisory {
+    /// Whether `version` is affected. Invalid requirements match nothing.
+    pub fn affects(&self, version: &str) -> bool {
+        self.affected.iter().any(|requirement| {
+            VersionReq::parse(requirement).is_ok_and(|requirement| requirement.matches(version))
+        })
+    }
+}
+
+impl Plugin {
+    /// The known versions, newest first.
+    fn available(&self) -> String {
+        let mut known: Vec<_> = self.versions.keys().map(String::as_str).collect();
+        known.sort_by(|a, b| compare_versions(b, a));
+        known.join(", ")
+    }
+
+    fn fill(&self, template: &str, version: &str) -> Result<String, String> {
+        let sha = self.versions.get(version).ok_or_else(|| {
+            format!(
+                "unknown version `{}` of `{}`, available versions: {}",
+                version,
+                self.name,
+                self.available()
+            )
+        })?;
+        Ok(template
+            .replace("{version}", version)
+            .replace("{sha}", sha)
+            .replace("{repository}", &config::repository_name(&self.name)))
+    }
+
+    /// The WORKSPACE rules of `version`, which must be a known one.
+    pub fn render(&self, version: &str) -> Result<String, String> {
+        self.fill(&self.build_rule, version)
+    }
+
+    /// The URL of the archive of `version`, if the recipe declares one.
+    pub fn archive_url(&self, version: &str) -> Result<Option<String>, String> {
+        self.archive
+            .as_ref()
+            .map(|archive| self.fill(archive, version))
+            .transpose()
+    }
+
+    /// Whether the build rule pins the sha256 of the archive: Bazel only
+    /// takes archives from the distdir, and only checks the ones it
+    /// downloads, when it does.
+    pub fn pins_checksum(&self) -> bool {
+        self.build_rule.contains("sha256")
+    }
+
+    /// The label of the target to link against, if the recipe declares one.
+    pub fn target_label(&self, version: &str) -> Result<Option<String>, String> {
+        self.target
+            .as_ref()
+            .map(|target| self.fill(target, version))
+            .transpose()
+    }
+
+    /// The version `requirement` picks: `locked` while it still satisfies
+    /// it, the newest matching version which isn't yanked otherwise. A bare
+    /// version is taken as it is, checked when the recipe is rendered.
+    pub fn select(&self, requirement: &str, locked: Option<&str>) -> Result<String, String> {
+        let requirement = VersionReq::parse(requirement)
+            .map_err(|error| format!("cannot resolve dependency `{}`: {}", self.name, error))?;
+        if requirement.is_exact() {
+            let version = requirement.to_string();
+            return Ok(match self.alias(&version) {
+                Some(known) => known.to_string(),
+                None => version,
+            });
+        }
+        if let Some(locked) = locked
+            .filter(|version| self.versions.contains_key(*version) && requirement.matches(version))
+        {
+            return Ok(locked.to_string());
+        }
+
+        let matching: Vec<&str> = self
+            .versions
+            .keys()
+            .map(String::as_str)
+            .filter(|version| requirement.matches(version))
+            .collect();
+        if let Some(newest) = matching
+            .iter()
+            .filter(|version| !self.yanked.contains_key(**version))
+            .max_by(|a, b| compare_versions(a, b))
+        {
+            return Ok(newest.to_string());
+        }
+        if matching.is_empty() {
+            Err(format!(
+                "no version of `{}` matches `{}`, available versions: {}",
+                self.name,
+                requirement,
+                self.available()
+            ))
+        } else {
+            Err(format!(
+                "every version of `{}` matching `{}` was yanked, available versions: {}",
+                self.name,
+                requirement,
+                self.available()
+            ))
+        }
+    }
+
+    /// The known version standing in for `version`, when it is an alias.
+    pub fn alias(&self, version: &str) -> Option<&'static str> {
+        if self.versions.contains_key(version) {
+            return None;
+        }
+        VERSION_ALIASES
+            .iter()
+            .find(|(name, alias, _)| *name == self.name && *alias == version)
+            .map(|(_, _, known)| *known)
+    }
+
+    /// The newest version which isn't yanked, suggested in place of yanked
+    /// ones.
+    pub fn latest_version(&self) -> Option<&str> {
+        self.versions
+            .keys()
+            .filter(|version| !self.yanked.contains_key(*version))
+            .max_by(|a, b| compare_versions(a, b))
+            .map(String::as_str)
+    }
+}
+
+/// Orders dotted versions component by component, numerically when both
+/// components are numbers.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let mut a = a.split('.');
+    let mut b = b.split('.');
+    loop {
+        match (a.next(), b.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) => {
+                let ordering = match (x.parse::<u64>(), y.parse::<u64>()) {
+                    (Ok(x), Ok(y)) => x.cmp(&y),
+                    _ => x.cmp(y),
+                };
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+        }
+    }
+}
+
+/// The recipes shipped with buddy.
+pub fn builtin() -> Vec<Plugin> {
+    vec![
+        Plugin {
+            name: "google-test".to_string(),
+            versions: [
+                (
+                    "1.13.0".to_string(),
+                    "b796f7d44681514f58a683a3a71ff17c94edb0c1".to_string(),
+                ),
+                (
+                    "1.12.1".to_string(),
+                    "58d77fa8070e8cec2dc1ed015d66b454c8d78850".to_string(),
+                ),
+            ]
+            .iter()
+            .cloned()
+            .collect(),
+            build_rule: r#"http_archive(
+  name = "com_google_googletest",
+  urls = ["https://github.com/google/googletest/archive/{sha}.zip"],
+  strip_prefix = "googletest-{sha}",
+)"#
+            .to_string(),
+            archive: Some("https://github.com/google/googletest/archive/{sha}.zip".to_string()),
+            target: Some("@com_google_googletest//:gtest".to_string()),
+            signing_key: None,
+            registry: None,
+            yanked: HashMap::new(),
+            deprecated: None,
+            description: Some("Google's C++ test and mocking framework".to_string()),
+            keywords: ["testing", "mocking", "gtest"].map(String::from).to_vec(),
+            license: Some("BSD-3-Clause".to_string()),
+            advisories: Vec::new(),
+        },
+        Plugin {
+            name: "google-benchmark".to_string(),
+            versions: [(
+                "1.8.3".to_string(),
+                "344117638c8ff7e239044fd0fa7085839fc03021".to_string(),
+            )]
+            .iter()
+            .cloned()
+            .collect(),
+            build_rule: r#"http_archive(
+  name = "com_github_google_benchmark",
+  urls = ["https://github.com/google/benchmark/archive/{sha}.zip"],
+  strip_prefix = "benchmark-{sha}",
+)"#
+            .to_string(),
+            archive: Some("https://github.com/google/benchmark/archive/{sha}.zip".to_string()),
+            target: Some("@com_github_google_benchmark//:benchmark".to_string()),
+            signing_key: None,
+            registry: None,
+            yanked: HashMap::new(),
+            deprecated: None,
+            description: Some(
+                "A library to benchmark code snippets, similar to unit tests".to_string(),
+            ),
+            keywords: ["benchmark", "performance"].map(String::from).to_vec(),
+            license: Some("Apache-2.0".to_string()),
+            advisories: Vec::new(),
+        },
+        Plugin {
+            name: "bazel-toolchain".to_string(),
+            versions: [(
+                "0.8.2".to_string(),
+                "0fc3a2b0c9c929920f4bed8f2b446a8274cad41f5ee823fd3faa0d7641f20db0".to_string(),
+            )]
+            .iter()
+            .cloned()
+            .collect(),
+            build_rule: r#"BAZEL_TOOLCHAIN_TAG = "{version}"
+BAZEL_TOOLCHAIN_SHA = "{sha}"
+
+http_archive(
+    name = "com_grail_bazel_toolchain",
+    sha256 = BAZEL_TOOLCHAIN_SHA,
+    strip_prefix = "bazel-toolchain-{tag}".format(tag = BAZEL_TOOLCHAIN_TAG),
+    canonical_id = BAZEL_TOOLCHAIN_TAG,
+    url = "https://github.com/grailbio/bazel-toolchain/archive/refs/tags/{version}.tar.gz",
+)
+
+load("@com_grail_bazel_toolchain//toolchain:deps.bzl", "bazel_toolchain_dependencies")
+
+bazel_toolchain_dependencies()
+
+load("@com_grail_bazel_toolchain//toolchain:rules.bzl", "llvm_toolchain")
+
+llvm_toolchain(
+    name = "llvm_toolchain",
+    llvm_version = "15.0.6",
+)
+
+load("@llvm_toolchain//:toolchains.bzl", "llvm_register_toolchains")
+
+llvm_register_toolchains()"#
+                .to_string(),
+            archive: Some(
+                "https://github.com/grailbio/bazel-toolchain/archive/refs/tags/{version}.tar.gz"
+                    .to_string(),
+            ),
+            target: None,
+            signing_key: None,
+            registry: None,
+            yanked: HashMap::new(),
+            deprecated: None,
+            description: Some("LLVM toolchain for hermetic C/C++ builds with Bazel".to_string()),
+            keywords: ["toolchain", "llvm", "clang"].map(String::from).to_vec(),
+            license: Some("Apache-2.0".to_string()),
+            advisories: Vec::new(),
+        },
+    ]
+}
+
+/// A recipe of a directory registry, stored as `[<scope>/]<name>.toml`.
+/// `{registry}` in the build rule and archive is replaced by the `file://`
+/// URL of the registry, so that archives kept next to the recipes are used
+/// without any network access.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct RecipeFile {
+    build_rule: String,
+    #[serde(default)]
+    versions: HashMap<String, String>,
+    archive: Option<String>,
+    target: Option<String>,
+    signing_key: Option<SigningKey>,
+    #[serde(default)]
+    yanked: HashMap<String, String>,
+    deprecated: Option<String>,
+    description: Option<String>,
+    #[serde(default)]
+    keywords: Vec<String>,
+    license: Option<String>,
+    #[serde(default, rename = "advisory")]
+    advisories: Vec<Advisory>,
+}
+
+fn recipe_files(dir: &Path, scope: Option<&str>) -> Result<Vec<(String, PathBuf)>, String> {
+    let mut entries = fs::read_dir(dir)
+        .map_err(|e| format!("cannot read registry `{}`: {}", dir.display(), e))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .collect::<Vec<_>>();
+    entries.sort();
+
+    let mut files = Vec::new();
+    for path in entries {
+        let Some(stem) = path.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        if path.is_dir() && scope.is_none() && !stem.starts_with('.') {
+            files.extend(recipe_files(&path, Some(stem))?);
+        } else if path.extension().is_some_and(|e| e == "toml") {
+            let name = match scope {
+                Some(scope) => format!("{}/{}", scope, stem),
+                None => stem.to_string(),
+            };
+            files.push((name, path));
+        }
+    }
+    Ok(files)
+}
+
+/// Loads the recipes of the directory registry `registry` stored in `dir`.
+pub fn load_directory(registry: &str, dir: &Path) -> Result<Vec<Plugin>, String> {
+    let dir = dir
+        .canonicalize()
+        .map_err(|e| format!("cannot read registry `{}`: {}", dir.display(), e))?;
+    let url = format!("file://{}", dir.display());
+    load_recipes(Some(registry), &dir, &url)
+}
+
+/// Where recipes are dropped to use them without a registry, in the
+/// project's `.buddy` directory or in `~/.buddy`.
+pub const PLUGINS_DIR: &str = "plugins";
+
+/// Loads the recipes of a plugin directory, laid out like a directory
+/// registry.
+pub fn load_plugins(dir: &Path) -> Result<Vec<Plugin>, String> {
+    let dir = dir
+        .canonicalize()
+        .map_err(|e| format!("cannot read plugins `{}`: {}", dir.display(), e))?;
+    let url = format!("file://{}", dir.display());
+    load_recipes(None, &dir, &url)
+}
+
+/// Loads the recipes stored in `dir`, with `{registry}` standing for `url`.
+fn load_recipes(registry: Option<&str>, dir: &Path, url: &str) -> Result<Vec<Plugin>, String> {
+    recipe_files(dir, None)?
+        .into_iter()
+        .map(|(name, path)| {
+            let contents = fs::read_to_string(&path).map_err(|e| e.to_string())?;
+            let recipe: RecipeFile = toml::from_str(&contents)
+                .map_err(|e| format!("failed to parse `{}`: {}", path.display(), e))?;
+            Ok(Plugin {
+                name,
+                versions: recipe.versions,
+                build_rule: recipe.build_rule.replace("{registry}", url),
+                archive: recipe
+                    .archive
+                    .map(|archive| archive.replace("{registry}", url)),
+                target: recipe.target,
+                signing_key: recipe.signing_key,
+                registry: registry.map(str::to_string),
+                yanked: recipe.yanked,
+                deprecated: recipe.deprecated,
+                description: recipe.description,
+                keywords: recipe.keywords,
+                license: recipe.license,
+                advisories: recipe.advisories,
+            })
+        })
+        .collect()
+}
+
+/// The recipes of the plugin directories, the project's `.buddy/plugins`
+/// then `~/.buddy/plugins`, which take precedence over the builtin ones,
+/// followed by the ones of the registries of the user configuration:
+/// directory registries, and the indexes of the others as last fetched by
+/// `buddy update-index`, where `{registry}` stands for the index URL.
+/// Broken directories and registries are skipped with a warning.
+pub fn available(global: &GlobalConfig) -> Vec<Plugin> {
+    let mut plugins = Vec::new();
+    for dir in [
+        Path::new(".buddy").join(PLUGINS_DIR),
+        global::buddy_home().join(PLUGINS_DIR),
+    ] {
+        if !dir.is_dir() {
+            continue;
+        }
+        match load_plugins(&dir) {
+            Ok(recipes) => plugins.extend(recipes),
+            Err(error) => style::warning(error),
+        }
+    }
+    plugins.extend(builtin());
+    for (name, registry) in &global.registries {
+        let loaded = match &registry.path {
+            Some(path) => load_directory(name, path),
+            None => {
+                let dir = index::root().join(name);
+                if !dir.is_dir() {
+                    continue;
+                }
+                load_recipes(Some(name), &dir, registry.index.trim_end_matches('/'))
+            }
+        };
+        match loaded {
+            Ok(recipes) => plugins.extend(recipes),
+            Err(error) => style::warning(error),
+        }
+    }
+    plugins
+}
+
+/// Resolves the version requirements of `dependencies` to the versions
+/// their recipes pick, keeping the ones of `lockfile` while they still
+/// satisfy them.
+pub fn select_versions(
+    dependencies: &HashMap<String, String>,
+    plugins: &[Plugin],
+    global: &GlobalConfig,
+    lockfile: &Lockfile,
+) -> Result<HashMap<String, String>, String> {
+    dependencies
+        .iter()
+        .map(|(name, requirement)| {
+            let plugin = resolve(plugins, name, global)?;
+            let version = plugin.select(requirement, lockfile.locked_version(name))?;
+            Ok((name.clone(), version))
+        })
+        .collect()
+}
+
+pub fn find<'a>(plugins: &'a [Plugin], name: &str) -> Option<&'a Plugin> {
+    plugins.iter().find(|plugin| plugin.name == name)
+}
+
+/// Finds the recipe of the dependency `name`. Scoped names only resolve to
+/// recipes of the plugin directories or of the registry serving their
+/// scope, so that an internal package can't be shadowed by a public one.
+pub fn resolve<'a>(
+    plugins: &'a [Plugin],
+    name: &str,
+    global: &GlobalConfig,
+) -> Result<&'a Plugin, String> {
+    config::validate_dependency_name(name)?;
+
+    match config::split_scope(name) {
+        (Some(scope), _) => {
+            // Recipes of the plugin directories are the user's own.
+            let local = plugins
+                .iter()
+                .find(|plugin| plugin.name == name && plugin.registry.is_none());
+            if let Some(plugin) = local {
+                return Ok(plugin);
+            }
+            let registry = global.registry_for_scope(scope).ok_or_else(|| {
+                format!(
+                    "no registry serves the `{}` scope of `{}`, set `scope = \"{}\"` on one in ~/.buddy/config.toml",
+                    scope, name, scope
+                )
+            })?;
+            plugins
+                .iter()
+                .find(|plugin| plugin.name == name && plugin.registry.as_deref() == Some(registry))
+                .ok_or_else(|| format!("no recipe found for `{}` in registry `{}`", name, registry))
+        }
+        (None, _) => {
+            find(plugins, name).ok_or_else(|| {
+                format!(
+                    "no recipe found for dependency `{}`, `buddy update-index` fetches the latest ones of the registries",
+                    name
+                )
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latest_version_skips_yanked() {
+        assert_eq!(compare_versions("1.10.0", "1.9.2"), Ordering::Greater);
+        assert_eq!(compare_versions("1.2", "1.2.0"), Ordering::Less);
+
+        let mut plugin = builtin().remove(0);
+        assert_eq!(plugin.latest_version(), Some("1.13.0"));
+
+        plugin
+            .yanked
+            .insert("1.13.0".to_string(), "broken release".to_string());
+        assert_eq!(plugin.latest_version(), Some("1.12.1"));
+    }
+
+    #[test]
+    fn test_render_requested_version() {
+        let plugin = builtin().remove(0);
+        let rule = plugin.render("1.12.1").unwrap();
+        assert!(rule.contains("archive/58d77fa8070e8cec2dc1ed015d66b454c8d78850.zip"));
+        assert!(
+            rule.contains("strip_prefix = \"googletest-58d77fa8070e8cec2dc1ed015d66b454c8d78850\"")
+        );
+
+        let error = plugin.render("1.11.0").unwrap_err();
+        assert_eq!(
+            error,
+            "unknown version `1.11.0` of `google-test`, available versions: 1.13.0, 1.12.1"
+        );
+    }
+
+    #[test]
+    fn test_select() {
+        let mut plugin = builtin().remove(0);
+        assert_eq!(plugin.select("^1.12", None).unwrap(), "1.13.0");
+        assert_eq!(plugin.select("~1.12.0", None).unwrap(), "1.12.1");
+        assert_eq!(
+            plugin.select(">=1.12, <2", Some("1.12.1")).unwrap(),
+            "1.12.1"
+        );
+        assert_eq!(plugin.select("^1.13", Some("1.12.1")).unwrap(), "1.13.0");
+        assert_eq!(plugin.select("1.11.0", None).unwrap(), "1.11.0");
+        assert_eq!(
+            plugin.select("^2", None).unwrap_err(),
+            "no version of `google-test` matches `^2`, available versions: 1.13.0, 1.12.1"
+        );
+
+        plugin
+            .yanked
+            .insert("1.13.0".to_string(), "broken release".to_string());
+        assert_eq!(plugin.select("^1.12", None).unwrap(), "1.12.1");
+        assert!(plugin
+            .select("=1.13", None)
+            .unwrap_err()
+            .starts_with("every version of `google-test` matching `=1.13` was yanked"));
+    }
+
+    #[test]
+    fn test_load_directory() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let dir = tmp_dir.path();
+        fs::create_dir(dir.join("corp")).unwrap();
+        fs::write(
+            dir.join("corp").join("logging.toml"),
+            r#"
+build-rule = """http_archive(
+    name = "{repository}",
+    urls = ["{registry}/archives/logging-{version}.tar.gz"],
+)"""
+archive = "{registry}/archives/logging-{version}.tar.gz"
+
+[versions]
+"2.1.0" = "2.1.0"
+"2.0.0" = "2.0.0"
+
+[yanked]
+"2.0.0" = "leaks file descriptors"
+"#,
+        )
+        .unwrap();
+        fs::write(dir.join("README.md"), "").unwrap();
+
+        let plugins = load_directory("local", dir).unwrap();
+        assert_eq!(plugins.len(), 1);
+        let plugin = &plugins[0];
+        let url = format!("file://{}", dir.canonicalize().unwrap().display());
+        assert_eq!(plugin.name, "corp/logging");
+        assert_eq!(plugin.registry.as_deref(), Some("local"));
+        assert_eq!(
+            plugin.archive_url("2.1.0").unwrap().unwrap(),
+            format!("{}/archives/logging-2.1.0.tar.gz", url)
+        );
+        assert!(plugin
+            .render("2.1.0")
+            .unwrap()
+            .contains(&format!("{}/archives/logging-2.1.0.tar.gz", url)));
+        assert_eq!(plugin.latest_version(), Some("2.1.0"));
+    }
+
+    #[test]
+    fn test_load_plugins() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let dir = tmp_dir.path();
+        fs::create_dir(dir.join("corp")).unwrap();
+        fs::write(
+            dir.join("corp").join("tracing.toml"),
+            r#"
+build-rule = "local_repository(name = \"{repository}\", path = \"/src/tracing\")"
+target = "@{repository}//:tracing"
+
+[versions]
+"0.3.0" = ""
+"#,
+        )
+        .unwrap();
+
+        let mut plugins = load_plugins(dir).unwrap();
+        assert_eq!(plugins[0].registry, None);
+        plugins.extend(builtin());
+        let plugin = resolve(&plugins, "corp/tracing", &GlobalConfig::default()).unwrap();
+        assert_eq!(
+            plugin.target_label("0.3.0").unwrap().as_deref(),
+            Some("@corp__tracing//:tracing")
+        );
+    }
+
+    #[test]
+    fn test_resolve_scoped_names() {
+        let mut plugins = builtin();
+        plugins.push(Plugin {
+            name: "corp/logging".to_string(),
+            versions: [("2.1".to_string(), "abc".to_string())].into(),
+            build_rule: "http_archive(name = \"{repository}\")".to_string(),
+            archive: None,
+            target: None,
+            signing_key: None,
+            registry: Some("internal".to_string()),
+            yanked: HashMap::new(),
+            deprecated: None,
+            description: None,
+            keywords: Vec::new(),
+            license: None,
+            advisories: Vec::new(),
+        });
+
+        let mut global = GlobalConfig::default();
+        assert!(resolve(&plugins, "corp/logging", &global).is_err());
+
+        global.registries.insert(
+            "internal".to_string(),
+            toml::from_str("index = \"https://buddy.corp\"\nscope = \"corp\"").unwrap(),
+        );
+        let plugin = resolve(&plugins, "corp/logging", &global).unwrap();
+        assert_eq!(
+            plugin.render("2.1").unwrap(),
+            "http_archive(name = \"corp__logging\")"
+        );
+        assert!(resolve(&plugins, "other/logging", &global).is_err());
+        assert!(resolve(&plugins, "google-test", &global).is_ok());
+    }
+}
@@ -0,0 +1,222 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, IsTerminal, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::heap::format_bytes;
+use crate::style::{self, Level};
+
+/// A download in progress, as reported by bazel's `Fetching` lines, e.g.
+/// `Fetching https://.../llvm.tar.xz; 96.3 MiB (100,958,208B) 8s`.
+#[derive(Debug, PartialEq)]
+pub struct Download {
+    pub name: String,
+    pub bytes: u64,
+    pub seconds: u64,
+}
+
+pub fn parse_download(line: &str) -> Option<Download> {
+    let (_, fetching) = line.split_once("Fetching ")?;
+    let (what, status) = fetching.split_once("; ")?;
+
+    let (_, size) = status.split_once('(')?;
+    let (size, elapsed) = size.split_once("B)")?;
+    let bytes = size.replace(',', "").parse().ok()?;
+    let seconds = elapsed
+        .trim()
+        .strip_suffix('s')
+        .and_then(|seconds| seconds.parse().ok())
+        .unwrap_or(0);
+
+    // URLs are long, their file name is what tells the downloads apart.
+    let name = what.trim().trim_start_matches("repository ");
+    let name = name.rsplit('/').next().unwrap_or(name);
+    Some(Download {
+        name: name.to_string(),
+        bytes,
+        seconds,
+    })
+}
+
+/// A completed fetch, from the Build Event Protocol.
+#[derive(Debug, PartialEq)]
+pub struct Fetched {
+    pub url: String,
+    pub success: bool,
+}
+
+pub fn parse_event(line: &str) -> Option<Fetched> {
+    let event: serde_json::Value = serde_json::from_str(line).ok()?;
+    let url = event["id"]["fetch"]["url"].as_str()?;
+    Some(Fetched {
+        url: url.to_string(),
+        success: event["fetch"]["success"].as_bool().unwrap_or(false),
+    })
+}
+
+/// Follows the JSON build event file bazel writes as it goes, forwarding
+/// the fetch events.
+pub struct Events {
+    receiver: Receiver<Fetched>,
+    done: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl Events {
+    pub fn watch(path: PathBuf) -> Events {
+        let (sender, receiver) = mpsc::channel();
+        let done = Arc::new(AtomicBool::new(false));
+
+        let finished = done.clone();
+        let thread = thread::spawn(move || {
+            let file = loop {
+                if let Ok(file) = File::open(&path) {
+                    break file;
+                }
+                if finished.load(Ordering::Relaxed) {
+                    return;
+                }
+                thread::sleep(Duration::from_millis(100));
+            };
+
+            let mut reader = BufReader::new(file);
+            let mut line = String::new();
+            loop {
+                // Read `done` first: once set, bazel has exited and whatever
+                // the file holds is complete.
+                let exited = finished.load(Ordering::Relaxed);
+                match reader.read_line(&mut line) {
+                    Ok(0) | Err(_) if exited => return,
+                    Ok(_) if line.ends_with('\n') => {
+                        if let Some(event) = parse_event(&line) {
+                            let _ = sender.send(event);
+                        }
+                        line.clear();
+                    }
+                    _ => thread::sleep(Duration::from_millis(100)),
+                }
+            }
+        });
+
+        Events {
+            receiver,
+            done,
+            thread,
+        }
+    }
+
+    /// The fetches completed since the last call.
+    pub fn completed(&self) -> Vec<Fetched> {
+        self.receiver.try_iter().collect()
+    }
+
+    /// Stops following the file once bazel exited, returning the remaining
+    /// events.
+    pub fn finish(self) -> Vec<Fetched> {
+        self.done.store(true, Ordering::Relaxed);
+        let _ = self.thread.join();
+        self.receiver.try_iter().collect()
+    }
+}
+
+/// Buddy's view of the downloads: a status line rewritten in place on a
+/// terminal, and a line per completed fetch.
+pub struct Progress {
+    tty: bool,
+    status_shown: bool,
+}
+
+impl Default for Progress {
+    fn default() -> Progress {
+        Progress {
+            tty: io::stdout().is_terminal(),
+            status_shown: false,
+        }
+    }
+}
+
+impl Progress {
+    pub fn show(&mut self, download: &Download) {
+        if !self.tty {
+            return;
+        }
+        let rate = match download.seconds {
+            0 => String::new(),
+            seconds => format!(", {}/s", format_bytes(download.bytes / seconds)),
+        };
+        print!(
+            "\r\x1b[2K    {} {} {}{}",
+            style::paint("Fetching", Level::Info),
+            download.name,
+            format_bytes(download.bytes),
+            rate
+        );
+        let _ = io::stdout().flush();
+        self.status_shown = true;
+    }
+
+    /// Removes the status line before something else gets printed.
+    pub fn clear(&mut self) {
+        if self.status_shown {
+            print!("\r\x1b[2K");
+            self.status_shown = false;
+        }
+    }
+
+    pub fn fetched(&mut self, events: Vec<Fetched>) {
+        for event in events {
+            self.clear();
+            if event.success {
+                style::status("Downloaded", event.url);
+            } else {
+                style::warning(format!("failed to download {}", event.url));
+            }
+        }
+    }
+}
+
+/// The build event file of a bazel invocation, if it writes one.
+pub fn event_file(args: &[&std::ffi::OsStr]) -> Option<PathBuf> {
+    args.iter().find_map(|arg| {
+        arg.to_str()?
+            .strip_prefix("--build_event_json_file=")
+            .map(PathBuf::from)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_download() {
+        let line = "    Fetching https://github.com/llvm/llvm-project/releases/download/llvmorg-15.0.6/clang+llvm-15.0.6-x86_64-linux-gnu-ubuntu-18.04.tar.xz; 96.3 MiB (100,958,208B) 8s";
+        assert_eq!(
+            parse_download(line),
+            Some(Download {
+                name: "clang+llvm-15.0.6-x86_64-linux-gnu-ubuntu-18.04.tar.xz".to_string(),
+                bytes: 100_958_208,
+                seconds: 8,
+            })
+        );
+        assert_eq!(
+            parse_download("Fetching repository @llvm_toolchain; starting 3s"),
+            None
+        );
+
+        let event =
+            r#"{"id":{"fetch":{"url":"https://example.com/a.zip"}},"fetch":{"success":true}}"#;
+        assert_eq!(
+            parse_event(event),
+            Some(Fetched {
+                url: "https://example.com/a.zip".to_string(),
+                success: true,
+            })
+        );
+        assert_eq!(parse_event(r#"{"id":{"started":{}}}"#), None);
+    }
+}
@@ -0,0 +1,220 @@
+use std::fmt;
+
+/// How a comparator of a requirement constrains the version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    /// A bare version, which must be requested literally.
+    Exact,
+    /// `=1.2`: any version starting with the given components.
+    Equal,
+    /// `^1.2.3`: the versions compatible with it, up to the next change of
+    /// the leftmost non-zero component.
+    Caret,
+    /// `~1.2.3`: the patch releases of the given minor version.
+    Tilde,
+    Greater,
+    GreaterEq,
+    Less,
+    LessEq,
+    /// `*`: any version.
+    Any,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Comparator {
+    op: Op,
+    version: String,
+    /// The numeric components given, one to three.
+    parts: Vec<u64>,
+}
+
+/// The version requirement of a dependency of `Buddy.toml`, comparators
+/// which must all hold, e.g. `>=1.12, <2`. A bare version (`1.13.0`) only
+/// matches itself, the operators match ranges: `^1.12`, `~1.13.0`, `=1.13`,
+/// `>`, `>=`, `<`, `<=` and `*`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionReq {
+    source: String,
+    comparators: Vec<Comparator>,
+}
+
+/// The numeric components of `version`, `None` when one isn't a number.
+fn numeric_parts(version: &str) -> Option<Vec<u64>> {
+    version.split('.').map(|part| part.parse().ok()).collect()
+}
+
+/// `parts` padded with zeros to a full `major.minor.patch`, comparable with
+/// the other padded versions.
+fn padded(parts: &[u64]) -> Vec<u64> {
+    let mut padded = parts.to_vec();
+    padded.resize(padded.len().max(3), 0);
+    padded
+}
+
+/// The smallest version above the ones sharing the first `len` components
+/// of `parts`, e.g. `1.3.0` for the first two of `1.2.5`.
+fn bump(parts: &[u64], len: usize) -> Vec<u64> {
+    let mut bumped = padded(&parts[..len]);
+    bumped[len - 1] += 1;
+    bumped.truncate(len);
+    padded(&bumped)
+}
+
+impl Comparator {
+    fn parse(comparator: &str) -> Result<Comparator, String> {
+        let comparator = comparator.trim();
+        if comparator == "*" {
+            return Ok(Comparator {
+                op: Op::Any,
+                version: String::new(),
+                parts: Vec::new(),
+            });
+        }
+        let (op, version) = [
+            (">=", Op::GreaterEq),
+            ("<=", Op::LessEq),
+            (">", Op::Greater),
+            ("<", Op::Less),
+            ("=", Op::Equal),
+            ("^", Op::Caret),
+            ("~", Op::Tilde),
+        ]
+        .iter()
+        .find_map(|(prefix, op)| Some((*op, comparator.strip_prefix(prefix)?.trim())))
+        .unwrap_or((Op::Exact, comparator));
+        if version.is_empty() {
+            return Err(format!("`{}` is missing a version", comparator));
+        }
+        let parts = match numeric_parts(version) {
+            Some(parts) if parts.len() <= 3 => parts,
+            // Recipes may name versions freely, requested as they are.
+            _ if op == Op::Exact => Vec::new(),
+            _ => {
+                return Err(format!(
+                    "`{}` is not a version of up to three numbers, e.g. `1.12` or `1.13.0`",
+                    version
+                ))
+            }
+        };
+        Ok(Comparator {
+            op,
+            version: version.to_string(),
+            parts,
+        })
+    }
+
+    /// The bounds of the range of `^` and `~`, the lower one included.
+    fn range(&self) -> (Vec<u64>, Vec<u64>) {
+        let parts = &self.parts;
+        let len = match self.op {
+            Op::Caret => parts
+                .iter()
+                .position(|part| *part != 0)
+                .map_or(parts.len(), |i| i + 1),
+            Op::Tilde => parts.len().min(2),
+            _ => parts.len(),
+        };
+        (padded(parts), bump(parts, len))
+    }
+
+    fn matches(&self, version: &str) -> bool {
+        if self.op == Op::Exact {
+            return version == self.version;
+        }
+        if self.op == Op::Any {
+            return true;
+        }
+        let Some(candidate) = numeric_parts(version).map(|parts| padded(&parts)) else {
+            return false;
+        };
+        let bound = padded(&self.parts);
+        match self.op {
+            Op::Greater => candidate > bound,
+            Op::GreaterEq => candidate >= bound,
+            Op::Less => candidate < bound,
+            Op::LessEq => candidate <= bound,
+            _ => {
+                let (lower, upper) = self.range();
+                lower <= candidate && candidate < upper
+            }
+        }
+    }
+}
+
+impl VersionReq {
+    pub fn parse(requirement: &str) -> Result<VersionReq, String> {
+        let comparators = requirement
+            .split(',')
+            .map(Comparator::parse)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|error| format!("invalid version requirement `{}`: {}", requirement, error))?;
+        Ok(VersionReq {
+            source: requirement.to_string(),
+            comparators,
+        })
+    }
+
+    /// Whether the requirement is a bare version, which only matches
+    /// itself.
+    pub fn is_exact(&self) -> bool {
+        matches!(self.comparators.as_slice(), [comparator] if comparator.op == Op::Exact)
+    }
+
+    pub fn matches(&self, version: &str) -> bool {
+        self.comparators
+            .iter()
+            .all(|comparator| comparator.matches(version))
+    }
+}
+
+impl fmt::Display for VersionReq {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(requirement: &str, version: &str) -> bool {
+        VersionReq::parse(requirement).unwrap().matches(version)
+    }
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("1.13.0", "1.13.0"));
+        assert!(!matches("1.13", "1.13.0"));
+        assert!(matches("=1.13", "1.13.2"));
+
+        assert!(matches("^1.12", "1.13.0"));
+        assert!(!matches("^1.12", "1.11.9"));
+        assert!(!matches("^1.12", "2.0.0"));
+        assert!(matches("^0.3.1", "0.3.9"));
+        assert!(!matches("^0.3.1", "0.4.0"));
+        assert!(!matches("^0.0.3", "0.0.4"));
+
+        assert!(matches("~1.13.0", "1.13.5"));
+        assert!(!matches("~1.13.0", "1.14.0"));
+        assert!(matches("~1", "1.9.0"));
+
+        assert!(matches(">=1.12, <2", "1.13.0"));
+        assert!(!matches(">=1.12, <2", "2.0.0"));
+        assert!(matches("<=1.12", "1.12.0"));
+        assert!(!matches(">1.12", "1.12.0"));
+        assert!(matches("*", "3.2.1"));
+        assert!(!matches("^1", "release-1"));
+        assert!(matches("release-1", "release-1"));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!(VersionReq::parse("1.13.0").unwrap().is_exact());
+        assert!(!VersionReq::parse("^1.13.0").unwrap().is_exact());
+        assert!(VersionReq::parse(">=1.12,").is_err());
+        assert!(VersionReq::parse("^1.x")
+            .unwrap_err()
+            .contains("invalid version requirement `^1.x`"));
+        assert!(VersionReq::parse("~1.2.3.4").is_err());
+    }
+}
@@ -0,0 +1,116 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::style::{self, Level};
+
+/// The outcome of a test target, from the Build Event Protocol.
+#[derive(Debug, PartialEq)]
+pub struct TestResult {
+    pub label: String,
+    /// Bazel's overall status, e.g. `PASSED`, `FAILED` or `FLAKY`.
+    pub status: String,
+    /// Whether every run came from the local or remote cache.
+    pub cached: bool,
+}
+
+/// The test results reported by the JSON build events, by label.
+pub fn parse(events: &str) -> Vec<TestResult> {
+    let mut statuses = BTreeMap::new();
+    let mut cached: BTreeMap<String, bool> = BTreeMap::new();
+
+    for line in events.lines() {
+        let Ok(event) = serde_json::from_str::<serde_json::Value>(line) else {
+            continue;
+        };
+        if let Some(label) = event["id"]["testResult"]["label"].as_str() {
+            let result = &event["testResult"];
+            let from_cache = result["cachedLocally"] == true
+                || result["executionInfo"]["cachedRemotely"] == true;
+            let all = cached.entry(label.to_string()).or_insert(true);
+            *all = *all && from_cache;
+        }
+        if let Some(label) = event["id"]["testSummary"]["label"].as_str() {
+            let status = event["testSummary"]["overallStatus"]
+                .as_str()
+                .unwrap_or("NO_STATUS");
+            statuses.insert(label.to_string(), status.to_string());
+        }
+    }
+
+    statuses
+        .into_iter()
+        .map(|(label, status)| TestResult {
+            cached: cached.get(&label).copied().unwrap_or(false),
+            label,
+            status,
+        })
+        .collect()
+}
+
+/// Prints the results of the tests bazel ran, marking the ones it took
+/// from the cache.
+pub fn print_summary(results: &[TestResult]) {
+    if results.is_empty() {
+        return;
+    }
+    let passed = results.iter().filter(|r| r.status == "PASSED").count();
+    let cached = results.iter().filter(|r| r.cached).count();
+    style::status(
+        "Summary",
+        format!(
+            "{} tests: {} passed, {} failed, {} cached",
+            results.len(),
+            passed,
+            results.len() - passed,
+            cached
+        ),
+    );
+    for result in results {
+        let status = format!("{:>12}", result.status);
+        let status = match result.status.as_str() {
+            "PASSED" => style::paint(&status, Level::Info),
+            "FAILED" => style::paint(&status, Level::Error),
+            _ => style::paint(&status, Level::Warning),
+        };
+        let cached = if result.cached { " (cached)" } else { "" };
+        println!("{} {}{}", status, result.label, cached);
+    }
+}
+
+/// Prints the summary of the tests recorded in the build event file.
+pub fn report(event_file: &Path) {
+    if let Ok(events) = fs::read_to_string(event_file) {
+        print_summary(&parse(&events));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let events = r#"{"id":{"testResult":{"label":"//test:a_test","run":1}},"testResult":{"status":"PASSED","cachedLocally":true}}
+{"id":{"testResult":{"label":"//test:b_test","run":1}},"testResult":{"status":"PASSED","executionInfo":{"cachedRemotely":true}}}
+{"id":{"testResult":{"label":"//test:b_test","run":2}},"testResult":{"status":"PASSED"}}
+{"id":{"testResult":{"label":"//test:c_test","run":1}},"testResult":{"status":"FAILED"}}
+{"id":{"testSummary":{"label":"//test:c_test"}},"testSummary":{"overallStatus":"FAILED"}}
+{"id":{"testSummary":{"label":"//test:a_test"}},"testSummary":{"overallStatus":"PASSED"}}
+{"id":{"testSummary":{"label":"//test:b_test"}},"testSummary":{"overallStatus":"PASSED"}}
+"#;
+        let results = parse(events);
+        let summary: Vec<_> = results
+            .iter()
+            .map(|r| (r.label.as_str(), r.status.as_str(), r.cached))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("//test:a_test", "PASSED", true),
+                ("//test:b_test", "PASSED", false),
+                ("//test:c_test", "FAILED", false),
+            ]
+        );
+    }
+}
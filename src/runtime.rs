@@ -0,0 +1,306 @@
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+use which::which;
+
+use crate::style;
+
+/// A shared library of the build, or of a toolchain, a binary loads.
+#[derive(Debug, PartialEq)]
+pub struct Library {
+    pub path: PathBuf,
+    /// Whether the dynamic loader finds it on its own, through the rpath
+    /// bazel linked the binary with.
+    pub resolved: bool,
+}
+
+/// Reads `ldd`: the libraries it resolved, with their path, and the ones
+/// it could not find.
+fn parse_ldd(output: &str) -> Vec<(String, Option<PathBuf>)> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (name, location) = line.trim().split_once(" => ")?;
+            let path = match location.trim() {
+                "not found" => None,
+                location => {
+                    let path = location.split(" (").next().unwrap_or(location);
+                    Some(PathBuf::from(path.trim()))
+                }
+            };
+            Some((name.trim().to_string(), path))
+        })
+        .collect()
+}
+
+/// Reads `otool -L`: the names of the libraries loaded through the rpath,
+/// the ones bazel builds. System libraries have absolute install names.
+fn parse_otool(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let name = line.trim().split(" (").next()?;
+            Some(name.strip_prefix("@rpath/")?.to_string())
+        })
+        .collect()
+}
+
+/// Whether `path` lies in bazel's output tree: the `_solib_*` directories
+/// shared libraries are linked into, or an external repository.
+fn from_build(path: &Path) -> bool {
+    path.components().any(|component| match component {
+        Component::Normal(name) => {
+            let name = name.to_string_lossy();
+            name.starts_with("_solib_") || name == "execroot" || name == "external"
+        }
+        _ => false,
+    })
+}
+
+/// Where to look for the libraries the loader can't find: the `_solib_*`
+/// directories of `target/bin`, and the `lib` directories of the external
+/// repositories, e.g. the one of the LLVM toolchain.
+fn search_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    let bin = Path::new("target/bin");
+    for entry in fs::read_dir(bin).into_iter().flatten().flatten() {
+        if entry.file_name().to_string_lossy().starts_with("_solib_") {
+            dirs.push(entry.path());
+        }
+    }
+    // target/out is <output_base>/execroot/<workspace>/bazel-out.
+    let output_base = fs::canonicalize("target/out")
+        .ok()
+        .and_then(|out| Some(out.parent()?.parent()?.parent()?.to_path_buf()));
+    if let Some(output_base) = output_base {
+        let external = output_base.join("external");
+        for entry in fs::read_dir(external).into_iter().flatten().flatten() {
+            let lib = entry.path().join("lib");
+            if lib.is_dir() {
+                dirs.push(lib);
+            }
+        }
+    }
+    dirs
+}
+
+fn run_tool(tool: &str, args: &[&str], binary: &Path) -> Option<String> {
+    let output = Command::new(which(tool).ok()?)
+        .args(args)
+        .arg(binary)
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    // Static executables and other files aren't an error.
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The libraries of the build or of the toolchains `binary` loads. Only
+/// Linux and macOS binaries are inspected.
+pub fn libraries(binary: &Path) -> Vec<Library> {
+    let mut search = None;
+    let mut find = |name: &str| {
+        search
+            .get_or_insert_with(search_dirs)
+            .iter()
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file())
+    };
+
+    let mut libraries = Vec::new();
+    if cfg!(target_os = "macos") {
+        let Some(output) = run_tool("otool", &["-L"], binary) else {
+            return libraries;
+        };
+        for name in parse_otool(&output) {
+            if let Some(path) = find(&name) {
+                libraries.push(Library {
+                    path,
+                    resolved: false,
+                });
+            }
+        }
+    } else if cfg!(target_os = "linux") {
+        let Some(output) = run_tool("ldd", &[], binary) else {
+            return libraries;
+        };
+        for (name, path) in parse_ldd(&output) {
+            match path {
+                Some(path) if from_build(&path) => libraries.push(Library {
+                    path,
+                    resolved: true,
+                }),
+                Some(_) => {}
+                None => match find(&name) {
+                    Some(path) => libraries.push(Library {
+                        path,
+                        resolved: false,
+                    }),
+                    None => style::warning(format!(
+                        "{} needs `{}`, which was not found",
+                        binary.display(),
+                        name
+                    )),
+                },
+            }
+        }
+    }
+    libraries
+}
+
+/// The variable the loader searches libraries in, and its value with
+/// `dirs` in front.
+pub fn library_path(dirs: &[PathBuf]) -> (&'static str, OsString) {
+    let variable = if cfg!(target_os = "macos") {
+        "DYLD_LIBRARY_PATH"
+    } else if cfg!(windows) {
+        "PATH"
+    } else {
+        "LD_LIBRARY_PATH"
+    };
+    let mut paths = dirs.to_vec();
+    if let Some(current) = env::var_os(variable) {
+        paths.extend(env::split_paths(&current));
+    }
+    (variable, env::join_paths(paths).unwrap_or_default())
+}
+
+/// The tool setting the rpath of a binary, its arguments making it
+/// `$ORIGIN`, and how to install it.
+fn rpath_tool() -> (&'static str, [&'static str; 2], &'static str) {
+    if cfg!(target_os = "macos") {
+        (
+            "install_name_tool",
+            ["-add_rpath", "@loader_path"],
+            "Install the Xcode command line tools with `xcode-select --install`",
+        )
+    } else {
+        (
+            "patchelf",
+            ["--set-rpath", "$ORIGIN"],
+            "Install it with your package manager (e.g. `apt install patchelf`)",
+        )
+    }
+}
+
+/// Makes a copied binary or library find the libraries next to it.
+fn set_rpath(tool: &Path, args: &[&str], path: &Path) -> Result<(), String> {
+    // Bazel's outputs are read-only, and so are their copies.
+    let mut permissions = fs::metadata(path).map_err(|e| e.to_string())?.permissions();
+    #[allow(clippy::permissions_set_readonly_false)]
+    permissions.set_readonly(false);
+    fs::set_permissions(path, permissions).map_err(|e| e.to_string())?;
+    let output = Command::new(tool)
+        .args(args)
+        .arg(path)
+        .output()
+        .map_err(|e| format!("failed to run {}: {}", tool.display(), e))?;
+    // install_name_tool refuses to add the rpath twice.
+    if !output.status.success() && !String::from_utf8_lossy(&output.stderr).contains("duplicate") {
+        return Err(format!(
+            "failed to set the rpath of {}: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+fn is_loadable(path: &Path) -> bool {
+    let extension = path.extension().and_then(|e| e.to_str());
+    if matches!(extension, Some("so" | "dylib")) {
+        return true;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+    }
+    #[cfg(not(unix))]
+    false
+}
+
+/// Copies the shared libraries `binary`, built by bazel, loads next to
+/// `copy`, and points the rpath of both at their directory, so that the
+/// copy runs outside of bazel.
+pub fn bundle(binary: &Path, copy: &Path) -> Result<(), String> {
+    if !is_loadable(binary) {
+        return Ok(());
+    }
+    let libraries = libraries(binary);
+    if libraries.is_empty() {
+        return Ok(());
+    }
+    let dir = copy.parent().unwrap_or(Path::new("."));
+    let mut bundled = vec![copy.to_path_buf()];
+    for library in &libraries {
+        let Some(name) = library.path.file_name() else {
+            continue;
+        };
+        let dest = dir.join(name);
+        if bundled.contains(&dest) {
+            continue;
+        }
+        let _ = fs::remove_file(&dest);
+        fs::copy(&library.path, &dest).map_err(|e| format!("{}: {}", library.path.display(), e))?;
+        style::status("Bundled", dest.display());
+        bundled.push(dest);
+    }
+
+    let (tool, args, hint) = rpath_tool();
+    let Ok(tool) = which(tool) else {
+        return Err(format!(
+            "`{}` not found, run {} with {}={}. {}",
+            tool,
+            copy.display(),
+            library_path(&[]).0,
+            dir.display(),
+            hint
+        ));
+    };
+    for path in &bundled {
+        set_rpath(&tool, &args, path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_libraries() {
+        let ldd = "\tlinux-vdso.so.1 (0x00007ffd5a1e5000)
+\tlibdemo.so => /home/me/.cache/bazel/_bazel_me/1234/execroot/__main__/bazel-out/k8-fastbuild/bin/src/../_solib_k8/libdemo.so (0x00007f0c1c000000)
+\tlibc++.so.1 => not found
+\tlibc.so.6 => /lib/x86_64-linux-gnu/libc.so.6 (0x00007f0c1bc00000)
+\t/lib64/ld-linux-x86-64.so.2 (0x00007f0c1c2f0000)
+";
+        let libraries = parse_ldd(ldd);
+        assert_eq!(
+            libraries
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>(),
+            ["libdemo.so", "libc++.so.1", "libc.so.6"]
+        );
+        assert_eq!(libraries[1].1, None);
+        assert!(from_build(libraries[0].1.as_ref().unwrap()));
+        assert!(!from_build(libraries[2].1.as_ref().unwrap()));
+
+        let otool = "target/bin/src/demo:
+\t@rpath/libdemo.dylib (compatibility version 0.0.0, current version 0.0.0)
+\t/usr/lib/libc++.1.dylib (compatibility version 1.0.0, current version 1500.65.0)
+";
+        assert_eq!(parse_otool(otool), ["libdemo.dylib"]);
+
+        let (_, value) = library_path(&[PathBuf::from("/a"), PathBuf::from("/b")]);
+        assert!(value.to_string_lossy().starts_with("/a:/b") || cfg!(windows));
+    }
+}
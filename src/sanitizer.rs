@@ -0,0 +1,303 @@
+use clap::ValueEnum;
+use std::fs;
+use std::path::Path;
+
+use crate::failure;
+use crate::style::{self, Level};
+
+/// Where the programs `buddy run` starts write their sanitizer reports.
+pub const REPORT_DIR: &str = "target/buddy/sanitizers";
+
+/// The sanitizers of `--sanitize`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Sanitizer {
+    /// AddressSanitizer, with LeakSanitizer
+    Address,
+    /// ThreadSanitizer
+    Thread,
+    /// UndefinedBehaviorSanitizer
+    Undefined,
+    /// MemorySanitizer, clang only
+    Memory,
+}
+
+const SANITIZERS: [Sanitizer; 4] = [
+    Sanitizer::Address,
+    Sanitizer::Thread,
+    Sanitizer::Undefined,
+    Sanitizer::Memory,
+];
+
+impl Sanitizer {
+    /// Its name for `-fsanitize`.
+    fn name(self) -> &'static str {
+        match self {
+            Sanitizer::Address => "address",
+            Sanitizer::Thread => "thread",
+            Sanitizer::Undefined => "undefined",
+            Sanitizer::Memory => "memory",
+        }
+    }
+
+    /// The name of its config in `.bazelrc`, e.g. `asan`.
+    pub fn config(self) -> &'static str {
+        match self {
+            Sanitizer::Address => "asan",
+            Sanitizer::Thread => "tsan",
+            Sanitizer::Undefined => "ubsan",
+            Sanitizer::Memory => "msan",
+        }
+    }
+
+    /// The variable of its run time options, e.g. `ASAN_OPTIONS`.
+    fn options_var(self) -> String {
+        format!("{}_OPTIONS", self.config().to_uppercase())
+    }
+
+    /// The flags building with it. Undefined behavior aborts the program
+    /// rather than being reported and ignored, for tests to fail on it.
+    fn flags(self) -> Vec<String> {
+        let mut copts = vec![
+            format!("-fsanitize={}", self.name()),
+            "-fno-omit-frame-pointer".to_string(),
+            "-g".to_string(),
+        ];
+        match self {
+            Sanitizer::Undefined => copts.push("-fno-sanitize-recover=undefined".to_string()),
+            Sanitizer::Memory => copts.push("-fsanitize-memory-track-origins".to_string()),
+            Sanitizer::Address | Sanitizer::Thread => {}
+        }
+        let mut flags: Vec<String> = copts
+            .into_iter()
+            .map(|copt| format!("--copt={}", copt))
+            .collect();
+        flags.push(format!("--linkopt=-fsanitize={}", self.name()));
+        if self == Sanitizer::Undefined {
+            flags.push("--test_env=UBSAN_OPTIONS=print_stacktrace=1".to_string());
+        }
+        flags
+    }
+}
+
+/// The bazel flags building with `sanitizers`. Undefined goes with any
+/// other, but address, thread and memory each replace the allocator and
+/// exclude one another.
+pub fn flags(sanitizers: &[Sanitizer]) -> Result<Vec<String>, String> {
+    let exclusive: Vec<_> = sanitizers
+        .iter()
+        .filter(|sanitizer| **sanitizer != Sanitizer::Undefined)
+        .collect();
+    if let [first, second, ..] = exclusive[..] {
+        if first != second {
+            return Err(format!(
+                "the {} and {} sanitizers can't be combined, build with one at a time",
+                first.name(),
+                second.name()
+            ));
+        }
+    }
+    let mut flags: Vec<String> = Vec::new();
+    for sanitizer in sanitizers {
+        for flag in sanitizer.flags() {
+            if !flags.contains(&flag) {
+                flags.push(flag);
+            }
+        }
+    }
+    if !flags.is_empty() {
+        // The reports symbolize the frames with the debug info.
+        flags.push("--strip=never".to_string());
+    }
+    Ok(flags)
+}
+
+/// The `.bazelrc` lines of the `asan`, `tsan`, `ubsan` and `msan` configs,
+/// for `bazel --config=asan`.
+pub fn bazelrc() -> Vec<String> {
+    SANITIZERS
+        .iter()
+        .flat_map(|sanitizer| {
+            sanitizer
+                .flags()
+                .into_iter()
+                .chain(["--strip=never".to_string()])
+                .map(move |flag| format!("build:{} {}", sanitizer.config(), flag))
+        })
+        .collect()
+}
+
+/// The environment of a program built with `sanitizers`, writing their
+/// reports under `dir`.
+pub fn env(sanitizers: &[Sanitizer], dir: &Path) -> Vec<(String, String)> {
+    sanitizers
+        .iter()
+        .map(|sanitizer| {
+            let mut options = format!("log_path={}", dir.join(sanitizer.config()).display());
+            if *sanitizer == Sanitizer::Undefined {
+                options.push_str(":print_stacktrace=1");
+            }
+            (sanitizer.options_var(), options)
+        })
+        .collect()
+}
+
+/// A sanitizer report found in the output of a program.
+#[derive(Debug, PartialEq)]
+pub struct Report {
+    /// What went wrong, e.g. `AddressSanitizer: heap-use-after-free`.
+    pub title: String,
+    pub lines: Vec<String>,
+}
+
+/// The title of the report starting at `line`, if one does.
+pub fn title(line: &str) -> Option<String> {
+    // `==42==ERROR: AddressSanitizer: heap-use-after-free on address ...`
+    // and `WARNING: ThreadSanitizer: data race (pid=42)`.
+    // libFuzzer reports its own errors the same way, with a space first.
+    let rest = line
+        .trim_start_matches(|c: char| c == '=' || c.is_ascii_digit())
+        .trim_start();
+    for prefix in ["ERROR: ", "WARNING: "] {
+        if let Some(rest) = rest.strip_prefix(prefix) {
+            let (tool, kind) = rest.split_once(": ")?;
+            if !tool.ends_with("Sanitizer") && tool != "libFuzzer" {
+                return None;
+            }
+            // The address, pc and pid don't say what went wrong.
+            let kind = [" on ", " at ", " ("]
+                .iter()
+                .filter_map(|separator| kind.find(separator))
+                .min()
+                .map_or(kind, |end| &kind[..end]);
+            return Some(format!("{}: {}", tool, kind));
+        }
+    }
+    // `demo.cc:3:12: runtime error: signed integer overflow: ...`
+    let (_, message) = line.split_once(": runtime error: ")?;
+    Some(format!("UndefinedBehaviorSanitizer: {}", message))
+}
+
+/// The sanitizer reports of `output`, each up to its `SUMMARY:` line.
+pub fn reports(output: &str) -> Vec<Report> {
+    let mut reports: Vec<Report> = Vec::new();
+    let mut open = false;
+    for line in output.lines() {
+        if let Some(title) = title(line) {
+            reports.push(Report {
+                title,
+                lines: Vec::new(),
+            });
+            open = true;
+        }
+        if !open {
+            continue;
+        }
+        if let Some(report) = reports.last_mut() {
+            report.lines.push(line.to_string());
+        }
+        if line.starts_with("SUMMARY: ") {
+            open = false;
+        }
+    }
+    reports
+}
+
+/// Prints `report` of `source`, a program or a test, dimming the frames of
+/// the system libraries and of the external dependencies.
+pub fn print(source: &str, report: &Report) {
+    println!(
+        "{}: {} in {}",
+        style::paint("sanitizer", Level::Error),
+        report.title,
+        source
+    );
+    for line in &report.lines {
+        let frame = line.trim_start();
+        let foreign = frame.starts_with('#')
+            && (frame.contains(" external/")
+                || frame.contains(" /usr/")
+                || frame.contains(" /lib")
+                || frame.contains("(/"));
+        if foreign {
+            println!("  {}", style::dim(line));
+        } else if line.starts_with("SUMMARY: ") || title(line).is_some() {
+            println!("  {}", style::paint(line, Level::Error));
+        } else {
+            println!("  {}", line);
+        }
+    }
+}
+
+/// Prints the sanitizer reports in the logs of the tests which failed, as
+/// recorded in the build event file.
+pub fn report_tests(event_file: &Path) {
+    let Ok(events) = fs::read_to_string(event_file) else {
+        return;
+    };
+    for target in failure::failed_targets(&events) {
+        let Some(log) = target
+            .test_log
+            .and_then(|path| fs::read_to_string(path).ok())
+        else {
+            continue;
+        };
+        for report in reports(&log) {
+            print(&target.label, &report);
+        }
+    }
+}
+
+/// Prints the reports written under `dir` by the program `label`, then
+/// removes them.
+pub fn report_logs(label: &str, dir: &Path) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        if let Ok(log) = fs::read_to_string(entry.path()) {
+            for report in reports(&log) {
+                print(label, &report);
+            }
+        }
+        let _ = fs::remove_file(entry.path());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitizers() {
+        let flags = flags(&[Sanitizer::Address, Sanitizer::Undefined]).unwrap();
+        assert_eq!(flags[0], "--copt=-fsanitize=address");
+        assert_eq!(flags.iter().filter(|f| *f == "--copt=-g").count(), 1);
+        assert!(flags.contains(&"--copt=-fno-sanitize-recover=undefined".to_string()));
+        assert_eq!(flags.last().unwrap(), "--strip=never");
+        assert!(super::flags(&[Sanitizer::Address, Sanitizer::Thread])
+            .unwrap_err()
+            .contains("address and thread"));
+        assert!(bazelrc().contains(&"build:tsan --linkopt=-fsanitize=thread".to_string()));
+
+        let output = r#"[ RUN      ] Demo.Overflow
+=================================================================
+==4242==ERROR: AddressSanitizer: heap-use-after-free on address 0x602000000010 at pc 0x55
+READ of size 4 at 0x602000000010 thread T0
+    #0 0x55d in demo::get() src/demo.cc:12:10
+    #1 0x7f1 in __libc_start_main (/lib/x86_64-linux-gnu/libc.so.6+0x21c86)
+SUMMARY: AddressSanitizer: heap-use-after-free src/demo.cc:12:10 in demo::get()
+==4242==ABORTING
+src/math.cc:3:12: runtime error: signed integer overflow: 2147483647 + 1 cannot be represented in type 'int'
+SUMMARY: UndefinedBehaviorSanitizer: undefined-behavior src/math.cc:3:12
+"#;
+        let reports = reports(output);
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].title, "AddressSanitizer: heap-use-after-free");
+        assert_eq!(reports[0].lines.len(), 5);
+        assert_eq!(
+            reports[1].title,
+            "UndefinedBehaviorSanitizer: signed integer overflow: 2147483647 + 1 cannot be represented in type 'int'"
+        );
+        assert_eq!(reports[1].lines.len(), 2);
+    }
+}
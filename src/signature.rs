@@ -0,0 +1,134 @@
+use serde::Deserialize;
+use std::path::Path;
+use std::process::Command;
+use which::which;
+
+use crate::global::GlobalConfig;
+use crate::plugins::Plugin;
+
+/// A public key release archives are signed with, declared in TOML as
+/// `signing-key = { minisign = "RWQ..." }` or `{ cosign = "cosign.pub" }`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SigningKey {
+    /// A minisign public key, in its base64 form.
+    Minisign(String),
+    /// A sigstore public key as understood by `cosign --key`: a file, an
+    /// URL or a KMS reference.
+    Cosign(String),
+}
+
+impl SigningKey {
+    /// URL of the detached signature published next to `archive`.
+    pub fn signature_url(&self, archive: &str) -> String {
+        match self {
+            SigningKey::Minisign(_) => format!("{}.minisig", archive),
+            SigningKey::Cosign(_) => format!("{}.sig", archive),
+        }
+    }
+
+    /// Checks `signature` is a valid signature of `archive` by this key.
+    pub fn verify(&self, archive: &Path, signature: &Path) -> Result<(), String> {
+        let (tool, install) = match self {
+            SigningKey::Minisign(_) => ("minisign", "https://jedisct1.github.io/minisign/"),
+            SigningKey::Cosign(_) => (
+                "cosign",
+                "https://docs.sigstore.dev/cosign/system_config/installation/",
+            ),
+        };
+        let bin = which(tool).map_err(|_| {
+            format!(
+                "`{}` not found, it is needed to verify signed dependencies. See {}",
+                tool, install
+            )
+        })?;
+
+        let mut cmd = Command::new(bin);
+        match self {
+            SigningKey::Minisign(key) => cmd
+                .args(["-V", "-q", "-P", key, "-m"])
+                .arg(archive)
+                .arg("-x")
+                .arg(signature),
+            SigningKey::Cosign(key) => cmd
+                .args(["verify-blob", "--key", key, "--signature"])
+                .arg(signature)
+                .arg(archive),
+        };
+
+        let output = cmd
+            .output()
+            .map_err(|e| format!("failed to run {}: {}", tool, e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "invalid signature for `{}`: {}",
+                archive.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The key `plugin`'s archive must be signed with: the recipe's own, else
+/// the one of the registry it comes from.
+pub fn signing_key(plugin: &Plugin, config: &GlobalConfig) -> Option<SigningKey> {
+    plugin.signing_key.clone().or_else(|| {
+        plugin
+            .registry
+            .as_ref()
+            .and_then(|registry| config.registries.get(registry))
+            .and_then(|registry| registry.signing_key.clone())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_signing_key_falls_back_to_registry() {
+        let mut plugin = Plugin {
+            name: "zlib".to_string(),
+            versions: HashMap::new(),
+            build_rule: String::new(),
+            archive: Some("https://example.com/zlib-1.3.tar.gz".to_string()),
+            target: None,
+            signing_key: None,
+            registry: Some("internal".to_string()),
+            yanked: HashMap::new(),
+            deprecated: None,
+            description: None,
+            keywords: Vec::new(),
+            license: None,
+            advisories: Vec::new(),
+        };
+
+        let mut config = GlobalConfig::default();
+        assert_eq!(signing_key(&plugin, &config), None);
+
+        config.registries.insert(
+            "internal".to_string(),
+            toml::from_str(
+                r#"
+index = "https://buddy.example.com/index"
+signing-key = { cosign = "cosign.pub" }
+"#,
+            )
+            .unwrap(),
+        );
+        let key = signing_key(&plugin, &config).unwrap();
+        assert_eq!(key, SigningKey::Cosign("cosign.pub".to_string()));
+        assert_eq!(
+            key.signature_url(plugin.archive.as_ref().unwrap()),
+            "https://example.com/zlib-1.3.tar.gz.sig"
+        );
+
+        plugin.signing_key = Some(SigningKey::Minisign("RWQ".to_string()));
+        assert_eq!(
+            signing_key(&plugin, &config),
+            Some(SigningKey::Minisign("RWQ".to_string()))
+        );
+    }
+}
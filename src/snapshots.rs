@@ -0,0 +1,262 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::commands::upgrade::print_diff;
+use crate::style;
+use crate::targets::{self, BuildFile, Kind};
+
+/// The helper tests include to compare values against their snapshots.
+pub const HEADER: &str = "snapshot.h";
+
+/// Directory of the snapshots, next to the tests using them.
+pub const DIR: &str = "snapshots";
+
+const GENERATED: &str = "// This file is automatically @generated by Buddy.";
+
+/// `EXPECT_SNAPSHOT(value)` prints `value` with `operator<<` and compares
+/// it with `snapshots/<file>__<suite>__<test>.snap`. Mismatches fail the
+/// test and leave the new output in the test's undeclared outputs, for
+/// `buddy test --update-snapshots` to pick up.
+fn render_header() -> String {
+    format!(
+        r#"{}
+// It is not intended for manual editing.
+#pragma once
+
+#include <gtest/gtest.h>
+
+#include <cstdlib>
+#include <fstream>
+#include <map>
+#include <sstream>
+#include <string>
+
+namespace buddy_snapshot {{
+
+inline std::string Name(const char* file) {{
+  const auto* info = ::testing::UnitTest::GetInstance()->current_test_info();
+  std::string stem = file;
+  stem = stem.substr(stem.find_last_of('/') + 1);
+  stem = stem.substr(0, stem.find_last_of('.'));
+  std::string name =
+      stem + "__" + info->test_suite_name() + "__" + info->name();
+  for (char& c : name) {{
+    if (c == '/') c = '_';
+  }}
+  // Every assertion of a test gets its own snapshot.
+  static std::map<std::string, int> counts;
+  int n = ++counts[name];
+  return n == 1 ? name : name + "__" + std::to_string(n);
+}}
+
+template <typename T>
+::testing::AssertionResult Matches(const T& value, const char* file) {{
+  std::ostringstream out;
+  out << value;
+  const std::string actual = out.str();
+
+  std::string dir = file;
+  dir = dir.substr(0, dir.find_last_of('/') + 1) + "{}/";
+  const std::string name = Name(file);
+  const std::string path = dir + name + ".snap";
+
+  std::ifstream in(path, std::ios::binary);
+  const bool exists = in.is_open();
+  std::stringstream expected;
+  if (exists) {{
+    expected << in.rdbuf();
+  }}
+  if (exists && expected.str() == actual) {{
+    return ::testing::AssertionSuccess();
+  }}
+
+  if (const char* outputs = std::getenv("TEST_UNDECLARED_OUTPUTS_DIR")) {{
+    std::ofstream(std::string(outputs) + "/" + name + ".snap.new",
+                  std::ios::binary)
+        << actual;
+  }}
+  auto failure = ::testing::AssertionFailure();
+  if (exists) {{
+    failure << "snapshot " << path << " does not match\n  expected: "
+            << expected.str() << "\n    actual: " << actual;
+  }} else {{
+    failure << "no snapshot " << path << ", got: " << actual;
+  }}
+  return failure << "\nreview and accept it with `buddy test --update-snapshots`";
+}}
+
+}}  // namespace buddy_snapshot
+
+#define EXPECT_SNAPSHOT(value) \
+  EXPECT_TRUE(::buddy_snapshot::Matches((value), __FILE__))
+"#,
+        GENERATED, DIR
+    )
+}
+
+/// Writes the snapshot helper into the test directories whose tests
+/// include it, unless it was edited by hand.
+pub fn sync_header(root: &Path) -> io::Result<()> {
+    let include = format!("#include \"{}\"", HEADER);
+    let contents = render_header();
+    for (dir, files) in targets::collect_sources(root)? {
+        if !targets::is_test_dir(&dir) {
+            continue;
+        }
+        let mut used = false;
+        for file in &files {
+            if fs::read_to_string(root.join(&dir).join(file))?.contains(&include) {
+                used = true;
+                break;
+            }
+        }
+        if !used {
+            continue;
+        }
+
+        let path = root.join(&dir).join(HEADER);
+        let current = fs::read_to_string(&path).ok();
+        // Like BUILD files, a header edited by hand is the user's.
+        if current.is_none()
+            || current
+                .as_ref()
+                .is_some_and(|current| current.starts_with(GENERATED) && *current != contents)
+        {
+            fs::write(&path, &contents)?;
+        }
+    }
+    Ok(())
+}
+
+/// The snapshots of the tests in `dir`, relative to it.
+pub fn files(root: &Path, dir: &Path) -> io::Result<Vec<String>> {
+    let snapshots = root.join(dir).join(DIR);
+    if !snapshots.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut files = Vec::new();
+    for entry in fs::read_dir(snapshots)? {
+        let name = entry?.file_name();
+        let name = name.to_string_lossy();
+        if name.ends_with(".snap") {
+            files.push(format!("{}/{}", DIR, name));
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// A snapshot a failing test recorded.
+#[derive(Debug, PartialEq)]
+pub struct Pending {
+    /// Label of the test.
+    pub test: String,
+    /// The new output, in the test's undeclared outputs.
+    pub output: PathBuf,
+    /// The snapshot it replaces, relative to the project.
+    pub snapshot: PathBuf,
+}
+
+/// Collects the snapshots recorded by the last run of the tests, found
+/// under `target/testlogs` when bazel doesn't zip the test outputs.
+pub fn pending(root: &Path, build_files: &[BuildFile]) -> io::Result<Vec<Pending>> {
+    let mut pending = Vec::new();
+    for build_file in build_files {
+        for test in build_file.targets.iter().filter(|t| t.kind == Kind::Test) {
+            let outputs = root
+                .join("target")
+                .join("testlogs")
+                .join(&build_file.dir)
+                .join(&test.name)
+                .join("test.outputs");
+            let Ok(entries) = fs::read_dir(&outputs) else {
+                continue;
+            };
+            let mut names: Vec<_> = entries
+                .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+                .filter(|name| name.ends_with(".snap.new"))
+                .collect();
+            names.sort();
+            for name in names {
+                pending.push(Pending {
+                    test: build_file.label(&test.name),
+                    output: outputs.join(&name),
+                    snapshot: build_file.dir.join(DIR).join(name.trim_end_matches(".new")),
+                });
+            }
+        }
+    }
+    Ok(pending)
+}
+
+/// Writes the recorded outputs over their snapshots, showing what changed.
+pub fn accept(root: &Path, pending: &[Pending]) -> io::Result<()> {
+    for snapshot in pending {
+        let new = fs::read_to_string(&snapshot.output)?;
+        let path = root.join(&snapshot.snapshot);
+        let old = fs::read_to_string(&path).ok();
+        let verb = if old.is_some() { "Updated" } else { "Created" };
+
+        style::status(verb, snapshot.snapshot.display());
+        print_diff(
+            &snapshot.snapshot.display().to_string(),
+            old.as_deref().unwrap_or_default(),
+            &new,
+        );
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(&path, new)?;
+        // Accepted once, a cached run must not bring it back.
+        let _ = fs::remove_file(&snapshot.output);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TestConfig;
+
+    #[test]
+    fn test_accept_pending_snapshots() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path();
+        fs::create_dir_all(root.join("test")).unwrap();
+        fs::write(
+            root.join("test/render_test.cc"),
+            "#include \"snapshot.h\"\nTEST(Render, Page) { EXPECT_SNAPSHOT(1); }\n",
+        )
+        .unwrap();
+        let outputs = root.join("target/testlogs/test/render_test/test.outputs");
+        fs::create_dir_all(&outputs).unwrap();
+        fs::write(outputs.join("render_test__Render__Page.snap.new"), "1").unwrap();
+
+        sync_header(root).unwrap();
+        assert!(fs::read_to_string(root.join("test/snapshot.h"))
+            .unwrap()
+            .contains("#define EXPECT_SNAPSHOT(value)"));
+
+        let config = TestConfig::default();
+        let build_files = targets::scan(root, "demo", &config).unwrap();
+        let pending = pending(root, &build_files).unwrap();
+        assert_eq!(
+            pending,
+            [Pending {
+                test: "//test:render_test".to_string(),
+                output: outputs.join("render_test__Render__Page.snap.new"),
+                snapshot: PathBuf::from("test/snapshots/render_test__Render__Page.snap"),
+            }]
+        );
+
+        accept(root, &pending).unwrap();
+        assert_eq!(
+            fs::read_to_string(root.join("test/snapshots/render_test__Render__Page.snap")).unwrap(),
+            "1"
+        );
+        let build_files = targets::scan(root, "demo", &config).unwrap();
+        assert_eq!(
+            build_files[0].targets[0].data,
+            ["snapshots/render_test__Render__Page.snap"]
+        );
+    }
+}
@@ -0,0 +1,238 @@
+use colored::*;
+use std::fmt::Display;
+use std::sync::OnceLock;
+
+use crate::global::{StatusLines, StyleConfig, Symbols};
+
+/// What a piece of output reports, which picks its color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    /// Progress and success, e.g. status verbs and bazel's `INFO:` lines.
+    Info,
+    Warning,
+    Error,
+}
+
+/// The style of the output, from the `[style]` table of the user
+/// configuration.
+#[derive(Debug, PartialEq)]
+pub struct Style {
+    compact: bool,
+    ascii: bool,
+    high_contrast: bool,
+    info: Color,
+    warning: Color,
+    error: Color,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Style {
+            compact: false,
+            ascii: false,
+            high_contrast: false,
+            info: Color::Green,
+            warning: Color::Yellow,
+            error: Color::Red,
+        }
+    }
+}
+
+/// Parses a color name, e.g. `bright red`, or a `#rrggbb` hex code.
+fn parse_color(color: &str) -> Result<Color, String> {
+    let invalid = || {
+        format!(
+            "unknown color `{}` in [style], expected a name such as `green` or `bright red`, or a #rrggbb code",
+            color
+        )
+    };
+    if let Some(hex) = color.strip_prefix('#') {
+        if hex.len() != 6 {
+            return Err(invalid());
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid());
+        return Ok(Color::TrueColor {
+            r: channel(0)?,
+            g: channel(2)?,
+            b: channel(4)?,
+        });
+    }
+    color.parse().map_err(|_| invalid())
+}
+
+/// The bright variant of the basic colors, for high contrast.
+fn brighten(color: Color) -> Color {
+    match color {
+        Color::Black => Color::BrightBlack,
+        Color::Red => Color::BrightRed,
+        Color::Green => Color::BrightGreen,
+        Color::Yellow => Color::BrightYellow,
+        Color::Blue => Color::BrightBlue,
+        Color::Magenta => Color::BrightMagenta,
+        Color::Cyan => Color::BrightCyan,
+        Color::White => Color::BrightWhite,
+        other => other,
+    }
+}
+
+impl Style {
+    pub fn from_config(config: &StyleConfig) -> Result<Style, String> {
+        let default = Style::default();
+        let color = |name: &Option<String>, default: Color| -> Result<Color, String> {
+            let color = name.as_deref().map(parse_color).transpose()?;
+            let color = color.unwrap_or(default);
+            Ok(if config.high_contrast {
+                brighten(color)
+            } else {
+                color
+            })
+        };
+        Ok(Style {
+            compact: config.status == StatusLines::Compact,
+            ascii: config.symbols == Symbols::Ascii,
+            high_contrast: config.high_contrast,
+            info: color(&config.colors.info, default.info)?,
+            warning: color(&config.colors.warning, default.warning)?,
+            error: color(&config.colors.error, default.error)?,
+        })
+    }
+
+    fn paint(&self, text: &str, level: Level) -> ColoredString {
+        let color = match level {
+            Level::Info => self.info,
+            Level::Warning => self.warning,
+            Level::Error => self.error,
+        };
+        let painted = text.color(color);
+        if self.high_contrast {
+            painted.bold()
+        } else {
+            painted
+        }
+    }
+
+    fn symbol(&self, unicode: &'static str, ascii: &'static str) -> &'static str {
+        if self.ascii {
+            ascii
+        } else {
+            unicode
+        }
+    }
+
+    fn status(&self, verb: &str, message: &str) -> String {
+        if self.compact {
+            let symbol = self.symbol("✓", "*");
+            format!("{} {} {}", self.paint(symbol, Level::Info), verb, message)
+        } else {
+            format!("{:>12} {}", self.paint(verb, Level::Info), message)
+        }
+    }
+
+    fn diagnostic(&self, level: Level, message: &str) -> String {
+        let (label, unicode, ascii) = match level {
+            Level::Error => ("error", "✗", "x"),
+            _ => ("warning", "⚠", "!"),
+        };
+        if self.compact {
+            format!(
+                "{} {}",
+                self.paint(self.symbol(unicode, ascii), level),
+                message
+            )
+        } else {
+            format!("{}: {}", self.paint(label, level), message)
+        }
+    }
+}
+
+static STYLE: OnceLock<Style> = OnceLock::new();
+
+/// Applies the `[style]` table, before anything is printed. Until then,
+/// and when it is invalid, the output keeps the default style.
+pub fn init(config: &StyleConfig) -> Result<(), String> {
+    let style = Style::from_config(config)?;
+    let _ = STYLE.set(style);
+    Ok(())
+}
+
+fn current() -> &'static Style {
+    STYLE.get_or_init(Style::default)
+}
+
+/// Colors `text` as output of `level`.
+pub fn paint(text: &str, level: Level) -> ColoredString {
+    current().paint(text, level)
+}
+
+/// Dims secondary text, left as is in high contrast mode.
+pub fn dim(text: &str) -> ColoredString {
+    if current().high_contrast {
+        text.normal()
+    } else {
+        text.dimmed()
+    }
+}
+
+/// `unicode`, or `ascii` when the style asks for ASCII symbols.
+pub fn symbol(unicode: &'static str, ascii: &'static str) -> &'static str {
+    current().symbol(unicode, ascii)
+}
+
+/// Prints a status line, such as `    Compiled //src:demo`.
+pub fn status(verb: &str, message: impl Display) {
+    println!("{}", current().status(verb, &message.to_string()));
+}
+
+pub fn warning(message: impl Display) {
+    println!(
+        "{}",
+        current().diagnostic(Level::Warning, &message.to_string())
+    );
+}
+
+pub fn error(message: impl Display) {
+    println!(
+        "{}",
+        current().diagnostic(Level::Error, &message.to_string())
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_style() {
+        colored::control::set_override(false);
+
+        let verbose = Style::default();
+        assert_eq!(verbose.status("Copied", "x"), "      Copied x");
+        assert_eq!(verbose.diagnostic(Level::Warning, "w"), "warning: w");
+
+        let config: StyleConfig = toml::from_str(
+            r##"
+status = "compact"
+symbols = "ascii"
+high-contrast = true
+colors = { info = "cyan", error = "#ff0080" }
+"##,
+        )
+        .unwrap();
+        let style = Style::from_config(&config).unwrap();
+        assert_eq!(style.info, Color::BrightCyan);
+        assert_eq!(style.warning, Color::BrightYellow);
+        assert_eq!(
+            style.error,
+            Color::TrueColor {
+                r: 255,
+                g: 0,
+                b: 128
+            }
+        );
+        assert_eq!(style.status("Copied", "x"), "* Copied x");
+        assert_eq!(style.diagnostic(Level::Error, "e"), "x e");
+
+        let config: StyleConfig = toml::from_str("colors = { warning = \"orange\" }").unwrap();
+        assert!(Style::from_config(&config).is_err());
+    }
+}
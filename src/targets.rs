@@ -0,0 +1,1908 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config::{Config, LibConfig, LibType, ModuleConfig, Package, TestConfig, Visibility};
+use crate::features;
+use crate::global::GlobalConfig;
+use crate::plugins::{self, Plugin};
+use crate::snapshots;
+
+pub const GENERATED_HEADER: &str = "# This file is automatically @generated by Buddy.
+# It is not intended for manual editing.
+";
+
+const SOURCE_EXTENSIONS: [&str; 6] = ["cc", "cpp", "cxx", "c", "m", "mm"];
+/// Objective-C and Objective-C++ sources, which only `objc_library` builds.
+const OBJC_EXTENSIONS: [&str; 2] = ["m", "mm"];
+const HEADER_EXTENSIONS: [&str; 4] = ["h", "hh", "hpp", "hxx"];
+const TEST_DIRS: [&str; 2] = ["test", "tests"];
+/// Programs showing how to use the package, each its own `cc_binary`.
+pub const EXAMPLES_DIR: &str = "examples";
+/// The package's other programs, next to the one of `src/`.
+pub const BIN_DIR: &str = "src/bin";
+/// Google Benchmark programs, each its own optimized `cc_binary`.
+pub const BENCH_DIR: &str = "bench";
+/// Provides the `main()` of the benchmarks that don't define their own.
+pub const BENCHMARK_MAIN: &str = "@com_github_google_benchmark//:benchmark_main";
+const BENCHMARK: &str = "@com_github_google_benchmark//:benchmark";
+/// libFuzzer targets, each its own `cc_test` built by `buddy fuzz run`.
+pub const FUZZ_DIR: &str = "fuzz";
+
+/// Directories holding the project's own C/C++ code.
+pub const PROJECT_DIRS: [&str; 7] = [
+    "src", "include", "test", "tests", "examples", "bench", "fuzz",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Kind {
+    #[default]
+    Library,
+    Binary,
+    Test,
+    SharedLibrary,
+    /// Objective-C(++) sources, built for Apple platforms only.
+    ObjcLibrary,
+}
+
+impl Kind {
+    fn rule(&self) -> &'static str {
+        match self {
+            Kind::Library => "cc_library",
+            Kind::Binary => "cc_binary",
+            Kind::Test => "cc_test",
+            Kind::SharedLibrary => "cc_shared_library",
+            Kind::ObjcLibrary => "objc_library",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Target {
+    pub kind: Kind,
+    pub name: String,
+    pub srcs: Vec<String>,
+    pub hdrs: Vec<String>,
+    /// Files the target reads at run time, e.g. the snapshots of a test.
+    pub data: Vec<String>,
+    pub deps: Vec<String>,
+    /// Deps only linked on a platform, by the condition selecting it.
+    pub platform_deps: BTreeMap<String, Vec<String>>,
+    pub tags: Vec<String>,
+    pub strip_include_prefix: Option<String>,
+    pub include_prefix: Option<String>,
+    pub copts: Vec<String>,
+    pub local_defines: Vec<String>,
+    /// File name of a shared library, e.g. `libdemo.so.1.2.0`.
+    pub shared_lib_name: Option<String>,
+    pub additional_linker_inputs: Vec<String>,
+    pub linkopts: Vec<String>,
+    /// Replaces `linkopts` on macOS, whose linker takes different flags.
+    pub linkopts_macos: Vec<String>,
+    /// Apple frameworks of an `objc_library`, e.g. `Foundation`.
+    pub sdk_frameworks: Vec<String>,
+    /// Links every object, for the sources of a binary or a test.
+    pub alwayslink: bool,
+    pub target_compatible_with: Vec<String>,
+    pub visibility: Vec<String>,
+}
+
+/// `label`, relative to the root of a workspace member, relative to the root
+/// of the workspace, the member being at `prefix`: `//src:app` becomes
+/// `//app/src:app`. Labels of other repositories stay as they are.
+pub fn relocate_label(label: &str, prefix: &str) -> String {
+    match label.strip_prefix("//") {
+        Some(rest) if !prefix.is_empty() => {
+            let separator = if rest.starts_with(':') { "" } else { "/" };
+            format!("//{}{}{}", prefix, separator, rest)
+        }
+        _ => label.to_string(),
+    }
+}
+
+/// The targets of a single Bazel package, i.e. one `BUILD` file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildFile {
+    /// Directory of the package, relative to the workspace root.
+    pub dir: PathBuf,
+    pub targets: Vec<Target>,
+}
+
+impl BuildFile {
+    pub fn label(&self, name: &str) -> String {
+        label(&self.dir, name)
+    }
+
+    /// Moves the labels and include paths of the targets, relative to the
+    /// package's own root, below `prefix`, the directory of a workspace
+    /// member relative to the root of the workspace.
+    pub fn relocate(&mut self, prefix: &Path) {
+        let prefix = prefix.to_str().unwrap().replace('\\', "/");
+        if prefix.is_empty() {
+            return;
+        }
+        let relocate = |label: &mut String| *label = relocate_label(label, &prefix);
+        let location = format!("$(location //{}/", prefix);
+        for target in &mut self.targets {
+            target
+                .deps
+                .iter_mut()
+                .chain(target.platform_deps.values_mut().flatten())
+                .chain(target.additional_linker_inputs.iter_mut())
+                .for_each(relocate);
+            for linkopt in target
+                .linkopts
+                .iter_mut()
+                .chain(target.linkopts_macos.iter_mut())
+            {
+                *linkopt = linkopt.replace("$(location //", &location);
+            }
+            if let Some(include) = &mut target.strip_include_prefix {
+                if include.starts_with('/') {
+                    *include = format!("/{}{}", prefix, include);
+                }
+            }
+        }
+    }
+
+    pub fn render(&self) -> String {
+        // `cc_shared_library` and `objc_library` are native, rules_cc
+        // doesn't export them.
+        let mut rules: Vec<&str> = self
+            .targets
+            .iter()
+            .filter(|t| !matches!(t.kind, Kind::SharedLibrary | Kind::ObjcLibrary))
+            .map(|t| t.kind.rule())
+            .collect();
+        rules.sort();
+        rules.dedup();
+
+        let mut out = String::from(GENERATED_HEADER);
+        out.push_str(&format!(
+            "load(\"@rules_cc//cc:defs.bzl\", {})\n",
+            rules
+                .iter()
+                .map(|r| format!("\"{}\"", r))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+
+        for target in &self.targets {
+            out.push('\n');
+            out.push_str(&format!("{}(\n", target.kind.rule()));
+            out.push_str(&format!("    name = \"{}\",\n", target.name));
+            if target.kind == Kind::Test {
+                out.push_str("    size = \"small\",\n");
+            }
+            if let Some(shared_lib_name) = &target.shared_lib_name {
+                out.push_str(&format!("    shared_lib_name = \"{}\",\n", shared_lib_name));
+            }
+            push_list(&mut out, "srcs", &target.srcs);
+            push_list(&mut out, "hdrs", &target.hdrs);
+            push_list(&mut out, "data", &target.data);
+            if let Some(prefix) = &target.strip_include_prefix {
+                out.push_str(&format!("    strip_include_prefix = \"{}\",\n", prefix));
+            }
+            if let Some(prefix) = &target.include_prefix {
+                out.push_str(&format!("    include_prefix = \"{}\",\n", prefix));
+            }
+            push_list(&mut out, "copts", &target.copts);
+            push_list(&mut out, "local_defines", &target.local_defines);
+            if target.platform_deps.is_empty() {
+                push_list(&mut out, "deps", &target.deps);
+            } else {
+                push_list_select(&mut out, "deps", &target.deps, &target.platform_deps);
+            }
+            push_list(&mut out, "sdk_frameworks", &target.sdk_frameworks);
+            if target.alwayslink {
+                out.push_str("    alwayslink = True,\n");
+            }
+            push_list(&mut out, "tags", &target.tags);
+            push_list(
+                &mut out,
+                "additional_linker_inputs",
+                &target.additional_linker_inputs,
+            );
+            let linkopts_attr = match target.kind {
+                Kind::SharedLibrary => "user_link_flags",
+                _ => "linkopts",
+            };
+            if target.linkopts_macos.is_empty() {
+                push_list(&mut out, linkopts_attr, &target.linkopts);
+            } else {
+                push_select(
+                    &mut out,
+                    linkopts_attr,
+                    &[
+                        ("@platforms//os:macos", &target.linkopts_macos),
+                        ("//conditions:default", &target.linkopts),
+                    ],
+                );
+            }
+            push_list(
+                &mut out,
+                "target_compatible_with",
+                &target.target_compatible_with,
+            );
+            push_list(&mut out, "visibility", &target.visibility);
+            out.push_str(")\n");
+        }
+
+        out
+    }
+}
+
+fn push_list(out: &mut String, attr: &str, values: &[String]) {
+    match values.len() {
+        0 => {}
+        1 => out.push_str(&format!("    {} = [\"{}\"],\n", attr, values[0])),
+        _ => {
+            out.push_str(&format!("    {} = [\n", attr));
+            for value in values {
+                out.push_str(&format!("        \"{}\",\n", value));
+            }
+            out.push_str("    ],\n");
+        }
+    }
+}
+
+/// A list extended with the values of the branches of a `select()`, empty
+/// for the other conditions.
+fn push_list_select(
+    out: &mut String,
+    attr: &str,
+    values: &[String],
+    branches: &BTreeMap<String, Vec<String>>,
+) {
+    let quoted = |values: &[String]| {
+        values
+            .iter()
+            .map(|v| format!("\"{}\"", v))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    out.push_str(&format!("    {} = ", attr));
+    if !values.is_empty() {
+        out.push_str(&format!("[{}] + ", quoted(values)));
+    }
+    out.push_str("select({\n");
+    for (condition, values) in branches {
+        out.push_str(&format!(
+            "        \"{}\": [{}],\n",
+            condition,
+            quoted(values)
+        ));
+    }
+    out.push_str("        \"//conditions:default\": [],\n    }),\n");
+}
+
+fn push_select(out: &mut String, attr: &str, branches: &[(&str, &Vec<String>)]) {
+    out.push_str(&format!("    {} = select({{\n", attr));
+    for (condition, values) in branches {
+        let values: Vec<_> = values.iter().map(|v| format!("\"{}\"", v)).collect();
+        out.push_str(&format!(
+            "        \"{}\": [{}],\n",
+            condition,
+            values.join(", ")
+        ));
+    }
+    out.push_str("    }),\n");
+}
+
+/// Returns true if the file at `path` looks like a C/C++ source or header.
+pub fn is_cc_file(path: &Path) -> bool {
+    is_source(path) || is_header(path)
+}
+
+fn is_source(path: &Path) -> bool {
+    matches!(path.extension().and_then(|e| e.to_str()), Some(ext) if SOURCE_EXTENSIONS.contains(&ext))
+}
+
+fn is_objc(path: &Path) -> bool {
+    matches!(path.extension().and_then(|e| e.to_str()), Some(ext) if OBJC_EXTENSIONS.contains(&ext))
+}
+
+/// An `objc_library` of `srcs`, which bazel can only build for macOS.
+fn objc_library(name: String, srcs: Vec<String>) -> Target {
+    Target {
+        kind: Kind::ObjcLibrary,
+        name,
+        srcs,
+        target_compatible_with: vec!["@platforms//os:macos".to_string()],
+        ..Default::default()
+    }
+}
+
+fn is_header(path: &Path) -> bool {
+    matches!(path.extension().and_then(|e| e.to_str()), Some(ext) if HEADER_EXTENSIONS.contains(&ext))
+}
+
+pub fn is_test_dir(dir: &Path) -> bool {
+    dir.components()
+        .any(|c| TEST_DIRS.contains(&c.as_os_str().to_str().unwrap_or("")))
+}
+
+/// Whether `dir` holds the examples of the package.
+pub fn is_example_dir(dir: &Path) -> bool {
+    dir.starts_with(EXAMPLES_DIR)
+}
+
+/// Whether `dir` holds programs of the package besides the one of `src/`.
+pub fn is_bin_dir(dir: &Path) -> bool {
+    dir.starts_with(BIN_DIR)
+}
+
+/// Whether `dir` holds the benchmarks of the package.
+pub fn is_bench_dir(dir: &Path) -> bool {
+    dir.starts_with(BENCH_DIR)
+}
+
+/// Whether `dir` holds the fuzz targets of the package.
+pub fn is_fuzz_dir(dir: &Path) -> bool {
+    dir.starts_with(FUZZ_DIR)
+}
+
+/// Matches `name` against a shell-style pattern supporting `*` and `?`.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    fn matches(pattern: &[char], name: &[char]) -> bool {
+        match pattern.split_first() {
+            None => name.is_empty(),
+            Some(('*', rest)) => (0..=name.len()).any(|i| matches(rest, &name[i..])),
+            Some(('?', rest)) => !name.is_empty() && matches(rest, &name[1..]),
+            Some((c, rest)) => name.first() == Some(c) && matches(rest, &name[1..]),
+        }
+    }
+
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    matches(&pattern, &name)
+}
+
+/// Rough heuristic for spotting a translation unit that defines `main()`.
+pub fn defines_main(contents: &str) -> bool {
+    contents.lines().any(|line| {
+        let line = line.split("//").next().unwrap_or("").trim();
+        ["int main", "auto main"].iter().any(|prefix| {
+            line.strip_prefix(prefix)
+                .map(|rest| rest.trim_start().starts_with('('))
+                .unwrap_or(false)
+        })
+    })
+}
+
+/// Whether the directory `name` holds build output or hidden files rather
+/// than the project's.
+pub fn skip_dir(name: &str) -> bool {
+    name.starts_with('.') || name.starts_with("bazel-") || name == "target"
+}
+
+/// Recursively collects the C/C++ files under `root`, grouped by directory
+/// relative to `root`. Build output and hidden directories are ignored.
+pub fn collect_sources(root: &Path) -> io::Result<BTreeMap<PathBuf, Vec<PathBuf>>> {
+    let mut found = BTreeMap::new();
+    collect_into(root, Path::new(""), &mut found)?;
+    Ok(found)
+}
+
+fn collect_into(
+    root: &Path,
+    rel: &Path,
+    found: &mut BTreeMap<PathBuf, Vec<PathBuf>>,
+) -> io::Result<()> {
+    let mut entries: Vec<_> = fs::read_dir(root.join(rel))?
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .map(|e| e.path())
+        .collect();
+    entries.sort();
+
+    for path in entries {
+        let name = path.file_name().unwrap().to_str().unwrap_or("");
+        if path.is_dir() {
+            if !skip_dir(name) {
+                collect_into(root, &rel.join(name), found)?;
+            }
+        } else if is_cc_file(&path) {
+            found
+                .entry(rel.to_path_buf())
+                .or_default()
+                .push(PathBuf::from(name));
+        }
+    }
+
+    Ok(())
+}
+
+/// Lists the C/C++ files of the project, i.e. the ones under the
+/// conventional project directories, as paths relative to `root`.
+pub fn project_sources(root: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for dir in PROJECT_DIRS {
+        if !root.join(dir).is_dir() {
+            continue;
+        }
+        for (sub_dir, names) in collect_sources(&root.join(dir))? {
+            files.extend(
+                names
+                    .into_iter()
+                    .map(|name| Path::new(dir).join(&sub_dir).join(name)),
+            );
+        }
+    }
+    Ok(files)
+}
+
+/// Returns true for translation units, as opposed to headers.
+pub fn is_translation_unit(path: &Path) -> bool {
+    is_source(path)
+}
+
+/// Scans the existing sources under `root` and derives the `cc_library`,
+/// `cc_binary` and `cc_test` targets that reflect them: files matching the
+/// test pattern become tests, files defining `main()` become binaries, and
+/// everything else is grouped into one library per directory. Sources under
+/// `test/` that don't match the pattern are helpers, compiled into each test
+/// of their directory.
+pub fn scan(root: &Path, package_name: &str, test: &TestConfig) -> io::Result<Vec<BuildFile>> {
+    let sources = collect_sources(root)?;
+    let mut build_files = Vec::new();
+
+    for (dir, files) in &sources {
+        // Sources in the workspace root cannot be addressed as `//src:...`
+        // style packages, so we leave them alone.
+        if dir.as_os_str().is_empty() {
+            continue;
+        }
+
+        let dir_name = dir.file_name().unwrap().to_str().unwrap().to_string();
+        let test_dir = is_test_dir(dir);
+        let bench_dir = is_bench_dir(dir);
+        let fuzz_dir = is_fuzz_dir(dir);
+        let mut lib_srcs = Vec::new();
+        let mut hdrs = Vec::new();
+        let mut mains = Vec::new();
+        let mut tests = Vec::new();
+        let mut helpers = Vec::new();
+        let mut benches = Vec::new();
+        let mut fuzzers = Vec::new();
+
+        for file in files {
+            let name = file.to_str().unwrap().to_string();
+            if is_header(file) {
+                hdrs.push(name);
+            } else if fuzz_dir {
+                fuzzers.push(name);
+            } else if glob_match(&test.pattern, &name) {
+                tests.push(name);
+            } else if test_dir {
+                helpers.push(name);
+            } else if bench_dir {
+                benches.push(name);
+            } else if defines_main(&fs::read_to_string(root.join(dir).join(file))?) {
+                mains.push(name);
+            } else {
+                lib_srcs.push(name);
+            }
+        }
+
+        let mut targets = Vec::new();
+        let mut names = Vec::new();
+
+        for main in &mains {
+            let stem = Path::new(main).file_stem().unwrap().to_str().unwrap();
+            let name = if stem != "main" {
+                stem.to_string()
+            } else if dir.as_os_str() == "src" {
+                package_name.to_string()
+            } else {
+                dir_name.clone()
+            };
+            names.push(name.clone());
+            // cc_binary can't compile Objective-C, the main is linked in
+            // from an objc_library instead.
+            if is_objc(Path::new(main)) {
+                let mut sources = objc_library(format!("{}_main", name), vec![main.clone()]);
+                sources.alwayslink = true;
+                targets.push(Target {
+                    kind: Kind::Binary,
+                    name,
+                    deps: vec![format!(":{}", sources.name)],
+                    ..Default::default()
+                });
+                targets.push(sources);
+                continue;
+            }
+            targets.push(Target {
+                kind: Kind::Binary,
+                name,
+                srcs: vec![main.clone()],
+                ..Default::default()
+            });
+        }
+
+        for file in &benches {
+            let name = Path::new(file).file_stem().unwrap().to_str().unwrap();
+            // Benchmarks calling BENCHMARK_MAIN() bring their own main().
+            let benchmark = if defines_main(&fs::read_to_string(root.join(dir).join(file))?) {
+                BENCHMARK
+            } else {
+                BENCHMARK_MAIN
+            };
+            names.push(name.to_string());
+            targets.push(Target {
+                kind: Kind::Binary,
+                name: name.to_string(),
+                srcs: std::iter::once(file.clone())
+                    .chain(hdrs.iter().cloned())
+                    .collect(),
+                deps: vec![benchmark.to_string()],
+                // Measured optimized, whatever the compilation mode.
+                copts: vec!["-O2".to_string()],
+                ..Default::default()
+            });
+        }
+
+        for file in &fuzzers {
+            let name = Path::new(file).file_stem().unwrap().to_str().unwrap();
+            names.push(name.to_string());
+            targets.push(Target {
+                kind: Kind::Test,
+                name: name.to_string(),
+                srcs: std::iter::once(file.clone())
+                    .chain(hdrs.iter().cloned())
+                    .collect(),
+                // libFuzzer brings the main() calling LLVMFuzzerTestOneInput,
+                // the target only links with the fuzzing flags.
+                linkopts: vec!["-fsanitize=fuzzer".to_string()],
+                tags: vec!["fuzz".to_string(), "manual".to_string()],
+                ..Default::default()
+            });
+        }
+
+        for file in &tests {
+            let name = Path::new(file).file_stem().unwrap().to_str().unwrap();
+            let mut srcs = vec![file.clone()];
+            // Test directories don't get a library, their helpers and
+            // headers are compiled along with each test instead.
+            if test_dir {
+                srcs.extend(helpers.iter().cloned());
+                srcs.extend(hdrs.iter().cloned());
+            }
+            // Bazel never caches the results of `external` tests.
+            let tags = match test.targets.get(name) {
+                Some(settings) => {
+                    let mut tags = settings.tags.clone();
+                    if !settings.cache {
+                        tags.push("external".to_string());
+                    }
+                    tags
+                }
+                None => Vec::new(),
+            };
+            names.push(name.to_string());
+            let mut target = Target {
+                kind: Kind::Test,
+                name: name.to_string(),
+                srcs,
+                data: snapshots::files(root, dir)?,
+                deps: test.deps.clone(),
+                tags,
+                ..Default::default()
+            };
+            if is_objc(Path::new(file)) {
+                let mut sources = objc_library(format!("{}_objc", name), target.srcs);
+                sources.deps = target.deps.clone();
+                sources.alwayslink = true;
+                target.srcs = Vec::new();
+                target.deps.push(format!(":{}", sources.name));
+                targets.push(target);
+                targets.push(sources);
+                continue;
+            }
+            targets.push(target);
+        }
+
+        let (objc_srcs, lib_srcs): (Vec<_>, Vec<_>) = lib_srcs
+            .into_iter()
+            .partition(|src| is_objc(Path::new(src)));
+        let cc = !lib_srcs.is_empty() || !hdrs.is_empty();
+        if !test_dir && !bench_dir && !fuzz_dir && (cc || !objc_srcs.is_empty()) {
+            let mut name = if dir.as_os_str() == "src" {
+                package_name.to_string()
+            } else {
+                dir_name.clone()
+            };
+            if names.contains(&name) {
+                name.push_str("_lib");
+            }
+
+            // Mixed directories keep their C/C++ in a cc_library, which the
+            // Objective-C(++) sources build on.
+            let mut libraries = Vec::new();
+            if cc {
+                // The package's library is public, for the packages
+                // depending on it with a path.
+                let visibility = if dir.as_os_str() == "src" {
+                    vec!["//visibility:public".to_string()]
+                } else {
+                    Vec::new()
+                };
+                libraries.push(Target {
+                    kind: Kind::Library,
+                    name: name.clone(),
+                    srcs: lib_srcs,
+                    hdrs,
+                    visibility,
+                    ..Default::default()
+                });
+            }
+            if !objc_srcs.is_empty() {
+                let mut objc = objc_library(format!("{}_objc", name), objc_srcs);
+                if cc {
+                    objc.deps.push(format!(":{}", name));
+                } else {
+                    objc.name = name;
+                }
+                libraries.push(objc);
+            }
+
+            for target in targets.iter_mut() {
+                target
+                    .deps
+                    .extend(libraries.iter().map(|library| format!(":{}", library.name)));
+            }
+            targets.splice(0..0, libraries);
+        }
+
+        if !targets.is_empty() {
+            build_files.push(BuildFile {
+                dir: dir.clone(),
+                targets,
+            });
+        }
+    }
+
+    link_tests_to_libraries(&mut build_files);
+    link_programs_to_library(&mut build_files);
+
+    Ok(build_files)
+}
+
+/// The binaries under `src/bin/`, `examples/` and `bench/` use the package's
+/// library, the way the packages depending on it do.
+fn link_programs_to_library(build_files: &mut [BuildFile]) {
+    let Some(library) = package_library(build_files).map(|t| label(Path::new("src"), &t.name))
+    else {
+        return;
+    };
+    for build_file in build_files.iter_mut() {
+        if !is_example_dir(&build_file.dir)
+            && !is_bin_dir(&build_file.dir)
+            && !is_bench_dir(&build_file.dir)
+        {
+            continue;
+        }
+        for target in build_file.targets.iter_mut() {
+            if target.kind == Kind::Binary && !target.deps.contains(&library) {
+                target.deps.push(library.clone());
+            }
+        }
+    }
+}
+
+/// Tests living in a directory without a library of their own (the usual
+/// `test/` layout) get every library in the workspace as a dependency.
+fn link_tests_to_libraries(build_files: &mut [BuildFile]) {
+    let is_library = |t: &Target| match t.kind {
+        Kind::Library => true,
+        Kind::ObjcLibrary => !t.alwayslink,
+        _ => false,
+    };
+    let libraries: Vec<String> = build_files
+        .iter()
+        .flat_map(|b| {
+            b.targets
+                .iter()
+                .filter(|t| is_library(t))
+                .map(move |t| b.label(&t.name))
+        })
+        .collect();
+
+    for build_file in build_files.iter_mut() {
+        if build_file.targets.iter().any(is_library) {
+            continue;
+        }
+        for target in build_file.targets.iter_mut() {
+            if target.kind == Kind::Test || target.kind == Kind::ObjcLibrary {
+                target.deps.extend(libraries.iter().cloned());
+            }
+        }
+    }
+}
+
+/// The package's library: the `cc_library` generated for `src/`.
+pub fn package_library(build_files: &[BuildFile]) -> Option<&Target> {
+    build_files
+        .iter()
+        .find(|b| b.dir == Path::new("src"))?
+        .targets
+        .iter()
+        .find(|t| t.kind == Kind::Library)
+}
+
+/// The directory a public header pattern is relative to: its leading
+/// components without wildcards, e.g. `include/mylib` for
+/// `include/mylib/**.h`.
+pub fn pattern_base(pattern: &str) -> &str {
+    let wildcard = pattern.find(['*', '?']).unwrap_or(pattern.len());
+    pattern[..wildcard]
+        .rfind('/')
+        .map_or("", |slash| &pattern[..slash])
+}
+
+/// The pattern of `[lib] public-headers` matching the header at `path`.
+fn public_header_pattern<'a>(lib: &'a LibConfig, path: &Path) -> Option<&'a str> {
+    let path = path.to_str()?.replace('\\', "/");
+    lib.public_headers
+        .iter()
+        .find(|pattern| glob_match(pattern, &path))
+        .map(String::as_str)
+}
+
+/// Where the public header at `path` is included from, e.g.
+/// `mylib/foo.h` for `include/mylib/foo.h`. `None` for private headers.
+pub fn public_header_path(lib: &LibConfig, path: &Path) -> Option<PathBuf> {
+    let base = pattern_base(public_header_pattern(lib, path)?);
+    let relative = path.strip_prefix(base).ok()?;
+    Some(match &lib.include_prefix {
+        Some(prefix) => Path::new(prefix).join(relative),
+        None => relative.to_path_buf(),
+    })
+}
+
+/// Lays the libraries out after `[lib] public-headers`: public headers are
+/// exposed at their include path, the other headers become private sources,
+/// and the package's library depends on the libraries holding the public
+/// headers.
+pub fn apply_public_headers(build_files: &mut [BuildFile], lib: &LibConfig) {
+    if lib.public_headers.is_empty() {
+        return;
+    }
+
+    let mut public_libraries = Vec::new();
+    for build_file in build_files.iter_mut() {
+        let dir = build_file.dir.clone();
+        for target in build_file.targets.iter_mut() {
+            if target.kind != Kind::Library {
+                continue;
+            }
+            let (public, private): (Vec<_>, Vec<_>) = target
+                .hdrs
+                .drain(..)
+                .partition(|hdr| public_header_pattern(lib, &dir.join(hdr)).is_some());
+            target.srcs.extend(private);
+            target.srcs.sort();
+            if let Some(first) = public.first() {
+                let base = pattern_base(public_header_pattern(lib, &dir.join(first)).unwrap());
+                target.strip_include_prefix = Some(format!("/{}", base));
+                target.include_prefix = lib.include_prefix.clone();
+                target.visibility = vec!["//visibility:public".to_string()];
+                public_libraries.push(label(&dir, &target.name));
+            }
+            target.hdrs = public;
+        }
+    }
+
+    let Some(build_file) = build_files.iter_mut().find(|b| b.dir == Path::new("src")) else {
+        return;
+    };
+    let dir = build_file.dir.clone();
+    if let Some(library) = build_file
+        .targets
+        .iter_mut()
+        .find(|t| t.kind == Kind::Library)
+    {
+        let own = label(&dir, &library.name);
+        library
+            .deps
+            .extend(public_libraries.into_iter().filter(|label| *label != own));
+    }
+}
+
+/// The label of target `name` of the package in `dir`.
+fn label(dir: &Path, name: &str) -> String {
+    format!("//{}:{}", dir.to_str().unwrap().replace('\\', "/"), name)
+}
+
+/// Identifier the export macro and its defines derive from, e.g.
+/// `MY_APP` for `my-app`.
+fn macro_prefix(package_name: &str) -> String {
+    package_name.to_ascii_uppercase().replace('-', "_")
+}
+
+/// Path of the header defining the export macro, relative to the project:
+/// next to the public headers when declared, in `src/` otherwise.
+pub fn export_header(config: &Config) -> PathBuf {
+    let dir = config
+        .lib
+        .public_headers
+        .first()
+        .map_or("src", |pattern| pattern_base(pattern));
+    Path::new(dir).join(format!(
+        "{}_export.h",
+        config.package.name.replace('-', "_")
+    ))
+}
+
+/// The header defining `<NAME>_EXPORT`, which marks the symbols of the
+/// public ABI when the library is built with hidden visibility.
+pub fn render_export_header(package_name: &str) -> String {
+    let prefix = macro_prefix(package_name);
+    format!(
+        r#"// This file is automatically @generated by Buddy.
+// It is not intended for manual editing.
+#pragma once
+
+#if defined(_WIN32)
+#  if defined({0}_BUILDING_LIBRARY)
+#    define {0}_EXPORT __declspec(dllexport)
+#  else
+#    define {0}_EXPORT __declspec(dllimport)
+#  endif
+#else
+#  define {0}_EXPORT __attribute__((visibility("default")))
+#endif
+"#,
+        prefix
+    )
+}
+
+/// The label of a file of the project from the `src` package.
+fn file_label(path: &str) -> String {
+    let path = Path::new(path);
+    match (
+        path.parent().and_then(Path::to_str),
+        path.file_name().and_then(|n| n.to_str()),
+    ) {
+        (Some("src"), Some(name)) => name.to_string(),
+        (Some(dir), Some(name)) => format!("//{}:{}", dir, name),
+        _ => path.display().to_string(),
+    }
+}
+
+/// Adds a `cc_shared_library` of the package's library, named after the
+/// package's version (`libdemo.so.1.2.0`) with the major version as soname
+/// (`libdemo.so.1`). Unless `[lib] visibility = "default"`, the library is
+/// compiled with hidden symbols, only the ones marked with the export macro
+/// being part of the ABI.
+pub fn add_shared_library(build_files: &mut [BuildFile], package: &Package, lib: &LibConfig) {
+    let Some(build_file) = build_files.iter_mut().find(|b| b.dir == Path::new("src")) else {
+        return;
+    };
+    let Some(library) = build_file
+        .targets
+        .iter_mut()
+        .find(|t| t.kind == Kind::Library)
+    else {
+        return;
+    };
+
+    library
+        .local_defines
+        .push(format!("{}_BUILDING_LIBRARY", macro_prefix(&package.name)));
+    if lib.visibility == Visibility::Hidden {
+        library.copts.push("-fvisibility=hidden".to_string());
+    }
+    let name = library.name.clone();
+
+    let mut shared = Target {
+        kind: Kind::SharedLibrary,
+        name: format!("{}_shared", name),
+        deps: vec![format!(":{}", name)],
+        shared_lib_name: Some(shared_lib_name(package)),
+        linkopts: vec![format!("-Wl,-soname,{}", soname(package))],
+        linkopts_macos: vec![format!("-Wl,-install_name,@rpath/{}", soname(package))],
+        ..Default::default()
+    };
+    if let Some(script) = &lib.version_script {
+        let label = file_label(script);
+        shared
+            .linkopts
+            .push(format!("-Wl,--version-script=$(location {})", label));
+        shared.additional_linker_inputs.push(label);
+    }
+    if let Some(list) = &lib.exported_symbols_list {
+        let label = file_label(list);
+        shared
+            .linkopts_macos
+            .push(format!("-Wl,-exported_symbols_list,$(location {})", label));
+        shared.additional_linker_inputs.push(label);
+    }
+    build_file.targets.push(shared);
+}
+
+/// Links the Apple frameworks of `[build] frameworks`: the Objective-C
+/// libraries declare them, and the package's library links them on macOS
+/// for the C/C++ code calling into them.
+pub fn apply_frameworks(build_files: &mut [BuildFile], frameworks: &[String]) {
+    if frameworks.is_empty() {
+        return;
+    }
+    for build_file in build_files.iter_mut() {
+        let src = build_file.dir == Path::new("src");
+        for target in build_file.targets.iter_mut() {
+            match target.kind {
+                Kind::ObjcLibrary => target.sdk_frameworks = frameworks.to_vec(),
+                Kind::Library if src => {
+                    for framework in frameworks {
+                        target.linkopts_macos.push("-framework".to_string());
+                        target.linkopts_macos.push(framework.clone());
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Wires the modules of the package, the subdirectories of `src/` which
+/// each get a library: a module depends on the ones its `[modules.<name>]`
+/// table lists, and the package's library on every module. Without a
+/// library in `src/`, its binaries depend on the modules instead.
+pub fn apply_modules(
+    build_files: &mut [BuildFile],
+    modules: &BTreeMap<String, ModuleConfig>,
+) -> Result<(), String> {
+    let module_name = |dir: &Path| {
+        let module = dir.strip_prefix("src").ok()?;
+        (!module.as_os_str().is_empty()).then(|| module.to_str().unwrap().replace('\\', "/"))
+    };
+    let libraries: BTreeMap<String, String> = build_files
+        .iter()
+        .filter_map(|b| {
+            let library = b.targets.iter().find(|t| t.kind == Kind::Library)?;
+            Some((module_name(&b.dir)?, b.label(&library.name)))
+        })
+        .collect();
+    if let Some(name) = modules.keys().find(|name| !libraries.contains_key(*name)) {
+        return Err(format!(
+            "[modules.{}] has no library, expected C/C++ sources under src/{}",
+            name, name
+        ));
+    }
+
+    for build_file in build_files.iter_mut() {
+        let Some(module) = module_name(&build_file.dir) else {
+            if build_file.dir != Path::new("src") {
+                continue;
+            }
+            let has_library = build_file.targets.iter().any(|t| t.kind == Kind::Library);
+            let umbrella = if has_library {
+                Kind::Library
+            } else {
+                Kind::Binary
+            };
+            for target in build_file.targets.iter_mut() {
+                if target.kind != umbrella {
+                    continue;
+                }
+                for label in libraries.values() {
+                    if !target.deps.contains(label) {
+                        target.deps.push(label.clone());
+                    }
+                }
+            }
+            continue;
+        };
+
+        let mut deps = Vec::new();
+        for dep in modules.get(&module).map_or(&[][..], |m| &m.deps) {
+            let label = if dep.starts_with('@') || dep.starts_with("//") {
+                dep.clone()
+            } else {
+                libraries.get(dep).cloned().ok_or_else(|| {
+                    format!(
+                        "[modules.{}] depends on `{}`, which is not a module of src/",
+                        module, dep
+                    )
+                })?
+            };
+            deps.push(label);
+        }
+        if let Some(library) = build_file
+            .targets
+            .iter_mut()
+            .find(|t| t.kind == Kind::Library)
+        {
+            library.deps.extend(deps);
+            library.visibility = vec!["//visibility:public".to_string()];
+        }
+    }
+    Ok(())
+}
+
+/// The targets of the external dependencies the package's library links
+/// against, besides the local packages.
+#[derive(Debug, Default)]
+pub struct ExternalDeps {
+    /// Targets of the enabled optional dependencies.
+    pub optional: Vec<String>,
+    /// Targets of the `[target]` dependencies, by the condition of their
+    /// platform.
+    pub platforms: BTreeMap<String, Vec<String>>,
+    /// Libraries of the other members of the workspace, relative to its
+    /// root.
+    pub members: Vec<String>,
+}
+
+impl ExternalDeps {
+    /// The targets of the dependencies of `config` its recipes declare,
+    /// with the features of `enabled`.
+    pub fn resolve(
+        config: &Config,
+        enabled: &BTreeSet<String>,
+        plugins: &[Plugin],
+        global: &GlobalConfig,
+    ) -> Result<ExternalDeps, String> {
+        let mut platforms = BTreeMap::new();
+        for (condition, dependencies) in config.platform_dependencies()? {
+            let mut names: Vec<_> = dependencies.keys().collect();
+            names.sort();
+            let mut labels = Vec::new();
+            for name in names {
+                let plugin = plugins::resolve(plugins, name, global)?;
+                let version = plugin.select(&dependencies[name], None)?;
+                labels.extend(plugin.target_label(&version)?);
+            }
+            if !labels.is_empty() {
+                platforms.insert(condition, labels);
+            }
+        }
+        Ok(ExternalDeps {
+            optional: features::optional_labels(config, enabled, plugins, global)?,
+            platforms,
+            members: Vec::new(),
+        })
+    }
+}
+
+/// Wires the dependencies given by their `labels`, the libraries of local
+/// packages and the targets of optional dependencies, and the `platforms`
+/// ones under a `select()`, into the package's library, or into its
+/// binaries when `src/` has no library.
+pub fn apply_dependencies(
+    build_files: &mut [BuildFile],
+    labels: &[String],
+    platforms: &BTreeMap<String, Vec<String>>,
+) {
+    let Some(build_file) = build_files.iter_mut().find(|b| b.dir == Path::new("src")) else {
+        return;
+    };
+    let umbrella = if build_file.targets.iter().any(|t| t.kind == Kind::Library) {
+        Kind::Library
+    } else {
+        Kind::Binary
+    };
+    for target in build_file.targets.iter_mut() {
+        if target.kind != umbrella {
+            continue;
+        }
+        for label in labels {
+            if !target.deps.contains(label) {
+                target.deps.push(label.clone());
+            }
+        }
+        for (condition, labels) in platforms {
+            target
+                .platform_deps
+                .entry(condition.clone())
+                .or_default()
+                .extend(labels.iter().cloned());
+        }
+    }
+}
+
+/// File name of the package's shared library.
+pub fn shared_lib_name(package: &Package) -> String {
+    format!("lib{}.so.{}", package.name, package.version)
+}
+
+/// The soname of the package's shared library: compatible versions share
+/// the major version.
+pub fn soname(package: &Package) -> String {
+    let major = package.version.split('.').next().unwrap_or_default();
+    format!("lib{}.so.{}", package.name, major)
+}
+
+/// The `BUILD` files of the project's sources laid out after `[lib]`, the
+/// `external` dependencies linked in.
+pub fn scan_library(
+    root: &Path,
+    config: &Config,
+    external: &ExternalDeps,
+) -> io::Result<Vec<BuildFile>> {
+    let mut build_files = scan(root, &config.package.name, &config.test)?;
+    apply_public_headers(&mut build_files, &config.lib);
+    if config.lib.types.contains(&LibType::Shared) {
+        add_shared_library(&mut build_files, &config.package, &config.lib);
+    }
+    apply_frameworks(&mut build_files, &config.build.frameworks);
+    apply_modules(&mut build_files, &config.modules).map_err(io::Error::other)?;
+    let mut labels = config
+        .path_dependencies()
+        .iter()
+        .map(|(name, dependency)| dependency.label(root, name))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(io::Error::other)?;
+    labels.extend(external.optional.iter().cloned());
+    apply_dependencies(&mut build_files, &labels, &external.platforms);
+    Ok(build_files)
+}
+
+/// Regenerates the generated `BUILD` files of the libraries after `[lib]`,
+/// linking the `external` dependencies in, the labels relocated below
+/// `prefix` in a workspace. Hand-written files are left untouched, returns
+/// false when `src/BUILD` is such a file and lacks the `cc_shared_library`
+/// asked for.
+pub fn sync_library(
+    root: &Path,
+    prefix: &Path,
+    config: &Config,
+    external: &ExternalDeps,
+) -> io::Result<bool> {
+    let shared = config.lib.types.contains(&LibType::Shared);
+    if shared {
+        let path = root.join(export_header(config));
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let current = fs::read_to_string(&path).ok();
+        let contents = render_export_header(&config.package.name);
+        // Like BUILD files, a header edited by hand is the user's.
+        if current.is_none()
+            || current.as_ref().is_some_and(|current| {
+                current.starts_with("// This file is automatically @generated by Buddy.")
+                    && *current != contents
+            })
+        {
+            fs::write(&path, contents)?;
+        }
+    }
+
+    let mut build_files = scan_library(root, config, external)?;
+    for build_file in &mut build_files {
+        build_file.relocate(prefix);
+    }
+    // Already relative to the root of the workspace.
+    apply_dependencies(&mut build_files, &external.members, &BTreeMap::new());
+
+    let mut complete = true;
+    for build_file in build_files {
+        // Left to `sync_tests`, `sync_examples`, `sync_binaries`,
+        // `sync_benches` and `sync_fuzzers`.
+        if is_test_dir(&build_file.dir)
+            || is_example_dir(&build_file.dir)
+            || is_bin_dir(&build_file.dir)
+            || is_bench_dir(&build_file.dir)
+            || is_fuzz_dir(&build_file.dir)
+        {
+            continue;
+        }
+        let path = root.join(&build_file.dir).join("BUILD");
+        let current = fs::read_to_string(&path).ok();
+        if let Some(contents) = current
+            .as_ref()
+            .filter(|contents| !contents.starts_with(GENERATED_HEADER))
+        {
+            if shared && build_file.dir == Path::new("src") {
+                complete = contents.contains("cc_shared_library");
+            }
+            continue;
+        }
+        let contents = build_file.render();
+        if current.as_deref() != Some(contents.as_str()) {
+            fs::write(&path, contents)?;
+        }
+    }
+    Ok(complete)
+}
+
+/// Regenerates the `BUILD` files of the test directories so that every file
+/// matching the test pattern gets its own `cc_test`, the labels relocated
+/// below `prefix` in a workspace. Hand-written `BUILD` files (the ones
+/// without the @generated header) are left untouched.
+pub fn sync_tests(
+    root: &Path,
+    prefix: &Path,
+    package_name: &str,
+    test: &TestConfig,
+) -> io::Result<()> {
+    sync_generated(root, prefix, package_name, test, is_test_dir)
+}
+
+/// Like `sync_tests`, for the directories of examples, every file defining
+/// `main()` getting its own `cc_binary`.
+pub fn sync_examples(
+    root: &Path,
+    prefix: &Path,
+    package_name: &str,
+    test: &TestConfig,
+) -> io::Result<()> {
+    sync_generated(root, prefix, package_name, test, is_example_dir)
+}
+
+/// Like `sync_examples`, for the programs under `src/bin/`.
+pub fn sync_binaries(
+    root: &Path,
+    prefix: &Path,
+    package_name: &str,
+    test: &TestConfig,
+) -> io::Result<()> {
+    sync_generated(root, prefix, package_name, test, is_bin_dir)
+}
+
+/// Like `sync_examples`, for the benchmarks under `bench/`, every source
+/// getting its own `cc_binary` linked with Google Benchmark.
+pub fn sync_benches(
+    root: &Path,
+    prefix: &Path,
+    package_name: &str,
+    test: &TestConfig,
+) -> io::Result<()> {
+    sync_generated(root, prefix, package_name, test, is_bench_dir)
+}
+
+/// Like `sync_tests`, for the fuzz targets under `fuzz/`, every source
+/// getting its own libFuzzer `cc_test`, left out of `bazel test //...`.
+pub fn sync_fuzzers(
+    root: &Path,
+    prefix: &Path,
+    package_name: &str,
+    test: &TestConfig,
+) -> io::Result<()> {
+    sync_generated(root, prefix, package_name, test, is_fuzz_dir)
+}
+
+/// Regenerates the generated `BUILD` files of the directories `dirs`
+/// selects.
+fn sync_generated(
+    root: &Path,
+    prefix: &Path,
+    package_name: &str,
+    test: &TestConfig,
+    dirs: fn(&Path) -> bool,
+) -> io::Result<()> {
+    for mut build_file in scan(root, package_name, test)? {
+        if !dirs(&build_file.dir) {
+            continue;
+        }
+        build_file.relocate(prefix);
+
+        let path = root.join(&build_file.dir).join("BUILD");
+        let generated = match fs::read_to_string(&path) {
+            Ok(contents) => contents.starts_with(GENERATED_HEADER),
+            Err(_) => true,
+        };
+        if generated {
+            let contents = build_file.render();
+            if fs::read_to_string(&path).ok().as_deref() != Some(contents.as_str()) {
+                fs::write(&path, contents)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The index of the bracket or parenthesis closing the one opened at
+/// `open`, skipping string literals.
+fn closing(contents: &str, open: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut in_string = false;
+    for (i, c) in contents[open..].char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            _ if in_string => {}
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(open + i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The parentheses of the rule named `target` in a BUILD file, and where
+/// its `name` attribute starts.
+fn rule_span(contents: &str, target: &str) -> Option<(usize, usize, usize)> {
+    let quoted = format!("\"{}\"", target);
+    for (i, _) in contents.match_indices(&quoted) {
+        let Some(before) = contents[..i].trim_end().strip_suffix('=') else {
+            continue;
+        };
+        let Some(name) = before.trim_end().strip_suffix("name") else {
+            continue;
+        };
+        let open = contents[..i].rfind('(')?;
+        return Some((open, name.len(), closing(contents, open)?));
+    }
+    None
+}
+
+/// The opening bracket of the `deps` list of the rule ending at `close`.
+fn deps_list(contents: &str, open: usize, close: usize) -> Result<Option<usize>, String> {
+    let args = &contents[..close];
+    for (i, _) in args[open..].match_indices("deps") {
+        let i = open + i;
+        let attribute = args[..i]
+            .chars()
+            .next_back()
+            .is_some_and(|c| c.is_whitespace() || c == ',' || c == '(');
+        let value = args[i + "deps".len()..].trim_start().strip_prefix('=');
+        let Some(value) = value.filter(|_| attribute) else {
+            continue;
+        };
+        if !value.trim_start().starts_with('[') {
+            return Err("its `deps` aren't a plain list".to_string());
+        }
+        return Ok(Some(args.len() - value.trim_start().len()));
+    }
+    Ok(None)
+}
+
+/// Adds `label` to the `deps` of the rule named `target` in the BUILD file
+/// `contents`, keeping the rest of it as it is. Returns `None` when the
+/// rule already depends on it.
+pub fn add_dep(contents: &str, target: &str, label: &str) -> Result<Option<String>, String> {
+    let (rule, name, end) =
+        rule_span(contents, target).ok_or_else(|| format!("no target `{}`", target))?;
+    let quoted = format!("\"{}\"", label);
+    let mut edited = contents.to_string();
+
+    let Some(open) = deps_list(contents, rule, end)? else {
+        // A new `deps` attribute, indented like the name.
+        let last = contents[..end].trim_end().len();
+        let line = contents[..name].rfind('\n').map_or(0, |i| i + 1);
+        let indent = match &contents[line..name] {
+            indent if indent.trim().is_empty() => indent,
+            _ => "    ",
+        };
+        let comma = if contents[..last].ends_with([',', '(']) {
+            ""
+        } else {
+            ","
+        };
+        edited.insert_str(last, &format!("{}\n{}deps = [{}],", comma, indent, quoted));
+        return Ok(Some(edited));
+    };
+
+    let close = closing(contents, open).ok_or("unterminated `deps` list")?;
+    let list = &contents[open + 1..close];
+    if list.contains(&quoted) {
+        return Ok(None);
+    }
+    let last = open + 1 + list.trim_end().len();
+    if list.trim().is_empty() {
+        edited.replace_range(open + 1..close, &quoted);
+    } else if list.contains('\n') {
+        // One dependency per line, like the others.
+        let line = contents[..last].rfind('\n').map_or(0, |i| i + 1);
+        let indent: String = contents[line..]
+            .chars()
+            .take_while(|c| c.is_whitespace())
+            .collect();
+        let comma = if contents[..last].ends_with(',') {
+            ""
+        } else {
+            ","
+        };
+        edited.insert_str(last, &format!("{}\n{}{},", comma, indent, quoted));
+    } else if contents[..last].ends_with(',') {
+        edited.insert_str(last, &format!(" {}", quoted));
+    } else {
+        edited.insert_str(last, &format!(", {}", quoted));
+    }
+    Ok(Some(edited))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TestTarget;
+
+    #[test]
+    fn test_add_dep() {
+        let build = r#"load("@rules_cc//cc:defs.bzl", "cc_binary", "cc_library")
+
+cc_library(
+    name = "util",
+    srcs = ["util.cc"],
+    deps = [
+        "@zlib//:zlib",
+        "@fmt//:fmt"
+    ],
+)
+
+cc_binary(name = "demo", srcs = ["main.cc"], deps = [":util"])
+"#;
+        let edited = add_dep(build, "util", "@spdlog//:spdlog").unwrap().unwrap();
+        assert!(edited.contains("        \"@fmt//:fmt\",\n        \"@spdlog//:spdlog\",\n    ],"));
+        let edited = add_dep(&edited, "demo", "@spdlog//:spdlog")
+            .unwrap()
+            .unwrap();
+        assert!(edited.contains("deps = [\":util\", \"@spdlog//:spdlog\"])"));
+        assert_eq!(add_dep(&edited, "util", "@fmt//:fmt").unwrap(), None);
+        assert!(add_dep(build, "missing", "@fmt//:fmt").is_err());
+        assert!(add_dep("cc_test(name = \"t\", deps = DEPS)", "t", "@fmt//:fmt").is_err());
+    }
+
+    #[test]
+    fn test_shared_library() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/util.cc"), "int util() { return 1; }").unwrap();
+        fs::write(root.join("src/util.h"), "int util();").unwrap();
+
+        let config: Config = toml::from_str(
+            r#"
+[package]
+name = "demo"
+version = "1.2.0"
+edition = "2023"
+
+[dependencies]
+
+[lib]
+type = ["static", "shared"]
+version-script = "src/demo.map"
+"#,
+        )
+        .unwrap();
+        assert!(sync_library(root, Path::new(""), &config, &ExternalDeps::default()).unwrap());
+
+        let header = fs::read_to_string(root.join("src/demo_export.h")).unwrap();
+        assert!(header.contains("#  define DEMO_EXPORT __attribute__((visibility(\"default\")))"));
+
+        let build = fs::read_to_string(root.join("src/BUILD")).unwrap();
+        assert!(build.contains("load(\"@rules_cc//cc:defs.bzl\", \"cc_library\")\n"));
+        assert!(build.contains(
+            r#"    hdrs = [
+        "demo_export.h",
+        "util.h",
+    ],
+    copts = ["-fvisibility=hidden"],
+    local_defines = ["DEMO_BUILDING_LIBRARY"],
+    visibility = ["//visibility:public"],
+)"#
+        ));
+        assert!(build.contains(
+            r#"cc_shared_library(
+    name = "demo_shared",
+    shared_lib_name = "libdemo.so.1.2.0",
+    deps = [":demo"],
+    additional_linker_inputs = ["demo.map"],
+    user_link_flags = select({
+        "@platforms//os:macos": ["-Wl,-install_name,@rpath/libdemo.so.1"],
+        "//conditions:default": ["-Wl,-soname,libdemo.so.1", "-Wl,--version-script=$(location demo.map)"],
+    }),
+)"#
+        ));
+
+        fs::write(root.join("src/BUILD"), "cc_library(name = \"demo\")").unwrap();
+        assert!(!sync_library(root, Path::new(""), &config, &ExternalDeps::default()).unwrap());
+    }
+
+    #[test]
+    fn test_public_headers() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path();
+        fs::create_dir_all(root.join("include/mylib")).unwrap();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("include/mylib/foo.h"), "int foo();").unwrap();
+        fs::write(root.join("src/foo.cc"), "int foo() { return 1; }").unwrap();
+        fs::write(root.join("src/detail.h"), "int detail();").unwrap();
+
+        let config: Config = toml::from_str(
+            r#"
+[package]
+name = "demo"
+version = "1.2.0"
+edition = "2023"
+
+[dependencies]
+
+[lib]
+public-headers = ["include/mylib/**.h"]
+include-prefix = "mylib"
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            public_header_path(&config.lib, Path::new("include/mylib/foo.h")),
+            Some(PathBuf::from("mylib/foo.h"))
+        );
+        assert_eq!(
+            public_header_path(&config.lib, Path::new("src/detail.h")),
+            None
+        );
+
+        let build_files = scan_library(root, &config, &ExternalDeps::default()).unwrap();
+        let include = &build_files[0].targets[0];
+        assert_eq!(include.hdrs, ["foo.h"]);
+        assert_eq!(
+            include.strip_include_prefix.as_deref(),
+            Some("/include/mylib")
+        );
+        assert_eq!(include.include_prefix.as_deref(), Some("mylib"));
+
+        let library = package_library(&build_files).unwrap();
+        assert!(library.hdrs.is_empty());
+        assert_eq!(library.srcs, ["detail.h", "foo.cc"]);
+        assert_eq!(library.deps, ["//include/mylib:mylib"]);
+    }
+
+    #[test]
+    fn test_defines_main() {
+        assert!(defines_main("int main(int argc, char** argv) {"));
+        assert!(defines_main("  int main (void)\n{"));
+        assert!(!defines_main("int maintain(int x);"));
+        assert!(!defines_main("// int main() {"));
+    }
+
+    #[test]
+    fn test_scan_mixed_tree() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(root.join("test")).unwrap();
+        fs::create_dir_all(root.join("target")).unwrap();
+        fs::write(root.join("src/app.cc"), "int main() { return 0; }").unwrap();
+        fs::write(root.join("src/util.cc"), "int util() { return 1; }").unwrap();
+        fs::write(root.join("src/util.h"), "int util();").unwrap();
+        fs::write(root.join("test/util_test.cc"), "TEST(A, B) {}").unwrap();
+        fs::write(root.join("target/ignored.cc"), "int main() {}").unwrap();
+
+        let build_files = scan(root, "demo", &TestConfig::default()).unwrap();
+        assert_eq!(build_files.len(), 2);
+
+        let src = &build_files[0];
+        assert_eq!(src.dir, PathBuf::from("src"));
+        assert_eq!(src.targets[0].kind, Kind::Library);
+        assert_eq!(src.targets[0].name, "demo");
+        assert_eq!(src.targets[0].srcs, vec!["util.cc"]);
+        assert_eq!(src.targets[0].hdrs, vec!["util.h"]);
+        assert_eq!(src.targets[1].kind, Kind::Binary);
+        assert_eq!(src.targets[1].name, "app");
+        assert_eq!(src.targets[1].deps, vec![":demo"]);
+
+        let test = &build_files[1];
+        assert_eq!(test.targets[0].kind, Kind::Test);
+        assert_eq!(test.targets[0].name, "util_test");
+        assert_eq!(
+            test.targets[0].deps,
+            vec!["@com_google_googletest//:gtest_main", "//src:demo"]
+        );
+    }
+
+    #[test]
+    fn test_scan_objective_c() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/engine.cc"), "int engine() { return 1; }").unwrap();
+        fs::write(root.join("src/engine.h"), "int engine();").unwrap();
+        fs::write(root.join("src/window.mm"), "void open_window() {}").unwrap();
+        fs::write(root.join("src/main.mm"), "int main() { return 0; }").unwrap();
+
+        let mut build_files = scan(root, "demo", &TestConfig::default()).unwrap();
+        apply_frameworks(&mut build_files, &["AppKit".to_string()]);
+        let rendered = build_files[0].render();
+        assert!(
+            rendered.contains("load(\"@rules_cc//cc:defs.bzl\", \"cc_binary\", \"cc_library\")\n")
+        );
+        assert!(rendered.contains(
+            r#"objc_library(
+    name = "demo_lib_objc",
+    srcs = ["window.mm"],
+    deps = [":demo_lib"],
+    sdk_frameworks = ["AppKit"],
+    target_compatible_with = ["@platforms//os:macos"],
+)"#
+        ));
+        assert!(rendered.contains(
+            r#"cc_binary(
+    name = "demo",
+    deps = [
+        ":demo_main",
+        ":demo_lib",
+        ":demo_lib_objc",
+    ],
+)"#
+        ));
+        assert!(rendered.contains(
+            r#"    name = "demo_main",
+    srcs = ["main.mm"],
+    deps = [
+        ":demo_lib",
+        ":demo_lib_objc",
+    ],
+    sdk_frameworks = ["AppKit"],
+    alwayslink = True,"#
+        ));
+        assert!(rendered.contains(r#"        "@platforms//os:macos": ["-framework", "AppKit"],"#));
+    }
+
+    #[test]
+    fn test_modules() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path();
+        fs::create_dir_all(root.join("src/net")).unwrap();
+        fs::create_dir_all(root.join("src/storage")).unwrap();
+        fs::write(root.join("src/demo.cc"), "int demo() { return 1; }").unwrap();
+        fs::write(root.join("src/net/socket.cc"), "int socket() { return 1; }").unwrap();
+        fs::write(root.join("src/storage/db.cc"), "int db() { return 1; }").unwrap();
+
+        let mut config: Config = toml::from_str(
+            r#"
+[package]
+name = "demo"
+version = "0.1.0"
+edition = "2023"
+
+[dependencies]
+
+[modules.storage]
+deps = ["net", "@sqlite//:sqlite"]
+"#,
+        )
+        .unwrap();
+        let build_files = scan_library(root, &config, &ExternalDeps::default()).unwrap();
+        let library = |dir: &str| {
+            build_files
+                .iter()
+                .find(|b| b.dir == Path::new(dir))
+                .unwrap()
+                .targets[0]
+                .clone()
+        };
+        assert_eq!(
+            library("src").deps,
+            ["//src/net:net", "//src/storage:storage"]
+        );
+        assert_eq!(
+            library("src/storage").deps,
+            ["//src/net:net", "@sqlite//:sqlite"]
+        );
+        assert_eq!(library("src/net").visibility, ["//visibility:public"]);
+
+        config.modules.get_mut("storage").unwrap().deps = vec!["cache".to_string()];
+        assert!(scan_library(root, &config, &ExternalDeps::default())
+            .unwrap_err()
+            .to_string()
+            .contains("depends on `cache`"));
+    }
+
+    #[test]
+    fn test_platform_dependencies() {
+        let mut build_files = vec![BuildFile {
+            dir: PathBuf::from("src"),
+            targets: vec![Target {
+                kind: Kind::Library,
+                name: "demo".to_string(),
+                srcs: vec!["demo.cc".to_string()],
+                ..Default::default()
+            }],
+        }];
+        let platforms = [(
+            "@platforms//os:linux".to_string(),
+            vec!["@liburing//:uring".to_string()],
+        )]
+        .into();
+        apply_dependencies(&mut build_files, &["@fmt//:fmt".to_string()], &platforms);
+        assert!(build_files[0].render().contains(
+            r#"    deps = ["@fmt//:fmt"] + select({
+        "@platforms//os:linux": ["@liburing//:uring"],
+        "//conditions:default": [],
+    }),
+"#
+        ));
+    }
+
+    #[test]
+    fn test_path_dependencies() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path().join("app");
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(tmp_dir.path().join("core")).unwrap();
+        fs::write(root.join("src/main.cc"), "int main() { return 0; }").unwrap();
+        fs::write(
+            tmp_dir.path().join("core/Buddy.toml"),
+            "[package]\nname = \"core\"\nversion = \"0.1.0\"\nedition = \"2023\"\n[dependencies]\n",
+        )
+        .unwrap();
+
+        let config: Config = toml::from_str(
+            r#"
+[package]
+name = "app"
+version = "0.1.0"
+edition = "2023"
+
+[dependencies]
+my-core = { path = "../core" }
+"#,
+        )
+        .unwrap();
+        let build_files = scan_library(&root, &config, &ExternalDeps::default()).unwrap();
+        assert_eq!(build_files[0].targets[0].kind, Kind::Binary);
+        assert_eq!(build_files[0].targets[0].deps, ["@my_core//src:core"]);
+
+        fs::write(root.join("src/app.cc"), "int app() { return 1; }").unwrap();
+        let build_files = scan_library(&root, &config, &ExternalDeps::default()).unwrap();
+        let library = &build_files[0].targets[0];
+        assert_eq!(library.kind, Kind::Library);
+        assert_eq!(library.deps, ["@my_core//src:core"]);
+        assert_eq!(library.visibility, ["//visibility:public"]);
+    }
+
+    #[test]
+    fn test_workspace_member() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path().join("libs/app");
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(root.join("include/app")).unwrap();
+        fs::create_dir_all(root.join("test")).unwrap();
+        fs::write(root.join("src/app.cc"), "int app() { return 1; }").unwrap();
+        fs::write(root.join("include/app/app.h"), "int app();").unwrap();
+        fs::write(root.join("test/app_test.cc"), "").unwrap();
+
+        let config: Config = toml::from_str(
+            r#"
+[package]
+name = "app"
+version = "0.1.0"
+edition = "2023"
+
+[lib]
+public-headers = ["include/**/*.h"]
+"#,
+        )
+        .unwrap();
+        let external = ExternalDeps {
+            members: vec!["//core/src:core".to_string()],
+            ..Default::default()
+        };
+        let prefix = Path::new("libs/app");
+        assert!(sync_library(&root, prefix, &config, &external).unwrap());
+        let src = fs::read_to_string(root.join("src/BUILD")).unwrap();
+        assert!(src.contains(
+            r#"    deps = [
+        "//libs/app/include/app:app",
+        "//core/src:core",
+    ],"#
+        ));
+        let include = fs::read_to_string(root.join("include/app/BUILD")).unwrap();
+        assert!(include.contains(r#"strip_include_prefix = "/libs/app/include""#));
+
+        sync_tests(&root, prefix, "app", &config.test).unwrap();
+        let test = fs::read_to_string(root.join("test/BUILD")).unwrap();
+        assert!(test.contains(r#""//libs/app/src:app""#));
+
+        assert_eq!(relocate_label("//:app", "libs/app"), "//libs/app:app");
+        assert_eq!(relocate_label("@fmt//:fmt", "libs/app"), "@fmt//:fmt");
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*_test.cc", "parser_test.cc"));
+        assert!(glob_match("test_?.cc", "test_a.cc"));
+        assert!(!glob_match("*_test.cc", "parser.cc"));
+        assert!(!glob_match("*_test.cc", "parser_test.cc.orig"));
+    }
+
+    #[test]
+    fn test_sync_tests_picks_up_new_files() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path();
+        fs::create_dir_all(root.join("test")).unwrap();
+        fs::write(root.join("test/a_test.cc"), "").unwrap();
+        fs::write(root.join("test/helpers.cc"), "").unwrap();
+
+        let mut test = TestConfig {
+            pattern: "*_test.cc".to_string(),
+            deps: vec!["//src:demo".to_string()],
+            targets: BTreeMap::new(),
+        };
+        test.targets.insert(
+            "b_test".to_string(),
+            TestTarget {
+                cache: false,
+                tags: vec!["integration".to_string()],
+            },
+        );
+        sync_tests(root, Path::new(""), "demo", &test).unwrap();
+        fs::write(root.join("test/b_test.cc"), "").unwrap();
+        sync_tests(root, Path::new(""), "demo", &test).unwrap();
+
+        let build = fs::read_to_string(root.join("test/BUILD")).unwrap();
+        assert!(build.contains("name = \"a_test\""));
+        assert!(build.contains("name = \"b_test\""));
+        assert_eq!(
+            build
+                .matches("_test.cc\",\n        \"helpers.cc\",\n    ],")
+                .count(),
+            2
+        );
+        assert!(build.contains("deps = [\"//src:demo\"]"));
+        assert_eq!(
+            build
+                .matches("tags = [\n        \"integration\",\n        \"external\",\n    ]")
+                .count(),
+            1
+        );
+
+        // Hand-written BUILD files are never replaced
+        fs::write(root.join("test/BUILD"), "# mine").unwrap();
+        sync_tests(root, Path::new(""), "demo", &test).unwrap();
+        assert_eq!(
+            fs::read_to_string(root.join("test/BUILD")).unwrap(),
+            "# mine"
+        );
+    }
+
+    #[test]
+    fn test_sync_examples() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(root.join("examples/server")).unwrap();
+        fs::write(root.join("src/demo.cc"), "int answer() { return 42; }").unwrap();
+        fs::write(root.join("examples/hello.cc"), "int main() {}").unwrap();
+        fs::write(root.join("examples/server/main.cc"), "int main() {}").unwrap();
+
+        sync_examples(root, Path::new("app"), "demo", &TestConfig::default()).unwrap();
+        let build = fs::read_to_string(root.join("examples/BUILD")).unwrap();
+        assert!(build.starts_with(GENERATED_HEADER));
+        assert!(build.contains("name = \"hello\""));
+        assert!(build.contains("deps = [\"//app/src:demo\"]"));
+        let build = fs::read_to_string(root.join("examples/server/BUILD")).unwrap();
+        assert!(build.contains("name = \"server\""));
+        assert!(!root.join("src/BUILD").exists());
+
+        fs::create_dir_all(root.join("src/bin")).unwrap();
+        fs::write(root.join("src/bin/tool.cc"), "int main() {}").unwrap();
+        sync_binaries(root, Path::new(""), "demo", &TestConfig::default()).unwrap();
+        let build = fs::read_to_string(root.join("src/bin/BUILD")).unwrap();
+        assert!(build.contains("name = \"tool\""));
+        assert!(build.contains("deps = [\"//src:demo\"]"));
+
+        fs::create_dir_all(root.join("bench")).unwrap();
+        fs::write(root.join("bench/demo_bench.cc"), "BENCHMARK(BM_Answer);").unwrap();
+        fs::write(root.join("bench/fixture.h"), "#pragma once").unwrap();
+        sync_benches(root, Path::new(""), "demo", &TestConfig::default()).unwrap();
+        let build = fs::read_to_string(root.join("bench/BUILD")).unwrap();
+        assert!(build.contains("name = \"demo_bench\""));
+        assert!(build.contains("\"fixture.h\""));
+        assert!(build.contains("copts = [\"-O2\"]"));
+        assert!(build.contains(BENCHMARK_MAIN));
+        assert!(!build.contains("cc_library"));
+
+        fs::create_dir_all(root.join("fuzz")).unwrap();
+        fs::write(
+            root.join("fuzz/parse_test.cc"),
+            "int LLVMFuzzerTestOneInput();",
+        )
+        .unwrap();
+        sync_fuzzers(root, Path::new(""), "demo", &TestConfig::default()).unwrap();
+        let build = fs::read_to_string(root.join("fuzz/BUILD")).unwrap();
+        assert!(build.contains("cc_test(\n    name = \"parse_test\""));
+        assert!(build.contains("        \"manual\",\n"));
+        assert!(build.contains("linkopts = [\"-fsanitize=fuzzer\"]"));
+    }
+}
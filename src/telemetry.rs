@@ -0,0 +1,346 @@
+use serde_json::{json, Value};
+use std::collections::hash_map::RandomState;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use which::which;
+
+use crate::credentials;
+use crate::global::{GlobalConfig, TelemetryConfig};
+use crate::style;
+
+/// What the build events tell about the work bazel did.
+#[derive(Debug, Default, PartialEq)]
+pub struct BuildMetrics {
+    pub targets_configured: u64,
+    pub targets_built: u64,
+    pub actions_executed: u64,
+    /// Spawns served by a local or remote cache.
+    pub cache_hits: u64,
+    /// Spawns which could have been cached, hits included.
+    pub cacheable: u64,
+}
+
+impl BuildMetrics {
+    pub fn cache_hit_rate(&self) -> Option<f64> {
+        (self.cacheable > 0).then(|| self.cache_hits as f64 / self.cacheable as f64)
+    }
+}
+
+/// The JSON build events write 64-bit integers as strings.
+fn number(value: &Value) -> u64 {
+    value
+        .as_u64()
+        .or_else(|| value.as_str()?.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Reads the `buildMetrics` event and counts the completed targets.
+pub fn parse(events: &str) -> BuildMetrics {
+    let mut metrics = BuildMetrics::default();
+    for line in events.lines() {
+        let Ok(event) = serde_json::from_str::<Value>(line) else {
+            continue;
+        };
+        let target = &event["id"]["targetCompleted"];
+        if !target["label"].is_null()
+            && target["aspect"].is_null()
+            && event["completed"]["success"] == true
+        {
+            metrics.targets_built += 1;
+        }
+
+        let build = &event["buildMetrics"];
+        if build.is_null() {
+            continue;
+        }
+        metrics.targets_configured = number(&build["targetMetrics"]["targetsConfigured"]);
+        let actions = &build["actionSummary"];
+        metrics.actions_executed = number(&actions["actionsExecuted"]);
+        for runner in actions["runnerCount"].as_array().into_iter().flatten() {
+            let name = runner["name"].as_str().unwrap_or_default();
+            let count = number(&runner["count"]);
+            // `internal` spawns, e.g. symlinks, never hit the cache.
+            if matches!(name, "total" | "internal") {
+                continue;
+            }
+            if name.ends_with("cache hit") {
+                metrics.cache_hits += count;
+            }
+            metrics.cacheable += count;
+        }
+    }
+    metrics
+}
+
+/// A finished build or test.
+pub struct Invocation<'a> {
+    pub command: &'a str,
+    pub package: &'a str,
+    pub success: bool,
+    pub start: SystemTime,
+    pub duration: Duration,
+    pub metrics: BuildMetrics,
+}
+
+fn random_hex(bytes: usize) -> String {
+    let mut hex = String::new();
+    while hex.len() < bytes * 2 {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_usize(hex.len());
+        hex.push_str(&format!("{:016x}", hasher.finish()));
+    }
+    hex.truncate(bytes * 2);
+    hex
+}
+
+fn nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn attribute(key: &str, value: Value) -> Value {
+    let value = match value {
+        Value::Bool(b) => json!({ "boolValue": b }),
+        Value::Number(n) if n.is_f64() => json!({ "doubleValue": n }),
+        // OTLP/JSON encodes 64-bit integers as strings too.
+        Value::Number(n) => json!({ "intValue": n.to_string() }),
+        other => json!({ "stringValue": other.as_str().unwrap_or_default() }),
+    };
+    json!({ "key": key, "value": value })
+}
+
+fn resource() -> Value {
+    json!({ "attributes": [attribute("service.name", json!("buddy"))] })
+}
+
+fn scope() -> Value {
+    json!({ "name": "buddy", "version": env!("CARGO_PKG_VERSION") })
+}
+
+fn attributes(invocation: &Invocation) -> Vec<Value> {
+    vec![
+        attribute("buddy.command", json!(invocation.command)),
+        attribute("buddy.package", json!(invocation.package)),
+        attribute("buddy.success", json!(invocation.success)),
+    ]
+}
+
+/// The OTLP/JSON export of the invocation as a single span.
+pub fn traces(invocation: &Invocation) -> Value {
+    let metrics = &invocation.metrics;
+    let mut attributes = attributes(invocation);
+    attributes.extend([
+        attribute(
+            "buddy.targets.configured",
+            json!(metrics.targets_configured),
+        ),
+        attribute("buddy.targets.built", json!(metrics.targets_built)),
+        attribute("buddy.actions.executed", json!(metrics.actions_executed)),
+    ]);
+    if let Some(rate) = metrics.cache_hit_rate() {
+        attributes.push(attribute("buddy.cache.hit_rate", json!(rate)));
+    }
+    json!({
+        "resourceSpans": [{
+            "resource": resource(),
+            "scopeSpans": [{
+                "scope": scope(),
+                "spans": [{
+                    "traceId": random_hex(16),
+                    "spanId": random_hex(8),
+                    "name": format!("buddy {}", invocation.command),
+                    // SPAN_KIND_INTERNAL
+                    "kind": 1,
+                    "startTimeUnixNano": nanos(invocation.start),
+                    "endTimeUnixNano": nanos(invocation.start + invocation.duration),
+                    "attributes": attributes,
+                    // STATUS_CODE_OK or STATUS_CODE_ERROR
+                    "status": { "code": if invocation.success { 1 } else { 2 } },
+                }],
+            }],
+        }],
+    })
+}
+
+/// The OTLP/JSON export of the invocation's metrics, as gauges.
+pub fn metrics(invocation: &Invocation) -> Value {
+    let time = nanos(invocation.start + invocation.duration);
+    let gauge = |name: &str, unit: &str, value: Value| {
+        let mut point = json!({
+            "timeUnixNano": time,
+            "attributes": attributes(invocation),
+        });
+        match value.as_u64() {
+            Some(n) => point["asInt"] = json!(n.to_string()),
+            None => point["asDouble"] = value,
+        }
+        json!({ "name": name, "unit": unit, "gauge": { "dataPoints": [point] } })
+    };
+
+    let build = &invocation.metrics;
+    let mut metrics = vec![
+        gauge(
+            "buddy.duration",
+            "s",
+            json!(invocation.duration.as_secs_f64()),
+        ),
+        gauge(
+            "buddy.targets.configured",
+            "{target}",
+            json!(build.targets_configured),
+        ),
+        gauge(
+            "buddy.targets.built",
+            "{target}",
+            json!(build.targets_built),
+        ),
+        gauge(
+            "buddy.actions.executed",
+            "{action}",
+            json!(build.actions_executed),
+        ),
+    ];
+    if let Some(rate) = build.cache_hit_rate() {
+        metrics.push(gauge("buddy.cache.hit_rate", "1", json!(rate)));
+    }
+    json!({
+        "resourceMetrics": [{
+            "resource": resource(),
+            "scopeMetrics": [{ "scope": scope(), "metrics": metrics }],
+        }],
+    })
+}
+
+fn post(
+    config: &TelemetryConfig,
+    path: &str,
+    body: &Value,
+    global: &GlobalConfig,
+) -> Result<(), String> {
+    let curl = which("curl").map_err(|_| "`curl` not found, it is needed to export telemetry")?;
+    let url = format!("{}/{}", config.endpoint.trim_end_matches('/'), path);
+    // The headers carry the collector's credentials.
+    let headers: Vec<(&str, &str)> = config
+        .headers
+        .iter()
+        .map(|(name, value)| (name.as_str(), value.as_str()))
+        .collect();
+    let headers = credentials::curl_headers(&headers)?;
+    let mut child = Command::new(curl)
+        .args(["-fsS", "-o", "/dev/null", "-m", "10", "-X", "POST"])
+        .args(["-H", "Content-Type: application/json", "-K"])
+        .arg(headers.path())
+        .args(["--data-binary", "@-"])
+        .arg(&url)
+        .envs(global.http.env())
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to run curl: {}", e))?;
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(body.to_string().as_bytes())
+        .map_err(|e| e.to_string())?;
+    let status = child.wait().map_err(|e| e.to_string())?;
+    if !status.success() {
+        return Err(format!("failed to export telemetry to {}", url));
+    }
+    Ok(())
+}
+
+/// Sends the span and metrics of a build or test to the collector of
+/// `[telemetry]`, if any. Failing to export only warns.
+pub fn export(
+    global: &GlobalConfig,
+    command: &str,
+    package: &str,
+    success: bool,
+    duration: Duration,
+    event_file: &Path,
+) {
+    let Some(config) = &global.telemetry else {
+        return;
+    };
+    let invocation = Invocation {
+        command,
+        package,
+        success,
+        start: SystemTime::now() - duration,
+        duration,
+        metrics: fs::read_to_string(event_file)
+            .map(|events| parse(&events))
+            .unwrap_or_default(),
+    };
+    let exports = [
+        ("v1/traces", traces(&invocation)),
+        ("v1/metrics", metrics(&invocation)),
+    ];
+    for (path, body) in exports {
+        if let Err(error) = post(config, path, &body, global) {
+            style::warning(error);
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export() {
+        let events = r#"{"id":{"targetCompleted":{"label":"//src:demo"}},"completed":{"success":true}}
+{"id":{"targetCompleted":{"label":"//src:demo","aspect":"lint"}},"completed":{"success":true}}
+{"id":{"buildMetrics":{}},"buildMetrics":{"actionSummary":{"actionsExecuted":"12","runnerCount":[{"name":"total","count":10},{"name":"internal","count":2},{"name":"remote cache hit","count":6},{"name":"linux-sandbox","count":2}]},"targetMetrics":{"targetsConfigured":"7"}}}
+"#;
+        let build = parse(events);
+        assert_eq!(
+            build,
+            BuildMetrics {
+                targets_configured: 7,
+                targets_built: 1,
+                actions_executed: 12,
+                cache_hits: 6,
+                cacheable: 8,
+            }
+        );
+        assert_eq!(build.cache_hit_rate(), Some(0.75));
+
+        let invocation = Invocation {
+            command: "build",
+            package: "demo",
+            success: false,
+            start: UNIX_EPOCH + Duration::from_secs(10),
+            duration: Duration::from_secs(2),
+            metrics: build,
+        };
+        let span = &traces(&invocation)["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+        assert_eq!(span["name"], "buddy build");
+        assert_eq!(span["endTimeUnixNano"], "12000000000");
+        assert_eq!(span["status"]["code"], 2);
+        assert_eq!(span["traceId"].as_str().unwrap().len(), 32);
+        assert_eq!(span["spanId"].as_str().unwrap().len(), 16);
+
+        let metrics = metrics(&invocation);
+        let metrics = metrics["resourceMetrics"][0]["scopeMetrics"][0]["metrics"]
+            .as_array()
+            .unwrap();
+        let rate = metrics
+            .iter()
+            .find(|m| m["name"] == "buddy.cache.hit_rate")
+            .unwrap();
+        assert_eq!(rate["gauge"]["dataPoints"][0]["asDouble"], 0.75);
+        let built = metrics
+            .iter()
+            .find(|m| m["name"] == "buddy.targets.built")
+            .unwrap();
+        assert_eq!(built["gauge"]["dataPoints"][0]["asInt"], "1");
+    }
+}
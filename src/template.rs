@@ -0,0 +1,399 @@
+use clap::ValueEnum;
+use handlebars::{handlebars_helper, Handlebars};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+use tempfile::TempDir;
+
+use crate::bazel;
+use crate::commands::dist;
+use crate::commands::init::PackageKind;
+use crate::config::Language;
+use crate::git;
+
+/// The manifest at the root of a project template.
+pub const MANIFEST: &str = "buddy-template.toml";
+
+/// `buddy-template.toml`: what `buddy new --template` makes of the files of
+/// a template.
+#[derive(Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct TemplateManifest {
+    /// Shown when a package is created from the template.
+    pub description: Option<String>,
+    /// Files of the template rendered into the new package, their path
+    /// included, e.g. `test/{{name}}_test.cc`.
+    #[serde(default)]
+    pub render: Vec<String>,
+    /// Directories created in the new package, even when empty.
+    #[serde(default)]
+    pub directories: Vec<String>,
+}
+
+/// The `.gitignore` of new packages.
+pub const GITIGNORE: &str = include_str!("templates/gitignore");
+
+/// The `.clang-format` of new packages, read by `buddy fmt`.
+pub const CLANG_FORMAT: &str = include_str!("templates/clang-format");
+
+/// A license a new package can be created with, as its SPDX identifier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ValueEnum)]
+pub enum License {
+    #[serde(rename = "MIT")]
+    #[value(name = "MIT", alias = "mit")]
+    Mit,
+    #[serde(rename = "Apache-2.0")]
+    #[value(name = "Apache-2.0", alias = "apache-2.0")]
+    Apache2,
+    #[serde(rename = "BSD-3-Clause")]
+    #[value(name = "BSD-3-Clause", alias = "BSD-3", alias = "bsd-3")]
+    Bsd3,
+}
+
+/// The variables templates are rendered with, as `{{name}}`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Vars {
+    pub name: String,
+    /// The name as a C or C++ identifier, e.g. for a namespace.
+    pub ident: String,
+    /// Whether the package is written in C rather than C++.
+    pub c: bool,
+    /// Whether the package is a library.
+    pub lib: bool,
+    /// Whether the tests use GoogleTest rather than being plain programs.
+    pub googletest: bool,
+    /// Whether the package has Google Benchmark programs under `bench/`.
+    pub bench: bool,
+    /// Whether bazel builds with a hermetic LLVM toolchain rather than the
+    /// compiler of the system.
+    pub llvm_toolchain: bool,
+    /// The C++ standard, e.g. `c++17`.
+    pub cxx_standard: String,
+    /// The C standard of C packages, e.g. `c17`.
+    pub c_standard: String,
+    /// `Name <email>` of the git configuration.
+    pub author: Option<String>,
+    pub license: Option<License>,
+    /// Who holds the copyright of the license, the git user or the
+    /// package's authors.
+    pub holder: String,
+    /// The current year, for copyright notices.
+    pub year: String,
+    pub buddy_version: String,
+}
+
+impl Vars {
+    pub fn new(name: &str, language: Language, kind: PackageKind) -> Vars {
+        Vars {
+            name: name.to_string(),
+            ident: name.replace('-', "_"),
+            c: language == Language::C,
+            lib: kind == PackageKind::Lib,
+            googletest: language == Language::Cxx,
+            bench: false,
+            llvm_toolchain: true,
+            cxx_standard: bazel::DEFAULT_CXX_STANDARD.to_string(),
+            c_standard: "c17".to_string(),
+            author: None,
+            license: None,
+            holder: format!("The {} authors", name),
+            year: dist::rfc3339(SystemTime::now())[..4].to_string(),
+            buddy_version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+}
+
+handlebars_helper!(upper: |text: String| text.to_uppercase());
+
+/// Renders the handlebars `template` with `vars`, as they are: no HTML
+/// escaping. `{{upper ident}}` gives the upper case of a variable.
+pub fn render(template: &str, vars: &Vars) -> Result<String, String> {
+    let mut registry = Handlebars::new();
+    registry.register_escape_fn(handlebars::no_escape);
+    registry.register_helper("upper", Box::new(upper));
+    registry
+        .render_template(template, vars)
+        .map_err(|e| format!("invalid template: {}", e))
+}
+
+/// Renders a template shipped with buddy, which is known to be valid.
+fn render_builtin(template: &str, vars: &Vars) -> String {
+    render(template, vars).expect("built-in templates are valid")
+}
+
+/// The manifest of a new package.
+pub fn manifest(vars: &Vars) -> String {
+    render_builtin(include_str!("templates/Buddy.toml.hbs"), vars)
+}
+
+/// The `LICENSE` of a new package under `license`.
+pub fn license_text(license: License, vars: &Vars) -> String {
+    let template = match license {
+        License::Mit => include_str!("templates/licenses/MIT.hbs"),
+        License::Apache2 => include_str!("templates/licenses/Apache-2.0.hbs"),
+        License::Bsd3 => include_str!("templates/licenses/BSD-3-Clause.hbs"),
+    };
+    render_builtin(template, vars)
+}
+
+/// The sources of a new package, relative to its root: the hello-world
+/// program and its `BUILD` file, or the public header, implementation,
+/// test and example of a library, then the benchmark if any.
+pub fn sources(vars: &Vars) -> Vec<(PathBuf, String)> {
+    let mut files: Vec<(&str, &str)> = match (vars.lib, vars.c) {
+        (false, false) => vec![
+            ("src/BUILD", include_str!("templates/bin/BUILD.hbs")),
+            ("src/main.cc", include_str!("templates/bin/main.cc")),
+            (
+                "test/hello_test.cc",
+                if vars.googletest {
+                    include_str!("templates/bin/hello_test.cc")
+                } else {
+                    include_str!("templates/bin/hello_test_plain.cc")
+                },
+            ),
+        ],
+        (false, true) => vec![
+            ("src/BUILD", include_str!("templates/bin/BUILD.hbs")),
+            ("src/main.c", include_str!("templates/bin/main.c")),
+            (
+                "test/hello_test.c",
+                include_str!("templates/bin/hello_test.c"),
+            ),
+        ],
+        (true, false) => vec![
+            (
+                "include/{{name}}/{{name}}.h",
+                include_str!("templates/lib/lib.h.hbs"),
+            ),
+            ("src/{{name}}.cc", include_str!("templates/lib/lib.cc.hbs")),
+            (
+                "test/{{ident}}_test.cc",
+                if vars.googletest {
+                    include_str!("templates/lib/lib_test.cc.hbs")
+                } else {
+                    include_str!("templates/lib/lib_test_plain.cc.hbs")
+                },
+            ),
+            (
+                "examples/hello.cc",
+                include_str!("templates/lib/example.cc.hbs"),
+            ),
+        ],
+        (true, true) => vec![
+            (
+                "include/{{name}}/{{name}}.h",
+                include_str!("templates/lib/lib_c.h.hbs"),
+            ),
+            ("src/{{name}}.c", include_str!("templates/lib/lib.c.hbs")),
+            (
+                "test/{{ident}}_test.c",
+                include_str!("templates/lib/lib_test.c.hbs"),
+            ),
+            (
+                "examples/hello.c",
+                include_str!("templates/lib/example.c.hbs"),
+            ),
+        ],
+    };
+    if vars.bench {
+        files.push((
+            "bench/{{ident}}_bench.cc",
+            if vars.lib {
+                include_str!("templates/lib/lib_bench.cc.hbs")
+            } else {
+                include_str!("templates/bin/hello_bench.cc")
+            },
+        ));
+    }
+    files
+        .iter()
+        .map(|(path, contents)| {
+            (
+                PathBuf::from(render_builtin(path, vars)),
+                render_builtin(contents, vars),
+            )
+        })
+        .collect()
+}
+
+/// A template on disk, a local directory or a clone of a git repository.
+#[derive(Debug)]
+pub struct Template {
+    root: PathBuf,
+    pub manifest: TemplateManifest,
+    /// Holds the clone of a git template until the template is dropped.
+    _clone: Option<TempDir>,
+}
+
+/// `path` of a template, which must stay inside the package.
+fn relative_path(path: &str) -> Result<PathBuf, String> {
+    let path = PathBuf::from(path);
+    if path.as_os_str().is_empty()
+        || !path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(format!(
+            "template path `{}` must be relative to the package and stay inside it",
+            path.display()
+        ));
+    }
+    Ok(path)
+}
+
+/// The file of the render list `file` of the template at `root`. It must
+/// be a file of the template, not a link: the one of a cloned repository
+/// could point anywhere on the machine.
+fn source_path(root: &Path, file: &str) -> Result<PathBuf, String> {
+    let path = root.join(relative_path(file)?);
+    let metadata = fs::symlink_metadata(&path)
+        .map_err(|_| format!("`{}` of the template's render list is missing", file))?;
+    if metadata.file_type().is_symlink() {
+        return Err(format!(
+            "`{}` of the template's render list is a symbolic link",
+            file
+        ));
+    }
+    let resolved = fs::canonicalize(&path).map_err(|e| format!("{}: {}", file, e))?;
+    let root = fs::canonicalize(root).map_err(|e| format!("{}: {}", root.display(), e))?;
+    if !resolved.starts_with(&root) {
+        return Err(format!(
+            "`{}` of the template's render list resolves outside the template",
+            file
+        ));
+    }
+    Ok(resolved)
+}
+
+impl Template {
+    /// Fetches the template at `source`, a local directory or the URL of a
+    /// git repository, which is cloned.
+    pub fn fetch(source: &str) -> Result<Template, String> {
+        let (root, clone) = if Path::new(source).is_dir() {
+            (PathBuf::from(source), None)
+        } else {
+            let tmp_dir = tempfile::tempdir().map_err(|e| e.to_string())?;
+            let root = tmp_dir.path().join("template");
+            let dest = root.to_str().ok_or("invalid temporary directory")?;
+            // Past `--`, a source starting with a dash isn't an option.
+            git::output(&["clone", "--quiet", "--depth", "1", "--", source, dest])
+                .map_err(|_| format!("`{}` is neither a directory nor a git repository", source))?;
+            (root, Some(tmp_dir))
+        };
+
+        let contents = fs::read_to_string(root.join(MANIFEST))
+            .map_err(|_| format!("template `{}` has no {}", source, MANIFEST))?;
+        let manifest = toml::from_str(&contents)
+            .map_err(|e| format!("failed to parse {} of `{}`: {}", MANIFEST, source, e))?;
+        Ok(Template {
+            root,
+            manifest,
+            _clone: clone,
+        })
+    }
+
+    /// Writes the directories and files of the template into `dest`,
+    /// rendered with `vars`.
+    pub fn generate(&self, dest: &Path, vars: &Vars) -> Result<(), String> {
+        for dir in &self.manifest.directories {
+            let dir = relative_path(&render(dir, vars)?)?;
+            fs::create_dir_all(dest.join(dir)).map_err(|e| e.to_string())?;
+        }
+        for file in &self.manifest.render {
+            let source = source_path(&self.root, file)?;
+            let contents = fs::read_to_string(&source).map_err(|e| format!("{}: {}", file, e))?;
+            let path = dest.join(relative_path(&render(file, vars)?)?);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+            }
+            let contents = render(&contents, vars).map_err(|e| format!("{}: {}", file, e))?;
+            fs::write(&path, contents).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let template = tmp_dir.path().join("template");
+        fs::create_dir_all(template.join("test")).unwrap();
+        fs::write(
+            template.join(MANIFEST),
+            r#"
+description = "A service"
+render = ["Buddy.toml", "test/{{name}}_test.cc"]
+directories = ["proto", "include/{{ name }}"]
+"#,
+        )
+        .unwrap();
+        fs::write(
+            template.join("Buddy.toml"),
+            "[package]\nname = \"{{name}}\"\n",
+        )
+        .unwrap();
+        fs::write(template.join("test/{{name}}_test.cc"), "// {{ name }}\n").unwrap();
+        fs::write(template.join("README.md"), "left out").unwrap();
+
+        let template = Template::fetch(template.to_str().unwrap()).unwrap();
+        assert_eq!(template.manifest.description.as_deref(), Some("A service"));
+        let dest = tmp_dir.path().join("svc");
+        let vars = Vars::new("svc", Language::Cxx, PackageKind::Bin);
+        template.generate(&dest, &vars).unwrap();
+        assert_eq!(
+            fs::read_to_string(dest.join("Buddy.toml")).unwrap(),
+            "[package]\nname = \"svc\"\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dest.join("test/svc_test.cc")).unwrap(),
+            "// svc\n"
+        );
+        assert!(dest.join("proto").is_dir());
+        assert!(dest.join("include/svc").is_dir());
+        assert!(!dest.join("README.md").exists());
+
+        assert!(relative_path("../outside").is_err());
+        assert!(Template::fetch(tmp_dir.path().join("missing").to_str().unwrap()).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_generate_links() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let template = tmp_dir.path().join("template");
+        let outside = tmp_dir.path().join("outside");
+        fs::create_dir_all(template.join("src")).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(outside.join("secret"), "secret").unwrap();
+        std::os::unix::fs::symlink(outside.join("secret"), template.join("notes.txt")).unwrap();
+        std::os::unix::fs::symlink(&outside, template.join("linked")).unwrap();
+        fs::write(template.join("src/main.cc"), "int main() {}\n").unwrap();
+
+        let generate = |render: &str| {
+            fs::write(
+                template.join(MANIFEST),
+                format!("render = [\"{}\"]\n", render),
+            )
+            .unwrap();
+            let template = Template::fetch(template.to_str().unwrap()).unwrap();
+            let vars = Vars::new("svc", Language::Cxx, PackageKind::Bin);
+            template.generate(&tmp_dir.path().join("svc"), &vars)
+        };
+        assert_eq!(
+            generate("notes.txt").unwrap_err(),
+            "`notes.txt` of the template's render list is a symbolic link"
+        );
+        assert_eq!(
+            generate("linked/secret").unwrap_err(),
+            "`linked/secret` of the template's render list resolves outside the template"
+        );
+        assert!(!tmp_dir.path().join("svc/linked").exists());
+        generate("src/main.cc").unwrap();
+    }
+}
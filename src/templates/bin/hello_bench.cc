@@ -0,0 +1,12 @@
+#include <benchmark/benchmark.h>
+
+#include <string>
+
+static void BM_StringCopy(benchmark::State& state) {
+  std::string hello = "Hello, world!";
+  for (auto _ : state) {
+    std::string copy(hello);
+    benchmark::DoNotOptimize(copy);
+  }
+}
+BENCHMARK(BM_StringCopy);
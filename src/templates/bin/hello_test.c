@@ -0,0 +1,19 @@
+#include <stdio.h>
+#include <string.h>
+
+static int failures = 0;
+
+#define CHECK(condition)                                                  \
+  do {                                                                    \
+    if (!(condition)) {                                                   \
+      fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, \
+              #condition);                                                \
+      failures++;                                                         \
+    }                                                                     \
+  } while (0)
+
+int main(void) {
+  CHECK(strcmp("hello", "world") != 0);
+  CHECK(7 * 6 == 42);
+  return failures == 0 ? 0 : 1;
+}
@@ -0,0 +1,19 @@
+#include <cstring>
+#include <iostream>
+
+static int failures = 0;
+
+#define CHECK(condition)                                                  \
+  do {                                                                    \
+    if (!(condition)) {                                                   \
+      std::cerr << __FILE__ << ":" << __LINE__                            \
+                << ": check failed: " #condition << std::endl;            \
+      failures++;                                                         \
+    }                                                                     \
+  } while (0)
+
+int main() {
+  CHECK(std::strcmp("hello", "world") != 0);
+  CHECK(7 * 6 == 42);
+  return failures == 0 ? 0 : 1;
+}
@@ -0,0 +1,13 @@
+#include <stdio.h>
+#include <time.h>
+
+int main(int argc, char** argv) {
+  const char* who = "world";
+  if (argc > 1) {
+    who = argv[1];
+  }
+  printf("Hello %s\n", who);
+  time_t now = time(NULL);
+  printf("%s", asctime(localtime(&now)));
+  return 0;
+}
@@ -0,0 +1,390 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::{self, GitDependency, Source};
+use crate::global::GlobalConfig;
+use crate::lockfile::{Lockfile, LOCKFILE};
+use crate::plugins::{self, Plugin};
+use crate::targets::GENERATED_HEADER;
+
+/// Where `buddy vendor` puts the archives of the dependencies.
+pub const VENDOR_DIR: &str = "vendor";
+
+/// The absolute path of `root`'s vendor directory, if it was vendored.
+pub fn vendor_dir(root: &Path) -> Option<PathBuf> {
+    fs::canonicalize(root.join(VENDOR_DIR))
+        .ok()
+        .filter(|dir| dir.is_dir())
+}
+
+/// Where `buddy vendor` keeps the archive of `name` downloaded from `url`
+/// in `vendor`: named after the repository too, as the archives of tags of
+/// different projects are named alike.
+pub fn vendor_path(vendor: &Path, name: &str, url: &str) -> PathBuf {
+    vendor.join(format!(
+        "{}-{}",
+        config::repository_name(name),
+        url.rsplit('/').next().unwrap_or(url)
+    ))
+}
+
+/// The label of the `build-file` of a git dependency, given as a label or
+/// as a path relative to the package.
+fn build_file_label(build_file: &str) -> String {
+    if build_file.starts_with("//") || build_file.starts_with('@') {
+        return build_file.to_string();
+    }
+    match build_file.rsplit_once('/') {
+        Some((dir, file)) => format!("//{}:{}", dir, file),
+        None => format!("//:{}", build_file),
+    }
+}
+
+/// The repository the source of the dependency `name` is fetched into:
+/// the one of its recipe's target, so that the labels of a patched recipe
+/// still resolve, its own name otherwise.
+fn repository(name: &str, plugins: &[Plugin]) -> String {
+    plugins::find(plugins, name)
+        .and_then(|plugin| plugin.target.as_deref())
+        .and_then(|label| Some(label.strip_prefix('@')?.split_once("//")?.0.to_string()))
+        .unwrap_or_else(|| config::repository_name(name))
+}
+
+/// The rule of the git dependency `name`, fetched into `repository`, at
+/// `commit` when it was locked, otherwise at the reference of the manifest.
+fn git_rule(
+    name: &str,
+    repository: &str,
+    dependency: &GitDependency,
+    commit: Option<&str>,
+) -> Result<String, String> {
+    let reference = dependency.reference()?;
+    let pin = match (commit, &dependency.branch, &dependency.tag) {
+        (Some(commit), _, _) => format!("commit = \"{}\"", commit),
+        (None, Some(branch), _) => format!("branch = \"{}\"", branch),
+        (None, None, Some(tag)) => format!("tag = \"{}\"", tag),
+        (None, None, None) if reference == "HEAD" => {
+            return Err(format!(
+                "`{}` isn't locked yet and sets none of `rev`, `branch` and `tag`",
+                name
+            ))
+        }
+        (None, None, None) => format!("commit = \"{}\"", reference),
+    };
+    let mut rule = match &dependency.build_file {
+        Some(_) => "new_git_repository(\n".to_string(),
+        None => "git_repository(\n".to_string(),
+    };
+    rule.push_str(&format!(
+        "    name = \"{}\",\n    remote = \"{}\",\n    {},\n",
+        repository, dependency.git, pin
+    ));
+    if let Some(build_file) = &dependency.build_file {
+        rule.push_str(&format!(
+            "    build_file = \"{}\",\n",
+            build_file_label(build_file)
+        ));
+    }
+    rule.push(')');
+    Ok(rule)
+}
+
+/// Renders the WORKSPACE of `dependencies`, each one from the recipe of the
+/// version its requirement picks, the locked one while it still satisfies
+/// it, and of the `sources`: git dependencies at the commit `lockfile`
+/// resolved them to, local packages at their path. The archives found in
+/// `vendor` are used in place of their download URL.
+pub fn render(
+    dependencies: &HashMap<String, String>,
+    sources: &BTreeMap<String, Source>,
+    lockfile: &Lockfile,
+    plugins: &[Plugin],
+    global: &GlobalConfig,
+    vendor: Option<&Path>,
+) -> Result<String, String> {
+    let mut names: Vec<_> = dependencies.keys().collect();
+    names.sort();
+
+    let mut out = format!(
+        "{}load(\"@bazel_tools//tools/build_defs/repo:http.bzl\", \"http_archive\")\n",
+        GENERATED_HEADER
+    );
+    for name in names {
+        let plugin = plugins::resolve(plugins, name, global)?;
+        let version = plugin.select(&dependencies[name], lockfile.locked_version(name))?;
+        let mut rule = plugin
+            .render(&version)
+            .map_err(|error| format!("cannot resolve dependency `{}`: {}", name, error))?;
+        // Rules building their URL are left as they are, and download it.
+        if let (Some(vendor), Some(url)) = (vendor, plugin.archive_url(&version)?) {
+            let archive = vendor_path(vendor, name, &url);
+            if archive.is_file() {
+                rule = rule.replace(&url, &format!("file://{}", archive.display()));
+            }
+        }
+        out.push('\n');
+        out.push_str(&rule);
+        out.push('\n');
+    }
+
+    if sources
+        .values()
+        .any(|source| matches!(source, Source::Git(_)))
+    {
+        out.push_str(
+            "\nload(\"@bazel_tools//tools/build_defs/repo:git.bzl\", \"git_repository\", \"new_git_repository\")\n",
+        );
+    }
+    for (name, source) in sources {
+        let repository = repository(name, plugins);
+        let dependency = match source {
+            Source::Git(dependency) => dependency,
+            Source::Path(dependency) => {
+                out.push_str(&format!(
+                    "\nlocal_repository(\n    name = \"{}\",\n    path = \"{}\",\n)\n",
+                    repository, dependency.path
+                ));
+                continue;
+            }
+        };
+        let commit = lockfile
+            .package
+            .iter()
+            .find(|package| {
+                package.name == *name
+                    && package.version == dependency.reference().unwrap_or_default()
+            })
+            .and_then(|package| package.commit.as_deref());
+        out.push('\n');
+        out.push_str(&git_rule(name, &repository, dependency, commit)?);
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// Regenerates `root`'s WORKSPACE from the manifest and the lockfile,
+/// unless it isn't one buddy generated.
+pub fn sync(
+    root: &Path,
+    dependencies: &HashMap<String, String>,
+    sources: &BTreeMap<String, Source>,
+    plugins: &[Plugin],
+    global: &GlobalConfig,
+) -> Result<(), String> {
+    let path = root.join("WORKSPACE");
+    let current = fs::read_to_string(&path).ok();
+    if current
+        .as_ref()
+        .is_some_and(|contents| !contents.starts_with(GENERATED_HEADER))
+    {
+        return Ok(());
+    }
+
+    let lockfile = Lockfile::load(&root.join(LOCKFILE))?;
+    let contents = render(
+        dependencies,
+        sources,
+        &lockfile,
+        plugins,
+        global,
+        vendor_dir(root).as_deref(),
+    )?;
+    if current.as_deref() != Some(contents.as_str()) {
+        fs::write(&path, contents).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, PathDependency};
+
+    #[test]
+    fn test_sync_follows_the_manifest() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path();
+        let plugins = plugins::builtin();
+        let global = GlobalConfig::default();
+
+        let mut dependencies = HashMap::new();
+        dependencies.insert("google-test".to_string(), "1.12.1".to_string());
+        sync(root, &dependencies, &BTreeMap::new(), &plugins, &global).unwrap();
+        let workspace = fs::read_to_string(root.join("WORKSPACE")).unwrap();
+        assert!(workspace.starts_with(GENERATED_HEADER));
+        assert!(workspace.contains("googletest-58d77fa8070e8cec2dc1ed015d66b454c8d78850"));
+
+        dependencies.insert("google-test".to_string(), "1.0.0".to_string());
+        assert!(
+            sync(root, &dependencies, &BTreeMap::new(), &plugins, &global)
+                .unwrap_err()
+                .contains("unknown version `1.0.0` of `google-test`")
+        );
+
+        fs::write(root.join("WORKSPACE"), "workspace(name = \"mine\")\n").unwrap();
+        dependencies.insert("google-test".to_string(), "1.13.0".to_string());
+        sync(root, &dependencies, &BTreeMap::new(), &plugins, &global).unwrap();
+        assert_eq!(
+            fs::read_to_string(root.join("WORKSPACE")).unwrap(),
+            "workspace(name = \"mine\")\n"
+        );
+    }
+
+    #[test]
+    fn test_sync_baseline_manifest() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path();
+        let plugins = plugins::builtin();
+        let global = GlobalConfig::default();
+        // The manifest the first `buddy new` wrote.
+        let config: Config = toml::from_str(
+            "[package]\nname = \"app\"\nversion = \"0.1.0\"\nedition = \"2023\"\n\n[dependencies]\nbazel-toolchain = \"0.8.0\"\ngoogle-test = \"1.13.0\"",
+        )
+        .unwrap();
+
+        crate::lockfile::sync(
+            root,
+            &config.versions(),
+            &BTreeMap::new(),
+            &Default::default(),
+            &plugins,
+            &global,
+            false,
+        )
+        .unwrap();
+        sync(
+            root,
+            &config.versions(),
+            &BTreeMap::new(),
+            &plugins,
+            &global,
+        )
+        .unwrap();
+        let workspace = fs::read_to_string(root.join("WORKSPACE")).unwrap();
+        assert!(workspace.contains("BAZEL_TOOLCHAIN_TAG = \"0.8.2\""));
+        assert!(workspace.contains(
+            "BAZEL_TOOLCHAIN_SHA = \"0fc3a2b0c9c929920f4bed8f2b446a8274cad41f5ee823fd3faa0d7641f20db0\""
+        ));
+        let lockfile = Lockfile::load(&root.join(LOCKFILE)).unwrap();
+        assert_eq!(lockfile.locked_version("bazel-toolchain"), Some("0.8.2"));
+    }
+
+    #[test]
+    fn test_render_vendored() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let vendor = tmp_dir.path();
+        fs::write(
+            vendor.join("google_test-b796f7d44681514f58a683a3a71ff17c94edb0c1.zip"),
+            "",
+        )
+        .unwrap();
+        fs::write(vendor.join("bazel_toolchain-0.8.2.tar.gz"), "").unwrap();
+        let mut dependencies = HashMap::new();
+        dependencies.insert("google-test".to_string(), "1.13.0".to_string());
+        dependencies.insert("bazel-toolchain".to_string(), "0.8.2".to_string());
+
+        let workspace = render(
+            &dependencies,
+            &BTreeMap::new(),
+            &Lockfile::default(),
+            &plugins::builtin(),
+            &GlobalConfig::default(),
+            Some(vendor),
+        )
+        .unwrap();
+        assert!(workspace.contains(&format!(
+            "urls = [\"file://{}/google_test-b796f7d44681514f58a683a3a71ff17c94edb0c1.zip\"]",
+            vendor.display()
+        )));
+        assert!(workspace.contains(&format!(
+            "url = \"file://{}/bazel_toolchain-0.8.2.tar.gz\"",
+            vendor.display()
+        )));
+    }
+
+    #[test]
+    fn test_render_sources() {
+        let mut sources = BTreeMap::new();
+        sources.insert(
+            "my-lib".to_string(),
+            Source::Git(GitDependency {
+                git: "https://github.com/me/mylib".to_string(),
+                tag: Some("v1.2.0".to_string()),
+                build_file: Some("third_party/mylib.BUILD".to_string()),
+                ..Default::default()
+            }),
+        );
+        sources.insert(
+            "core".to_string(),
+            Source::Path(PathDependency {
+                path: "../core".to_string(),
+            }),
+        );
+        let render_with = |lockfile: &Lockfile| {
+            render(
+                &HashMap::new(),
+                &sources,
+                lockfile,
+                &[],
+                &GlobalConfig::default(),
+                None,
+            )
+            .unwrap()
+        };
+
+        let workspace = render_with(&Lockfile::default());
+        assert!(workspace.contains("load(\"@bazel_tools//tools/build_defs/repo:git.bzl\""));
+        assert!(workspace.contains(
+            "new_git_repository(\n    name = \"my_lib\",\n    remote = \"https://github.com/me/mylib\",\n    tag = \"v1.2.0\",\n    build_file = \"//third_party:mylib.BUILD\",\n)"
+        ));
+        assert!(workspace
+            .contains("local_repository(\n    name = \"core\",\n    path = \"../core\",\n)"));
+
+        let lockfile: Lockfile = toml::from_str(
+            "[[package]]\nname = \"my-lib\"\nversion = \"v1.2.0\"\ncommit = \"4f2a\"\n",
+        )
+        .unwrap();
+        assert!(render_with(&lockfile).contains("    commit = \"4f2a\",\n"));
+    }
+
+    #[test]
+    fn test_render_patch() {
+        let config: Config = toml::from_str(
+            r#"
+[package]
+name = "demo"
+version = "0.1.0"
+edition = "2024"
+
+[dependencies]
+google-test = "1.13.0"
+
+[patch]
+google-test = { path = "../googletest" }
+fmt = { path = "../fmt" }
+"#,
+        )
+        .unwrap();
+        assert!(config.versions().is_empty());
+        assert_eq!(
+            config.patched().into_iter().collect::<Vec<_>>(),
+            ["google-test"]
+        );
+
+        let workspace = render(
+            &config.versions(),
+            &config.sources(),
+            &Lockfile::default(),
+            &plugins::builtin(),
+            &GlobalConfig::default(),
+            None,
+        )
+        .unwrap();
+        assert!(!workspace.contains("http_archive(\n"));
+        assert!(!workspace.contains("../fmt"));
+        assert!(workspace.contains(
+            "local_repository(\n    name = \"com_google_googletest\",\n    path = \"../googletest\",\n)"
+        ));
+    }
+}
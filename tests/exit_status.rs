@@ -0,0 +1,42 @@
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Command;
+
+/// A project without dependencies, with a bazelisk on the `PATH` which
+/// fails every command.
+fn failing_project(dir: &Path) -> String {
+    fs::write(
+        dir.join("Buddy.toml"),
+        "[package]\nname = \"demo\"\nversion = \"0.1.0\"\nedition = \"2023\"\n\n[dependencies]\n",
+    )
+    .unwrap();
+    let bin = dir.join("bin");
+    fs::create_dir(&bin).unwrap();
+    let bazelisk = bin.join("bazelisk");
+    fs::write(&bazelisk, "#!/bin/sh\nexit 1\n").unwrap();
+    fs::set_permissions(&bazelisk, fs::Permissions::from_mode(0o755)).unwrap();
+    format!(
+        "{}:{}",
+        bin.display(),
+        std::env::var("PATH").unwrap_or_default()
+    )
+}
+
+#[test]
+fn test_failing_commands_exit_nonzero() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let root = tmp_dir.path();
+    let path = failing_project(root);
+    for command in ["build", "check", "test"] {
+        let status = Command::new(env!("CARGO_BIN_EXE_buddy"))
+            .arg(command)
+            .current_dir(root)
+            .env("PATH", &path)
+            .env("BUDDY_HOME", root.join("home"))
+            .output()
+            .unwrap()
+            .status;
+        assert_eq!(status.code(), Some(1), "buddy {}", command);
+    }
+}